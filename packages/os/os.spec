@@ -44,6 +44,7 @@ Source201: host-containers-tmpfiles.conf
 Source202: thar-be-updates-tmpfiles.conf
 Source203: bootstrap-containers-tmpfiles.conf
 Source204: netdog-tmpfiles.conf
+Source205: thar-be-settings-tmpfiles.conf
 
 # 3xx sources: udev rules
 Source300: ephemeral-storage.rules
@@ -54,6 +55,7 @@ Requires: %{_cross_os}apiserver
 Requires: %{_cross_os}bootstrap-containers
 Requires: %{_cross_os}bork
 Requires: %{_cross_os}corndog
+Requires: %{_cross_os}datastore-fsck
 Requires: %{_cross_os}early-boot-config
 Requires: %{_cross_os}ghostdog
 Requires: %{_cross_os}growpart
@@ -156,6 +158,11 @@ Summary: Data store creator
 %description -n %{_cross_os}storewolf
 %{summary}.
 
+%package -n %{_cross_os}datastore-fsck
+Summary: Data store checker and repair tool
+%description -n %{_cross_os}datastore-fsck
+%{summary}.
+
 %package -n %{_cross_os}migration
 Summary: Tools to migrate version formats
 %description -n %{_cross_os}migration
@@ -288,6 +295,7 @@ echo "** Output from non-static builds:"
     -p servicedog \
     -p host-containers \
     -p storewolf \
+    -p datastore-fsck \
     -p settings-committer \
     -p migrator \
     -p signpost \
@@ -327,7 +335,7 @@ for p in \
   apiserver \
   early-boot-config netdog sundog schnauzer bork corndog \
   thar-be-settings thar-be-updates servicedog host-containers \
-  storewolf settings-committer \
+  storewolf datastore-fsck settings-committer \
   migrator prairiedog \
   signpost updog metricdog logdog \
   ghostdog bootstrap-containers \
@@ -403,6 +411,7 @@ install -p -m 0644 %{S:201} %{buildroot}%{_cross_tmpfilesdir}/host-containers.co
 install -p -m 0644 %{S:202} %{buildroot}%{_cross_tmpfilesdir}/thar-be-updates.conf
 install -p -m 0644 %{S:203} %{buildroot}%{_cross_tmpfilesdir}/bootstrap-containers.conf
 install -p -m 0644 %{S:204} %{buildroot}%{_cross_tmpfilesdir}/netdog.conf
+install -p -m 0644 %{S:205} %{buildroot}%{_cross_tmpfilesdir}/thar-be-settings.conf
 
 install -d %{buildroot}%{_cross_udevrulesdir}
 install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-storage.rules
@@ -446,6 +455,7 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 %files -n %{_cross_os}thar-be-settings
 %{_cross_bindir}/thar-be-settings
 %{_cross_unitdir}/settings-applier.service
+%{_cross_tmpfilesdir}/thar-be-settings.conf
 
 %files -n %{_cross_os}thar-be-updates
 %{_cross_bindir}/thar-be-updates
@@ -463,6 +473,9 @@ install -p -m 0644 %{S:300} %{buildroot}%{_cross_udevrulesdir}/80-ephemeral-stor
 %{_cross_bindir}/storewolf
 %{_cross_unitdir}/storewolf.service
 
+%files -n %{_cross_os}datastore-fsck
+%{_cross_bindir}/datastore-fsck
+
 %files -n %{_cross_os}migration
 %{_cross_bindir}/migrator
 %{_cross_tmpfilesdir}/migration.conf
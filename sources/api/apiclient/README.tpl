@@ -27,6 +27,44 @@ Or, request all settings whose names start with a given `prefix`.
 apiclient -u /settings?prefix=host-containers.admin
 ```
 
+### Get mode
+
+Rather than building the URIs above yourself, you can use `get` mode to print settings:
+
+```
+apiclient get settings.motd kernel.lockdown
+```
+
+As with `set`, the "settings." prefix on the setting names is optional.
+You can also give the name of a group of settings to print all the settings in it:
+
+```
+apiclient get kubernetes
+```
+
+Or, print all settings whose names start with a given prefix with `--prefix`, which can be given multiple times:
+
+```
+apiclient get --prefix host-containers.ad
+```
+
+With no arguments, all settings are printed.
+
+Sensitive settings, like the Kubernetes bootstrap token and the user data of host containers, are left out unless you add `--secrets`:
+
+```
+apiclient get --secrets kubernetes.bootstrap-token
+```
+
+Settings are printed as JSON by default.
+You can choose another format with `--output`: `toml` prints the settings in the same format accepted by `apiclient apply`, and `flat` prints one `key = value` line per setting, which can be easier to handle in shell scripts:
+
+```
+$ apiclient get --output flat motd kernel.lockdown
+settings.kernel.lockdown = "integrity"
+settings.motd = "hi there"
+```
+
 ### Set mode
 
 This allows you to change settings on the system.
@@ -75,6 +113,40 @@ You can use JSON form to set it:
 apiclient set --json '{"motd": "42"}'
 ```
 
+### Unset mode
+
+This resets settings to their default values, and applies the change to the system like `set` does.
+Settings that have no default value are removed.
+
+```
+apiclient unset motd kubernetes.node-labels
+```
+
+As with `set`, the "settings." prefix is optional, and you can give the name of a group of settings to reset all the settings in it.
+
+### Rollback mode
+
+This undoes the most recent settings change, restoring the previous values of the settings it changed, and applies the restored settings to the system.
+
+```
+apiclient rollback
+```
+
+Running it again undoes the change before that, and so on, for a limited number of recent changes.
+The names of the restored settings are printed.
+
+### Apply status mode
+
+When you change settings, configuration files are rewritten and services are restarted in the background.
+To see whether that worked, you can print the results of recent applies:
+
+```
+apiclient apply-status
+```
+
+For each apply, this shows whether it succeeded, any error that stopped it, each configuration file it rendered, and the exit code and stderr of each restart command it ran.
+The APIs that start an apply return its ID in the `Apply-Id` header; you can print the results of just that apply with `apiclient apply-status --id ID`.
+
 ### Update mode
 
 To start, you can check what updates are available:
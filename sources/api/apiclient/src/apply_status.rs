@@ -0,0 +1,40 @@
+//! The apply_status module lets you check the results of recent settings applies, which update
+//! configuration files and restart services after settings changes.
+
+use snafu::ResultExt;
+use std::path::Path;
+
+/// Returns the results of recent settings applies, oldest first, as a JSON string.  If `id` is
+/// given, only returns the apply with that ID, as returned in the Apply-Id header of apply APIs.
+pub async fn get<P>(socket_path: P, id: Option<&str>) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let uri = match id {
+        Some(id) => format!("/actions/apply-status?id={}", id),
+        None => "/actions/apply-status".to_string(),
+    };
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    Ok(body)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -0,0 +1,203 @@
+//! The get module lets you fetch a subset of settings, selected by key or prefix.
+
+use datastore::serialization::{to_list_keys, to_pairs};
+use datastore::Key;
+use serde_json::{json, Map, Value};
+use snafu::{ensure, OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Fetches settings through the API and returns them as a JSON object with a top-level "settings"
+/// key, the same form accepted by `apply`.
+///
+/// Each of the given keys must start with "settings"; the key itself is returned if it's a single
+/// setting, or all settings under it if it's a group of settings, like `settings.kubernetes`.
+/// Each of the given prefixes, which may omit the leading "settings.", returns all settings whose
+/// names start with it.  If no keys or prefixes are given, all settings are returned.
+///
+/// Sensitive settings, like secrets, are only returned if `secrets` is true.
+pub async fn get<P>(
+    socket_path: P,
+    keys: &[Key],
+    prefixes: &[String],
+    secrets: bool,
+) -> Result<Value>
+where
+    P: AsRef<Path>,
+{
+    let mut pairs = HashMap::new();
+    let mut lists = HashSet::new();
+
+    if keys.is_empty() && prefixes.is_empty() {
+        let (prefix_pairs, prefix_lists) = get_prefix(&socket_path, "", secrets).await?;
+        pairs.extend(prefix_pairs);
+        lists.extend(prefix_lists);
+    }
+
+    for key in keys {
+        let segments = key.segments();
+        ensure!(
+            segments[0] == "settings",
+            error::NonSettingsKey { key: key.name() }
+        );
+        // The API takes prefixes without the "settings" segment.
+        let prefix = match segments.len() {
+            1 => String::new(),
+            _ => Key::from_segments(datastore::KeyType::Data, &segments[1..])
+                .context(error::InvalidKey { key: key.name() })?
+                .name()
+                .clone(),
+        };
+        // The API matches prefixes as strings, so 'motd' would also match 'motd-extra'; only keep
+        // the key itself and the keys under it.
+        let (prefix_pairs, prefix_lists) = get_prefix(&socket_path, &prefix, secrets).await?;
+        pairs.extend(
+            prefix_pairs
+                .into_iter()
+                .filter(|(k, _)| k.starts_with_segments(segments)),
+        );
+        lists.extend(prefix_lists);
+    }
+
+    for prefix in prefixes {
+        let prefix = prefix.strip_prefix("settings.").unwrap_or(prefix);
+        let (prefix_pairs, prefix_lists) = get_prefix(&socket_path, prefix, secrets).await?;
+        pairs.extend(prefix_pairs);
+        lists.extend(prefix_lists);
+    }
+
+    nest(pairs, &lists)
+}
+
+/// Fetches the settings whose names start with "settings." followed by the given prefix, and
+/// returns them as pairs of keys and serialized values, along with the keys of any lists, whose
+/// elements can have their own keys.  An empty prefix fetches all settings.  Sensitive settings
+/// are only fetched if `secrets` is true.
+async fn get_prefix<P>(
+    socket_path: P,
+    prefix: &str,
+    secrets: bool,
+) -> Result<(HashMap<Key, String>, HashSet<Key>)>
+where
+    P: AsRef<Path>,
+{
+    let mut uri = format!("/settings?secrets={}", secrets);
+    if !prefix.is_empty() {
+        // Keys can contain quotes, which aren't allowed in URIs.
+        let encoded: String = url::form_urlencoded::byte_serialize(prefix.as_bytes()).collect();
+        uri.push_str(&format!("&prefix={}", encoded));
+    }
+    let method = "GET";
+    let (_status, body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    let settings: Value = serde_json::from_str(&body).context(error::ResponseJson { body })?;
+    let settings = json!({ "settings": settings });
+    let pairs = to_pairs(&settings).context(error::Flatten)?;
+    let lists = to_list_keys(&settings).context(error::Flatten)?;
+    Ok((pairs, lists))
+}
+
+/// Builds a nested JSON object from pairs of keys and serialized values, the reverse of
+/// `to_pairs`.  The given lists of structures, whose elements are keyed by index, are turned back
+/// into lists.
+fn nest(pairs: HashMap<Key, String>, lists: &HashSet<Key>) -> Result<Value> {
+    let mut root = Map::new();
+    for (key, data) in pairs {
+        let value = serde_json::from_str(&data).context(error::ValueJson { key: key.name() })?;
+        let (last, parents) = key.segments().split_last().expect("Key has no segments?!");
+
+        let mut map = &mut root;
+        for segment in parents {
+            let entry = map
+                .entry(segment.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            map = entry
+                .as_object_mut()
+                .context(error::KeyConflict { key: key.name() })?;
+        }
+        map.insert(last.clone(), value);
+    }
+
+    // Handle the innermost lists first, so the path to each list is still made of objects.
+    let mut lists: Vec<&Key> = lists.iter().collect();
+    lists.sort_by_key(|list| std::cmp::Reverse(list.segments().len()));
+    for list in lists {
+        let (last, parents) = list.segments().split_last().expect("Key has no segments?!");
+        // Lists that weren't requested aren't present, and lists of scalars are already lists.
+        let map = match find_object(&mut root, parents) {
+            Some(map) => map,
+            None => continue,
+        };
+        if let Some(Value::Object(elements)) = map.remove(last) {
+            let mut elements: Vec<(usize, Value)> = elements
+                .into_iter()
+                .map(|(index, element)| (index.parse().unwrap_or(usize::MAX), element))
+                .collect();
+            elements.sort_by_key(|(index, _)| *index);
+            let elements = elements.into_iter().map(|(_, element)| element).collect();
+            map.insert(last.clone(), Value::Array(elements));
+        }
+    }
+
+    Ok(Value::Object(root))
+}
+
+/// Returns the object at the given path of keys in the given object, if there is one.
+fn find_object<'a>(
+    root: &'a mut Map<String, Value>,
+    path: &[String],
+) -> Option<&'a mut Map<String, Value>> {
+    let mut map = root;
+    for segment in path {
+        map = map.get_mut(segment)?.as_object_mut()?;
+    }
+    Some(map)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Unable to flatten settings into keys: {}", source))]
+        Flatten {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Unable to remove 'settings' from key '{}': {}", key, source))]
+        InvalidKey {
+            key: String,
+            source: datastore::Error,
+        },
+
+        #[snafu(display("Setting '{}' is both a value and a group of settings", key))]
+        KeyConflict { key: String },
+
+        #[snafu(display("Key '{}' is not a setting; it must start with 'settings'", key))]
+        NonSettingsKey { key: String },
+
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response was not valid JSON: {} - response: {}", source, body))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Value of '{}' was not valid JSON: {}", key, source))]
+        ValueJson {
+            key: String,
+            source: serde_json::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
@@ -1,14 +1,17 @@
 #![deny(rust_2018_idioms)]
 
 //! The apiclient library provides high-level methods to interact with the Bottlerocket API.  See
-//! the documentation for submodules [`reboot`], [`set`], and [`update`] for high-level helpers.
+//! the documentation for submodules [`apply_status`], [`get`], [`reboot`], [`rollback`], [`set`],
+//! [`unset`], and [`update`] for high-level helpers.
 //!
 //! For more control, and to handle APIs without high-level wrappers, there are also 'raw' methods
 //! to query an HTTP API over a Unix-domain socket.
 //!
 //! The `raw_request` method takes care of the basics of making an HTTP request on a Unix-domain
 //! socket, and requires you to specify the socket path, the URI (including query string), the
-//! HTTP method, and any request body data.
+//! HTTP method, and any request body data.  `raw_request_with_headers` also lets you send extra
+//! request headers, for example the `Setting-Source` header that tells the server the source of a
+//! settings change.
 
 // Think "reqwest" but for Unix-domain sockets.  Would be nice to use the simpler reqwest instead
 // of hyper, but it lacks Unix-domain socket support:
@@ -21,8 +24,12 @@ use snafu::{ensure, ResultExt};
 use std::path::Path;
 
 pub mod apply;
+pub mod apply_status;
+pub mod get;
 pub mod reboot;
+pub mod rollback;
 pub mod set;
+pub mod unset;
 pub mod update;
 
 mod error {
@@ -55,6 +62,8 @@ mod error {
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, error::Error>;
 
+pub use datastore::SETTING_SOURCE_HEADER;
+
 /// Makes an HTTP request to a Unix-domain socket.
 ///
 /// The socket is specified as a path, for example "/tmp/api.sock".
@@ -81,7 +90,24 @@ where
     S1: AsRef<str>,
     S2: AsRef<str>,
 {
-    let (status, body) = raw_request_unchecked(&socket_path, &uri, &method, data).await?;
+    raw_request_with_headers(socket_path, uri, method, data, &[]).await
+}
+
+/// Works exactly like raw_request, but also sends the given headers, as pairs of names and values,
+/// with the request.
+pub async fn raw_request_with_headers<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+    headers: &[(&str, &str)],
+) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    let (status, body) = send_request(&socket_path, &uri, &method, data, headers).await?;
 
     // Error if the response status is in not in the 2xx range.
     ensure!(
@@ -106,6 +132,23 @@ pub async fn raw_request_unchecked<P, S1, S2>(
     method: S2,
     data: Option<String>,
 ) -> Result<(http::StatusCode, String)>
+where
+    P: AsRef<Path>,
+    S1: AsRef<str>,
+    S2: AsRef<str>,
+{
+    send_request(socket_path, uri, method, data, &[]).await
+}
+
+/// Makes an HTTP request to a Unix-domain socket with the given headers, and returns the status
+/// code and body of the response without checking the status.
+async fn send_request<P, S1, S2>(
+    socket_path: P,
+    uri: S1,
+    method: S2,
+    data: Option<String>,
+    headers: &[(&str, &str)],
+) -> Result<(http::StatusCode, String)>
 where
     P: AsRef<Path>,
     S1: AsRef<str>,
@@ -123,10 +166,14 @@ where
     } else {
         Body::empty()
     };
-    let request = Request::builder()
+    let mut builder = Request::builder()
         .method(method)
         .uri(&uri)
-        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let request = builder
         .body(Body::from(request_data))
         .context(error::RequestSetup)?;
 
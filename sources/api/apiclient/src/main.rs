@@ -6,7 +6,7 @@
 // library calls based on the given flags, etc.)  The library modules contain the code for talking
 // to the API, which is intended to be reusable by other crates.
 
-use apiclient::{apply, reboot, set, update};
+use apiclient::{apply, apply_status, get, reboot, rollback, set, unset, update};
 use datastore::{serialize_scalar, Key, KeyType};
 use log::{info, log_enabled, trace, warn};
 use simplelog::{
@@ -42,9 +42,13 @@ impl Default for Args {
 #[derive(Debug)]
 enum Subcommand {
     Apply(ApplyArgs),
+    ApplyStatus(ApplyStatusArgs),
+    Get(GetArgs),
     Raw(RawArgs),
     Reboot(RebootArgs),
+    Rollback(RollbackArgs),
     Set(SetArgs),
+    Unset(UnsetArgs),
     Update(UpdateSubcommand),
 }
 
@@ -54,6 +58,29 @@ struct ApplyArgs {
     input_sources: Vec<String>,
 }
 
+/// Stores user-supplied arguments for the 'apply-status' subcommand.
+#[derive(Debug)]
+struct ApplyStatusArgs {
+    id: Option<String>,
+}
+
+/// Stores user-supplied arguments for the 'get' subcommand.
+#[derive(Debug)]
+struct GetArgs {
+    keys: Vec<Key>,
+    prefixes: Vec<String>,
+    format: GetFormat,
+    secrets: bool,
+}
+
+/// The output formats of the 'get' subcommand.
+#[derive(Debug)]
+enum GetFormat {
+    Json,
+    Toml,
+    Flat,
+}
+
 /// Stores user-supplied arguments for the 'raw' subcommand.
 #[derive(Debug)]
 struct RawArgs {
@@ -66,6 +93,10 @@ struct RawArgs {
 #[derive(Debug)]
 struct RebootArgs {}
 
+/// Stores user-supplied arguments for the 'rollback' subcommand.
+#[derive(Debug)]
+struct RollbackArgs {}
+
 /// Stores user-supplied arguments for the 'set' subcommand.
 #[derive(Debug)]
 enum SetArgs {
@@ -73,6 +104,12 @@ enum SetArgs {
     Json(serde_json::Value),
 }
 
+/// Stores user-supplied arguments for the 'unset' subcommand.
+#[derive(Debug)]
+struct UnsetArgs {
+    keys: Vec<Key>,
+}
+
 /// Stores the 'update' subcommand specified by the user.
 #[derive(Debug)]
 enum UpdateSubcommand {
@@ -112,11 +149,17 @@ fn usage() -> ! {
                                        'raw' is the default subcommand and may be omitted.
             apply                      Applies settings from TOML/JSON files at given URIs,
                                        or from stdin.
+            get                        Prints the current value of settings.
             set                        Changes settings and applies them to the system.
+            unset                      Resets settings to their defaults and applies them to
+                                       the system.
+            apply-status               Prints the results of recent settings applies.
             update check               Prints information about available updates.
             update apply               Applies available updates.
             update cancel              Deactivates an applied update.
             reboot                     Reboots the host.
+            rollback                   Undoes the most recent settings change and applies the
+                                       previous settings to the system.
 
         raw options:
             -u, --uri URI              Required; URI to request from the server, e.g. /tx
@@ -128,9 +171,27 @@ fn usage() -> ! {
                                        want to apply to the system.  If no URI is specified, or
                                        if "-" is given, reads from stdin.
 
+        apply-status options:
+            --id ID                    Only print the results of the apply with this ID.
+
+        get options:
+            [ KEY ...]                 The settings you want to print, e.g. settings.motd, or
+                                       groups of settings, e.g. kubernetes.  The "settings."
+                                       prefix is optional.  If no keys or prefixes are given,
+                                       prints all settings.
+            -p, --prefix PREFIX        Print settings whose names start with PREFIX, e.g.
+                                       host-containers.ad.  May be given multiple times.
+            -o, --output FORMAT        Output format: json, toml, or flat, which prints
+                                       "key = value" lines.  Default: json
+            --secrets                  Include sensitive settings, like tokens and user data,
+                                       which are left out by default.
+
         reboot options:
             None.
 
+        rollback options:
+            None.
+
         set options:
             KEY=VALUE [KEY=VALUE ...]  The settings you want to set.  For example:
                                           settings.motd="hi there" settings.ecs.cluster=example
@@ -142,6 +203,11 @@ fn usage() -> ! {
                                        for some numeric settings.  For example:
                                           -j '{{"kernel": {{"sysctl": {{"vm.max_map_count": "262144"}}}}}}'
 
+        unset options:
+            KEY [KEY ...]              The settings you want to reset, e.g. settings.motd, or
+                                       groups of settings, e.g. ntp.  The "settings." prefix is
+                                       optional.  Settings with no default value are removed.
+
         update check options:
             None.
 
@@ -198,7 +264,8 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
             }
 
             // Subcommands
-            "raw" | "apply" | "reboot" | "set" | "update"
+            "raw" | "apply" | "apply-status" | "get" | "reboot" | "rollback" | "set" | "unset"
+            | "update"
                 if subcommand.is_none() && !arg.starts_with('-') =>
             {
                 subcommand = Some(arg)
@@ -213,8 +280,12 @@ fn parse_args(args: env::Args) -> (Args, Subcommand) {
         // Default subcommand is 'raw'
         None | Some("raw") => return (global_args, parse_raw_args(subcommand_args)),
         Some("apply") => return (global_args, parse_apply_args(subcommand_args)),
+        Some("apply-status") => return (global_args, parse_apply_status_args(subcommand_args)),
+        Some("get") => return (global_args, parse_get_args(subcommand_args)),
         Some("reboot") => return (global_args, parse_reboot_args(subcommand_args)),
+        Some("rollback") => return (global_args, parse_rollback_args(subcommand_args)),
         Some("set") => return (global_args, parse_set_args(subcommand_args)),
+        Some("unset") => return (global_args, parse_unset_args(subcommand_args)),
         Some("update") => return (global_args, parse_update_args(subcommand_args)),
         _ => usage_msg("Missing or unknown subcommand"),
     }
@@ -286,6 +357,75 @@ fn parse_apply_args(args: Vec<String>) -> Subcommand {
     Subcommand::Apply(ApplyArgs { input_sources })
 }
 
+/// Parses arguments for the 'apply-status' subcommand.
+fn parse_apply_status_args(args: Vec<String>) -> Subcommand {
+    let mut id = None;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--id" => {
+                id = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --id")),
+                )
+            }
+
+            x => usage_msg(&format!("Unknown argument '{}'", x)),
+        }
+    }
+
+    Subcommand::ApplyStatus(ApplyStatusArgs { id })
+}
+
+/// Parses arguments for the 'get' subcommand.
+fn parse_get_args(args: Vec<String>) -> Subcommand {
+    let mut keys = Vec::new();
+    let mut prefixes = Vec::new();
+    let mut format = None;
+    let mut secrets = false;
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "-p" | "--prefix" => {
+                let prefix = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -p | --prefix"));
+                if prefix.is_empty() {
+                    usage_msg("Prefix must not be empty");
+                }
+                prefixes.push(prefix);
+            }
+
+            "-o" | "--output" => {
+                let format_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to -o | --output"));
+                format = Some(match format_str.as_ref() {
+                    "json" => GetFormat::Json,
+                    "toml" => GetFormat::Toml,
+                    "flat" => GetFormat::Flat,
+                    x => usage_msg(&format!("Unknown output format '{}'", x)),
+                });
+            }
+
+            "--secrets" => secrets = true,
+
+            x if x.starts_with('-') => usage_msg(&format!("Unknown argument '{}'", x)),
+
+            x => keys.push(parse_settings_key(x)),
+        }
+    }
+
+    Subcommand::Get(GetArgs {
+        keys,
+        prefixes,
+        format: format.unwrap_or(GetFormat::Json),
+        secrets,
+    })
+}
+
 /// Parses arguments for the 'reboot' subcommand.
 fn parse_reboot_args(args: Vec<String>) -> Subcommand {
     if !args.is_empty() {
@@ -294,6 +434,14 @@ fn parse_reboot_args(args: Vec<String>) -> Subcommand {
     Subcommand::Reboot(RebootArgs {})
 }
 
+/// Parses arguments for the 'rollback' subcommand.
+fn parse_rollback_args(args: Vec<String>) -> Subcommand {
+    if !args.is_empty() {
+        usage_msg(&format!("Unknown arguments: {}", args.join(", ")));
+    }
+    Subcommand::Rollback(RollbackArgs {})
+}
+
 /// Parses arguments for the 'set' subcommand.
 // Note: the API doesn't allow setting non-settings keys, e.g. services, configuration-files, and
 // metadata.  If we allow it in the future, we should revisit this 'set' parsing code and decide
@@ -346,19 +494,7 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
                 let raw_key = split.next().unwrap();
                 let value = split.next().unwrap();
 
-                let mut key = Key::new(KeyType::Data, raw_key).unwrap_or_else(|_| {
-                    usage_msg(&format!("Given key '{}' is not a valid format", raw_key))
-                });
-
-                // Add "settings" prefix if the user didn't give a known prefix, to ease usage
-                let key_prefix = &key.segments()[0];
-                if key_prefix != "settings" {
-                    let mut segments = key.segments().clone();
-                    segments.insert(0, "settings".to_string());
-                    key = Key::from_segments(KeyType::Data, &segments)
-                        .expect("Adding prefix to key resulted in invalid key?!");
-                }
-
+                let key = parse_settings_key(raw_key);
                 simple.insert(key, value.to_string());
             }
 
@@ -377,6 +513,40 @@ fn parse_set_args(args: Vec<String>) -> Subcommand {
     }
 }
 
+/// Parses arguments for the 'unset' subcommand.
+fn parse_unset_args(args: Vec<String>) -> Subcommand {
+    let mut keys = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            x if x.starts_with('-') => usage_msg(&format!("Unknown argument '{}'", x)),
+            x => keys.push(parse_settings_key(x)),
+        }
+    }
+
+    if keys.is_empty() {
+        usage_msg("Must specify settings to reset with 'unset'");
+    }
+    Subcommand::Unset(UnsetArgs { keys })
+}
+
+/// Parses a user-given setting name into a Key, exiting with a usage message if it's invalid.
+fn parse_settings_key(raw_key: &str) -> Key {
+    let key = Key::new(KeyType::Data, raw_key)
+        .unwrap_or_else(|_| usage_msg(&format!("Given key '{}' is not a valid format", raw_key)));
+
+    // Add "settings" prefix if the user didn't give a known prefix, to ease usage
+    let key_prefix = &key.segments()[0];
+    if key_prefix != "settings" {
+        let mut segments = key.segments().clone();
+        segments.insert(0, "settings".to_string());
+        Key::from_segments(KeyType::Data, &segments)
+            .expect("Adding prefix to key resulted in invalid key?!")
+    } else {
+        key
+    }
+}
+
 /// Parses the desired subcommand of 'update'.
 fn parse_update_args(args: Vec<String>) -> Subcommand {
     let mut subcommand = None;
@@ -448,16 +618,43 @@ async fn check(args: &Args) -> Result<String> {
     let output = update::check(&args.socket_path)
         .await
         .context(error::UpdateCheck)?;
+    print_json(&output);
+    Ok(output)
+}
 
-    match serde_json::from_str::<serde_json::Value>(&output) {
+/// Prints a JSON response from the server in a pretty format, or as-is if it's not valid JSON.
+fn print_json(output: &str) {
+    match serde_json::from_str::<serde_json::Value>(output) {
         Ok(value) => println!("{:#}", value),
         Err(e) => {
             warn!("Unable to deserialize response (invalid JSON?): {}", e);
             println!("{}", output);
         }
     }
+}
 
-    Ok(output)
+/// Prints settings returned by 'get' in the requested format.
+fn print_settings(settings: &serde_json::Value, format: &GetFormat) -> Result<()> {
+    match format {
+        GetFormat::Json => println!("{:#}", settings),
+        GetFormat::Toml => {
+            // Convert to a toml::Value first; it knows to write plain values before tables.
+            let toml = toml::Value::try_from(settings).context(error::TomlSerialize)?;
+            print!("{}", toml::to_string(&toml).context(error::TomlSerialize)?);
+        }
+        GetFormat::Flat => {
+            let pairs = datastore::serialization::to_pairs(settings).context(error::Flatten)?;
+            let mut lines: Vec<_> = pairs
+                .iter()
+                .map(|(key, value)| format!("{} = {}", key, value))
+                .collect();
+            lines.sort();
+            for line in lines {
+                println!("{}", line);
+            }
+        }
+    }
+    Ok(())
 }
 
 /// We want the key=val form of 'set' to be as simple as possible; we don't want users to have to
@@ -540,12 +737,36 @@ async fn run() -> Result<()> {
                 .context(error::Apply)?;
         }
 
+        Subcommand::ApplyStatus(apply_status) => {
+            let output = apply_status::get(&args.socket_path, apply_status.id.as_deref())
+                .await
+                .context(error::ApplyStatus)?;
+            print_json(&output);
+        }
+
+        Subcommand::Get(get) => {
+            let settings = get::get(&args.socket_path, &get.keys, &get.prefixes, get.secrets)
+                .await
+                .context(error::Get)?;
+            print_settings(&settings, &get.format)?;
+        }
+
         Subcommand::Reboot(_reboot) => {
             reboot::reboot(&args.socket_path)
                 .await
                 .context(error::Reboot)?;
         }
 
+        Subcommand::Rollback(_rollback) => {
+            let mut changed: Vec<_> = rollback::rollback(&args.socket_path)
+                .await
+                .context(error::Rollback)?
+                .into_iter()
+                .collect();
+            changed.sort();
+            info!("Rolled back settings: {}", changed.join(", "));
+        }
+
         Subcommand::Set(set) => {
             let settings: model::Settings;
             match set {
@@ -572,6 +793,12 @@ async fn run() -> Result<()> {
                 .context(error::Set)?;
         }
 
+        Subcommand::Unset(unset) => {
+            unset::unset(&args.socket_path, &unset.keys)
+                .await
+                .context(error::Unset)?;
+        }
+
         Subcommand::Update(subcommand) => match subcommand {
             UpdateSubcommand::Check(_check) => {
                 check(&args).await?;
@@ -625,7 +852,7 @@ async fn main() {
 }
 
 mod error {
-    use apiclient::{apply, reboot, set, update};
+    use apiclient::{apply, apply_status, get, reboot, rollback, set, unset, update};
     use snafu::Snafu;
 
     #[derive(Debug, Snafu)]
@@ -634,6 +861,9 @@ mod error {
         #[snafu(display("Failed to apply settings: {}", source))]
         Apply { source: apply::Error },
 
+        #[snafu(display("Failed to get settings apply status: {}", source))]
+        ApplyStatus { source: apply_status::Error },
+
         #[snafu(display("Unable to deserialize input JSON into model: {}", source))]
         DeserializeJson { source: serde_json::Error },
 
@@ -645,6 +875,14 @@ mod error {
             source: datastore::deserialization::Error,
         },
 
+        #[snafu(display("Unable to flatten settings into keys: {}", source))]
+        Flatten {
+            source: datastore::serialization::Error,
+        },
+
+        #[snafu(display("Failed to get settings: {}", source))]
+        Get { source: get::Error },
+
         #[snafu(display("Logger setup error: {}", source))]
         Logger { source: log::SetLoggerError },
 
@@ -658,12 +896,21 @@ mod error {
             source: apiclient::Error,
         },
 
+        #[snafu(display("Failed to roll back settings: {}", source))]
+        Rollback { source: rollback::Error },
+
         #[snafu(display("Unable to serialize data: {}", source))]
         Serialize { source: serde_json::Error },
 
         #[snafu(display("Failed to change settings: {}", source))]
         Set { source: set::Error },
 
+        #[snafu(display("Failed to reset settings: {}", source))]
+        Unset { source: unset::Error },
+
+        #[snafu(display("Unable to serialize settings as TOML: {}", source))]
+        TomlSerialize { source: toml::ser::Error },
+
         #[snafu(display("Failed to apply update: {}", source))]
         UpdateApply { source: update::Error },
 
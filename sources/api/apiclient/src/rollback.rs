@@ -0,0 +1,42 @@
+use snafu::ResultExt;
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Undoes the most recent commit through the API, and applies the restored settings to the
+/// system.  Returns the names of the changed keys.
+pub async fn rollback<P>(socket_path: P) -> Result<HashSet<String>>
+where
+    P: AsRef<Path>,
+{
+    let uri = "/tx/rollback_and_apply";
+    let method = "POST";
+    let (_status, body) = crate::raw_request(&socket_path, uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    let changed = serde_json::from_str(&body).context(error::ResponseJson { body })?;
+    Ok(changed)
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+
+        #[snafu(display("Response was not a list of changed keys: {}: {}", source, body))]
+        ResponseJson {
+            body: String,
+            source: serde_json::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
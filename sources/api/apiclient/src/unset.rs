@@ -0,0 +1,52 @@
+use crate::rando;
+use datastore::Key;
+use snafu::ResultExt;
+use std::path::Path;
+
+/// Resets the requested settings through the API, then commits and applies the transaction
+/// containing those changes.  Settings with a default value are set back to it, and other settings
+/// are removed.  Keys can name single settings, like `settings.motd`, or groups of settings, like
+/// `settings.ntp`.
+pub async fn unset<P>(socket_path: P, keys: &[Key]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    // We use a specific transaction ID so we don't commit any other changes that may be pending.
+    let transaction = format!("apiclient-unset-{}", rando());
+
+    // Stage the removals on the server.  Keys can contain quotes, which aren't allowed in URIs.
+    let names: Vec<_> = keys.iter().map(|key| key.name().as_str()).collect();
+    let encoded: String =
+        url::form_urlencoded::byte_serialize(names.join(",").as_bytes()).collect();
+    let uri = format!("/settings?keys={}&tx={}", encoded, transaction);
+    let method = "DELETE";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    // Commit the transaction and apply it to the system.
+    let uri = format!("/tx/commit_and_apply?tx={}", transaction);
+    let method = "POST";
+    let (_status, _body) = crate::raw_request(&socket_path, &uri, method, None)
+        .await
+        .context(error::Request { uri, method })?;
+
+    Ok(())
+}
+
+mod error {
+    use snafu::Snafu;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub enum Error {
+        #[snafu(display("Failed {} request to '{}': {}", method, uri, source))]
+        Request {
+            method: String,
+            uri: String,
+            source: crate::Error,
+        },
+    }
+}
+pub use error::Error;
+pub type Result<T> = std::result::Result<T, error::Error>;
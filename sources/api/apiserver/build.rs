@@ -1,11 +1,43 @@
-// Automatically generate README.md from rustdoc.
-
+// This build script generates README.md from rustdoc, like our other crates, but also generates
+// the OpenAPI document for the current variant.  The document is served by the API server.
+//
+// The paths of the API are described by hand in openapi.yaml, which references the types of the
+// model by name.  We fill in their schemas from the JSON Schema of the variant's model, so the
+// document always matches the model the server was built with.
+use model::schema::{JsonSchema, SchemaGenerator, Value};
+use model::{ConfigurationFiles, Model, Services};
+use serde_json::json;
+use snafu::{ensure, ResultExt};
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// The hand-written part of the OpenAPI document.
+const OPENAPI_TEMPLATE: &str = "../openapi.yaml";
+
+/// The template refers to schemas of the model under this prefix.
+const SCHEMA_REF_PREFIX: &str = "#/components/schemas/";
+
+/// The model's schemas use JSON Schema 2020-12, which OpenAPI supports starting with 3.1.
+const OPENAPI_VERSION: &str = "3.1.0";
 
-fn main() {
+fn main() -> Result<()> {
+    generate_readme();
+    generate_openapi()?;
+
+    // Reflect that we need to rerun if the variant has changed to pick up the new model, or if
+    // any of our inputs have changed.
+    println!("cargo:rerun-if-env-changed=VARIANT");
+    println!("cargo:rerun-if-changed={}", OPENAPI_TEMPLATE);
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=README.tpl");
+
+    Ok(())
+}
+
+fn generate_readme() {
     // Check for environment variable "SKIP_README". If it is set,
     // skip README generation
     if env::var_os("SKIP_README").is_some() {
@@ -30,3 +62,101 @@ fn main() {
     let mut readme = File::create("README.md").unwrap();
     readme.write_all(content.as_bytes()).unwrap();
 }
+
+/// Combine the OpenAPI template with the schemas of the variant's model.  The result is written
+/// as JSON to a file in OUT_DIR for the API server to serve.
+fn generate_openapi() -> Result<()> {
+    let data = fs::read_to_string(OPENAPI_TEMPLATE).context(error::File {
+        op: "read",
+        path: OPENAPI_TEMPLATE,
+    })?;
+    let template: serde_yaml::Value =
+        serde_yaml::from_str(&data).context(error::YamlDeserialize {
+            path: OPENAPI_TEMPLATE,
+        })?;
+    // Numeric keys in the template, like response codes, become strings in JSON.
+    let mut document = serde_json::to_value(&template).context(error::JsonConvert)?;
+
+    let mut gen = SchemaGenerator::new(SCHEMA_REF_PREFIX);
+    Model::json_schema(&mut gen);
+    // These are maps rather than model structs, so they're only named for the template's use.
+    gen.named("Services", Services::json_schema);
+    gen.named("ConfigurationFiles", ConfigurationFiles::json_schema);
+    let schemas = gen.into_definitions();
+
+    update_schemas(&mut document, &schemas)?;
+    document["openapi"] = OPENAPI_VERSION.into();
+    document["components"] = json!({ "schemas": schemas });
+
+    // Serialize to disk for the API server to read.
+    let data = serde_json::to_string_pretty(&document).context(error::JsonConvert)?;
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set; are you not using cargo?");
+    let path = Path::new(&out_dir).join("openapi.json");
+    fs::write(&path, data).context(error::File { op: "write", path })?;
+
+    Ok(())
+}
+
+/// Updates the schemas written in the template for OpenAPI 3.1, which replaces `nullable` with a
+/// "null" type, and makes sure each schema the template references is in the model.
+fn update_schemas(value: &mut Value, schemas: &BTreeMap<String, Value>) -> Result<()> {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::String(reference)) = map.get("$ref") {
+                let name = reference.strip_prefix(SCHEMA_REF_PREFIX);
+                ensure!(
+                    name.map(|name| schemas.contains_key(name)) == Some(true),
+                    error::UnknownSchema { reference }
+                );
+            }
+            if map.remove("nullable") == Some(Value::Bool(true)) {
+                if let Some(schema_type) = map.remove("type") {
+                    map.insert("type".to_string(), json!([schema_type, "null"]));
+                }
+            }
+            for value in map.values_mut() {
+                update_schemas(value, schemas)?;
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                update_schemas(value, schemas)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to {} {}: {}", op, path.display(), source))]
+        File {
+            op: String,
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("{} is not valid YAML: {}", path.display(), source))]
+        YamlDeserialize {
+            path: PathBuf,
+            source: serde_yaml::Error,
+        },
+
+        #[snafu(display("Failed to convert OpenAPI document to JSON: {}", source))]
+        JsonConvert { source: serde_json::Error },
+
+        #[snafu(display(
+            "OpenAPI template references '{}', which isn't a schema in the model",
+            reference
+        ))]
+        UnknownSchema { reference: String },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
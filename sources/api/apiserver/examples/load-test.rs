@@ -0,0 +1,249 @@
+//! Measures request latency of a running API server under concurrent load.
+//!
+//! Reader threads repeatedly fetch `/settings`, or another URI given with `--read-uri`, while writer
+//! threads repeatedly change `settings.motd` in their own transaction and commit it, like boot-time
+//! agents reading settings while others write them.  When the readers are done, it prints latency
+//! percentiles for reads and writes, and the number of failed requests.
+//!
+//! This changes live settings, so only point it at a test server, for example:
+//!
+//! `cargo run --example load-test -- --socket-path /tmp/bottlerocket/api.sock`
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_SOCKET_PATH: &str = "/run/api.sock";
+const DEFAULT_READ_URI: &str = "/settings";
+const DEFAULT_READERS: usize = 8;
+const DEFAULT_WRITERS: usize = 1;
+const DEFAULT_REQUESTS: usize = 200;
+
+/// The prefix of the transactions the writers use, so they don't disturb the default transaction
+/// or each other.
+const TRANSACTION_PREFIX: &str = "load-test";
+
+/// Stores user-supplied arguments.
+struct Args {
+    socket_path: String,
+    read_uri: String,
+    readers: usize,
+    writers: usize,
+    requests: usize,
+}
+
+/// Informs the user about proper usage of the program and exits.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --socket-path PATH ]
+            [ --read-uri URI ]
+            [ --readers COUNT ]
+            [ --writers COUNT ]
+            [ --requests COUNT ]
+
+    Socket path defaults to {}
+    Read URI defaults to {}
+    Defaults to {} readers making {} requests each, and {} writer(s)",
+        program_name,
+        DEFAULT_SOCKET_PATH,
+        DEFAULT_READ_URI,
+        DEFAULT_READERS,
+        DEFAULT_REQUESTS,
+        DEFAULT_WRITERS,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the count given to the named argument.
+fn parse_count(name: &str, value: Option<String>) -> usize {
+    let value = value.unwrap_or_else(|| usage_msg(format!("Did not give argument to {}", name)));
+    value.parse().unwrap_or_else(|e| {
+        usage_msg(format!(
+            "Invalid count '{}' given to {}: {}",
+            value, name, e
+        ))
+    })
+}
+
+/// Parses user arguments into an Args structure.
+fn parse_args(args: env::Args) -> Args {
+    let mut socket_path = None;
+    let mut read_uri = None;
+    let mut readers = None;
+    let mut writers = None;
+    let mut requests = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--socket-path" => {
+                socket_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --socket-path")),
+                )
+            }
+            "--read-uri" => {
+                read_uri = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --read-uri")),
+                )
+            }
+            "--readers" => readers = Some(parse_count(&arg, iter.next())),
+            "--writers" => writers = Some(parse_count(&arg, iter.next())),
+            "--requests" => requests = Some(parse_count(&arg, iter.next())),
+            _ => usage(),
+        }
+    }
+
+    Args {
+        socket_path: socket_path.unwrap_or_else(|| DEFAULT_SOCKET_PATH.to_string()),
+        read_uri: read_uri.unwrap_or_else(|| DEFAULT_READ_URI.to_string()),
+        readers: readers.unwrap_or(DEFAULT_READERS),
+        writers: writers.unwrap_or(DEFAULT_WRITERS),
+        requests: requests.unwrap_or(DEFAULT_REQUESTS),
+    }
+}
+
+/// The latencies of the requests a thread made, and how many of them failed.
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    failures: usize,
+}
+
+impl Results {
+    fn merge(&mut self, other: Results) {
+        self.latencies.extend(other.latencies);
+        self.failures += other.failures;
+    }
+}
+
+/// Makes an HTTP request over the API socket, returning whether it succeeded.  Each request uses
+/// its own connection, like most API clients.
+fn request(socket_path: &str, method: &str, uri: &str, body: &str) -> bool {
+    let mut stream = match UnixStream::connect(socket_path) {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        uri,
+        body.len(),
+        body
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+    let mut response = String::new();
+    if stream.read_to_string(&mut response).is_err() {
+        return false;
+    }
+    // The status line looks like "HTTP/1.1 200 OK"; any 2xx status is a success.
+    response
+        .split(' ')
+        .nth(1)
+        .map(|status| status.starts_with('2'))
+        .unwrap_or(false)
+}
+
+/// Makes the given request, recording its latency and whether it failed.
+fn timed_request(results: &mut Results, socket_path: &str, method: &str, uri: &str, body: &str) {
+    let start = Instant::now();
+    if !request(socket_path, method, uri, body) {
+        results.failures += 1;
+    }
+    results.latencies.push(start.elapsed());
+}
+
+/// Prints latency percentiles for the given results.
+fn report(name: &str, mut results: Results) {
+    results.latencies.sort();
+    let count = results.latencies.len();
+    if count == 0 {
+        println!("{}: no requests", name);
+        return;
+    }
+    let percentile = |p: usize| results.latencies[(count - 1) * p / 100];
+    println!(
+        "{}: {} requests, {} failed; p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        name,
+        count,
+        results.failures,
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        results.latencies[count - 1],
+    );
+}
+
+fn main() {
+    let args = parse_args(env::args());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let writers: Vec<_> = (0..args.writers)
+        .map(|writer| {
+            let socket_path = args.socket_path.clone();
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                let mut results = Results::default();
+                let transaction = format!("{}-{}", TRANSACTION_PREFIX, writer);
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let body = format!("{{\"motd\": \"load test {}.{}\"}}", writer, i);
+                    let patch = format!("/settings?tx={}", transaction);
+                    timed_request(&mut results, &socket_path, "PATCH", &patch, &body);
+                    let commit = format!("/tx/commit?tx={}", transaction);
+                    timed_request(&mut results, &socket_path, "POST", &commit, "");
+                    i += 1;
+                }
+                results
+            })
+        })
+        .collect();
+
+    let start = Instant::now();
+    let readers: Vec<_> = (0..args.readers)
+        .map(|_| {
+            let socket_path = args.socket_path.clone();
+            let read_uri = args.read_uri.clone();
+            let requests = args.requests;
+            thread::spawn(move || {
+                let mut results = Results::default();
+                for _ in 0..requests {
+                    timed_request(&mut results, &socket_path, "GET", &read_uri, "");
+                }
+                results
+            })
+        })
+        .collect();
+
+    let mut reads = Results::default();
+    for reader in readers {
+        reads.merge(reader.join().expect("reader thread panicked"));
+    }
+    let elapsed = start.elapsed();
+    done.store(true, Ordering::Relaxed);
+    let mut writes = Results::default();
+    for writer in writers {
+        writes.merge(writer.join().expect("writer thread panicked"));
+    }
+
+    println!("Finished in {:?}", elapsed);
+    report("reads", reads);
+    report("writes", writes);
+}
@@ -13,11 +13,15 @@ use std::env;
 use std::path::Path;
 use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
 use apiserver::serve;
 
 const DEFAULT_BIND_PATH: &str = "/run/api.sock";
 
+/// How long requests wait for access to the datastore before failing, by default.
+const DEFAULT_LOCK_TIMEOUT_SECONDS: u64 = 30;
+
 type Result<T> = std::result::Result<T, error::Error>;
 
 mod error {
@@ -40,6 +44,7 @@ mod error {
 /// Stores user-supplied arguments.
 struct Args {
     datastore_path: String,
+    lock_timeout: Duration,
     log_level: LevelFilter,
     socket_gid: Option<Gid>,
     socket_path: String,
@@ -53,11 +58,13 @@ fn usage() -> ! {
             --datastore-path PATH
             [ --socket-path PATH ]
             [ --socket-gid GROUP_ID ]
+            [ --lock-timeout SECONDS ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]
 
-    Socket path defaults to {}",
-        program_name, DEFAULT_BIND_PATH
+    Socket path defaults to {}
+    Lock timeout defaults to {} seconds",
+        program_name, DEFAULT_BIND_PATH, DEFAULT_LOCK_TIMEOUT_SECONDS
     );
     process::exit(2);
 }
@@ -71,6 +78,7 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 /// Parses user arguments into an Args structure.
 fn parse_args(args: env::Args) -> Args {
     let mut datastore_path = None;
+    let mut lock_timeout = None;
     let mut log_level = None;
     let mut socket_gid = None;
     let mut socket_path = None;
@@ -114,6 +122,19 @@ fn parse_args(args: env::Args) -> Args {
                 socket_gid = Some(Gid::from_raw(gid));
             }
 
+            "--lock-timeout" => {
+                let seconds_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --lock-timeout"));
+                let seconds = seconds_str.parse::<u64>().unwrap_or_else(|e| {
+                    usage_msg(format!(
+                        "Invalid number of seconds '{}' given to --lock-timeout: {}",
+                        seconds_str, e
+                    ))
+                });
+                lock_timeout = Some(Duration::from_secs(seconds));
+            }
+
             _ => usage(),
         }
     }
@@ -121,6 +142,8 @@ fn parse_args(args: env::Args) -> Args {
     Args {
         socket_gid,
         datastore_path: datastore_path.unwrap_or_else(|| usage()),
+        lock_timeout: lock_timeout
+            .unwrap_or_else(|| Duration::from_secs(DEFAULT_LOCK_TIMEOUT_SECONDS)),
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
         socket_path: socket_path.unwrap_or_else(|| DEFAULT_BIND_PATH.to_string()),
     }
@@ -139,8 +162,8 @@ async fn run() -> Result<()> {
         error::NonexistentDatastore
     );
 
-    // Each request makes its own handle to the datastore; there's no locking or
-    // synchronization yet.  Therefore, only use 1 thread for safety.
+    // Datastore access happens on a separate pool of blocking threads, so one worker thread is
+    // enough to keep accepting and routing requests.
     let threads = 1;
 
     let threads_suffix = match threads {
@@ -157,6 +180,7 @@ async fn run() -> Result<()> {
         &args.datastore_path,
         threads,
         args.socket_gid,
+        args.lock_timeout,
     )
     .await
     .context(error::Server)
@@ -14,6 +14,8 @@ Remote access should only be allowed through an authenticated control channel su
 
 We present an HTTP interface to configurable settings and other state.
 The interface is documented in [OpenAPI format](https://swagger.io/docs/specification/about/) in [openapi.yaml](../openapi.yaml).
+That file describes the paths of the API and refers to the types of the model; the build fills in their schemas from the variant's model, and the server returns the complete document from `/schema/openapi`.
+You can GET a [JSON Schema](https://json-schema.org/) of the model from `/schema`, including the checks made on input to each setting, like the pattern for Kubernetes label keys, so clients can check settings before sending them.
 
 The Settings APIs are particularly important.
 You can GET settings from the `/settings` endpoint.
@@ -21,14 +23,87 @@ You can also PATCH changes to the `/settings` endpoint.
 Settings are stored as a pending transaction until a commit API is called.
 Pending settings can be retrieved from `/tx` to see what will change.
 
+To reset settings, DELETE them from `/settings` with a `keys` parameter, e.g. `/settings?keys=settings.motd,settings.ntp`.
+Keys can name single settings or groups of settings.
+Settings that have a default value for the variant are set back to it in the pending transaction, and other settings are staged for removal; `/tx` only shows the former.
+When the transaction is committed, the staged settings are removed from live data.
+
 Upon making a `/tx/commit` POST call, the pending transaction is made live.
 Upon making an `/tx/apply` POST call, an external settings applier tool is called to apply the changes to the system and restart services as necessary.
 There's also `/tx/commit_and_apply` to do both, which is the most common case.
 
+Settings are checked against rules of the model that involve more than one setting, like a setting that's required when another is enabled.
+`PATCH /settings` checks the rules that the change itself can break, and a commit checks the settings as they'd be once committed; rules that need a setting from elsewhere are only checked at commit.
+A struct with settings that have a setting generator but no value yet isn't complete until they're generated at boot, so its rules aren't checked at commit.
+If any rules are broken, the call fails with status 400, and the body lists each problem with the path of the settings that have it.
+Commits are only rejected for problems in the settings they change, so an existing problem doesn't block unrelated changes.
+
+Some settings hold secrets, like the Kubernetes bootstrap token and the user data of host containers; the model marks them sensitive, and they're recorded with "sensitive" metadata when they're written.
+Sensitive settings are left out of `GET /`, `GET /settings`, and `GET /tx`, and `GET /settings/history` shows their values as `<redacted>`, so they don't end up in logs.
+Clients that need them, like the services that configure host containers, can add `secrets=true` to those requests, e.g. `/settings?secrets=true`.
+
+The settings applier runs in the background, so the apply APIs return before it's done.
+They return an ID for the run in the `Apply-Id` header, and the applier records the results of each run: each configuration file it rendered, and the exit code and stderr of each restart command.
+You can GET the results of recent runs from `/actions/apply-status`, or of a single run from `/actions/apply-status?id=ID`.
+
+To see what a change would do before making it, add `dry_run=true` to `PATCH /settings` or `/tx/commit_and_apply`.
+Nothing is written; instead, the response lists the keys that would change, a unified diff of each configuration file that the settings applier would render, and the restart commands it would run.
+Affected services are found through the "affected-services" metadata of the changed keys, like the settings applier does, and their templates are rendered with the settings as they'd be after the commit; for `PATCH /settings`, that includes the settings in the request.
+The values of sensitive settings are shown as `<redacted>` in the diffs unless `secrets=true` is given.
+Templates can transform values, for example by decoding them, so files that use sensitive settings have their diffs left out, and are marked as `redacted`.
+
+Each commit saves the previous live values of the keys it changes, and the most recent commits can be undone with a `/tx/rollback` POST call.
+Each call undoes one more commit, newest first.
+There's also `/tx/rollback_and_apply` to apply the restored settings to the system, like `/tx/commit_and_apply`.
+
+Each commit and rollback is recorded in a history log under the data store directory, with the transaction name, a timestamp, and the old and new values of each changed key.
+The migrator copies the log to the new data store on updates, so it's kept across versions.
+You can GET the log from `/settings/history`, oldest change first; add a `prefix` parameter to only see changes to matching settings, e.g. `/settings/history?prefix=kubernetes`.
+Only the most recent changes are kept, and the log starts over when the data store is migrated to a new version.
+
+To find out where the current value of a setting came from, GET `/settings/provenance`, optionally with a `keys` parameter, e.g. `/settings/provenance?keys=settings.motd`.
+The provenance of each setting gives the source and transaction of its most recent write, for example:
+
+```json
+{"settings.motd": {"source": "user-data", "transaction": "bottlerocket-launch"}}
+```
+
+Boot-time services share a transaction, so changes can name their source in a `Setting-Source` header of `PATCH /settings` or `DELETE /settings`; early-boot-config uses "user-data" and sundog uses "generator".
+Changes without the header are recorded with source "api", and you can tell clients apart by the transaction name, e.g. `apiclient set` uses transactions starting with "apiclient-set-".
+Default settings are recorded with source "defaults", and settings restored by a rollback with source "rollback" and the transaction whose commit was undone.
+The provenance is stored as metadata of each setting, and is only updated when the change is committed.
+
+To react to settings changes without polling, GET `/events`.
+The response is a long-lived stream in [server-sent event](https://html.spec.whatwg.org/multipage/server-sent-events.html) format, with an event for each commit, rollback, and apply, listing the changed keys.
+For example, a commit looks like:
+
+```text
+event: commit
+data: {"kind":"commit","transaction":"default","keys":["settings.motd"],"apply_id":null}
+```
+
+Apply events are sent when the settings applier is started, have no keys if changes to all keys are being applied, and include the ID of the applier run.
+Events are only sent while you're connected; if you fall too far behind in reading them, the stream ends, and you should fetch settings again before reconnecting.
+
 If you don't specify a transaction, the "default" transaction is used, so you usually don't have to think about it.
 If you want to group changes into transactions yourself, you can add a `tx` parameter to the APIs mentioned above.
 For example, if you want the name "FOO", you can `PATCH` to `/settings?tx=FOO` and `POST` to `/tx/commit_and_apply?tx=FOO`.
 
+Live settings and each transaction have a revision number that changes each time they do.
+Revisions only ever go up, and a transaction keeps moving up after it's committed or deleted, so a revision you saw can't match a later transaction with the same name.
+To make sure you don't overwrite or commit changes made by someone else in the meantime, add a `revision` parameter with the transaction revision you last saw to `PATCH /settings`, `DELETE /settings`, `/tx/commit`, or `/tx/commit_and_apply`, e.g. `/settings?tx=FOO&revision=3`.
+If the transaction has changed since, the request fails with status 409 Conflict, and you can fetch the transaction again to see what changed.
+
+The `revision` parameter is always checked against the revision of the transaction.
+You get it in the `ETag` header of `GET /tx`, and of `PATCH /settings` and `DELETE /settings`, which return the new revision.
+The `ETag` header of `GET /settings` is the revision of live settings instead, which you can use to tell whether live settings have changed, but not as a `revision` parameter.
+
+Requests that use the data store take a lock on it; reads can happen in parallel, and writes happen one at a time.
+A waiting write holds back new reads, so a stream of reads can't keep a write waiting.
+The lock is only eventually fair, though: a request can sometimes take it ahead of requests that were already waiting, but the lock is regularly handed to the request that has waited longest, so no request waits indefinitely.
+Data store work runs on a separate pool of threads, so a slow commit doesn't hold up requests that don't use the data store, like `/events`.
+If a request waits longer than the lock timeout, 30 seconds unless given with `--lock-timeout`, it fails with status 503 Service Unavailable, and can be retried.
+
 Requests are directed by `server::router`.
 `server::controller` maps requests into our data model.
 
@@ -47,18 +122,22 @@ Metadata entries can be stored for any data field in the model.
 Data from the model is stored in a key/value data store.
 Keys are dotted strings like "settings.service.abc".
 This naturally implies some grouping and hierarchy of the data, corresponding to the model.
+Lists of structures are stored with a key per element, using the index of the element, like "settings.a.0.b"; `PATCH /settings` replaces a list as a whole, removing any elements beyond the end of the new list.
 
 The current data store implementation maps keys to filesystem paths and stores the value in a file.
 Metadata about a data key is stored in a file at the data key path + "." + the metadata key.
 The default data store location is `/var/lib/bottlerocket/datastore/current`, and the filesystem format makes it fairly easy to inspect.
+Commits are atomic; if the server is interrupted during a commit, for example by a power loss, the commit is finished when the server starts again.
+The server keeps a copy of live data and metadata in memory, loaded at startup and updated for the keys each write changes, so reads of live data don't touch the filesystem.
 
 For more detail, see [datastore](../datastore).
 
 # Current limitations
 
-* Data store locking is coarse; read requests can happen in parallel, but a write request will block everything else.
-* There's no support for rolling back commits.
+* Data store locking is coarse; a write request blocks all other requests that use the data store until it's done.
+* Only a limited number of recent commits can be rolled back, and a rollback can't be undone.
 * There are no metrics.
+* Live data is cached in memory, so changes made to the data store directory by other programs while the server is running aren't seen until it restarts.
 
 # Example usage
 
@@ -68,6 +147,10 @@ You can start the API server from the `apiserver` directory with a command like:
 
 Then, from another shell, you can query or modify data.
 See `../../apiclient/README.md` for client examples.
+
+To measure request latency under load, run the load test against the server; it changes `settings.motd`, so only use it with a test data store:
+
+`cargo run --example load-test -- --socket-path /tmp/bottlerocket/api.sock`
 */
 #![deny(rust_2018_idioms)]
 
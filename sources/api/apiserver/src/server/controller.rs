@@ -2,22 +2,42 @@
 //! controller in the MVC model.
 
 use bottlerocket_release::BottlerocketRelease;
+use lazy_static::lazy_static;
 use serde::de::DeserializeOwned;
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::process::{Command, Stdio};
 
+use crate::server::dry_run::{self, DryRun};
 use crate::server::error::{self, Result};
+use crate::server::history::{self, CommitHistory, HistoryEntry};
 use actix_web::HttpResponse;
 use datastore::deserialization::{from_map, from_map_with_prefix};
-use datastore::serialization::to_pairs;
-use datastore::{deserialize_scalar, Committed, DataStore, Key, KeyType, ScalarError, Value};
-use model::{ConfigurationFiles, Services, Settings};
+use datastore::serialization::{to_list_keys, to_pairs};
+use datastore::{
+    deserialize_scalar, serialize_scalar, Committed, DataStore, Key, KeyType, Provenance,
+    ScalarError, Value, PROVENANCE_METADATA_KEY, SENSITIVE_METADATA_KEY,
+};
+use model::sensitive::SensitivePaths;
+use model::validation::{self, Scope, Violation};
+use model::{ConfigurationFiles, Model, Services, Settings};
 use num::FromPrimitive;
 use std::os::unix::process::ExitStatusExt;
+use thar_be_settings::status::{ApplyStatus, APPLY_STATUS_FILE};
 use thar_be_updates::error::TbuErrorStatus;
 
+/// The provenance source recorded for settings restored by a rollback.
+const ROLLBACK_SOURCE: &str = "rollback";
+
+/// The value shown in place of the values of sensitive settings where they can't be left out.
+const REDACTED: &str = "<redacted>";
+
+lazy_static! {
+    /// The settings marked sensitive in the model.
+    static ref SENSITIVE_PATHS: SensitivePaths = SensitivePaths::of::<Settings>("settings");
+}
+
 /// List the open transactions from the data store.
 pub(crate) fn list_transactions<D>(datastore: &D) -> Result<HashSet<String>>
 where
@@ -231,20 +251,306 @@ where
     Ok(result)
 }
 
-/// Given a Settings, takes any Some values and updates them in the datastore.
+/// Given a Settings, takes any Some values and updates them in the datastore.  The given source is
+/// recorded as the provenance of each setting when the transaction is committed.
+///
+/// Lists are replaced as a whole, so elements of an existing list of structures that aren't in the
+/// new list are removed, in the pending transaction and when it's committed.
 pub(crate) fn set_settings<D: DataStore>(
     datastore: &mut D,
     settings: &Settings,
     transaction: &str,
+    source: &str,
 ) -> Result<()> {
+    // Rules that need fields the change doesn't include are checked when it's committed.
+    let violations = validation::validate(settings, "settings", Scope::Change);
+    ensure!(violations.is_empty(), error::InvalidSettings { violations });
+
     trace!("Serializing Settings to write to data store");
     let pairs = to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    let lists =
+        to_list_keys(settings).context(error::DataStoreSerialization { given: "Settings" })?;
     let pending = Committed::Pending {
         tx: transaction.into(),
     };
+
+    // Find keys under the lists that the new values don't replace.  They have to be removed
+    // before the new values are written, because they can be in the way; for example, a list of
+    // structures has a key per element, but an empty list is a single key.
+    let stale_live = stale_list_keys(datastore, &lists, &pairs, &Committed::Live)?;
+    let stale_pending = stale_list_keys(datastore, &lists, &pairs, &pending)?;
+    datastore
+        .unset_keys(&stale_pending, &pending)
+        .context(error::DataStore { op: "unset_keys" })?;
+    if !stale_live.is_empty() {
+        datastore
+            .stage_removals(&stale_live, transaction)
+            .context(error::DataStore {
+                op: "stage_removals",
+            })?;
+    }
+
     datastore
         .set_keys(&pairs, &pending)
-        .context(error::DataStore { op: "set_keys" })
+        .context(error::DataStore { op: "set_keys" })?;
+    stage_sensitive(datastore, pairs.keys(), transaction)?;
+    stage_provenance(datastore, pairs.keys(), transaction, source)
+}
+
+/// Returns the populated keys at or under the given lists that aren't in the given new values.
+fn stale_list_keys<D: DataStore>(
+    datastore: &D,
+    lists: &HashSet<Key>,
+    pairs: &HashMap<Key, String>,
+    committed: &Committed,
+) -> Result<HashSet<Key>> {
+    let mut stale = HashSet::new();
+    for list in lists {
+        let populated = datastore
+            .list_populated_keys(list.name(), committed)
+            .context(error::DataStore {
+                op: "list_populated_keys",
+            })?;
+        // The data store matches prefixes as strings, so only keep the list and keys under it.
+        stale.extend(
+            populated.into_iter().filter(|key| {
+                key.starts_with_segments(list.segments()) && !pairs.contains_key(key)
+            }),
+        );
+    }
+    Ok(stale)
+}
+
+/// Returns the metadata key and serialized value recording that data keys were written by the
+/// given source in the given transaction.
+fn provenance(source: &str, transaction: &str) -> Result<(Key, String)> {
+    let md_key = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).context(error::NewKey {
+        key_type: "meta",
+        name: PROVENANCE_METADATA_KEY,
+    })?;
+    let provenance = Provenance {
+        source: source.to_string(),
+        transaction: transaction.to_string(),
+    };
+    let value =
+        serialize_scalar::<_, ScalarError>(&provenance).context(error::ProvenanceSerialization)?;
+    Ok((md_key, value))
+}
+
+/// Stages the provenance of the given keys in the given transaction, so it's made live along with
+/// the keys when the transaction is committed.
+fn stage_provenance<'a, D, I>(
+    datastore: &mut D,
+    keys: I,
+    transaction: &str,
+    source: &str,
+) -> Result<()>
+where
+    D: DataStore,
+    I: IntoIterator<Item = &'a Key>,
+{
+    let (md_key, value) = provenance(source, transaction)?;
+    for key in keys {
+        datastore
+            .set_pending_metadata(&md_key, key, &value, transaction)
+            .context(error::DataStore {
+                op: "set_pending_metadata",
+            })?;
+    }
+    Ok(())
+}
+
+/// Stages metadata marking the given keys as sensitive in the given transaction, for those that
+/// are sensitive settings in the model, so it's made live along with the keys when the
+/// transaction is committed.
+fn stage_sensitive<'a, D, I>(datastore: &mut D, keys: I, transaction: &str) -> Result<()>
+where
+    D: DataStore,
+    I: IntoIterator<Item = &'a Key>,
+{
+    let md_key = Key::new(KeyType::Meta, SENSITIVE_METADATA_KEY).context(error::NewKey {
+        key_type: "meta",
+        name: SENSITIVE_METADATA_KEY,
+    })?;
+    for key in keys {
+        if SENSITIVE_PATHS.is_sensitive(key.segments()) {
+            datastore
+                .set_pending_metadata(&md_key, key, "true", transaction)
+                .context(error::DataStore {
+                    op: "set_pending_metadata",
+                })?;
+        }
+    }
+    Ok(())
+}
+
+/// SensitiveKeys describes the data keys whose values are redacted from API responses unless
+/// they're requested: settings marked sensitive in the model, and keys whose "sensitive" metadata
+/// is true, along with the keys under them.
+pub(crate) struct SensitiveKeys {
+    marked: Vec<Key>,
+}
+
+impl SensitiveKeys {
+    fn contains(&self, key: &Key) -> bool {
+        SENSITIVE_PATHS.is_sensitive(key.segments())
+            || self
+                .marked
+                .iter()
+                .any(|marked| key.starts_with_segments(marked.segments()))
+    }
+}
+
+/// Finds the keys whose values are sensitive in the data store.
+pub(crate) fn get_sensitive_keys<D: DataStore>(datastore: &D) -> Result<SensitiveKeys> {
+    let mut marked = Vec::new();
+    for (name, value) in get_metadata_for_all_data_keys(datastore, SENSITIVE_METADATA_KEY)? {
+        if value == Value::Bool(true) {
+            marked.push(Key::new(KeyType::Data, &name).context(error::NewKey {
+                key_type: "data",
+                name,
+            })?);
+        }
+    }
+    Ok(SensitiveKeys { marked })
+}
+
+/// Returns the given settings without sensitive settings.  Their values can't be replaced with
+/// a placeholder, which may not be valid for their type, so they're left out.
+pub(crate) fn redact_settings(settings: Settings, sensitive: &SensitiveKeys) -> Result<Settings> {
+    let pairs = to_pairs(&settings).context(error::DataStoreSerialization { given: "Settings" })?;
+    if !pairs.keys().any(|key| sensitive.contains(key)) {
+        return Ok(settings);
+    }
+
+    let data: HashMap<Key, String> = pairs
+        .into_iter()
+        .filter(|(key, _)| !sensitive.contains(key))
+        .collect();
+    if data.is_empty() {
+        return Ok(Settings::default());
+    }
+    from_map(&data).context(error::Deserialization {
+        given: "redacted settings",
+    })
+}
+
+/// Replaces the values of sensitive settings in the given history entries with a placeholder.
+pub(crate) fn redact_history(
+    mut entries: Vec<HistoryEntry>,
+    sensitive: &SensitiveKeys,
+) -> Result<Vec<HistoryEntry>> {
+    for entry in &mut entries {
+        for (name, change) in &mut entry.changes {
+            let key = Key::new(KeyType::Data, name).context(error::NewKey {
+                key_type: "data",
+                name: name.as_str(),
+            })?;
+            if sensitive.contains(&key) {
+                for value in change.old.iter_mut().chain(change.new.iter_mut()) {
+                    *value = REDACTED.into();
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Stages the given settings to be reset in the given transaction.  Each key can name a single
+/// setting or a group of settings, like "settings.ntp".  Settings with a value in the given
+/// defaults are set back to it, and other settings that are live are staged for removal.  The
+/// given source is recorded as the provenance of each setting that's reset.  Returns the keys that
+/// were staged.
+pub(crate) fn unset_settings<D: DataStore>(
+    datastore: &mut D,
+    keys: &HashSet<&str>,
+    defaults: &HashMap<Key, String>,
+    transaction: &str,
+    source: &str,
+) -> Result<HashSet<Key>> {
+    let mut to_reset = HashMap::new();
+    let mut to_remove = HashSet::new();
+    for key_str in keys {
+        let key = Key::new(KeyType::Data, key_str).context(error::NewKey {
+            key_type: "data",
+            name: *key_str,
+        })?;
+        // Only settings can be changed through the API, and we don't want to reset all of them
+        // at once by accident.
+        let segments = key.segments();
+        ensure!(
+            segments.len() > 1 && segments[0] == "settings",
+            error::UnsetNonSetting { key: key.name() }
+        );
+
+        for (default_key, value) in defaults {
+            if default_key.starts_with_segments(segments) {
+                to_reset.insert(default_key.clone(), value.clone());
+            }
+        }
+        // The data store matches prefixes as strings, so 'settings.motd' would also match
+        // 'settings.motd-extra'; only keep the key itself and the keys under it.
+        let live = datastore
+            .list_populated_keys(key.name(), &Committed::Live)
+            .context(error::DataStore {
+                op: "list_populated_keys",
+            })?;
+        to_remove.extend(
+            live.into_iter()
+                .filter(|k| k.starts_with_segments(segments)),
+        );
+    }
+    to_remove.retain(|key| !to_reset.contains_key(key));
+
+    let pending = Committed::Pending {
+        tx: transaction.into(),
+    };
+    datastore
+        .set_keys(&to_reset, &pending)
+        .context(error::DataStore { op: "set_keys" })?;
+    datastore
+        .stage_removals(&to_remove, transaction)
+        .context(error::DataStore {
+            op: "stage_removals",
+        })?;
+
+    // Removed settings have no provenance.
+    stage_provenance(datastore, to_reset.keys(), transaction, source)?;
+    let mut staged: HashSet<Key> = to_reset.keys().cloned().collect();
+    staged.extend(to_remove);
+    Ok(staged)
+}
+
+/// Returns the revision of live data or of a pending transaction, which is bumped each time the
+/// data changes.
+pub(crate) fn get_revision<D: DataStore>(datastore: &D, committed: &Committed) -> Result<u64> {
+    datastore
+        .revision(committed)
+        .context(error::DataStore { op: "revision" })
+}
+
+/// Checks that the given transaction is at the revision the caller expects, if they gave one, so
+/// they don't overwrite or commit changes they haven't seen.
+pub(crate) fn check_revision<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+    expected: Option<u64>,
+) -> Result<()> {
+    if let Some(expected) = expected {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let actual = get_revision(datastore, &pending)?;
+        ensure!(
+            actual == expected,
+            error::RevisionMismatch {
+                transaction,
+                expected,
+                actual,
+            }
+        );
+    }
+    Ok(())
 }
 
 // This is not as nice as get_settings, which uses Serializer/Deserializer to properly use the
@@ -314,14 +620,352 @@ pub(crate) fn get_metadata_for_all_data_keys<D: DataStore, S: AsRef<str>>(
     Ok(result)
 }
 
-/// Makes live any pending settings in the datastore, returning the changed keys.
-pub(crate) fn commit_transaction<D>(datastore: &mut D, transaction: &str) -> Result<HashSet<Key>>
+/// Makes live any pending settings in the datastore, returning the changed keys.  The change is
+/// recorded in the given history.
+pub(crate) fn commit_transaction<D>(
+    datastore: &mut D,
+    transaction: &str,
+    history: &CommitHistory,
+) -> Result<HashSet<Key>>
 where
     D: DataStore,
 {
-    datastore
+    let pending_keys = pending_keys(datastore, transaction)?;
+    let data = merged_settings_data(datastore, transaction)?;
+    let generated = generated_keys(datastore, &data)?;
+    check_merged_settings(&data, &pending_keys, &generated)?;
+    let old = history::live_values(datastore, &pending_keys)?;
+
+    let changes = datastore
         .commit_transaction(transaction)
-        .context(error::DataStore { op: "commit" })
+        .context(error::DataStore { op: "commit" })?;
+
+    if !changes.is_empty() {
+        let new = history::live_values(datastore, &changes)?;
+        record_history(history, history::build_entry(transaction, false, old, new));
+    }
+    Ok(changes)
+}
+
+/// Returns the keys that committing the given transaction would change: its pending settings, and
+/// the settings it would remove.
+fn pending_keys<D: DataStore>(datastore: &D, transaction: &str) -> Result<HashSet<Key>> {
+    let pending = Committed::Pending {
+        tx: transaction.to_string(),
+    };
+    let mut keys = datastore
+        .list_populated_keys("settings.", &pending)
+        .context(error::DataStore {
+            op: "list_populated_keys",
+        })?;
+    keys.extend(
+        datastore
+            .pending_removals(transaction)
+            .context(error::DataStore {
+                op: "pending_removals",
+            })?,
+    );
+    Ok(keys)
+}
+
+/// Returns the settings data as it'd be after committing the given transaction: live data, without
+/// the keys the transaction removes, overlaid with its pending data.
+fn merged_settings_data<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+) -> Result<HashMap<Key, String>> {
+    let pending = Committed::Pending {
+        tx: transaction.to_string(),
+    };
+    let removals = datastore
+        .pending_removals(transaction)
+        .context(error::DataStore {
+            op: "pending_removals",
+        })?;
+    let mut data = datastore
+        .get_prefix("settings.", &Committed::Live)
+        .context(error::DataStore { op: "get_prefix" })?;
+    data.retain(|key, _| !removals.contains(key));
+    data.extend(
+        datastore
+            .get_prefix("settings.", &pending)
+            .context(error::DataStore { op: "get_prefix" })?,
+    );
+    Ok(data)
+}
+
+/// Returns the keys that have a setting generator but no value in the given settings data.  Their
+/// values are generated at boot, after the settings from defaults and user data are committed.
+fn generated_keys<D: DataStore>(
+    datastore: &D,
+    data: &HashMap<Key, String>,
+) -> Result<HashSet<Key>> {
+    let generators = datastore
+        .get_metadata_prefix("settings.", &Some("setting-generator"))
+        .context(error::DataStore {
+            op: "get_metadata_prefix",
+        })?;
+    Ok(generators
+        .keys()
+        .filter(|key| !data.contains_key(key))
+        .cloned()
+        .collect())
+}
+
+/// Checks the rules of the model against the given merged settings data, whose changed keys are
+/// given.  Only violations in the structs that the change touches are returned as an error, so
+/// existing problems in live data don't block other changes.  Structs with settings that are yet
+/// to be generated aren't complete, so their violations are skipped too.
+fn check_merged_settings(
+    data: &HashMap<Key, String>,
+    changed_keys: &HashSet<Key>,
+    generated_keys: &HashSet<Key>,
+) -> Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let settings: Settings = from_map_with_prefix(None, data).context(error::Deserialization {
+        given: "merged settings",
+    })?;
+    let violations: Vec<Violation> = validation::validate(&settings, "settings", Scope::Merged)
+        .into_iter()
+        .filter(|violation| {
+            changed_keys
+                .iter()
+                .any(|key| key.starts_with_segments(&violation.path))
+                && !generated_keys
+                    .iter()
+                    .any(|key| key.starts_with_segments(&violation.path))
+        })
+        .collect();
+    ensure!(violations.is_empty(), error::InvalidSettings { violations });
+    Ok(())
+}
+
+/// Describes what committing the given transaction and applying its changes would do, without
+/// changing the data store or the system.  If settings are given, they're treated as if they'd
+/// been added to the transaction first.
+///
+/// Affected services are found through the "affected-services" metadata of the changed keys, and
+/// their configuration files are rendered with the merged settings and the given OS info.  Unless
+/// secrets are requested, the values of sensitive settings are replaced with a placeholder in the
+/// rendered diffs, and the diffs of files that use sensitive settings are left out.
+pub(crate) fn dry_run_transaction<D: DataStore>(
+    datastore: &D,
+    transaction: &str,
+    settings: Option<&Settings>,
+    os: Option<BottlerocketRelease>,
+    secrets: bool,
+) -> Result<DryRun> {
+    let mut changed_keys = pending_keys(datastore, transaction)?;
+    let mut data = merged_settings_data(datastore, transaction)?;
+
+    if let Some(settings) = settings {
+        let violations = validation::validate(settings, "settings", Scope::Change);
+        ensure!(violations.is_empty(), error::InvalidSettings { violations });
+
+        let pairs =
+            to_pairs(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+        let lists =
+            to_list_keys(settings).context(error::DataStoreSerialization { given: "Settings" })?;
+        // Lists are replaced as a whole, like in set_settings, so keys under them that the new
+        // values don't include would be removed.
+        let stale: Vec<Key> = data
+            .keys()
+            .filter(|key| {
+                lists
+                    .iter()
+                    .any(|list| key.starts_with_segments(list.segments()))
+                    && !pairs.contains_key(key)
+            })
+            .cloned()
+            .collect();
+        for key in stale {
+            data.remove(&key);
+            changed_keys.insert(key);
+        }
+        changed_keys.extend(pairs.keys().cloned());
+        data.extend(pairs);
+    }
+    let generated = generated_keys(datastore, &data)?;
+    check_merged_settings(&data, &changed_keys, &generated)?;
+
+    let merged: Settings = if data.is_empty() {
+        Settings::default()
+    } else {
+        from_map_with_prefix(None, &data).context(error::Deserialization {
+            given: "merged settings",
+        })?
+    };
+
+    // Find the services affected by the changed keys, the same way the settings applier does.
+    let changed_names: HashSet<&str> = changed_keys.iter().map(|key| key.name().as_str()).collect();
+    let affected = get_metadata_for_data_keys(datastore, "affected-services", &changed_names)?;
+    let mut service_names = HashSet::new();
+    for (_, value) in affected {
+        let names: Vec<String> = serde_json::from_value(value).context(error::InvalidMetadata {
+            key: "affected-services",
+        })?;
+        service_names.extend(names);
+    }
+    let service_names: HashSet<&str> = service_names.iter().map(String::as_str).collect();
+    let affected_services = get_services_names(datastore, &service_names, &Committed::Live)?;
+    let file_names: HashSet<&str> = affected_services
+        .values()
+        .flat_map(|service| service.configuration_files.iter().map(AsRef::as_ref))
+        .collect();
+    let affected_files = get_configuration_files_names(datastore, &file_names, &Committed::Live)?;
+
+    // Unless secrets are requested, the values of sensitive settings are replaced in the diffs,
+    // including the live values that the change replaces, which the current files may hold.
+    // Templates can transform values, though, so files are also rendered without sensitive
+    // settings, and any file that renders differently has its diff left out.
+    let mut secret_values = Vec::new();
+    let mut redacted = None;
+    if !secrets {
+        let sensitive = get_sensitive_keys(datastore)?;
+        let live = datastore
+            .get_prefix("settings.", &Committed::Live)
+            .context(error::DataStore { op: "get_prefix" })?;
+        for (key, value) in live.iter().chain(&data) {
+            if sensitive.contains(key) {
+                // Rendered files hold the values themselves, rather than their serialized form.
+                secret_values.push(match serde_json::from_str::<Value>(value) {
+                    Ok(Value::String(s)) => s,
+                    _ => value.clone(),
+                });
+            }
+        }
+        let unsensitive: HashMap<Key, String> = data
+            .iter()
+            .filter(|(key, _)| !sensitive.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        redacted = Some(if unsensitive.is_empty() {
+            Settings::default()
+        } else {
+            from_map_with_prefix(None, &unsensitive).context(error::Deserialization {
+                given: "redacted settings",
+            })?
+        });
+    }
+
+    // Templates are rendered with the whole model, as they are by the settings applier.
+    let model_with = |settings: Settings| -> Result<Model> {
+        Ok(Model {
+            settings: Some(settings),
+            services: Some(get_services(datastore)?),
+            configuration_files: Some(get_configuration_files(datastore)?),
+            os: os.clone(),
+        })
+    };
+    let redacted_model = redacted.map(model_with).transpose()?;
+    let model = model_with(merged)?;
+    let config_files = dry_run::diff_config_files(
+        &affected_files,
+        &model,
+        redacted_model.as_ref(),
+        &secret_values,
+        REDACTED,
+    )?;
+
+    Ok(DryRun {
+        keys: changed_keys.iter().map(|key| key.name().clone()).collect(),
+        config_files,
+        restart_commands: dry_run::restart_commands(&affected_services),
+    })
+}
+
+/// Undoes the most recent commit still in the datastore's snapshot history, returning the changed
+/// keys, or None if there's nothing to roll back.  The change is recorded in the given history,
+/// and the provenance of the changed keys records the rollback of the transaction.
+pub(crate) fn rollback_transaction<D>(
+    datastore: &mut D,
+    history: &CommitHistory,
+) -> Result<Option<HashSet<Key>>>
+where
+    D: DataStore,
+{
+    let snapshot = match datastore.newest_snapshot().context(error::DataStore {
+        op: "newest_snapshot",
+    })? {
+        Some(snapshot) => snapshot,
+        None => return Ok(None),
+    };
+    let snapshot_keys = snapshot
+        .values
+        .keys()
+        .map(|name| Key::new(KeyType::Data, name))
+        .collect::<std::result::Result<HashSet<_>, _>>()
+        .context(error::DataStore { op: "rollback" })?;
+    let old = history::live_values(datastore, &snapshot_keys)?;
+
+    let changes = datastore
+        .rollback()
+        .context(error::DataStore { op: "rollback" })?
+        .unwrap_or_default();
+
+    // Like a commit, removed keys lose their provenance.
+    let (md_key, value) = provenance(ROLLBACK_SOURCE, &snapshot.transaction)?;
+    for key in &changes {
+        if let Some(None) = snapshot.values.get(key.name()) {
+            datastore
+                .unset_metadata(&md_key, key)
+                .context(error::DataStore {
+                    op: "unset_metadata",
+                })?;
+        } else {
+            datastore
+                .set_metadata(&md_key, key, &value)
+                .context(error::DataStore { op: "set_metadata" })?;
+        }
+    }
+
+    if !changes.is_empty() {
+        let new = history::live_values(datastore, &changes)?;
+        record_history(
+            history,
+            history::build_entry(snapshot.transaction, true, old, new),
+        );
+    }
+    Ok(Some(changes))
+}
+
+/// Adds an entry to the settings history.  The change has already been made at this point, so
+/// rather than failing the request, we log any problem.
+fn record_history(history: &CommitHistory, entry: HistoryEntry) {
+    if let Err(e) = history.append(&entry) {
+        error!(
+            "Failed to record change of transaction '{}' in history: {}",
+            entry.transaction, e
+        );
+    }
+}
+
+/// Returns the history of changes to live settings, oldest first.  If a prefix is given, only
+/// changes to settings starting with the prefix are included.  (The prefix should not include
+/// "settings.")
+pub(crate) fn get_settings_history(
+    history: &CommitHistory,
+    prefix: Option<&str>,
+) -> Result<Vec<HistoryEntry>> {
+    let mut entries = history.entries()?;
+    if let Some(prefix) = prefix {
+        let full_prefix = format!("settings.{}", prefix);
+        for entry in entries.iter_mut() {
+            entry
+                .changes
+                .retain(|name, _| name.starts_with(&full_prefix));
+        }
+        entries.retain(|entry| !entry.changes.is_empty());
+    }
+    Ok(entries)
+}
+
+/// Returns the results of recent runs of the config applier, oldest first.
+pub(crate) fn get_apply_status() -> Result<ApplyStatus> {
+    thar_be_settings::status::get_apply_status(APPLY_STATUS_FILE).context(error::ApplyStatus)
 }
 
 /// Launches the config applier to make appropriate changes to the system based on any settings
@@ -330,10 +974,15 @@ where
 ///
 /// If `keys_limit` is Some, gives those keys to the applier so only changes relevant to those
 /// keys are made.  Otherwise, tells the applier to apply changes for all known keys.
-pub(crate) fn apply_changes<S>(keys_limit: Option<&HashSet<S>>) -> Result<()>
+///
+/// Returns the ID under which the applier records its results.
+pub(crate) fn apply_changes<S>(keys_limit: Option<&HashSet<S>>) -> Result<String>
 where
     S: AsRef<str>,
 {
+    // The config applier records its results under this ID, so callers can check on them.
+    let apply_id = thar_be_settings::status::new_apply_id();
+
     if let Some(keys_limit) = keys_limit {
         let keys_limit: Vec<&str> = keys_limit.iter().map(|s| s.as_ref()).collect();
         // Prepare input to config applier; it uses the changed keys to update the right config
@@ -348,10 +997,11 @@ where
         let mut cmd = Command::new("/usr/bin/thar-be-settings")
             // Ask it to fork itself so we don't block the API
             .arg("--daemon")
+            .arg("--apply-id")
+            .arg(&apply_id)
             .stdin(Stdio::piped())
-            // FIXME where to send output?
-            //.stdout()
-            //.stderr()
+            // Its output is logged along with ours, and its results are recorded in its status
+            // file under the apply ID.
             .spawn()
             .context(error::ConfigApplierStart)?;
 
@@ -384,9 +1034,8 @@ where
         let status = Command::new("/usr/bin/thar-be-settings")
             .arg("--daemon")
             .arg("--all")
-            // FIXME where to send output?
-            //.stdout()
-            //.stderr()
+            .arg("--apply-id")
+            .arg(&apply_id)
             .status()
             .context(error::ConfigApplierStart)?;
         ensure!(
@@ -400,7 +1049,7 @@ where
         );
     }
 
-    Ok(())
+    Ok(apply_id)
 }
 
 /// Dispatches an update command via `thar-be-updates`
@@ -431,10 +1080,12 @@ pub(crate) fn dispatch_update_command(args: &[&str]) -> Result<HttpResponse> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::server::history::ValueChange;
     use datastore::memory::MemoryDataStore;
     use datastore::{Committed, DataStore, Key, KeyType};
     use maplit::{hashmap, hashset};
     use model::Service;
+    use std::collections::BTreeMap;
     use std::convert::TryInto;
 
     #[test]
@@ -538,7 +1189,7 @@ mod test {
         let mut ds = MemoryDataStore::new();
         let tx = "test transaction";
         let pending = Committed::Pending { tx: tx.into() };
-        set_settings(&mut ds, &settings, tx).unwrap();
+        set_settings(&mut ds, &settings, tx, "api").unwrap();
 
         // Retrieve directly
         let key = Key::new(KeyType::Data, "settings.motd").unwrap();
@@ -548,6 +1199,223 @@ mod test {
         );
     }
 
+    #[test]
+    fn sensitive_settings() {
+        let settings: Settings = serde_json::from_value(serde_json::json!({
+            "motd": "hi",
+            "host-containers": {
+                "admin": { "enabled": true, "source": "example.com/admin", "user-data": "c2VjcmV0" },
+            },
+        }))
+        .unwrap();
+        let user_data =
+            Key::new(KeyType::Data, "settings.host-containers.admin.user-data").unwrap();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let md_key = Key::new(KeyType::Meta, SENSITIVE_METADATA_KEY).unwrap();
+
+        // Sensitive settings are marked when they're committed
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        set_settings(&mut ds, &settings, tx, "api").unwrap();
+        commit_transaction(&mut ds, tx, &history).unwrap();
+        assert_eq!(
+            ds.get_metadata(&md_key, &user_data).unwrap(),
+            Some("true".to_string())
+        );
+        assert_eq!(ds.get_metadata(&md_key, &motd).unwrap(), None);
+
+        // They're left out of redacted settings, along with settings marked in metadata
+        let live = get_settings(&ds, &Committed::Live).unwrap();
+        let redacted = redact_settings(live, &get_sensitive_keys(&ds).unwrap()).unwrap();
+        let admin = &redacted.host_containers.as_ref().unwrap()["admin"];
+        assert_eq!(admin.user_data, None);
+        assert_eq!(admin.enabled, Some(true));
+        assert!(redacted.motd.is_some());
+
+        ds.set_metadata(&md_key, &motd, "true").unwrap();
+        let live = get_settings(&ds, &Committed::Live).unwrap();
+        let redacted = redact_settings(live, &get_sensitive_keys(&ds).unwrap()).unwrap();
+        assert_eq!(redacted.motd, None);
+
+        // Their values are replaced in history
+        let entries = get_settings_history(&history, None).unwrap();
+        let entries = redact_history(entries, &get_sensitive_keys(&ds).unwrap()).unwrap();
+        assert_eq!(
+            entries[0].changes[user_data.name()],
+            ValueChange {
+                old: None,
+                new: Some(REDACTED.into())
+            }
+        );
+    }
+
+    #[test]
+    fn unset_settings_works() {
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let ntp = Key::new(KeyType::Data, "settings.ntp.time-servers").unwrap();
+        let label = Key::new(KeyType::Data, "settings.labels.a").unwrap();
+        let other = Key::new(KeyType::Data, "settings.motd-extra").unwrap();
+        let mut ds = MemoryDataStore::new();
+        ds.set_keys(
+            &hashmap!(
+                motd.clone() => "\"hi\"",
+                ntp.clone() => "[\"a\"]",
+                label.clone() => "\"x\"",
+                other.clone() => "\"y\"",
+            ),
+            &Committed::Live,
+        )
+        .unwrap();
+        let defaults = hashmap!(ntp.clone() => "[\"default\"]".to_string());
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        let staged = unset_settings(
+            &mut ds,
+            &hashset!("settings.motd", "settings.ntp", "settings.labels"),
+            &defaults,
+            tx,
+            "api",
+        )
+        .unwrap();
+        assert_eq!(staged, hashset!(motd.clone(), ntp.clone(), label.clone()));
+        // Settings with defaults are reset to them, others are removed
+        assert_eq!(
+            ds.get_key(&ntp, &pending).unwrap(),
+            Some("[\"default\"]".to_string())
+        );
+        assert_eq!(ds.pending_removals(tx).unwrap(), hashset!(motd, label));
+
+        // Only settings can be unset, and not all at once
+        assert!(unset_settings(&mut ds, &hashset!("services.x"), &defaults, tx, "api").is_err());
+        assert!(unset_settings(&mut ds, &hashset!("settings"), &defaults, tx, "api").is_err());
+    }
+
+    #[test]
+    fn stale_list_keys_works() {
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let element0 = Key::new(KeyType::Data, "settings.list.0.id").unwrap();
+        let element1 = Key::new(KeyType::Data, "settings.list.1.id").unwrap();
+        let other = Key::new(KeyType::Data, "settings.list-extra").unwrap();
+        let mut ds = MemoryDataStore::new();
+        ds.set_keys(
+            &hashmap!(
+                element0.clone() => "1",
+                element1.clone() => "2",
+                other.clone() => "3",
+            ),
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // Elements beyond the end of the new list are stale
+        let pairs = hashmap!(element0.clone() => "4".to_string());
+        assert_eq!(
+            stale_list_keys(&ds, &hashset!(list.clone()), &pairs, &Committed::Live).unwrap(),
+            hashset!(element1.clone())
+        );
+        // All elements are stale if the list is replaced with a single value
+        let pairs = hashmap!(list.clone() => "[]".to_string());
+        assert_eq!(
+            stale_list_keys(&ds, &hashset!(list), &pairs, &Committed::Live).unwrap(),
+            hashset!(element0, element1)
+        );
+    }
+
+    #[test]
+    fn provenance_works() {
+        let mut settings = Settings::default();
+        settings.motd = Some("tz".try_into().unwrap());
+        let mut ds = MemoryDataStore::new();
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        let keys = hashset!("settings.motd");
+
+        // Provenance is only recorded once the change is committed
+        set_settings(&mut ds, &settings, "boot", "user-data").unwrap();
+        assert!(
+            get_metadata_for_data_keys(&ds, PROVENANCE_METADATA_KEY, &keys)
+                .unwrap()
+                .is_empty()
+        );
+        commit_transaction(&mut ds, "boot", &history).unwrap();
+        assert_eq!(
+            get_metadata_for_data_keys(&ds, PROVENANCE_METADATA_KEY, &keys).unwrap(),
+            hashmap!(
+                "settings.motd".to_string() =>
+                    serde_json::json!({"source": "user-data", "transaction": "boot"}),
+            )
+        );
+
+        // A later change replaces it, and rolling back the change records the rollback
+        settings.motd = Some("later".try_into().unwrap());
+        set_settings(&mut ds, &settings, "default", "api").unwrap();
+        commit_transaction(&mut ds, "default", &history).unwrap();
+        assert_eq!(
+            get_metadata_for_data_keys(&ds, PROVENANCE_METADATA_KEY, &keys).unwrap()
+                ["settings.motd"]["source"],
+            "api"
+        );
+        rollback_transaction(&mut ds, &history).unwrap();
+        assert_eq!(
+            get_metadata_for_data_keys(&ds, PROVENANCE_METADATA_KEY, &keys).unwrap(),
+            hashmap!(
+                "settings.motd".to_string() =>
+                    serde_json::json!({"source": "rollback", "transaction": "default"}),
+            )
+        );
+    }
+
+    #[test]
+    fn check_revision_works() {
+        let mut settings = Settings::default();
+        settings.motd = Some("tz".try_into().unwrap());
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+
+        check_revision(&ds, tx, Some(0)).unwrap();
+        set_settings(&mut ds, &settings, tx, "api").unwrap();
+        assert_eq!(get_revision(&ds, &pending).unwrap(), 1);
+
+        // Someone who saw the old revision can't make changes
+        check_revision(&ds, tx, None).unwrap();
+        check_revision(&ds, tx, Some(1)).unwrap();
+        match check_revision(&ds, tx, Some(0)) {
+            Err(error::Error::RevisionMismatch {
+                expected, actual, ..
+            }) => assert_eq!((expected, actual), (0, 1)),
+            other => panic!("Expected revision mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn old_revision_after_commit() {
+        let mut settings = Settings::default();
+        settings.motd = Some("tz".try_into().unwrap());
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+
+        set_settings(&mut ds, &settings, tx, "api").unwrap();
+        let old = get_revision(&ds, &pending).unwrap();
+        commit_transaction(&mut ds, tx, &history).unwrap();
+
+        // Someone else stages the same change in a new transaction with the same name
+        set_settings(&mut ds, &settings, tx, "api").unwrap();
+        assert!(get_revision(&ds, &pending).unwrap() > old);
+
+        // A PATCH with the revision from before the commit doesn't match it
+        match check_revision(&ds, tx, Some(old)) {
+            Err(error::Error::RevisionMismatch { expected, .. }) => assert_eq!(expected, old),
+            other => panic!("Expected revision mismatch, got {:?}", other),
+        }
+    }
+
     #[test]
     fn get_metadata_keys_works() {
         let mut ds = MemoryDataStore::new();
@@ -615,12 +1483,278 @@ mod test {
         get_settings(&ds, &Committed::Live).unwrap_err();
 
         // Commit, pending -> live
-        commit_transaction(&mut ds, tx).unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        commit_transaction(&mut ds, tx, &history).unwrap();
 
         // No more pending settings
         get_settings(&ds, &pending).unwrap_err();
         // Confirm live
         let settings = get_settings(&ds, &Committed::Live).unwrap();
         assert_eq!(settings.motd, Some("json string".try_into().unwrap()));
+
+        // Confirm history
+        let entries = get_settings_history(&history, None).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].transaction, tx);
+        assert!(!entries[0].rollback);
+        assert_eq!(
+            entries[0].changes["settings.motd"],
+            ValueChange {
+                old: None,
+                new: Some("json string".into())
+            }
+        );
+    }
+
+    #[test]
+    fn commit_checks_merged_settings() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        let enabled = Key::new(KeyType::Data, "settings.host-containers.x.enabled").unwrap();
+        let source = Key::new(KeyType::Data, "settings.host-containers.x.source").unwrap();
+
+        // The change is fine on its own, but the container has no source once merged
+        ds.set_key(&enabled, "true", &pending).unwrap();
+        match commit_transaction(&mut ds, tx, &history) {
+            Err(error::Error::InvalidSettings { violations }) => assert_eq!(
+                violations[0].to_string(),
+                "settings.host-containers.x: source is required when enabled"
+            ),
+            other => panic!("Expected invalid settings, got {:?}", other),
+        }
+        assert_eq!(ds.get_key(&enabled, &Committed::Live).unwrap(), None);
+
+        // A source in live data satisfies the rule
+        ds.set_key(&source, "\"example.com/x\"", &Committed::Live)
+            .unwrap();
+        commit_transaction(&mut ds, tx, &history).unwrap();
+
+        // Removing it again is a violation
+        ds.stage_removals(&hashset!(source.clone()), tx).unwrap();
+        assert!(commit_transaction(&mut ds, tx, &history).is_err());
+    }
+
+    #[test]
+    fn commit_skips_rules_of_generated_settings() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        let enabled = Key::new(KeyType::Data, "settings.host-containers.x.enabled").unwrap();
+        let source = Key::new(KeyType::Data, "settings.host-containers.x.source").unwrap();
+        let generator = Key::new(KeyType::Meta, "setting-generator").unwrap();
+
+        // The source is generated at boot, after defaults are committed, so it isn't required yet
+        ds.set_metadata(&generator, &source, "\"schnauzer source\"")
+            .unwrap();
+        ds.set_key(&enabled, "true", &pending).unwrap();
+        commit_transaction(&mut ds, tx, &history).unwrap();
+        assert_eq!(
+            ds.get_key(&enabled, &Committed::Live).unwrap(),
+            Some("true".to_string())
+        );
+    }
+
+    #[test]
+    fn commit_ignores_unchanged_violations() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.host-containers.x.enabled").unwrap(),
+            "true",
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // An existing problem doesn't block changes to other settings
+        ds.set_key(
+            &Key::new(KeyType::Data, "settings.motd").unwrap(),
+            "\"hi\"",
+            &pending,
+        )
+        .unwrap();
+        commit_transaction(&mut ds, tx, &history).unwrap();
+    }
+
+    #[test]
+    fn dry_run_works() {
+        let mut ds = MemoryDataStore::new();
+        let tx = "test transaction";
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("motd");
+        let template_path = tmp.path().join("motd.template");
+        std::fs::write(&path, "old\n").unwrap();
+        std::fs::write(&template_path, "{{settings.motd}}\n").unwrap();
+
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        let path_json = serde_json::to_string(&path).unwrap();
+        let template_path_json = serde_json::to_string(&template_path).unwrap();
+        let live: HashMap<Key, String> = vec![
+            ("settings.motd", "\"old\""),
+            ("services.motd.configuration-files", "[\"motd\"]"),
+            ("services.motd.restart-commands", "[\"echo hi\"]"),
+            ("services.other.configuration-files", "[]"),
+            ("services.other.restart-commands", "[\"echo no\"]"),
+            ("configuration-files.motd.path", path_json.as_str()),
+            (
+                "configuration-files.motd.template-path",
+                template_path_json.as_str(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (Key::new(KeyType::Data, name).unwrap(), value.to_string()))
+        .collect();
+        ds.set_keys(&live, &Committed::Live).unwrap();
+        let affected = Key::new(KeyType::Meta, "affected-services").unwrap();
+        ds.set_metadata(&affected, &motd, "[\"motd\"]").unwrap();
+
+        // The given settings are treated as part of the transaction
+        let mut settings = Settings::default();
+        settings.motd = Some("new".try_into().unwrap());
+        let dry_run = dry_run_transaction(&ds, tx, Some(&settings), None, false).unwrap();
+        assert_eq!(
+            dry_run.keys.iter().collect::<Vec<_>>(),
+            vec!["settings.motd"]
+        );
+        assert_eq!(dry_run.config_files.len(), 1);
+        assert_eq!(dry_run.config_files[0].name, "motd");
+        assert!(dry_run.config_files[0].diff.contains("-old\n+new\n"));
+        assert_eq!(
+            dry_run.restart_commands,
+            vec![dry_run::RestartCommand {
+                service: "motd".to_string(),
+                command: "echo hi".to_string(),
+            }]
+        );
+
+        // Nothing was changed
+        assert!(get_transaction(&ds, tx).unwrap().motd.is_none());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+
+        // Sensitive values are redacted unless requested, including the live values the change
+        // replaces.  Files that use sensitive settings have their diffs left out, and other files
+        // have any sensitive values replaced.
+        let other_path = tmp.path().join("other");
+        let other_template_path = tmp.path().join("other.template");
+        std::fs::write(&other_path, "token: old\n").unwrap();
+        std::fs::write(&other_template_path, "token: gone\n").unwrap();
+        let other: HashMap<Key, String> = vec![
+            (
+                "services.motd.configuration-files",
+                "[\"motd\", \"other\"]".to_string(),
+            ),
+            (
+                "configuration-files.other.path",
+                serde_json::to_string(&other_path).unwrap(),
+            ),
+            (
+                "configuration-files.other.template-path",
+                serde_json::to_string(&other_template_path).unwrap(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, value)| (Key::new(KeyType::Data, name).unwrap(), value))
+        .collect();
+        ds.set_keys(&other, &Committed::Live).unwrap();
+        let md_key = Key::new(KeyType::Meta, SENSITIVE_METADATA_KEY).unwrap();
+        ds.set_metadata(&md_key, &motd, "true").unwrap();
+
+        let dry_run = dry_run_transaction(&ds, tx, Some(&settings), None, false).unwrap();
+        assert_eq!(dry_run.config_files.len(), 2);
+        assert_eq!(dry_run.config_files[0].diff, "");
+        assert!(dry_run.config_files[0].redacted);
+        assert!(dry_run.config_files[1]
+            .diff
+            .contains("-token: <redacted>\n+token: gone\n"));
+        assert!(!dry_run.config_files[1].redacted);
+
+        let dry_run = dry_run_transaction(&ds, tx, Some(&settings), None, true).unwrap();
+        assert!(dry_run.config_files[0].diff.contains("-old\n+new\n"));
+        assert!(!dry_run.config_files[0].redacted);
+        assert!(dry_run.config_files[1].diff.contains("-token: old\n"));
+    }
+
+    #[test]
+    fn rollback_works() {
+        let mut ds = MemoryDataStore::new();
+        let motd = Key::new(KeyType::Data, "settings.motd").unwrap();
+        ds.set_key(&motd, "\"old\"", &Committed::Live).unwrap();
+
+        let tx = "test transaction";
+        let pending = Committed::Pending { tx: tx.into() };
+        ds.set_key(&motd, "\"new\"", &pending).unwrap();
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        commit_transaction(&mut ds, tx, &history).unwrap();
+
+        // Rollback, live -> previous live
+        assert_eq!(
+            rollback_transaction(&mut ds, &history).unwrap(),
+            Some(hashset!(motd))
+        );
+        let settings = get_settings(&ds, &Committed::Live).unwrap();
+        assert_eq!(settings.motd, Some("old".try_into().unwrap()));
+
+        // Both changes are in history
+        let entries = get_settings_history(&history, None).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].transaction, tx);
+        assert!(entries[1].rollback);
+        assert_eq!(
+            entries[1].changes["settings.motd"],
+            ValueChange {
+                old: Some("new".into()),
+                new: Some("old".into())
+            }
+        );
+
+        // Nothing left to roll back
+        assert!(rollback_transaction(&mut ds, &history).unwrap().is_none());
+    }
+
+    #[test]
+    fn history_prefix_works() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        let change = ValueChange {
+            old: None,
+            new: Some(true.into()),
+        };
+        for (tx, key) in &[
+            ("tx1", "settings.kubernetes.a"),
+            ("tx2", "settings.motd"),
+            ("tx3", "settings.kubernetes.b"),
+        ] {
+            let mut entry = history::build_entry(*tx, false, BTreeMap::new(), BTreeMap::new());
+            entry.changes.insert(key.to_string(), change.clone());
+            entry
+                .changes
+                .insert("settings.ntp".to_string(), change.clone());
+            history.append(&entry).unwrap();
+        }
+
+        let entries = get_settings_history(&history, Some("kubernetes")).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| {
+                let keys: Vec<_> = e.changes.keys().map(String::as_str).collect();
+                (e.transaction.as_str(), keys)
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("tx1", vec!["settings.kubernetes.a"]),
+                ("tx3", vec!["settings.kubernetes.b"]),
+            ]
+        );
     }
 }
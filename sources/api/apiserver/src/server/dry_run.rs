@@ -0,0 +1,250 @@
+//! The dry_run module describes what committing and applying a transaction would do to the system,
+//! without doing it: which configuration files would be rewritten and how, and which restart
+//! commands would run.
+//!
+//! Affected services are found through the "affected-services" metadata of the changed settings,
+//! like the settings applier does.  Their configuration files are rendered from their templates
+//! with the settings as they'd be after the commit, and compared to the files currently on disk.
+
+use model::{ConfigurationFiles, Model, Services};
+use serde::Serialize;
+use similar::TextDiff;
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+
+use crate::server::error::{self, Result};
+
+/// DryRun describes what committing and applying a transaction would do.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct DryRun {
+    /// The settings that would change.
+    pub(crate) keys: BTreeSet<String>,
+    /// The configuration files that would be rendered, in order of name.
+    pub(crate) config_files: Vec<ConfigFileDiff>,
+    /// The restart commands that would run, in order of service name.
+    pub(crate) restart_commands: Vec<RestartCommand>,
+}
+
+/// ConfigFileDiff describes the change that rendering would make to one configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ConfigFileDiff {
+    pub(crate) name: String,
+    pub(crate) path: String,
+    /// A unified diff from the current file to the rendered file, which is empty if they're the
+    /// same.  A file that doesn't exist yet is compared as if it were empty.
+    pub(crate) diff: String,
+    /// The reason the file couldn't be rendered or compared, if it couldn't.
+    pub(crate) error: Option<String>,
+    /// Whether the diff was left out because the file uses sensitive settings.
+    pub(crate) redacted: bool,
+}
+
+/// RestartCommand describes one restart command that would run for a service.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct RestartCommand {
+    pub(crate) service: String,
+    pub(crate) command: String,
+}
+
+/// Renders the given configuration files with the given model, and compares each to the file
+/// currently at its path.  Any of the given secrets found in a diff are replaced with the given
+/// placeholder.
+///
+/// If a redacted model, without sensitive settings, is given, files are also rendered with it.
+/// Files that render differently use sensitive settings, perhaps transformed by a helper so the
+/// secrets can't be found in the diff, so their diffs are left out.
+pub(crate) fn diff_config_files(
+    config_files: &ConfigurationFiles,
+    model: &Model,
+    redacted_model: Option<&Model>,
+    secrets: &[String],
+    placeholder: &str,
+) -> Result<Vec<ConfigFileDiff>> {
+    let mut registry = schnauzer::build_template_registry().context(error::TemplateRegistry)?;
+
+    let mut names: Vec<&String> = config_files.keys().collect();
+    names.sort();
+    let mut diffs = Vec::new();
+    for name in names {
+        let metadata = &config_files[name];
+        let path: &str = metadata.path.as_ref();
+        debug!("Rendering {} for dry run", name);
+
+        let rendered = registry
+            .register_template_file(name, metadata.template_path.as_ref())
+            .map_err(|e| e.to_string())
+            .and_then(|()| registry.render(name, model).map_err(|e| e.to_string()));
+        let redacted = match (&rendered, redacted_model) {
+            (Ok(rendered), Some(redacted_model)) => {
+                registry.render(name, redacted_model).ok().as_ref() != Some(rendered)
+            }
+            _ => false,
+        };
+        let result = rendered.and_then(|rendered| {
+            current_contents(path).map(|current| diff(path, &current, &rendered))
+        });
+
+        let (diff, error) = match result {
+            Ok(_) if redacted => (String::new(), None),
+            Ok(mut diff) => {
+                for secret in secrets.iter().filter(|s| !s.is_empty()) {
+                    diff = diff.replace(secret.as_str(), placeholder);
+                }
+                (diff, None)
+            }
+            Err(e) => (String::new(), Some(e)),
+        };
+        diffs.push(ConfigFileDiff {
+            name: name.clone(),
+            path: path.to_string(),
+            diff,
+            error,
+            redacted,
+        });
+    }
+    Ok(diffs)
+}
+
+/// Returns the restart commands of the given services, in order of service name.
+pub(crate) fn restart_commands(services: &Services) -> Vec<RestartCommand> {
+    let mut names: Vec<&String> = services.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .flat_map(|name| {
+            services[name]
+                .restart_commands
+                .iter()
+                .map(move |command| RestartCommand {
+                    service: name.clone(),
+                    command: command.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Returns the current contents of the file at the given path, or an empty string if it doesn't
+/// exist yet.
+fn current_contents(path: &str) -> std::result::Result<String, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("Unable to read current file: {}", e)),
+    }
+}
+
+/// Returns a unified diff from the current contents of the file at the given path to its rendered
+/// contents, or an empty string if they're the same.
+fn diff(path: &str, current: &str, rendered: &str) -> String {
+    TextDiff::from_lines(current, rendered)
+        .unified_diff()
+        .header(path, path)
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashmap;
+    use model::ConfigurationFile;
+    use model::Settings;
+    use std::convert::TryInto;
+
+    fn empty_model() -> Model {
+        Model {
+            settings: None,
+            services: None,
+            configuration_files: None,
+            os: None,
+        }
+    }
+
+    fn config_file(dir: &std::path::Path, name: &str, template: &str) -> ConfigurationFile {
+        let template_path = dir.join(format!("{}.template", name));
+        fs::write(&template_path, template).unwrap();
+        ConfigurationFile {
+            path: dir.join(name).to_str().unwrap().try_into().unwrap(),
+            template_path: template_path.to_str().unwrap().try_into().unwrap(),
+        }
+    }
+
+    #[test]
+    fn diffs() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("changed"), "first\nold\n").unwrap();
+        fs::write(dir.path().join("same"), "hi\n").unwrap();
+        let config_files = hashmap! {
+            "changed".to_string() => config_file(dir.path(), "changed", "first\nnew\n"),
+            "same".to_string() => config_file(dir.path(), "same", "hi\n"),
+            "new".to_string() => config_file(dir.path(), "new", "created\n"),
+            "broken".to_string() => config_file(dir.path(), "broken", "{{settings.missing}}\n"),
+        };
+
+        let diffs = diff_config_files(&config_files, &empty_model(), None, &[], "").unwrap();
+        let names: Vec<&str> = diffs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["broken", "changed", "new", "same"]);
+
+        assert!(diffs[0].error.is_some());
+        assert!(diffs[1].diff.contains("-old\n+new\n"));
+        assert!(diffs[1].diff.contains(&format!("--- {}", diffs[1].path)));
+        assert!(diffs[2].diff.contains("+created\n"));
+        assert_eq!(diffs[3].diff, "");
+        assert!(diffs.iter().skip(1).all(|d| d.error.is_none()));
+
+        // The files aren't changed.
+        assert!(!dir.path().join("new").exists());
+    }
+
+    #[test]
+    fn secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_files = hashmap! {
+            "file".to_string() => config_file(dir.path(), "file", "token: hunter2\n"),
+        };
+        let diffs = diff_config_files(
+            &config_files,
+            &empty_model(),
+            None,
+            &["hunter2".to_string()],
+            "<redacted>",
+        )
+        .unwrap();
+        assert!(diffs[0].diff.contains("+token: <redacted>\n"));
+        assert!(!diffs[0].diff.contains("hunter2"));
+    }
+
+    #[test]
+    fn sensitive_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("plain"), "old\n").unwrap();
+        let config_files = hashmap! {
+            "plain".to_string() => config_file(dir.path(), "plain", "new\n"),
+            "secret".to_string() => config_file(
+                dir.path(),
+                "secret",
+                "{{base64_decode settings.motd}}\n",
+            ),
+        };
+        let mut model = empty_model();
+        let mut settings = Settings::default();
+        // "hunter2", which the helper decodes, so it can't be found by its encoded value
+        settings.motd = Some("aHVudGVyMg==".try_into().unwrap());
+        model.settings = Some(settings);
+
+        let diffs = diff_config_files(
+            &config_files,
+            &model,
+            Some(&empty_model()),
+            &["aHVudGVyMg==".to_string()],
+            "<redacted>",
+        )
+        .unwrap();
+        assert!(diffs[0].diff.contains("-old\n+new\n"));
+        assert!(!diffs[0].redacted);
+        assert_eq!(diffs[1].diff, "");
+        assert!(diffs[1].redacted);
+        assert!(diffs[1].error.is_none());
+    }
+}
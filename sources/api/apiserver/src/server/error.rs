@@ -5,6 +5,7 @@ use snafu::Snafu;
 use std::io;
 use std::path::PathBuf;
 use std::string::String;
+use std::time::Duration;
 
 // We want server (router/handler) and controller errors together so it's easy to define response
 // error codes for all the high-level types of errors that could happen during a request.
@@ -40,8 +41,16 @@ pub enum Error {
     #[snafu(display("Input '{}' cannot be empty", input))]
     EmptyInput { input: String },
 
-    #[snafu(display("Another thread poisoned the data store lock by panicking"))]
-    DataStoreLock,
+    #[snafu(display("Input '{}' must be 'true' or 'false', not '{}'", input, given))]
+    InvalidFlag { input: String, given: String },
+
+    #[snafu(display("Timed out after {:?} waiting for the data store lock", timeout))]
+    DataStoreLock { timeout: Duration },
+
+    #[snafu(display("Unable to run data store operation: {}", source))]
+    Blocking {
+        source: actix_web::error::BlockingError,
+    },
 
     #[snafu(display("Unable to serialize response: {}", source))]
     ResponseSerialization { source: serde_json::Error },
@@ -55,6 +64,53 @@ pub enum Error {
     #[snafu(display("Tried to commit with no pending changes"))]
     CommitWithNoPending,
 
+    #[snafu(display("Tried to roll back with no previous commits"))]
+    RollbackWithNoHistory,
+
+    #[snafu(display("Invalid revision '{}': {}", given, source))]
+    InvalidRevision {
+        given: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display(
+        "Transaction '{}' is at revision {}, not expected revision {}",
+        transaction,
+        actual,
+        expected
+    ))]
+    RevisionMismatch {
+        transaction: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[snafu(display(
+        "Can only unset individual settings or groups of settings, not '{}'",
+        key
+    ))]
+    UnsetNonSetting { key: String },
+
+    #[snafu(display("Invalid {} header: {}", header, reason))]
+    InvalidSettingSource {
+        header: &'static str,
+        reason: &'static str,
+    },
+
+    #[snafu(display(
+        "Settings are invalid:\n{}",
+        violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join("\n")
+    ))]
+    InvalidSettings {
+        violations: Vec<model::validation::Violation>,
+    },
+
+    #[snafu(display("Unable to load default settings: {}", source))]
+    DefaultSettings { source: storewolf::error::Error },
+
+    #[snafu(display("Unable to finish interrupted commit: {}", source))]
+    DataStoreRecovery { source: datastore::Error },
+
     #[snafu(display("Unable to get OS release data: {}", source))]
     ReleaseData { source: bottlerocket_release::Error },
 
@@ -101,6 +157,9 @@ pub enum Error {
         source: datastore::Error,
     },
 
+    #[snafu(display("Unable to serialize provenance: {}", source))]
+    ProvenanceSerialization { source: serde_json::Error },
+
     #[snafu(display("Metadata '{}' is not valid JSON: {}", key, source))]
     InvalidMetadata {
         key: String,
@@ -125,6 +184,18 @@ pub enum Error {
     #[snafu(display("Unable to send input to config applier: {}", source))]
     ConfigApplierWrite { source: io::Error },
 
+    #[snafu(display("Unable to access settings history at '{}': {}", path.display(), source))]
+    HistoryIo { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Settings history at '{}' is invalid: {}", path.display(), source))]
+    HistoryFormat {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Unable to serialize settings history: {}", source))]
+    HistorySerialization { source: serde_json::Error },
+
     #[snafu(display("Unable to start shutdown: {}", source))]
     Shutdown { source: io::Error },
 
@@ -161,6 +232,15 @@ pub enum Error {
     #[snafu(display("Update status is uninitialized, refresh-updates to initialize it"))]
     UninitializedUpdateStatus,
 
+    #[snafu(display("Unable to read settings apply status: {}", source))]
+    ApplyStatus { source: thar_be_settings::Error },
+
+    #[snafu(display("Unable to build template registry: {}", source))]
+    TemplateRegistry { source: schnauzer::Error },
+
+    #[snafu(display("No settings apply with ID '{}'", id))]
+    ApplyNotFound { id: String },
+
     #[snafu(display("Failed to parse update status: {} ", source))]
     UpdateStatusParse { source: serde_json::Error },
 
@@ -0,0 +1,176 @@
+//! The events module lets clients watch for changes to live settings, rather than polling.
+//!
+//! Each client that calls `/events` subscribes to an EventBroadcaster, and handlers publish a
+//! ChangeEvent to all subscribers when settings are committed, rolled back, or applied.  Events
+//! are sent to the client in server-sent event format: an "event" line with the kind of change,
+//! and a "data" line with the JSON-serialized ChangeEvent.
+
+use actix_web::web::Bytes;
+use datastore::Key;
+use futures::channel::mpsc;
+use log::{debug, error};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// The number of events we queue for a subscriber that isn't reading them.  If a subscriber falls
+/// further behind, we drop it, which ends its stream; it can subscribe again and refetch settings
+/// to catch up.
+const MAX_QUEUED_EVENTS: usize = 64;
+
+/// The kinds of change that can be reported in a ChangeEvent.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ChangeKind {
+    Commit,
+    Rollback,
+    Apply,
+}
+
+impl ChangeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Commit => "commit",
+            ChangeKind::Rollback => "rollback",
+            ChangeKind::Apply => "apply",
+        }
+    }
+}
+
+/// ChangeEvent describes one change to live settings.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ChangeEvent {
+    pub(crate) kind: ChangeKind,
+    /// The name of the committed transaction; not set for rollbacks and applies.
+    pub(crate) transaction: Option<String>,
+    /// The changed keys; for an apply, the keys whose changes were applied, or None if changes
+    /// to all keys were applied.
+    pub(crate) keys: Option<HashSet<Key>>,
+    /// For an apply, the ID under which the config applier records its results.
+    pub(crate) apply_id: Option<String>,
+}
+
+impl ChangeEvent {
+    /// Formats the event as a server-sent event.
+    pub(crate) fn to_sse(&self) -> serde_json::Result<Bytes> {
+        let data = serde_json::to_string(self)?;
+        Ok(Bytes::from(format!(
+            "event: {}\ndata: {}\n\n",
+            self.kind.name(),
+            data
+        )))
+    }
+}
+
+/// EventBroadcaster sends each published ChangeEvent to all current subscribers.
+#[derive(Debug, Default)]
+pub(crate) struct EventBroadcaster {
+    subscribers: Mutex<Vec<mpsc::Sender<ChangeEvent>>>,
+}
+
+impl EventBroadcaster {
+    /// Returns a stream of the events published from now on.
+    pub(crate) fn subscribe(&self) -> mpsc::Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel(MAX_QUEUED_EVENTS);
+        self.lock_subscribers().push(sender);
+        receiver
+    }
+
+    /// Sends the event to all subscribers, dropping any that have disconnected or fallen too far
+    /// behind.
+    pub(crate) fn publish(&self, event: ChangeEvent) {
+        let mut subscribers = self.lock_subscribers();
+        let remaining = subscribers
+            .drain(..)
+            .filter_map(|mut subscriber| match subscriber.try_send(event.clone()) {
+                Ok(()) => Some(subscriber),
+                Err(e) => {
+                    if e.is_full() {
+                        debug!("Dropping event subscriber that fell behind");
+                    }
+                    None
+                }
+            })
+            .collect();
+        *subscribers = remaining;
+    }
+
+    /// Locks the list of subscribers.  The list is valid even if another thread panicked while
+    /// holding the lock, so we carry on rather than stopping all events.
+    fn lock_subscribers(&self) -> std::sync::MutexGuard<'_, Vec<mpsc::Sender<ChangeEvent>>> {
+        self.subscribers.lock().unwrap_or_else(|poisoned| {
+            error!("Event subscriber lock was poisoned; continuing");
+            poisoned.into_inner()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datastore::KeyType;
+    use maplit::hashset;
+
+    fn commit_event() -> ChangeEvent {
+        ChangeEvent {
+            kind: ChangeKind::Commit,
+            transaction: Some("default".to_string()),
+            keys: Some(hashset!(Key::new(KeyType::Data, "settings.motd").unwrap())),
+            apply_id: None,
+        }
+    }
+
+    #[test]
+    fn publish_to_subscribers() {
+        let events = EventBroadcaster::default();
+        let mut first = events.subscribe();
+        let mut second = events.subscribe();
+
+        events.publish(commit_event());
+        assert_eq!(first.try_next().unwrap(), Some(commit_event()));
+        assert_eq!(second.try_next().unwrap(), Some(commit_event()));
+    }
+
+    #[test]
+    fn drop_disconnected() {
+        let events = EventBroadcaster::default();
+        let subscriber = events.subscribe();
+        drop(subscriber);
+        events.publish(commit_event());
+        assert!(events.lock_subscribers().is_empty());
+    }
+
+    #[test]
+    fn drop_slow() {
+        let events = EventBroadcaster::default();
+        let mut subscriber = events.subscribe();
+        for _ in 0..MAX_QUEUED_EVENTS + 2 {
+            events.publish(commit_event());
+        }
+        assert!(events.lock_subscribers().is_empty());
+
+        // The subscriber still gets the queued events, then the stream ends.
+        let mut received = 0;
+        while subscriber.try_next().unwrap().is_some() {
+            received += 1;
+        }
+        assert!(received >= MAX_QUEUED_EVENTS);
+    }
+
+    #[test]
+    fn sse_format() {
+        let event = ChangeEvent {
+            kind: ChangeKind::Apply,
+            transaction: None,
+            keys: None,
+            apply_id: Some("1".to_string()),
+        };
+        assert_eq!(
+            event.to_sse().unwrap(),
+            Bytes::from(concat!(
+                "event: apply\n",
+                "data: {\"kind\":\"apply\",\"transaction\":null,\"keys\":null,\"apply_id\":\"1\"}\n\n"
+            ))
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! The history module keeps a persistent log of changes to live settings, so we can tell when a
+//! setting changed, in which transaction, and what its value was before.
+//!
+//! The log is stored as JSON lines, one entry per commit or rollback, in a file under the data
+//! store directory.  Only the most recent MAX_HISTORY_ENTRIES entries are kept.  The migrator
+//! copies the file to the new data store on updates, so entries from before an update keep the
+//! key names and values of the version they were made in.
+
+use chrono::{DateTime, Utc};
+use datastore::filesystem::HISTORY_FILE;
+use datastore::{deserialize_scalar, Committed, DataStore, Key, Value};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::server::error::{self, Result};
+
+/// The number of history entries we keep; older entries are removed as new ones are added.
+pub(crate) const MAX_HISTORY_ENTRIES: usize = 500;
+
+/// HistoryEntry describes one change to live settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HistoryEntry {
+    /// The name of the committed transaction, or for a rollback, the name of the transaction
+    /// whose commit was undone.
+    pub(crate) transaction: String,
+    /// Whether the change was a rollback of an earlier commit.
+    #[serde(default)]
+    pub(crate) rollback: bool,
+    /// When the change was made.
+    pub(crate) timestamp: DateTime<Utc>,
+    /// Mapping of changed data key names to their old and new values.
+    pub(crate) changes: BTreeMap<String, ValueChange>,
+}
+
+/// ValueChange holds the live value of a key before and after a change.  A value of None means
+/// the key wasn't set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ValueChange {
+    pub(crate) old: Option<Value>,
+    pub(crate) new: Option<Value>,
+}
+
+/// CommitHistory is a handle to the history log of a data store.  Callers are expected to hold
+/// the data store lock while using it, so writes don't race.
+#[derive(Debug)]
+pub(crate) struct CommitHistory {
+    path: PathBuf,
+}
+
+impl CommitHistory {
+    /// Creates a handle to the history log of the data store at the given path.
+    pub(crate) fn new<P: AsRef<Path>>(datastore_path: P) -> Self {
+        Self {
+            path: datastore_path.as_ref().join(HISTORY_FILE),
+        }
+    }
+
+    /// Returns the logged history entries, oldest first.  If nothing has been logged, returns an
+    /// empty list.
+    pub(crate) fn entries(&self) -> Result<Vec<HistoryEntry>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(error::HistoryIo { path: &self.path }),
+        };
+
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).context(error::HistoryFormat { path: &self.path })
+            })
+            .collect()
+    }
+
+    /// Adds the given entry to the log, removing the oldest entries if there are more than
+    /// MAX_HISTORY_ENTRIES.
+    pub(crate) fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        let mut entries = self.entries()?;
+        entries.push(entry.clone());
+        let excess = entries.len().saturating_sub(MAX_HISTORY_ENTRIES);
+
+        let mut data = String::new();
+        for entry in entries.iter().skip(excess) {
+            let line = serde_json::to_string(entry).context(error::HistorySerialization)?;
+            data.push_str(&line);
+            data.push('\n');
+        }
+
+        // Write to a temporary file and rename it into place, so a failed write doesn't lose the
+        // existing history.
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, data).context(error::HistoryIo { path: &tmp_path })?;
+        fs::rename(&tmp_path, &self.path).context(error::HistoryIo { path: &self.path })
+    }
+}
+
+/// Reads the current live values of the given keys, for building a HistoryEntry.  Keys that
+/// aren't set have a value of None.
+pub(crate) fn live_values<D: DataStore>(
+    datastore: &D,
+    keys: &HashSet<Key>,
+) -> Result<BTreeMap<String, Option<Value>>> {
+    let mut values = BTreeMap::new();
+    for key in keys {
+        let raw = datastore
+            .get_key(key, &Committed::Live)
+            .context(error::DataStore { op: "get_key" })?;
+        // Values should always be valid; if not, show the raw string so there's still a record.
+        let value = raw.map(|raw| {
+            deserialize_scalar::<_, datastore::ScalarError>(&raw).unwrap_or(Value::String(raw))
+        });
+        values.insert(key.name().clone(), value);
+    }
+    Ok(values)
+}
+
+/// Builds a HistoryEntry from the live values of keys before and after a change.
+pub(crate) fn build_entry<S: Into<String>>(
+    transaction: S,
+    rollback: bool,
+    mut old: BTreeMap<String, Option<Value>>,
+    new: BTreeMap<String, Option<Value>>,
+) -> HistoryEntry {
+    let changes = new
+        .into_iter()
+        .map(|(name, new)| {
+            let old = old.remove(&name).flatten();
+            (name, ValueChange { old, new })
+        })
+        .collect();
+    HistoryEntry {
+        transaction: transaction.into(),
+        rollback,
+        timestamp: Utc::now(),
+        changes,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::btreemap;
+
+    fn entry(transaction: &str) -> HistoryEntry {
+        build_entry(
+            transaction,
+            false,
+            btreemap! {"settings.motd".to_string() => None},
+            btreemap! {"settings.motd".to_string() => Some(Value::from(transaction))},
+        )
+    }
+
+    #[test]
+    fn empty() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        assert!(history.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn append() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        let first = entry("first");
+        let second = entry("second");
+        history.append(&first).unwrap();
+        history.append(&second).unwrap();
+        assert_eq!(history.entries().unwrap(), vec![first, second]);
+    }
+
+    #[test]
+    fn bounded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = CommitHistory::new(tmp.path());
+        for i in 0..MAX_HISTORY_ENTRIES + 2 {
+            history.append(&entry(&i.to_string())).unwrap();
+        }
+        let entries = history.entries().unwrap();
+        assert_eq!(entries.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(entries[0].transaction, "2");
+    }
+}
@@ -2,21 +2,32 @@
 //! server::controller module.
 
 mod controller;
+mod dry_run;
 mod error;
+mod events;
+mod history;
 pub use error::Error;
 
 use actix_web::{
-    body::Body, error::ResponseError, web, App, BaseHttpResponse, FromRequest, HttpRequest,
-    HttpResponse, HttpServer, Responder,
+    body::Body, error::ResponseError, http::header, web, App, BaseHttpResponse, FromRequest,
+    HttpRequest, HttpResponse, HttpServer, Responder,
 };
 use bottlerocket_release::BottlerocketRelease;
-use datastore::{Committed, FilesystemDataStore, Key, Value};
+use datastore::{
+    CachedDataStore, Committed, FilesystemDataStore, Key, KeyType, Value, PROVENANCE_METADATA_KEY,
+    SETTING_SOURCE_HEADER,
+};
+use dry_run::DryRun;
 use error::Result;
+use events::{ChangeEvent, ChangeKind, EventBroadcaster};
 use fs2::FileExt;
+use futures::StreamExt;
+use history::{CommitHistory, HistoryEntry};
 use http::StatusCode;
 use log::info;
-use model::{ConfigurationFiles, Model, Services, Settings};
+use model::{schema, ConfigurationFiles, Model, Services, Settings};
 use nix::unistd::{chown, Gid};
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::env;
@@ -25,9 +36,20 @@ use std::os::unix::fs::PermissionsExt;
 use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
 use std::process::Command;
-use std::sync;
+use std::time::Duration;
+use thar_be_settings::status::ApplyStatus;
 use thar_be_updates::status::{UpdateStatus, UPDATE_LOCKFILE};
 
+/// The response header giving the ID under which the config applier records its results.
+const APPLY_ID_HEADER: &str = "Apply-Id";
+
+/// The source recorded for settings changes that don't give a Setting-Source header.
+const DEFAULT_SETTING_SOURCE: &str = "api";
+
+/// The OpenAPI document for the current variant, generated by build.rs from openapi.yaml and the
+/// schemas of the model.
+const OPENAPI_DOCUMENT: &str = include_str!(concat!(env!("OUT_DIR"), "/openapi.json"));
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // sd_notify helper
@@ -53,19 +75,30 @@ fn notify_unix_socket_ready() -> Result<()> {
 
 /// This is the primary interface of the module.  It defines the server and application that actix
 /// spawns for requests.  It creates a shared datastore handle that can be used by handler methods
-/// to interface with the controller.
+/// to interface with the controller.  Requests that wait longer than `lock_timeout` for access to
+/// the datastore fail.
 pub async fn serve<P1, P2>(
     socket_path: P1,
     datastore_path: P2,
     threads: usize,
     socket_gid: Option<Gid>,
+    lock_timeout: Duration,
 ) -> Result<()>
 where
     P1: AsRef<Path>,
     P2: AsRef<Path>,
 {
+    // Finish any commit that was interrupted, for example by a power loss, before serving requests.
+    let mut ds = FilesystemDataStore::new(&datastore_path);
+    ds.recover().context(error::DataStoreRecovery)?;
+
+    // Requests are served from an in-memory copy of live data, since we're the only writer.
     let shared_datastore = web::Data::new(SharedDataStore {
-        ds: sync::RwLock::new(FilesystemDataStore::new(datastore_path)),
+        ds: RwLock::new(CachedDataStore::new(ds)),
+        lock_timeout,
+        history: CommitHistory::new(&datastore_path),
+        events: EventBroadcaster::default(),
+        defaults: storewolf::default_settings().context(error::DefaultSettings)?,
     });
 
     let http_server = HttpServer::new(move || {
@@ -83,10 +116,15 @@ where
             .app_data(shared_datastore.clone())
             // Retrieve the full API model; not all data is writable, so we only support GET.
             .route("/", web::get().to(get_model))
+            // Stream changes to live settings as they happen.
+            .route("/events", web::get().to(get_events))
             .service(
                 web::scope("/settings")
                     .route("", web::get().to(get_settings))
-                    .route("", web::patch().to(patch_settings)),
+                    .route("", web::patch().to(patch_settings))
+                    .route("", web::delete().to(delete_settings))
+                    .route("/history", web::get().to(get_settings_history))
+                    .route("/provenance", web::get().to(get_settings_provenance)),
             )
             .service(
                 // Transaction support
@@ -99,9 +137,19 @@ where
                     .route(
                         "/commit_and_apply",
                         web::post().to(commit_transaction_and_apply),
+                    )
+                    .route("/rollback", web::post().to(rollback_transaction))
+                    .route(
+                        "/rollback_and_apply",
+                        web::post().to(rollback_transaction_and_apply),
                     ),
             )
             .service(web::scope("/os").route("", web::get().to(get_os_info)))
+            .service(
+                web::scope("/schema")
+                    .route("", web::get().to(get_schema))
+                    .route("/openapi", web::get().to(get_openapi)),
+            )
             .service(
                 web::scope("/metadata")
                     .route("/affected-services", web::get().to(get_affected_services))
@@ -119,7 +167,8 @@ where
                     .route("/refresh-updates", web::post().to(refresh_updates))
                     .route("/prepare-update", web::post().to(prepare_update))
                     .route("/activate-update", web::post().to(activate_update))
-                    .route("/deactivate-update", web::post().to(deactivate_update)),
+                    .route("/deactivate-update", web::post().to(deactivate_update))
+                    .route("/apply-status", web::get().to(get_apply_status)),
             )
             .service(web::scope("/updates").route("/status", web::get().to(get_update_status)))
     })
@@ -149,77 +198,246 @@ where
 
 // Handler methods called by the router
 
-/// Returns all data in the API model.
-async fn get_model(data: web::Data<SharedDataStore>) -> Result<ModelResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-
-    let settings = Some(controller::get_settings(&*datastore, &Committed::Live)?);
-    let services = Some(controller::get_services(&*datastore)?);
-    let configuration_files = Some(controller::get_configuration_files(&*datastore)?);
-    let os = Some(controller::get_os_info()?);
-
-    let model = Model {
-        settings,
-        services,
-        configuration_files,
-        os,
-    };
-    Ok(ModelResponse(model))
+/// Returns all data in the API model.  Sensitive settings are left out unless 'secrets' is true
+/// in query parameters.
+async fn get_model(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<ModelResponse> {
+    blocking(&data, move |data| {
+        let secrets = show_secrets(&query)?;
+        let datastore = data.read()?;
+
+        let mut settings = controller::get_settings(&*datastore, &Committed::Live)?;
+        if !secrets {
+            let sensitive = controller::get_sensitive_keys(&*datastore)?;
+            settings = controller::redact_settings(settings, &sensitive)?;
+        }
+        let settings = Some(settings);
+        let services = Some(controller::get_services(&*datastore)?);
+        let configuration_files = Some(controller::get_configuration_files(&*datastore)?);
+        let os = Some(controller::get_os_info()?);
+
+        let model = Model {
+            settings,
+            services,
+            configuration_files,
+            os,
+        };
+        Ok(ModelResponse(model))
+    })
+    .await
 }
 
 // actix-web doesn't support Query for enums, so we use a HashMap and check for the expected keys
 // ourselves.
 /// Return the live settings from the data store; if 'keys' or 'prefix' are specified in query
-/// parameters, return the subset of matching settings.
+/// parameters, return the subset of matching settings.  Sensitive settings are left out unless
+/// 'secrets' is true.  The revision of live data, not of a transaction, is returned in the ETag
+/// header.
 async fn get_settings(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<SettingsResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+) -> Result<RevisionedSettingsResponse> {
+    blocking(&data, move |data| {
+        let secrets = show_secrets(&query)?;
+        let datastore = data.read()?;
+
+        let mut settings = if let Some(keys_str) = query.get("keys") {
+            let keys = comma_separated("keys", keys_str)?;
+            controller::get_settings_keys(&*datastore, &keys, &Committed::Live)
+        } else if let Some(prefix_str) = query.get("prefix") {
+            if prefix_str.is_empty() {
+                return error::EmptyInput { input: "prefix" }.fail();
+            }
+            // Note: the prefix should not include "settings."
+            controller::get_settings_prefix(&*datastore, prefix_str, &Committed::Live)
+        } else {
+            controller::get_settings(&*datastore, &Committed::Live)
+        }?;
+        if !secrets {
+            let sensitive = controller::get_sensitive_keys(&*datastore)?;
+            settings = controller::redact_settings(settings, &sensitive)?;
+        }
+        let revision = controller::get_revision(&*datastore, &Committed::Live)?;
 
-    let settings = if let Some(keys_str) = query.get("keys") {
-        let keys = comma_separated("keys", keys_str)?;
-        controller::get_settings_keys(&*datastore, &keys, &Committed::Live)
-    } else if let Some(prefix_str) = query.get("prefix") {
-        if prefix_str.is_empty() {
-            return error::EmptyInput { input: "prefix" }.fail();
+        Ok(RevisionedSettingsResponse { settings, revision })
+    })
+    .await
+}
+
+/// Return the history of changes to live settings, oldest first; if 'prefix' is specified in
+/// query parameters, only return changes to matching settings.  The values of sensitive settings
+/// are replaced with a placeholder unless 'secrets' is true.
+async fn get_settings_history(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HistoryResponse> {
+    blocking(&data, move |data| {
+        let secrets = show_secrets(&query)?;
+        // Hold the data store lock so we don't read history while it's being written.
+        let datastore = data.read()?;
+
+        let prefix = match query.get("prefix") {
+            Some(prefix_str) if prefix_str.is_empty() => {
+                return error::EmptyInput { input: "prefix" }.fail();
+            }
+            // Note: the prefix should not include "settings."
+            Some(prefix_str) => Some(prefix_str.as_str()),
+            None => None,
+        };
+        let mut entries = controller::get_settings_history(&data.history, prefix)?;
+        if !secrets {
+            let sensitive = controller::get_sensitive_keys(&*datastore)?;
+            entries = controller::redact_history(entries, &sensitive)?;
         }
-        // Note: the prefix should not include "settings."
-        controller::get_settings_prefix(&*datastore, prefix_str, &Committed::Live)
-    } else {
-        controller::get_settings(&*datastore, &Committed::Live)
-    }?;
 
-    Ok(SettingsResponse(settings))
+        Ok(HistoryResponse(entries))
+    })
+    .await
+}
+
+/// Return the provenance of live settings, meaning the source and transaction of the most recent
+/// write of each; if 'keys' is specified in query parameters, only return the provenance of those
+/// settings.
+async fn get_settings_provenance(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<MetadataResponse> {
+    blocking(&data, move |data| {
+        let datastore = data.read()?;
+        let resp = if let Some(keys_str) = query.get("keys") {
+            let data_keys = comma_separated("keys", keys_str)?;
+            controller::get_metadata_for_data_keys(&*datastore, PROVENANCE_METADATA_KEY, &data_keys)
+        } else {
+            controller::get_metadata_for_all_data_keys(&*datastore, PROVENANCE_METADATA_KEY)
+        }?;
+        Ok(MetadataResponse(resp))
+    })
+    .await
+}
+
+/// Stream events describing each commit, rollback, and apply of settings, in server-sent event
+/// format.  The response doesn't end until the server stops or the client falls too far behind.
+async fn get_events(data: web::Data<SharedDataStore>) -> HttpResponse {
+    let events = data
+        .events
+        .subscribe()
+        .map(|event| event.to_sse().context(error::ResponseSerialization));
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(events)
 }
 
-/// Apply the requested settings to the pending data store
+/// Apply the requested settings to the pending data store.  If 'revision' is specified in query
+/// parameters, the transaction must be at that revision.  The source of the change is taken from
+/// the Setting-Source header, if given.  The new revision of the transaction is returned in the
+/// ETag header.
+///
+/// If 'dry_run' is true, nothing is changed; instead, returns what committing and applying the
+/// transaction with these settings would do.
 async fn patch_settings(
+    req: HttpRequest,
     settings: web::Json<Settings>,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<HttpResponse> {
-    let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-    controller::set_settings(&mut *datastore, &settings, transaction)?;
-    Ok(HttpResponse::NoContent().finish()) // 204
+    if is_dry_run(&query)? {
+        let dry_run = blocking(&data, move |data| preview(data, &query, Some(&*settings))).await?;
+        return Ok(DryRunResponse(dry_run).respond_to(&req));
+    }
+
+    let source = setting_source(&req)?.to_string();
+    let revision = blocking(&data, move |data| {
+        let transaction = transaction_name(&query);
+        let expected = expected_revision(&query)?;
+        let mut datastore = data.write()?;
+        controller::check_revision(&*datastore, transaction, expected)?;
+        controller::set_settings(&mut *datastore, &settings, transaction, &source)?;
+
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        controller::get_revision(&*datastore, &pending)
+    })
+    .await?;
+    Ok(HttpResponse::NoContent()
+        .insert_header((header::ETAG, etag(revision)))
+        .finish()) // 204
+}
+
+/// Stage the settings given in the 'keys' query parameter to be reset in the given transaction,
+/// or the "default" transaction if unspecified.  Keys can name single settings or groups of
+/// settings.  When the transaction is committed, settings with a default value are set back to it,
+/// and other settings are removed.  The source of the change is taken from the Setting-Source
+/// header, if given.  Returns the staged keys, and the new revision of the transaction in the ETag
+/// header.
+async fn delete_settings(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<impl Responder> {
+    let source = setting_source(&req)?.to_string();
+    let (staged, revision) = blocking(&data, move |data| {
+        let keys_str = query
+            .get("keys")
+            .context(error::MissingInput { input: "keys" })?;
+        let keys = comma_separated("keys", keys_str)?;
+
+        let transaction = transaction_name(&query);
+        let expected = expected_revision(&query)?;
+        let mut datastore = data.write()?;
+        controller::check_revision(&*datastore, transaction, expected)?;
+        let staged = controller::unset_settings(
+            &mut *datastore,
+            &keys,
+            &data.defaults,
+            transaction,
+            &source,
+        )?;
+
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let revision = controller::get_revision(&*datastore, &pending)?;
+        Ok((staged, revision))
+    })
+    .await?;
+    Ok(ChangedKeysResponse(staged).with_header((header::ETAG, etag(revision))))
 }
 
 async fn get_transaction_list(data: web::Data<SharedDataStore>) -> Result<TransactionListResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-    let data = controller::list_transactions(&*datastore)?;
-    Ok(TransactionListResponse(data))
+    blocking(&data, |data| {
+        let datastore = data.read()?;
+        let data = controller::list_transactions(&*datastore)?;
+        Ok(TransactionListResponse(data))
+    })
+    .await
 }
 
 /// Get any pending settings in the given transaction, or the "default" transaction if unspecified.
+/// Sensitive settings are left out unless 'secrets' is true in query parameters.  The revision of
+/// the transaction is returned in the ETag header.
 async fn get_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<SettingsResponse> {
-    let transaction = transaction_name(&query);
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-    let data = controller::get_transaction(&*datastore, transaction)?;
-    Ok(SettingsResponse(data))
+) -> Result<RevisionedSettingsResponse> {
+    blocking(&data, move |data| {
+        let transaction = transaction_name(&query);
+        let secrets = show_secrets(&query)?;
+        let datastore = data.read()?;
+        let mut settings = controller::get_transaction(&*datastore, transaction)?;
+        if !secrets {
+            let sensitive = controller::get_sensitive_keys(&*datastore)?;
+            settings = controller::redact_settings(settings, &sensitive)?;
+        }
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let revision = controller::get_revision(&*datastore, &pending)?;
+        Ok(RevisionedSettingsResponse { settings, revision })
+    })
+    .await
 }
 
 /// Delete the given transaction, or the "default" transaction if unspecified.
@@ -227,65 +445,111 @@ async fn delete_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ChangedKeysResponse> {
-    let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-    let deleted = controller::delete_transaction(&mut *datastore, transaction)?;
-    Ok(ChangedKeysResponse(deleted))
+    blocking(&data, move |data| {
+        let transaction = transaction_name(&query);
+        let mut datastore = data.write()?;
+        let deleted = controller::delete_transaction(&mut *datastore, transaction)?;
+        Ok(ChangedKeysResponse(deleted))
+    })
+    .await
 }
 
 /// Save settings changes from the given transaction, or the "default" transaction if unspecified,
-/// to the live data store.  Returns the list of changed keys.
+/// to the live data store.  If 'revision' is specified in query parameters, the transaction must
+/// be at that revision.  Returns the list of changed keys.
 async fn commit_transaction(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ChangedKeysResponse> {
-    let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
-
-    if changes.is_empty() {
-        return error::CommitWithNoPending.fail();
-    }
-
+    let changes = blocking(&data, move |data| commit(data, &query)).await?;
     Ok(ChangedKeysResponse(changes))
 }
 
 /// Starts settings appliers for any changes that have been committed to the data store.  This
-/// updates config files, runs restart commands, etc.
-async fn apply_changes(query: web::Query<HashMap<String, String>>) -> Result<HttpResponse> {
-    if let Some(keys_str) = query.get("keys") {
+/// updates config files, runs restart commands, etc.  The ID under which the applier records its
+/// results is returned in the Apply-Id header.
+async fn apply_changes(
+    query: web::Query<HashMap<String, String>>,
+    data: web::Data<SharedDataStore>,
+) -> Result<HttpResponse> {
+    let (applied, apply_id) = if let Some(keys_str) = query.get("keys") {
         let keys = comma_separated("keys", keys_str)?;
-        controller::apply_changes(Some(&keys))?;
+        let applied = keys
+            .iter()
+            .map(|name| {
+                Key::new(KeyType::Data, name).context(error::NewKey {
+                    key_type: "data",
+                    name: *name,
+                })
+            })
+            .collect::<Result<HashSet<_>>>()?;
+        let apply_id = controller::apply_changes(Some(&keys))?;
+        (Some(applied), apply_id)
     } else {
-        controller::apply_changes(None as Option<&HashSet<&str>>)?;
-    }
+        let apply_id = controller::apply_changes(None as Option<&HashSet<&str>>)?;
+        (None, apply_id)
+    };
+    data.events.publish(ChangeEvent {
+        kind: ChangeKind::Apply,
+        transaction: None,
+        keys: applied,
+        apply_id: Some(apply_id.clone()),
+    });
 
-    Ok(HttpResponse::NoContent().json(()))
+    Ok(HttpResponse::NoContent()
+        .insert_header((APPLY_ID_HEADER, apply_id))
+        .json(()))
 }
 
 /// Usually you want to apply settings changes you've committed, so this is a convenience method to
 /// perform both a commit and an apply.  Commits the given transaction, or the "default"
-/// transaction if unspecified.
+/// transaction if unspecified.  The apply ID is returned in the Apply-Id header.
+///
+/// If 'dry_run' is true, nothing is committed or applied; instead, returns the configuration file
+/// changes and restart commands that committing and applying the transaction would cause.
 async fn commit_transaction_and_apply(
+    req: HttpRequest,
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
-) -> Result<ChangedKeysResponse> {
-    let transaction = transaction_name(&query);
-    let mut datastore = data.ds.write().ok().context(error::DataStoreLock)?;
-
-    let changes = controller::commit_transaction(&mut *datastore, transaction)?;
-
-    if changes.is_empty() {
-        return error::CommitWithNoPending.fail();
+) -> Result<HttpResponse> {
+    if is_dry_run(&query)? {
+        let dry_run = blocking(&data, move |data| preview(data, &query, None)).await?;
+        return Ok(DryRunResponse(dry_run).respond_to(&req));
     }
 
-    let key_names = changes.iter().map(|k| k.name()).collect();
-    controller::apply_changes(Some(&key_names))?;
+    let (changes, apply_id) = blocking(&data, move |data| {
+        let changes = commit(data, &query)?;
+        let apply_id = apply(data, &changes)?;
+        Ok((changes, apply_id))
+    })
+    .await?;
+    Ok(ChangedKeysResponse(changes)
+        .with_header((APPLY_ID_HEADER, apply_id))
+        .respond_to(&req))
+}
 
+/// Undo the most recent commit, restoring the previous live values of the keys it changed.
+/// Repeated calls undo earlier commits, up to the number of commits the data store keeps.  Returns
+/// the list of changed keys.
+async fn rollback_transaction(data: web::Data<SharedDataStore>) -> Result<ChangedKeysResponse> {
+    let changes = blocking(&data, rollback).await?;
     Ok(ChangedKeysResponse(changes))
 }
 
+/// Usually you want to apply settings changes you've rolled back, so this is a convenience method
+/// to perform both a rollback and an apply.  The apply ID is returned in the Apply-Id header.
+async fn rollback_transaction_and_apply(
+    data: web::Data<SharedDataStore>,
+) -> Result<impl Responder> {
+    let (changes, apply_id) = blocking(&data, |data| {
+        let changes = rollback(data)?;
+        let apply_id = apply(data, &changes)?;
+        Ok((changes, apply_id))
+    })
+    .await?;
+    Ok(ChangedKeysResponse(changes).with_header((APPLY_ID_HEADER, apply_id)))
+}
+
 async fn get_os_info() -> Result<BottlerocketReleaseResponse> {
     Ok(BottlerocketReleaseResponse(controller::get_os_info()?))
 }
@@ -295,23 +559,32 @@ async fn get_affected_services(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<MetadataResponse> {
-    if let Some(keys_str) = query.get("keys") {
-        let data_keys = comma_separated("keys", keys_str)?;
-        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-        let resp =
-            controller::get_metadata_for_data_keys(&*datastore, "affected-services", &data_keys)?;
-
-        Ok(MetadataResponse(resp))
-    } else {
-        return error::MissingInput { input: "keys" }.fail();
-    }
+    blocking(&data, move |data| {
+        if let Some(keys_str) = query.get("keys") {
+            let data_keys = comma_separated("keys", keys_str)?;
+            let datastore = data.read()?;
+            let resp = controller::get_metadata_for_data_keys(
+                &*datastore,
+                "affected-services",
+                &data_keys,
+            )?;
+
+            Ok(MetadataResponse(resp))
+        } else {
+            return error::MissingInput { input: "keys" }.fail();
+        }
+    })
+    .await
 }
 
 /// Get all settings that have setting-generator metadata
 async fn get_setting_generators(data: web::Data<SharedDataStore>) -> Result<MetadataResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-    let resp = controller::get_metadata_for_all_data_keys(&*datastore, "setting-generator")?;
-    Ok(MetadataResponse(resp))
+    blocking(&data, |data| {
+        let datastore = data.read()?;
+        let resp = controller::get_metadata_for_all_data_keys(&*datastore, "setting-generator")?;
+        Ok(MetadataResponse(resp))
+    })
+    .await
 }
 
 /// Get the template metadata for a list of data keys
@@ -319,15 +592,18 @@ async fn get_templates(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<MetadataResponse> {
-    if let Some(keys_str) = query.get("keys") {
-        let data_keys = comma_separated("keys", keys_str)?;
-        let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
-        let resp = controller::get_metadata_for_data_keys(&*datastore, "template", &data_keys)?;
-
-        Ok(MetadataResponse(resp))
-    } else {
-        return error::MissingInput { input: "keys" }.fail();
-    }
+    blocking(&data, move |data| {
+        if let Some(keys_str) = query.get("keys") {
+            let data_keys = comma_separated("keys", keys_str)?;
+            let datastore = data.read()?;
+            let resp = controller::get_metadata_for_data_keys(&*datastore, "template", &data_keys)?;
+
+            Ok(MetadataResponse(resp))
+        } else {
+            return error::MissingInput { input: "keys" }.fail();
+        }
+    })
+    .await
 }
 
 /// Get all services, or if 'names' is specified, services with those names
@@ -335,16 +611,19 @@ async fn get_services(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ServicesResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    blocking(&data, move |data| {
+        let datastore = data.read()?;
 
-    let resp = if let Some(names_str) = query.get("names") {
-        let names = comma_separated("names", names_str)?;
-        controller::get_services_names(&*datastore, &names, &Committed::Live)
-    } else {
-        controller::get_services(&*datastore)
-    }?;
+        let resp = if let Some(names_str) = query.get("names") {
+            let names = comma_separated("names", names_str)?;
+            controller::get_services_names(&*datastore, &names, &Committed::Live)
+        } else {
+            controller::get_services(&*datastore)
+        }?;
 
-    Ok(ServicesResponse(resp))
+        Ok(ServicesResponse(resp))
+    })
+    .await
 }
 
 /// Get all configuration files, or if 'names' is specified, configuration files with those names
@@ -352,16 +631,32 @@ async fn get_configuration_files(
     query: web::Query<HashMap<String, String>>,
     data: web::Data<SharedDataStore>,
 ) -> Result<ConfigurationFilesResponse> {
-    let datastore = data.ds.read().ok().context(error::DataStoreLock)?;
+    blocking(&data, move |data| {
+        let datastore = data.read()?;
 
-    let resp = if let Some(names_str) = query.get("names") {
-        let names = comma_separated("names", names_str)?;
-        controller::get_configuration_files_names(&*datastore, &names, &Committed::Live)
-    } else {
-        controller::get_configuration_files(&*datastore)
-    }?;
+        let resp = if let Some(names_str) = query.get("names") {
+            let names = comma_separated("names", names_str)?;
+            controller::get_configuration_files_names(&*datastore, &names, &Committed::Live)
+        } else {
+            controller::get_configuration_files(&*datastore)
+        }?;
+
+        Ok(ConfigurationFilesResponse(resp))
+    })
+    .await
+}
 
-    Ok(ConfigurationFilesResponse(resp))
+/// Get the results of recent settings applies from 'thar-be-settings', oldest first; if 'id' is
+/// specified in query parameters, only return the apply with that ID.
+async fn get_apply_status(
+    query: web::Query<HashMap<String, String>>,
+) -> Result<ApplyStatusResponse> {
+    let mut status = controller::get_apply_status()?;
+    if let Some(id) = query.get("id") {
+        status.runs.retain(|run| &run.id == id);
+        ensure!(!status.runs.is_empty(), error::ApplyNotFound { id });
+    }
+    Ok(ApplyStatusResponse(status))
 }
 
 /// Get the update status from 'thar-be-updates'
@@ -421,10 +716,111 @@ async fn reboot() -> Result<HttpResponse> {
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// Return the JSON Schema of the model, describing the input accepted for each setting.
+async fn get_schema() -> SchemaResponse {
+    SchemaResponse(schema::schema_for::<Model>())
+}
+
+/// Return the OpenAPI document for the current variant.
+async fn get_openapi() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(OPENAPI_DOCUMENT)
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 // Helpers for handler methods called by the router
 
+/// Runs the given function on actix's pool of blocking threads, so that waiting for the data store
+/// lock and reading and writing files doesn't hold up the worker serving other requests.
+async fn blocking<F, T>(data: &web::Data<SharedDataStore>, f: F) -> Result<T>
+where
+    F: FnOnce(&SharedDataStore) -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let data = data.clone();
+    web::block(move || f(&data))
+        .await
+        .context(error::Blocking)?
+}
+
+/// Commits the transaction named in the query, checking the expected revision if given, and
+/// publishes the commit.  Returns the changed keys.
+fn commit(
+    data: &SharedDataStore,
+    query: &web::Query<HashMap<String, String>>,
+) -> Result<HashSet<Key>> {
+    let transaction = transaction_name(query);
+    let expected = expected_revision(query)?;
+    let mut datastore = data.write()?;
+    controller::check_revision(&*datastore, transaction, expected)?;
+
+    let changes = controller::commit_transaction(&mut *datastore, transaction, &data.history)?;
+
+    if changes.is_empty() {
+        return error::CommitWithNoPending.fail();
+    }
+    data.events.publish(ChangeEvent {
+        kind: ChangeKind::Commit,
+        transaction: Some(transaction.to_string()),
+        keys: Some(changes.clone()),
+        apply_id: None,
+    });
+
+    Ok(changes)
+}
+
+/// Rolls back the most recent commit and publishes the rollback.  Returns the changed keys.
+fn rollback(data: &SharedDataStore) -> Result<HashSet<Key>> {
+    let mut datastore = data.write()?;
+
+    let changes = controller::rollback_transaction(&mut *datastore, &data.history)?
+        .context(error::RollbackWithNoHistory)?;
+
+    data.events.publish(ChangeEvent {
+        kind: ChangeKind::Rollback,
+        transaction: None,
+        keys: Some(changes.clone()),
+        apply_id: None,
+    });
+
+    Ok(changes)
+}
+
+/// Describes what committing and applying the transaction named in the query would do, checking
+/// the expected revision if given.  If settings are given, they're treated as part of the
+/// transaction.  Sensitive values are redacted from rendered files unless 'secrets' is true.
+fn preview(
+    data: &SharedDataStore,
+    query: &web::Query<HashMap<String, String>>,
+    settings: Option<&Settings>,
+) -> Result<DryRun> {
+    let transaction = transaction_name(query);
+    let expected = expected_revision(query)?;
+    let secrets = show_secrets(query)?;
+    let os = controller::get_os_info()?;
+    let datastore = data.read()?;
+    controller::check_revision(&*datastore, transaction, expected)?;
+
+    controller::dry_run_transaction(&*datastore, transaction, settings, Some(os), secrets)
+}
+
+/// Starts settings appliers for the given changed keys and publishes the apply.  Returns the apply
+/// ID.
+fn apply(data: &SharedDataStore, changes: &HashSet<Key>) -> Result<String> {
+    let key_names = changes.iter().map(|k| k.name()).collect();
+    let apply_id = controller::apply_changes(Some(&key_names))?;
+    data.events.publish(ChangeEvent {
+        kind: ChangeKind::Apply,
+        transaction: None,
+        keys: Some(changes.clone()),
+        apply_id: Some(apply_id.clone()),
+    });
+
+    Ok(apply_id)
+}
+
 fn comma_separated<'a>(key_name: &'static str, input: &'a str) -> Result<HashSet<&'a str>> {
     if input.is_empty() {
         return error::EmptyInput { input: key_name }.fail();
@@ -440,6 +836,65 @@ fn transaction_name(query: &web::Query<HashMap<String, String>>) -> &str {
     }
 }
 
+/// Returns the revision the caller expects the transaction to be at, if they gave one.
+fn expected_revision(query: &web::Query<HashMap<String, String>>) -> Result<Option<u64>> {
+    query
+        .get("revision")
+        .map(|revision_str| {
+            revision_str.parse().context(error::InvalidRevision {
+                given: revision_str,
+            })
+        })
+        .transpose()
+}
+
+/// Returns whether the caller asked for the values of sensitive settings by giving 'secrets' as
+/// true.
+fn show_secrets(query: &web::Query<HashMap<String, String>>) -> Result<bool> {
+    flag(query, "secrets")
+}
+
+/// Returns whether the caller asked to see what a change would do, rather than make it, by giving
+/// 'dry_run' as true.
+fn is_dry_run(query: &web::Query<HashMap<String, String>>) -> Result<bool> {
+    flag(query, "dry_run")
+}
+
+/// Returns the value of a boolean query parameter, which is false if it's not given.
+fn flag(query: &web::Query<HashMap<String, String>>, name: &'static str) -> Result<bool> {
+    match query.get(name).map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(given) => error::InvalidFlag { input: name, given }.fail(),
+    }
+}
+
+/// Returns the source of a settings change given in the Setting-Source header, or "api" if the
+/// header wasn't given.
+fn setting_source(req: &HttpRequest) -> Result<&str> {
+    let value = match req.headers().get(SETTING_SOURCE_HEADER) {
+        Some(value) => value,
+        None => return Ok(DEFAULT_SETTING_SOURCE),
+    };
+    let source = value.to_str().ok().context(error::InvalidSettingSource {
+        header: SETTING_SOURCE_HEADER,
+        reason: "not printable ASCII",
+    })?;
+    ensure!(
+        !source.is_empty(),
+        error::InvalidSettingSource {
+            header: SETTING_SOURCE_HEADER,
+            reason: "empty",
+        }
+    );
+    Ok(source)
+}
+
+/// Formats a revision as an ETag header value, which is a quoted string.
+fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
 // Can also override `render_response` if we want to change headers, content type, etc.
 impl ResponseError for error::Error {
     /// Maps our error types to the HTTP error code they should return.
@@ -449,7 +904,12 @@ impl ResponseError for error::Error {
             // 400 Bad Request
             MissingInput { .. } => StatusCode::BAD_REQUEST,
             EmptyInput { .. } => StatusCode::BAD_REQUEST,
+            InvalidFlag { .. } => StatusCode::BAD_REQUEST,
             NewKey { .. } => StatusCode::BAD_REQUEST,
+            UnsetNonSetting { .. } => StatusCode::BAD_REQUEST,
+            InvalidRevision { .. } => StatusCode::BAD_REQUEST,
+            InvalidSettingSource { .. } => StatusCode::BAD_REQUEST,
+            InvalidSettings { .. } => StatusCode::BAD_REQUEST,
 
             // 404 Not Found
             MissingData { .. } => StatusCode::NOT_FOUND,
@@ -457,9 +917,11 @@ impl ResponseError for error::Error {
             UpdateDoesNotExist { .. } => StatusCode::NOT_FOUND,
             NoStagedImage { .. } => StatusCode::NOT_FOUND,
             UninitializedUpdateStatus { .. } => StatusCode::NOT_FOUND,
+            ApplyNotFound { .. } => StatusCode::NOT_FOUND,
 
             // 422 Unprocessable Entity
             CommitWithNoPending => StatusCode::UNPROCESSABLE_ENTITY,
+            RollbackWithNoHistory => StatusCode::UNPROCESSABLE_ENTITY,
 
             // 423 Locked
             UpdateShareLock { .. } => StatusCode::LOCKED,
@@ -467,9 +929,16 @@ impl ResponseError for error::Error {
 
             // 409 Conflict
             DisallowCommand { .. } => StatusCode::CONFLICT,
+            RevisionMismatch { .. } => StatusCode::CONFLICT,
+
+            // 503 Service Unavailable
+            DataStoreLock { .. } => StatusCode::SERVICE_UNAVAILABLE,
 
             // 500 Internal Server Error
-            DataStoreLock => StatusCode::INTERNAL_SERVER_ERROR,
+            Blocking { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryIo { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistoryFormat { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            HistorySerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             BindSocket { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ServerStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
@@ -479,6 +948,7 @@ impl ResponseError for error::Error {
             DataStoreSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             CommandSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             InvalidMetadata { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ProvenanceSerialization { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierFork { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStart { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             ConfigApplierStdin {} => StatusCode::INTERNAL_SERVER_ERROR,
@@ -496,14 +966,56 @@ impl ResponseError for error::Error {
             UpdateStatusParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateInfoParse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             UpdateLockOpen { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            ApplyStatus { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            TemplateRegistry { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DefaultSettings { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            DataStoreRecovery { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         };
 
-        HttpResponse::new(status_code)
+        match self {
+            // Settings can have several problems, so the body lists them for the caller to fix.
+            InvalidSettings { .. } => HttpResponse::build(status_code).body(self.to_string()),
+            _ => HttpResponse::new(status_code),
+        }
     }
 }
 
+/// The data store served by the API.
+type ServedDataStore = CachedDataStore<FilesystemDataStore>;
+
 struct SharedDataStore {
-    ds: sync::RwLock<FilesystemDataStore>,
+    // A waiting writer holds back new readers, so a stream of readers can't starve a writer.  The
+    // lock is only eventually fair, but it's regularly handed to the longest waiter, so neither
+    // readers nor writers wait indefinitely.
+    ds: RwLock<ServedDataStore>,
+    // How long a request waits for the data store lock before giving up.
+    lock_timeout: Duration,
+    // Writes to the history happen while holding the write lock of the data store.
+    history: CommitHistory,
+    // Commits and rollbacks are published while holding the write lock, so events are in order.
+    events: EventBroadcaster,
+    // The variant's default settings, which unset settings are reset to.
+    defaults: HashMap<Key, String>,
+}
+
+impl SharedDataStore {
+    /// Locks the data store for reading, waiting at most the lock timeout.
+    fn read(&self) -> Result<RwLockReadGuard<'_, ServedDataStore>> {
+        self.ds
+            .try_read_for(self.lock_timeout)
+            .context(error::DataStoreLock {
+                timeout: self.lock_timeout,
+            })
+    }
+
+    /// Locks the data store for writing, waiting at most the lock timeout.
+    fn write(&self) -> Result<RwLockWriteGuard<'_, ServedDataStore>> {
+        self.ds
+            .try_write_for(self.lock_timeout)
+            .context(error::DataStoreLock {
+                timeout: self.lock_timeout,
+            })
+    }
 }
 
 /// Helper macro for implementing the actix-web Responder trait for a type.
@@ -531,9 +1043,25 @@ macro_rules! impl_responder_for {
 struct ModelResponse(Model);
 impl_responder_for!(ModelResponse, self, self.0);
 
-/// This lets us respond from our handler methods with a Settings (or Result<Settings>)
-struct SettingsResponse(Settings);
-impl_responder_for!(SettingsResponse, self, self.0);
+/// This lets us respond from our handler methods with a Settings and its revision, which is
+/// returned in the ETag header so clients can detect changes.
+struct RevisionedSettingsResponse {
+    settings: Settings,
+    revision: u64,
+}
+
+impl Responder for RevisionedSettingsResponse {
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let body = match serde_json::to_string(&self.settings) {
+            Ok(s) => s,
+            Err(e) => return Error::ResponseSerialization { source: e }.into(),
+        };
+        HttpResponse::Ok()
+            .content_type("application/json")
+            .insert_header((header::ETAG, etag(self.revision)))
+            .body(body)
+    }
+}
 
 /// This lets us respond from our handler methods with a BottlerocketRelease (or Result<BottlerocketRelease>)
 struct BottlerocketReleaseResponse(BottlerocketRelease);
@@ -547,6 +1075,10 @@ impl_responder_for!(MetadataResponse, self, self.0);
 struct ServicesResponse(Services);
 impl_responder_for!(ServicesResponse, self, self.0);
 
+/// This lets us respond from our handler methods with an ApplyStatus (or Result<ApplyStatus>)
+struct ApplyStatusResponse(ApplyStatus);
+impl_responder_for!(ApplyStatusResponse, self, self.0);
+
 /// This lets us respond from our handler methods with a UpdateStatus (or Result<UpdateStatus>)
 struct UpdateStatusResponse(UpdateStatus);
 impl_responder_for!(UpdateStatusResponse, self, self.0);
@@ -561,3 +1093,68 @@ impl_responder_for!(ChangedKeysResponse, self, self.0);
 
 struct TransactionListResponse(HashSet<String>);
 impl_responder_for!(TransactionListResponse, self, self.0);
+
+struct HistoryResponse(Vec<HistoryEntry>);
+impl_responder_for!(HistoryResponse, self, self.0);
+
+struct DryRunResponse(DryRun);
+impl_responder_for!(DryRunResponse, self, self.0);
+
+/// This lets us respond from our handler methods with a JSON Schema document
+struct SchemaResponse(serde_json::Value);
+impl_responder_for!(SchemaResponse, self, self.0);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    fn shared_datastore(path: &Path, lock_timeout: Duration) -> SharedDataStore {
+        SharedDataStore {
+            ds: RwLock::new(CachedDataStore::new(FilesystemDataStore::new(path))),
+            lock_timeout,
+            history: CommitHistory::new(path),
+            events: EventBroadcaster::default(),
+            defaults: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn lock_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let timeout = Duration::from_millis(50);
+        let data = shared_datastore(tmp.path(), timeout);
+
+        let writer = data.write().unwrap();
+        let start = Instant::now();
+        assert!(matches!(
+            data.read(),
+            Err(error::Error::DataStoreLock { .. })
+        ));
+        assert!(matches!(
+            data.write(),
+            Err(error::Error::DataStoreLock { .. })
+        ));
+        assert!(start.elapsed() >= timeout * 2);
+
+        drop(writer);
+        let _reader = data.read().unwrap();
+        let _other_reader = data.read().unwrap();
+    }
+
+    #[test]
+    fn openapi_document() {
+        let document: serde_json::Value = serde_json::from_str(OPENAPI_DOCUMENT).unwrap();
+        assert_eq!(document["openapi"], "3.1.0");
+
+        // The schemas referenced by the paths come from the model.
+        let schemas = &document["components"]["schemas"];
+        assert_eq!(schemas["Settings"]["type"], "object");
+        assert!(schemas["SingleLineString"]["pattern"].is_string());
+        assert_eq!(
+            document["paths"]["/settings"]["get"]["responses"]["200"]["content"]
+                ["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Settings"
+        );
+    }
+}
@@ -92,7 +92,8 @@ use model::modeled_types::{BootstrapContainerMode, Identifier};
 
 // FIXME Get from configuration in the future
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
-const API_SETTINGS_URI: &str = "/settings";
+// Ask for secrets too; we need the user data of the containers.
+const API_SETTINGS_URI: &str = "/settings?secrets=true";
 
 const ENV_FILE_DIR: &str = "/etc/bootstrap-containers";
 const DROPIN_FILE_DIR: &str = "/etc/systemd/system";
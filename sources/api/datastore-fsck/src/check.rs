@@ -0,0 +1,596 @@
+//! This module scans a filesystem data store and its version links for problems.
+
+use crate::error::{self, Result};
+use datastore::filesystem::{
+    decode_path_component, encode_path_component, JOURNAL_FILE, LATEST_REVISION_FILE, LIVE_DIR,
+    METADATA_KEY_PREFIX, PENDING_DIR, REMOVALS_DIR, REVISIONS_DIR, SNAPSHOTS_DIR, SNAPSHOT_SUFFIX,
+    TMP_SUFFIX,
+};
+use datastore::{
+    deserialize_scalar, Committed, DataStore, FilesystemDataStore, Key, KeyType, ScalarError,
+    Snapshot, Value,
+};
+use snafu::ResultExt;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Problem is something wrong with an entry in the data store, or with its version links.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Problem {
+    /// The path of the entry with the problem.
+    pub(crate) path: PathBuf,
+    pub(crate) kind: ProblemKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProblemKind {
+    /// A file or directory name isn't encoded the way the data store encodes key names.
+    BadEncoding,
+    /// A path doesn't make a valid key.
+    InvalidKey { msg: String },
+    /// A data or metadata file doesn't hold a JSON value.
+    InvalidValue { msg: String },
+    /// A metadata file has no data file next to it.
+    OrphanedMetadata,
+    /// An entry the data store doesn't create, like a symlink in a data tree.
+    UnexpectedEntry,
+    /// A revision, staged removals, or snapshot file can't be read.
+    InvalidState { msg: String },
+    /// A commit was interrupted and hasn't been finished.
+    InterruptedCommit,
+    /// A version link is dangling or points somewhere unexpected.
+    BrokenLink { msg: String },
+    /// The data store can't be loaded, for a reason the other checks don't cover.
+    Unloadable { msg: String },
+}
+
+/// Repair describes how a problem is repaired.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Repair {
+    /// The entry is moved out of the data store.
+    Quarantine,
+    /// The interrupted commit is finished.
+    Recover,
+    /// We don't know how to repair it safely.
+    None,
+}
+
+impl ProblemKind {
+    pub(crate) fn repair(&self) -> Repair {
+        match self {
+            ProblemKind::InterruptedCommit => Repair::Recover,
+            ProblemKind::BrokenLink { .. } | ProblemKind::Unloadable { .. } => Repair::None,
+            _ => Repair::Quarantine,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.path.display())?;
+        match &self.kind {
+            ProblemKind::BadEncoding => write!(f, "name is not a valid encoded key segment"),
+            ProblemKind::InvalidKey { msg } => write!(f, "not a valid key: {}", msg),
+            ProblemKind::InvalidValue { msg } => write!(f, "value is not valid JSON: {}", msg),
+            ProblemKind::OrphanedMetadata => write!(f, "metadata for a data key that isn't set"),
+            ProblemKind::UnexpectedEntry => write!(f, "unexpected entry in data store"),
+            ProblemKind::InvalidState { msg } => write!(f, "unreadable: {}", msg),
+            ProblemKind::InterruptedCommit => write!(f, "commit was interrupted"),
+            ProblemKind::BrokenLink { msg } => write!(f, "broken version link: {}", msg),
+            ProblemKind::Unloadable { msg } => write!(f, "unable to load data store: {}", msg),
+        }
+    }
+}
+
+/// Checks the version links leading to the data store, if the given path is one.  Each link
+/// points to the next more specific version, like "current" -> "v1" -> "v1.5" -> "v1.5.2", and
+/// the last one points to a data store directory, like "v1.5.2_0123456789abcdef".
+pub(crate) fn check_links(datastore_path: &Path) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let metadata = fs::symlink_metadata(datastore_path).context(error::DataStorePath {
+        path: datastore_path,
+    })?;
+    // A data store directory can also be checked directly.
+    if !metadata.file_type().is_symlink() {
+        return Ok(problems);
+    }
+    let dir = datastore_path.parent().unwrap_or_else(|| Path::new("/"));
+
+    let mut link = datastore_path.to_path_buf();
+    let mut version_prefix = "v".to_string();
+    for level in 0..4 {
+        let broken = |msg: String| Problem {
+            path: link.clone(),
+            kind: ProblemKind::BrokenLink { msg },
+        };
+
+        let target = match fs::read_link(&link) {
+            Ok(target) => target,
+            Err(e) => {
+                problems.push(broken(format!("unable to read link: {}", e)));
+                break;
+            }
+        };
+        // Links are relative, to a name in the same directory.
+        let target_name = match target.to_str() {
+            Some(name) if target.components().count() == 1 => name.to_string(),
+            _ => {
+                problems.push(broken(format!(
+                    "points to '{}', outside the data store directory",
+                    target.display()
+                )));
+                break;
+            }
+        };
+        let next = dir.join(&target_name);
+        let next_type = match fs::symlink_metadata(&next) {
+            Ok(metadata) => metadata.file_type(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                problems.push(broken(format!("points to missing '{}'", target_name)));
+                break;
+            }
+            Err(e) => return Err(e).context(error::DataStorePath { path: next }),
+        };
+
+        if level < 3 {
+            let is_version = target_name
+                .strip_prefix(&version_prefix)
+                .map(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))
+                .unwrap_or(false);
+            if !is_version || !next_type.is_symlink() {
+                problems.push(broken(format!(
+                    "points to '{}', not a version link like '{}N'",
+                    target_name, version_prefix
+                )));
+                break;
+            }
+            version_prefix = format!("{}.", target_name);
+        } else {
+            // If an update needed no migrations, the data store directory can be named for an
+            // earlier version, so we don't check that it matches.
+            let is_datastore = target_name.starts_with('v') && target_name.contains('_');
+            if !is_datastore || !next_type.is_dir() {
+                problems.push(broken(format!(
+                    "points to '{}', not a data store directory like '{}_ID'",
+                    target_name,
+                    version_prefix.trim_end_matches('.')
+                )));
+            }
+        }
+        link = next;
+    }
+    Ok(problems)
+}
+
+/// Checks the data store at the given path, which should be a data store directory with links
+/// already resolved.  Returns the problems found, sorted by path.
+pub(crate) fn check_datastore(base: &Path) -> Result<Vec<Problem>> {
+    let mut checker = Checker {
+        problems: Vec::new(),
+    };
+
+    if base.join(JOURNAL_FILE).exists() {
+        checker.add(base.join(JOURNAL_FILE), ProblemKind::InterruptedCommit);
+    }
+    checker.check_tree(&base.join(LIVE_DIR))?;
+    checker.check_pending(&base.join(PENDING_DIR))?;
+    checker.check_revisions(&base.join(REVISIONS_DIR))?;
+    checker.check_removals(&base.join(REMOVALS_DIR))?;
+    checker.check_snapshots(&base.join(SNAPSHOTS_DIR))?;
+
+    // If we didn't find anything wrong, make sure the data store loads, in case we missed
+    // something.  If we did, loading would likely fail for the same reasons.
+    if checker.problems.is_empty() {
+        if let Err(e) = load(&FilesystemDataStore::new(base)) {
+            checker.add(base, ProblemKind::Unloadable { msg: e.to_string() });
+        }
+    }
+
+    let mut problems = checker.problems;
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(problems)
+}
+
+/// Reads everything from the data store that the API server reads.
+fn load(datastore: &FilesystemDataStore) -> datastore::Result<()> {
+    datastore.get_prefix("", &Committed::Live)?;
+    datastore.list_populated_metadata("", &None as &Option<&str>)?;
+    datastore.revision(&Committed::Live)?;
+    for tx in datastore.list_transactions()? {
+        datastore.pending_removals(&tx)?;
+        let pending = Committed::Pending { tx };
+        datastore.get_prefix("", &pending)?;
+        datastore.revision(&pending)?;
+    }
+    datastore.list_snapshots()?;
+    Ok(())
+}
+
+/// Returns whether the given name is a key segment encoded the way the data store encodes it.
+fn is_encoded(name: &str) -> bool {
+    match decode_path_component(name, name) {
+        Ok(decoded) => !name.is_empty() && encode_path_component(decoded) == name,
+        Err(_) => false,
+    }
+}
+
+/// Lists the entries of a directory, or nothing if it doesn't exist.
+fn list_dir(path: &Path) -> Result<Vec<fs::DirEntry>> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context(error::ListDirectory { path }),
+    };
+    let mut result = Vec::new();
+    for entry in entries {
+        result.push(entry.context(error::ListDirectory { path })?);
+    }
+    Ok(result)
+}
+
+/// Checker collects the problems found in a data store.
+struct Checker {
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn add<P: Into<PathBuf>>(&mut self, path: P, kind: ProblemKind) {
+        self.problems.push(Problem {
+            path: path.into(),
+            kind,
+        });
+    }
+
+    /// Checks a tree of data and metadata files, like live data or a pending transaction.
+    fn check_tree(&mut self, root: &Path) -> Result<()> {
+        if !root.exists() {
+            return Ok(());
+        }
+        let mut walker = WalkDir::new(root).min_depth(1).into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = entry.context(error::Walk { path: root })?;
+            let path = entry.path();
+            let file_type = entry.file_type();
+            let name = entry.file_name().to_str().unwrap_or_default();
+
+            if file_type.is_dir() {
+                // Everything under a bad directory has a bad key, so it's quarantined as a whole.
+                if !is_encoded(name) {
+                    self.add(path, ProblemKind::BadEncoding);
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+            if !file_type.is_file() {
+                self.add(path, ProblemKind::UnexpectedEntry);
+                continue;
+            }
+
+            // Metadata files are named by their data key and the metadata key, e.g. "b.meta" is
+            // metadata "meta" for data key "b".
+            let mut parts = name.splitn(2, METADATA_KEY_PREFIX);
+            let data_name = parts.next().unwrap_or_default();
+            let metadata_name = parts.next();
+            if !is_encoded(data_name) || !metadata_name.map(is_encoded).unwrap_or(true) {
+                self.add(path, ProblemKind::BadEncoding);
+                continue;
+            }
+
+            // We checked the encoding of the directories and the name already.
+            let mut segments = Vec::new();
+            if let Some(parent) = entry
+                .path()
+                .parent()
+                .and_then(|p| p.strip_prefix(root).ok())
+            {
+                for component in parent.iter() {
+                    let component = component.to_str().unwrap_or_default();
+                    segments.push(decode_path_component(component, path).unwrap_or_default());
+                }
+            }
+            segments.push(decode_path_component(data_name, path).unwrap_or_default());
+            if let Err(e) = Key::from_segments(KeyType::Data, &segments) {
+                self.add(path, ProblemKind::InvalidKey { msg: e.to_string() });
+                continue;
+            }
+
+            if let Some(metadata_name) = metadata_name {
+                let metadata_name = decode_path_component(metadata_name, path).unwrap_or_default();
+                if let Err(e) = Key::new(KeyType::Meta, metadata_name) {
+                    self.add(path, ProblemKind::InvalidKey { msg: e.to_string() });
+                    continue;
+                }
+                if !path.with_file_name(data_name).is_file() {
+                    self.add(path, ProblemKind::OrphanedMetadata);
+                    continue;
+                }
+            }
+
+            let valid = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| {
+                    deserialize_scalar::<Value, ScalarError>(&data).map_err(|e| e.to_string())
+                });
+            if let Err(msg) = valid {
+                self.add(path, ProblemKind::InvalidValue { msg });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the directories of pending transactions, named by the encoded transaction name.
+    fn check_pending(&mut self, dir: &Path) -> Result<()> {
+        for entry in list_dir(dir)? {
+            let path = entry.path();
+            let name = entry.file_name();
+            if !fs::symlink_metadata(&path).map_or(false, |m| m.is_dir()) {
+                self.add(path, ProblemKind::UnexpectedEntry);
+            } else if !is_encoded(&name.to_string_lossy()) {
+                self.add(path, ProblemKind::BadEncoding);
+            } else {
+                self.check_tree(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the revision of live data and of each pending transaction.
+    fn check_revisions(&mut self, dir: &Path) -> Result<()> {
+        for entry in list_dir(dir)? {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name == PENDING_DIR && path.is_dir() {
+                for entry in list_dir(&path)? {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    self.check_state_file(&entry.path(), &name, parse_revision);
+                }
+            } else if name == LIVE_DIR || name == LATEST_REVISION_FILE {
+                self.check_state_file(&path, &name, parse_revision);
+            } else if !name.ends_with(TMP_SUFFIX) {
+                self.add(path, ProblemKind::UnexpectedEntry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the keys staged for removal in each pending transaction.
+    fn check_removals(&mut self, dir: &Path) -> Result<()> {
+        for entry in list_dir(dir)? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            self.check_state_file(&entry.path(), &name, parse_removals);
+        }
+        Ok(())
+    }
+
+    /// Checks the snapshots saved by commits, named by sequence number.
+    fn check_snapshots(&mut self, dir: &Path) -> Result<()> {
+        for entry in list_dir(dir)? {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_sequence = name
+                .strip_suffix(SNAPSHOT_SUFFIX)
+                .map(|sequence| sequence.parse::<u64>().is_ok())
+                .unwrap_or(false);
+            if !is_sequence || !path.is_file() {
+                self.add(path, ProblemKind::UnexpectedEntry);
+                continue;
+            }
+            if let Err(msg) = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| parse_snapshot(&data))
+            {
+                self.add(path, ProblemKind::InvalidState { msg });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a revision or removals file of live data or a pending transaction, named by the
+    /// encoded transaction name, with the given parser.  Temporary files left by an interrupted
+    /// write are ignored; the data store doesn't read them.
+    fn check_state_file<F>(&mut self, path: &Path, name: &str, parse: F)
+    where
+        F: Fn(&str) -> std::result::Result<(), String>,
+    {
+        if name.ends_with(TMP_SUFFIX) {
+            return;
+        }
+        if !path.is_file() {
+            self.add(path, ProblemKind::UnexpectedEntry);
+        } else if !is_encoded(name) {
+            self.add(path, ProblemKind::BadEncoding);
+        } else if let Err(msg) = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| parse(&data))
+        {
+            self.add(path, ProblemKind::InvalidState { msg });
+        }
+    }
+}
+
+fn parse_revision(data: &str) -> std::result::Result<(), String> {
+    data.trim()
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|e| format!("invalid revision: {}", e))
+}
+
+fn parse_removals(data: &str) -> std::result::Result<(), String> {
+    let names: Vec<String> = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    for name in names {
+        Key::new(KeyType::Data, name).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn parse_snapshot(data: &str) -> std::result::Result<(), String> {
+    let snapshot: Snapshot = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    for name in snapshot.values.keys() {
+        Key::new(KeyType::Data, name).map_err(|e| e.to_string())?;
+    }
+    for (name, metadata) in &snapshot.metadata {
+        Key::new(KeyType::Data, name).map_err(|e| e.to_string())?;
+        for metadata_name in metadata.keys() {
+            Key::new(KeyType::Meta, metadata_name).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maplit::hashmap;
+    use std::os::unix::fs::symlink;
+
+    /// Creates a data store with live data and metadata, a pending transaction, and a snapshot.
+    fn datastore(base: &Path) {
+        let mut f = FilesystemDataStore::new(base);
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.\"b.c\"").unwrap();
+        let md = Key::new(KeyType::Meta, "provenance").unwrap();
+        f.set_keys(
+            &hashmap!(a.clone() => "1"),
+            &Committed::Pending { tx: "tx".into() },
+        )
+        .unwrap();
+        f.commit_transaction("tx").unwrap();
+        f.set_metadata(&md, &a, "\"api\"").unwrap();
+        let pending = Committed::Pending {
+            tx: "bottlerocket-launch".into(),
+        };
+        f.set_keys(&hashmap!(b.clone() => "\"x\""), &pending)
+            .unwrap();
+        f.set_pending_metadata(&md, &b, "\"user-data\"", "bottlerocket-launch")
+            .unwrap();
+    }
+
+    fn kinds(base: &Path, problems: Vec<Problem>) -> Vec<(PathBuf, ProblemKind)> {
+        problems
+            .into_iter()
+            .map(|p| (p.path.strip_prefix(base).unwrap().into(), p.kind))
+            .collect()
+    }
+
+    #[test]
+    fn clean() {
+        let tmp = tempfile::tempdir().unwrap();
+        datastore(tmp.path());
+        assert_eq!(check_datastore(tmp.path()).unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn problems() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+        datastore(base);
+        let write = |path: &str, data: &str| fs::write(base.join(path), data).unwrap();
+        write("live/settings/bad%zz", "1");
+        write("live/settings/a%20b", "1");
+        write("live/settings/a", "not json");
+        write("live/settings/gone.provenance", "\"api\"");
+        fs::create_dir_all(base.join("live/settings/Bad Dir")).unwrap();
+        write("live/settings/Bad Dir/x", "1");
+        symlink("a", base.join("live/settings/link")).unwrap();
+        write("pending/bottlerocket-launch/settings/b%2Ec.provenance", "{");
+        write("revisions/live", "x");
+        write("revisions/live.tmp", "x");
+        fs::create_dir_all(base.join("removals")).unwrap();
+        write("removals/tx", "[\"settings..a\"]");
+        write("snapshots/notes.txt", "");
+        write("journal", "");
+
+        let (quarantine, recover): (Vec<_>, Vec<_>) = kinds(base, check_datastore(base).unwrap())
+            .into_iter()
+            .partition(|(_, kind)| kind.repair() == Repair::Quarantine);
+        assert_eq!(
+            recover,
+            vec![("journal".into(), ProblemKind::InterruptedCommit)]
+        );
+        let quarantine: Vec<_> = quarantine
+            .into_iter()
+            .map(|(path, kind)| {
+                let kind = match kind {
+                    ProblemKind::InvalidKey { .. } => "key",
+                    ProblemKind::InvalidValue { .. } => "value",
+                    ProblemKind::InvalidState { .. } => "state",
+                    ProblemKind::BadEncoding => "encoding",
+                    ProblemKind::OrphanedMetadata => "orphan",
+                    ProblemKind::UnexpectedEntry => "unexpected",
+                    _ => "other",
+                };
+                (path.to_string_lossy().into_owned(), kind)
+            })
+            .collect();
+        assert_eq!(
+            quarantine,
+            vec![
+                ("live/settings/Bad Dir".to_string(), "encoding"),
+                ("live/settings/a".to_string(), "value"),
+                ("live/settings/a%20b".to_string(), "key"),
+                ("live/settings/bad%zz".to_string(), "encoding"),
+                ("live/settings/gone.provenance".to_string(), "orphan"),
+                ("live/settings/link".to_string(), "unexpected"),
+                (
+                    "pending/bottlerocket-launch/settings/b%2Ec.provenance".to_string(),
+                    "value"
+                ),
+                ("removals/tx".to_string(), "state"),
+                ("revisions/live".to_string(), "state"),
+                ("snapshots/notes.txt".to_string(), "unexpected"),
+            ]
+        );
+    }
+
+    /// Creates version links to a data store directory for version 1.5.2.
+    fn links(dir: &Path) {
+        fs::create_dir(dir.join("v1.5.2_0123456789abcdef")).unwrap();
+        symlink("v1.5.2_0123456789abcdef", dir.join("v1.5.2")).unwrap();
+        symlink("v1.5.2", dir.join("v1.5")).unwrap();
+        symlink("v1.5", dir.join("v1")).unwrap();
+        symlink("v1", dir.join("current")).unwrap();
+    }
+
+    #[test]
+    fn good_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        links(tmp.path());
+        assert!(check_links(&tmp.path().join("current")).unwrap().is_empty());
+        // Data store directories can be checked directly
+        assert!(check_links(&tmp.path().join("v1.5.2_0123456789abcdef"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn broken_links() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        links(dir);
+        let broken_at = |link: &str| {
+            let problems = check_links(&dir.join("current")).unwrap();
+            assert_eq!(problems.len(), 1);
+            assert_eq!(problems[0].path, dir.join(link));
+            assert_eq!(problems[0].kind.repair(), Repair::None);
+        };
+
+        fs::remove_file(dir.join("v1.5")).unwrap();
+        symlink("v1.6", dir.join("v1.5")).unwrap();
+        broken_at("v1.5");
+
+        fs::remove_file(dir.join("v1.5")).unwrap();
+        symlink("v1.5.2", dir.join("v1.5")).unwrap();
+        fs::remove_file(dir.join("v1")).unwrap();
+        symlink("v2.5", dir.join("v1")).unwrap();
+        broken_at("v1");
+
+        fs::remove_file(dir.join("v1")).unwrap();
+        symlink("v1.5", dir.join("v1")).unwrap();
+        fs::remove_file(dir.join("v1.5.2")).unwrap();
+        symlink("/tmp", dir.join("v1.5.2")).unwrap();
+        broken_at("v1.5.2");
+    }
+}
@@ -0,0 +1,35 @@
+//! This module owns the error type used by datastore-fsck.
+
+use snafu::Snafu;
+use std::io;
+use std::path::PathBuf;
+
+/// Error contains the errors that keep us from checking or repairing a data store.  Problems
+/// found in the data store itself are reported as a `Problem` instead.
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub(crate) enum Error {
+    #[snafu(display("Unable to find data store at '{}': {}", path.display(), source))]
+    DataStorePath { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to list directory '{}': {}", path.display(), source))]
+    ListDirectory { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to walk directory '{}': {}", path.display(), source))]
+    Walk {
+        path: PathBuf,
+        source: walkdir::Error,
+    },
+
+    #[snafu(display("Unable to move '{}' to '{}': {}", from.display(), to.display(), source))]
+    Quarantine {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+    },
+
+    #[snafu(display("Unable to finish interrupted commit: {}", source))]
+    Recover { source: datastore::Error },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,191 @@
+/*!
+# Introduction
+
+datastore-fsck checks a filesystem data store for problems that can keep the API server from loading it, for example after an unclean shutdown, and can repair them.
+
+It reports:
+* file and directory names in live data and pending transactions that aren't encoded the way the data store encodes keys, or don't make valid keys
+* data and metadata files that don't hold a JSON value
+* metadata files with no data key next to them
+* entries the data store doesn't create, like symlinks in live data
+* revisions, staged removals, and snapshots that can't be read
+* a commit that was interrupted and hasn't been finished
+* broken version links, if the given path is a link like `current`
+
+If it finds nothing wrong, it loads the data store with `FilesystemDataStore` to make sure.
+It doesn't check values against the settings model, since it's not tied to a variant.
+
+With `--repair`, it finishes interrupted commits, and moves other bad entries into a `quarantine` directory inside the data store, in a subdirectory named for the current time, at the same path relative to the data store.
+That way, nothing is lost, and the entries can be inspected or moved back by hand.
+Broken version links are only reported, because the right data store to point them at is a decision for a person or the migrator.
+The quarantine directory isn't part of the data store, so it's dropped when the data store is migrated.
+
+It prints each problem and repair, and exits with status 0 if no problems remain, or 1 otherwise.
+
+# Example usage
+
+`datastore-fsck --datastore-path /var/lib/bottlerocket/datastore/current --repair`
+*/
+
+#![deny(rust_2018_idioms)]
+
+#[macro_use]
+extern crate log;
+
+use simplelog::{Config as LogConfig, LevelFilter, SimpleLogger};
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, process};
+
+use check::Problem;
+use error::Result;
+
+mod check;
+mod error;
+mod repair;
+
+const DEFAULT_DATASTORE_PATH: &str = "/var/lib/bottlerocket/datastore/current";
+
+/// Stores user-supplied arguments.
+struct Args {
+    datastore_path: PathBuf,
+    log_level: LevelFilter,
+    repair: bool,
+}
+
+/// Informs the user about proper usage of the program and exits.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --datastore-path PATH ]
+            [ --repair ]
+            [ --log-level trace|debug|info|warn|error ]
+
+    Default data store path: {}",
+        program_name, DEFAULT_DATASTORE_PATH,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the args to the program and returns an Args struct.
+fn parse_args(args: env::Args) -> Args {
+    let mut datastore_path = None;
+    let mut log_level = None;
+    let mut repair = false;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--datastore-path" => {
+                datastore_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --datastore-path")),
+                )
+            }
+
+            "--log-level" => {
+                let log_level_str = iter
+                    .next()
+                    .unwrap_or_else(|| usage_msg("Did not give argument to --log-level"));
+                log_level = Some(LevelFilter::from_str(&log_level_str).unwrap_or_else(|_| {
+                    usage_msg(format!("Invalid log level '{}'", log_level_str))
+                }));
+            }
+
+            "--repair" => repair = true,
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        datastore_path: datastore_path
+            .unwrap_or_else(|| DEFAULT_DATASTORE_PATH.to_string())
+            .into(),
+        log_level: log_level.unwrap_or(LevelFilter::Info),
+        repair,
+    }
+}
+
+/// Checks the version links and the data store at the given path.  If the links don't lead to a
+/// data store, only their problems are returned.
+fn check(datastore_path: &Path) -> Result<Vec<Problem>> {
+    let mut problems = check::check_links(datastore_path)?;
+    match fs::canonicalize(datastore_path) {
+        Ok(base) => problems.extend(check::check_datastore(&base)?),
+        Err(e) => {
+            if problems.is_empty() {
+                return Err(e).context(error::DataStorePath {
+                    path: datastore_path,
+                });
+            }
+        }
+    }
+    Ok(problems)
+}
+
+fn print_problems(problems: &[Problem]) {
+    for problem in problems {
+        println!("{}", problem);
+    }
+}
+
+/// Checks the data store, and repairs it if requested.  Returns whether the data store is free of
+/// problems at the end.
+fn run(args: &Args) -> Result<bool> {
+    info!("Checking data store at {}", args.datastore_path.display());
+    let problems = check(&args.datastore_path)?;
+    print_problems(&problems);
+    if problems.is_empty() {
+        info!("No problems found");
+        return Ok(true);
+    }
+    println!("Found {} problem(s)", problems.len());
+    if !args.repair {
+        return Ok(false);
+    }
+
+    // We only repair entries in the data store, and only get problems in it if the links are OK.
+    if let Ok(base) = fs::canonicalize(&args.datastore_path) {
+        repair::repair(&base, &problems)?;
+    }
+
+    // Check again to see what's left.
+    let remaining = check(&args.datastore_path)?;
+    if remaining.is_empty() {
+        println!("Repaired all problems");
+    } else {
+        println!("{} problem(s) remain:", remaining.len());
+        print_problems(&remaining);
+    }
+    Ok(remaining.is_empty())
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    let args = parse_args(env::args());
+    // SimpleLogger will send errors to stderr and anything less to stdout.
+    if let Err(e) = SimpleLogger::init(args.log_level, LogConfig::default()) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
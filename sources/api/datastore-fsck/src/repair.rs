@@ -0,0 +1,152 @@
+//! This module repairs the problems found by the check module.
+
+use crate::check::{Problem, Repair};
+use crate::error::{self, Result};
+use datastore::filesystem::{LIVE_DIR, METADATA_KEY_PREFIX, PENDING_DIR};
+use datastore::FilesystemDataStore;
+use snafu::ResultExt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The directory in the data store where bad entries are moved.
+const QUARANTINE_DIR: &str = "quarantine";
+
+/// Repairs the given problems in the data store at the given path, printing each repair.
+///
+/// Interrupted commits are finished first, because that changes other entries.  Bad entries are
+/// then moved to a directory under "quarantine" in the data store, named for the current time, at
+/// the same path relative to the data store, so they can be inspected or restored by hand.  The
+/// metadata of a bad data key is moved along with it.
+/// Problems we can't repair safely are left alone.
+pub(crate) fn repair(base: &Path, problems: &[Problem]) -> Result<()> {
+    if problems
+        .iter()
+        .any(|problem| problem.kind.repair() == Repair::Recover)
+    {
+        let mut datastore = FilesystemDataStore::new(base);
+        if let Some(transaction) = datastore.recover().context(error::Recover)? {
+            println!(
+                "Finished interrupted commit of transaction '{}'",
+                transaction
+            );
+        }
+    }
+
+    let quarantine = quarantine_path(base);
+    for problem in problems {
+        if problem.kind.repair() != Repair::Quarantine {
+            continue;
+        }
+        // Finishing a commit can remove the entry, for example if it was pending.
+        if fs::symlink_metadata(&problem.path).is_err() {
+            continue;
+        }
+        // Metadata is stored next to its data key, so it goes along with the data key's file.
+        let mut paths = vec![problem.path.clone()];
+        paths.extend(metadata_files(base, &problem.path)?);
+        for path in paths {
+            move_to_quarantine(base, &quarantine, &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Moves the given entry to the same path relative to the data store under the given quarantine
+/// directory.
+fn move_to_quarantine(base: &Path, quarantine: &Path, path: &Path) -> Result<()> {
+    let relative = match path.strip_prefix(base) {
+        Ok(relative) => relative,
+        Err(_) => return Ok(()),
+    };
+    let to = quarantine.join(relative);
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent).context(error::Quarantine {
+            from: path,
+            to: &to,
+        })?;
+    }
+    fs::rename(path, &to).context(error::Quarantine {
+        from: path,
+        to: &to,
+    })?;
+    println!("Moved {} to {}", path.display(), to.display());
+    Ok(())
+}
+
+/// Returns the metadata files stored next to the given data key file in live data or a pending
+/// transaction, i.e. the files named with the data key's file name, the metadata prefix, and a
+/// metadata key.
+fn metadata_files(base: &Path, path: &Path) -> Result<Vec<PathBuf>> {
+    let in_data = path.starts_with(base.join(LIVE_DIR)) || path.starts_with(base.join(PENDING_DIR));
+    let (parent, name) = match (path.parent(), path.file_name().and_then(|n| n.to_str())) {
+        (Some(parent), Some(name)) => (parent, name),
+        _ => return Ok(Vec::new()),
+    };
+    // Metadata files have no metadata of their own.
+    if !in_data || !path.is_file() || name.contains(METADATA_KEY_PREFIX) {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}{}", name, METADATA_KEY_PREFIX);
+    let mut files = Vec::new();
+    for entry in fs::read_dir(parent).context(error::ListDirectory { path: parent })? {
+        let entry = entry.context(error::ListDirectory { path: parent })?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns the directory to move bad entries into, named for the current time so repeated
+/// repairs don't collide.
+fn quarantine_path(base: &Path) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    base.join(QUARANTINE_DIR).join(seconds.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::check::check_datastore;
+    use datastore::{Committed, DataStore, Key, KeyType};
+
+    #[test]
+    fn quarantine() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path();
+        let mut f = FilesystemDataStore::new(base);
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        f.set_key(&a, "1", &Committed::Live).unwrap();
+        fs::write(base.join("live/settings/b"), "not json").unwrap();
+        fs::write(base.join("live/settings/b.provenance"), "\"api\"").unwrap();
+        fs::write(base.join("live/settings/b.sensitive"), "true").unwrap();
+        fs::write(base.join("live/settings/c.provenance"), "\"api\"").unwrap();
+
+        let problems = check_datastore(base).unwrap();
+        assert_eq!(problems.len(), 2);
+        repair(base, &problems).unwrap();
+        assert!(check_datastore(base).unwrap().is_empty());
+
+        // The bad entries are kept, and the good ones are untouched
+        let quarantine = fs::read_dir(base.join(QUARANTINE_DIR))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        assert_eq!(
+            fs::read_to_string(quarantine.join("live/settings/b")).unwrap(),
+            "not json"
+        );
+        assert!(quarantine.join("live/settings/b.provenance").is_file());
+        assert!(quarantine.join("live/settings/b.sensitive").is_file());
+        assert!(quarantine.join("live/settings/c.provenance").is_file());
+        assert_eq!(f.get_key(&a, &Committed::Live).unwrap(), Some("1".into()));
+    }
+}
@@ -0,0 +1,95 @@
+//! Compares reading live data from a filesystem data store directly and through the cache, the
+//! way the API server's users read settings at boot, and measures the cost of keeping the cache
+//! up to date after a commit.
+//!
+//! Run with `cargo bench -p datastore`.
+
+use bencher::{benchmark_group, benchmark_main, Bencher};
+use datastore::{
+    CachedDataStore, Committed, DataStore, FilesystemDataStore, Key, KeyType,
+    PROVENANCE_METADATA_KEY,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Number of settings in the data store; a loaded host has a few thousand.
+const SETTINGS: usize = 2000;
+
+thread_local! {
+    // Bencher runs each benchmark function many times, and writing the settings takes seconds,
+    // so the benchmarks share one data store.
+    static DATASTORE: TempDir = populated_datastore();
+}
+
+/// Creates a filesystem data store with SETTINGS settings spread over a few levels, each with
+/// provenance metadata.
+fn populated_datastore() -> TempDir {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut f = FilesystemDataStore::new(tmp.path());
+    let mut pairs = HashMap::new();
+    for i in 0..SETTINGS {
+        pairs.insert(setting(i), format!("\"value {}\"", i));
+    }
+    f.set_keys(&pairs, &Committed::Live).unwrap();
+    let provenance = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+    for key in pairs.keys() {
+        f.set_metadata(&provenance, key, "\"defaults\"").unwrap();
+    }
+    tmp
+}
+
+/// Returns the key of the given setting in the populated data store.
+fn setting(i: usize) -> Key {
+    let name = format!("settings.group{}.subgroup{}.setting{}", i % 20, i % 7, i);
+    Key::new(KeyType::Data, name).unwrap()
+}
+
+/// Returns the path of the shared data store.
+fn datastore_path() -> PathBuf {
+    DATASTORE.with(|tmp| tmp.path().to_path_buf())
+}
+
+/// Reads all settings and their metadata, like `GET /settings` and `GET /settings/provenance`.
+fn read_settings<D: DataStore>(d: &D) {
+    let settings = d.get_prefix("settings.", &Committed::Live).unwrap();
+    assert_eq!(settings.len(), SETTINGS);
+    let metadata = d
+        .list_populated_metadata("settings.", &Some(PROVENANCE_METADATA_KEY))
+        .unwrap();
+    assert_eq!(metadata.len(), SETTINGS);
+}
+
+fn filesystem(b: &mut Bencher) {
+    let f = FilesystemDataStore::new(datastore_path());
+    b.iter(|| read_settings(&f));
+}
+
+fn cached(b: &mut Bencher) {
+    let c = CachedDataStore::new(FilesystemDataStore::new(datastore_path()));
+    b.iter(|| read_settings(&c));
+}
+
+/// Loading the cache happens once, at startup.
+fn cache_load(b: &mut Bencher) {
+    let path = datastore_path();
+    b.iter(|| CachedDataStore::new(FilesystemDataStore::new(&path)));
+}
+
+/// Committing a change to one setting, like `PATCH /settings` and `POST /tx/commit`; the cache
+/// reads back the committed setting and its metadata.
+fn cached_commit(b: &mut Bencher) {
+    let mut c = CachedDataStore::new(FilesystemDataStore::new(datastore_path()));
+    let key = setting(0);
+    let pending = Committed::Pending { tx: "bench".into() };
+    let mut i = 0;
+    b.iter(|| {
+        i += 1;
+        c.set_key(&key, format!("\"changed {}\"", i), &pending)
+            .unwrap();
+        c.commit_transaction("bench").unwrap()
+    });
+}
+
+benchmark_group!(reads, filesystem, cached, cache_load, cached_commit);
+benchmark_main!(reads);
@@ -0,0 +1,474 @@
+//! This implementation of the DataStore trait wraps another data store and keeps a copy of its
+//! live data and metadata in memory, so that reading live data doesn't have to walk and read the
+//! underlying data store each time.
+//!
+//! The copy is loaded when the cache is created.  Writes go to the underlying data store first,
+//! then the cache is updated for exactly the keys that were written; after a commit, for example,
+//! only the committed keys are read back.  Pending transactions aren't cached.
+//!
+//! If the copy can't be loaded or updated, reads go straight to the underlying data store until
+//! it's loaded again after a later write, so the cache never serves data it isn't sure of.
+//!
+//! The cache assumes all writes to the underlying data store go through it while it's in use.
+
+use log::{debug, error, trace};
+use std::collections::{HashMap, HashSet};
+
+use super::{Committed, DataStore, Key, Result, Snapshot};
+
+/// The cached copy of live data and metadata.
+#[derive(Debug)]
+struct LiveCache {
+    /// Mapping of data keys to their values.
+    data: HashMap<Key, String>,
+    /// Mapping of data keys to their metadata keys and values.
+    metadata: HashMap<Key, HashMap<Key, String>>,
+}
+
+#[derive(Debug)]
+pub struct CachedDataStore<D: DataStore> {
+    inner: D,
+    // None if the copy couldn't be loaded or updated.
+    live: Option<LiveCache>,
+}
+
+impl<D: DataStore> CachedDataStore<D> {
+    /// Wraps the given data store, loading its live data and metadata into memory.
+    pub fn new(inner: D) -> Self {
+        let mut cached = Self { inner, live: None };
+        cached.load();
+        cached
+    }
+
+    /// Returns the underlying data store.
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    /// Loads a fresh copy of all live data and metadata.  If we fail, reads go to the underlying
+    /// data store.
+    fn load(&mut self) {
+        self.live = match read_live(&self.inner) {
+            Ok(live) => {
+                debug!(
+                    "Cached {} live data keys and metadata for {} keys",
+                    live.data.len(),
+                    live.metadata.len()
+                );
+                Some(live)
+            }
+            Err(e) => {
+                error!("Unable to cache live data, reading it directly: {}", e);
+                None
+            }
+        };
+    }
+
+    /// Reads the live data and metadata of the given keys back from the underlying data store,
+    /// after a change we can't describe more precisely, like a commit.  If we fail, the whole
+    /// copy is dropped, because we don't know what's current.
+    fn refresh(&mut self, keys: &HashSet<Key>) {
+        if keys.is_empty() {
+            return;
+        }
+        let live = match self.live.as_mut() {
+            Some(live) => live,
+            None => return self.load(),
+        };
+        if let Err(e) = refresh_keys(&self.inner, live, keys) {
+            error!("Unable to update cached live data, reloading it: {}", e);
+            self.load();
+        }
+    }
+}
+
+/// Reads all live data and metadata from the given data store.
+fn read_live<D: DataStore>(datastore: &D) -> Result<LiveCache> {
+    let mut live = LiveCache {
+        data: datastore.get_prefix("", &Committed::Live)?,
+        metadata: HashMap::new(),
+    };
+    for (data_key, metadata_keys) in
+        datastore.list_populated_metadata("", &None as &Option<&str>)?
+    {
+        for metadata_key in metadata_keys {
+            if let Some(value) = datastore.get_metadata_raw(&metadata_key, &data_key)? {
+                live.metadata
+                    .entry(data_key.clone())
+                    .or_default()
+                    .insert(metadata_key, value);
+            }
+        }
+    }
+    Ok(live)
+}
+
+/// Replaces the cached live data and metadata of the given keys with what's in the given data
+/// store.
+fn refresh_keys<D: DataStore>(
+    datastore: &D,
+    live: &mut LiveCache,
+    keys: &HashSet<Key>,
+) -> Result<()> {
+    trace!("Refreshing cached live data for {} keys", keys.len());
+    for key in keys {
+        match datastore.get_key(key, &Committed::Live)? {
+            Some(value) => live.data.insert(key.clone(), value),
+            None => live.data.remove(key),
+        };
+
+        // Metadata can be added or removed along with data, so find what's there now.
+        let mut metadata = HashMap::new();
+        for metadata_key in datastore.list_key_metadata(key)? {
+            if let Some(value) = datastore.get_metadata_raw(&metadata_key, key)? {
+                metadata.insert(metadata_key, value);
+            }
+        }
+        if metadata.is_empty() {
+            live.metadata.remove(key);
+        } else {
+            live.metadata.insert(key.clone(), metadata);
+        }
+    }
+    Ok(())
+}
+
+impl<D: DataStore> DataStore for CachedDataStore<D> {
+    fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool> {
+        match (committed, &self.live) {
+            (Committed::Live, Some(live)) => Ok(live.data.contains_key(key)),
+            _ => self.inner.key_populated(key, committed),
+        }
+    }
+
+    fn list_populated_keys<S: AsRef<str>>(
+        &self,
+        prefix: S,
+        committed: &Committed,
+    ) -> Result<HashSet<Key>> {
+        match (committed, &self.live) {
+            (Committed::Live, Some(live)) => Ok(live
+                .data
+                .keys()
+                // Make sure the data keys start with the given prefix.
+                .filter(|k| k.name().starts_with(prefix.as_ref()))
+                .cloned()
+                .collect()),
+            _ => self.inner.list_populated_keys(prefix, committed),
+        }
+    }
+
+    fn list_populated_metadata<S1, S2>(
+        &self,
+        prefix: S1,
+        metadata_key_name: &Option<S2>,
+    ) -> Result<HashMap<Key, HashSet<Key>>>
+    where
+        S1: AsRef<str>,
+        S2: AsRef<str>,
+    {
+        let live = match &self.live {
+            Some(live) => live,
+            None => {
+                return self
+                    .inner
+                    .list_populated_metadata(prefix, metadata_key_name)
+            }
+        };
+
+        let mut result = HashMap::new();
+        for (data_key, metadata) in &live.metadata {
+            if !data_key.name().starts_with(prefix.as_ref()) {
+                continue;
+            }
+            let metadata_keys: HashSet<Key> = metadata
+                .keys()
+                .filter(|metadata_key| match metadata_key_name {
+                    Some(name) => name.as_ref() == metadata_key.name(),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            // Only add an entry for the data key if we found metadata.
+            if !metadata_keys.is_empty() {
+                result.insert(data_key.clone(), metadata_keys);
+            }
+        }
+        Ok(result)
+    }
+
+    fn list_key_metadata(&self, data_key: &Key) -> Result<HashSet<Key>> {
+        match &self.live {
+            Some(live) => Ok(live
+                .metadata
+                .get(data_key)
+                .map(|metadata| metadata.keys().cloned().collect())
+                .unwrap_or_default()),
+            None => self.inner.list_key_metadata(data_key),
+        }
+    }
+
+    fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
+        match (committed, &self.live) {
+            (Committed::Live, Some(live)) => Ok(live.data.get(key).cloned()),
+            _ => self.inner.get_key(key, committed),
+        }
+    }
+
+    fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        if let Committed::Pending { .. } = committed {
+            return self.inner.set_key(key, value, committed);
+        }
+        let result = self.inner.set_key(key, value.as_ref(), committed);
+        match (&result, self.live.as_mut()) {
+            (Ok(()), Some(live)) => {
+                live.data.insert(key.clone(), value.as_ref().to_string());
+            }
+            // We don't know whether the write happened.
+            (Err(_), Some(_)) => self.refresh(&vec![key.clone()].into_iter().collect()),
+            // Try loading again, in case the write fixed what kept us from loading.
+            (_, None) => self.load(),
+        }
+        result
+    }
+
+    fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
+        if let Committed::Pending { .. } = committed {
+            return self.inner.unset_key(key, committed);
+        }
+        let result = self.inner.unset_key(key, committed);
+        match (&result, self.live.as_mut()) {
+            (Ok(()), Some(live)) => {
+                live.data.remove(key);
+            }
+            (Err(_), Some(_)) => self.refresh(&vec![key.clone()].into_iter().collect()),
+            (_, None) => self.load(),
+        }
+        result
+    }
+
+    fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
+        match &self.live {
+            Some(live) => Ok(live
+                .metadata
+                .get(data_key)
+                .and_then(|metadata| metadata.get(metadata_key))
+                .cloned()),
+            None => self.inner.get_metadata_raw(metadata_key, data_key),
+        }
+    }
+
+    fn set_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+    ) -> Result<()> {
+        let result = self
+            .inner
+            .set_metadata(metadata_key, data_key, value.as_ref());
+        match (&result, self.live.as_mut()) {
+            (Ok(()), Some(live)) => {
+                live.metadata
+                    .entry(data_key.clone())
+                    .or_default()
+                    .insert(metadata_key.clone(), value.as_ref().to_string());
+            }
+            (Err(_), Some(_)) => self.refresh(&vec![data_key.clone()].into_iter().collect()),
+            (_, None) => self.load(),
+        }
+        result
+    }
+
+    fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()> {
+        let result = self.inner.unset_metadata(metadata_key, data_key);
+        match (&result, self.live.as_mut()) {
+            (Ok(()), Some(live)) => {
+                if let Some(metadata) = live.metadata.get_mut(data_key) {
+                    metadata.remove(metadata_key);
+                    if metadata.is_empty() {
+                        live.metadata.remove(data_key);
+                    }
+                }
+            }
+            (Err(_), Some(_)) => self.refresh(&vec![data_key.clone()].into_iter().collect()),
+            (_, None) => self.load(),
+        }
+        result
+    }
+
+    fn set_pending_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        transaction: &str,
+    ) -> Result<()> {
+        self.inner
+            .set_pending_metadata(metadata_key, data_key, value, transaction)
+    }
+
+    fn pending_metadata(&self, transaction: &str) -> Result<HashMap<Key, HashMap<Key, String>>> {
+        self.inner.pending_metadata(transaction)
+    }
+
+    /// After a commit, the committed keys are read back from the underlying data store, because
+    /// it decides which pending metadata becomes live.
+    fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        match self.inner.commit_transaction(transaction) {
+            Ok(keys) => {
+                self.refresh(&keys);
+                Ok(keys)
+            }
+            Err(e) => {
+                // We don't know which keys were changed, if any.
+                self.load();
+                Err(e)
+            }
+        }
+    }
+
+    fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
+    where
+        S: Into<String> + AsRef<str>,
+    {
+        self.inner.delete_transaction(transaction)
+    }
+
+    fn list_transactions(&self) -> Result<HashSet<String>> {
+        self.inner.list_transactions()
+    }
+
+    fn pending_removals(&self, transaction: &str) -> Result<HashSet<Key>> {
+        self.inner.pending_removals(transaction)
+    }
+
+    fn set_pending_removals(&mut self, transaction: &str, keys: &HashSet<Key>) -> Result<()> {
+        self.inner.set_pending_removals(transaction, keys)
+    }
+
+    fn revision(&self, committed: &Committed) -> Result<u64> {
+        self.inner.revision(committed)
+    }
+
+    fn bump_revision(&mut self, committed: &Committed) -> Result<u64> {
+        self.inner.bump_revision(committed)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        self.inner.save_snapshot(snapshot)
+    }
+
+    fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        self.inner.list_snapshots()
+    }
+
+    fn newest_snapshot(&self) -> Result<Option<Snapshot>> {
+        self.inner.newest_snapshot()
+    }
+
+    fn remove_newest_snapshot(&mut self) -> Result<Option<Snapshot>> {
+        self.inner.remove_newest_snapshot()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::memory::MemoryDataStore;
+    use super::super::{FilesystemDataStore, KeyType, PROVENANCE_METADATA_KEY};
+    use super::*;
+    use maplit::{hashmap, hashset};
+    use std::fs;
+
+    /// Returns everything a reader can see in live data, for comparing data stores.
+    fn live_view<D: DataStore>(d: &D) -> (HashMap<Key, String>, HashMap<Key, HashSet<Key>>) {
+        (
+            d.get_prefix("", &Committed::Live).unwrap(),
+            d.list_populated_metadata("", &None as &Option<&str>)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn matches_inner() {
+        let mut c = CachedDataStore::new(MemoryDataStore::new());
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.b").unwrap();
+        let md = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        c.set_keys(&hashmap!(a.clone() => "1"), &Committed::Live)
+            .unwrap();
+        c.set_metadata(&md, &a, "\"defaults\"").unwrap();
+        assert_eq!(live_view(&c), live_view(c.inner()));
+
+        // Pending changes aren't visible until they're committed
+        c.set_keys(&hashmap!(a.clone() => "2", b.clone() => "3"), &pending)
+            .unwrap();
+        c.set_pending_metadata(&md, &b, "\"api\"", "tx").unwrap();
+        assert_eq!(c.get_key(&b, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            c.get_key(&b, &pending).unwrap(),
+            c.inner().get_key(&b, &pending).unwrap()
+        );
+        c.commit_transaction("tx").unwrap();
+        assert_eq!(c.get_key(&b, &Committed::Live).unwrap(), Some("3".into()));
+        assert_eq!(c.get_metadata_raw(&md, &b).unwrap(), Some("\"api\"".into()));
+        assert_eq!(live_view(&c), live_view(c.inner()));
+
+        c.stage_removals(&hashset!(a.clone()), "tx").unwrap();
+        c.commit_transaction("tx").unwrap();
+        assert_eq!(c.get_key(&a, &Committed::Live).unwrap(), None);
+        assert_eq!(live_view(&c), live_view(c.inner()));
+
+        c.rollback().unwrap();
+        assert_eq!(c.get_key(&a, &Committed::Live).unwrap(), Some("2".into()));
+        c.unset_metadata(&md, &a).unwrap();
+        assert_eq!(live_view(&c), live_view(c.inner()));
+    }
+
+    #[test]
+    fn precise_updates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.b").unwrap();
+        f.set_keys(
+            &hashmap!(a.clone() => "1", b.clone() => "1"),
+            &Committed::Live,
+        )
+        .unwrap();
+        let mut c = CachedDataStore::new(f);
+
+        // Reads come from the cache, so changes behind its back aren't seen...
+        let live = tmp.path().join("live").join("settings");
+        fs::write(live.join("a"), "2").unwrap();
+        fs::write(live.join("b"), "2").unwrap();
+        assert_eq!(c.get_key(&a, &Committed::Live).unwrap(), Some("1".into()));
+
+        // ...until a write to the same key.  Only the committed key is read back.
+        let pending = Committed::Pending { tx: "tx".into() };
+        c.set_key(&b, "3", &pending).unwrap();
+        c.commit_transaction("tx").unwrap();
+        assert_eq!(c.get_key(&a, &Committed::Live).unwrap(), Some("1".into()));
+        assert_eq!(c.get_key(&b, &Committed::Live).unwrap(), Some("3".into()));
+    }
+
+    #[test]
+    fn uncached_without_live_data() {
+        // The filesystem data store fails to list keys if live data is missing
+        let tmp = tempfile::tempdir().unwrap();
+        let mut c = CachedDataStore::new(FilesystemDataStore::new(tmp.path()));
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        assert!(c.live.is_none());
+        assert!(c.get_prefix("", &Committed::Live).is_err());
+
+        // Writes try loading again
+        c.set_key(&a, "1", &Committed::Live).unwrap();
+        assert!(c.live.is_some());
+        assert_eq!(c.get_key(&a, &Committed::Live).unwrap(), Some("1".into()));
+    }
+}
@@ -30,13 +30,19 @@
 //! provide the value.  We use it recursively, and at each recursion, append a dot and the name of
 //! the field to our "path" string.  In the example above, when we're looking at field "c", path
 //! would be "a.b", so we know we should look for "a.b.c" in our input mapping.
+//!
+//! Lists of structures are stored with the index of each element as a key segment, like
+//! "a.list.0.c", and serde's SeqDeserializer is used for them in the same way, with the index in
+//! place of the field name.  If the output type doesn't say whether it wants a list or a map, for
+//! example a generic JSON Value, a structure whose keys are exactly the indexes 0 through n, each
+//! holding a structure, is deserialized as a list.
 
 use log::{error, trace};
-use serde::de::{value::MapDeserializer, IntoDeserializer, Visitor};
+use serde::de::{value::MapDeserializer, value::SeqDeserializer, IntoDeserializer, Visitor};
 use serde::{forward_to_deserialize_any, Deserialize};
-use snafu::ResultExt;
+use snafu::{ensure, OptionExt, ResultExt};
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::hash::Hash;
 
 use super::{error, Error, Result};
@@ -135,7 +141,26 @@ where
                     .context(error::DeserializeScalar)
             }
             ValueDeserializer::Compound(compound_deserializer) => {
-                compound_deserializer.deserialize_map(visitor)
+                compound_deserializer.deserialize_any(visitor)
+            }
+        }
+    }
+
+    /// Lists of scalars are stored as a single scalar value, and lists of structures as a
+    /// compound structure with a key per element.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            ValueDeserializer::Scalar(mut scalar_deserializer) => {
+                trace!("Handing off to scalar deserializer for deserialize_seq");
+                scalar_deserializer
+                    .deserialize_seq(visitor)
+                    .context(error::DeserializeScalar)
+            }
+            ValueDeserializer::Compound(compound_deserializer) => {
+                compound_deserializer.deserialize_seq(visitor)
             }
         }
     }
@@ -161,7 +186,7 @@ where
 
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        bytes byte_buf unit unit_struct newtype_struct tuple
         tuple_struct map struct enum identifier ignored_any
     }
 }
@@ -209,6 +234,95 @@ fn bad_root<T>() -> Result<T> {
     error::BadRoot.fail()
 }
 
+/// Parses a key segment as a list index.  Only canonical numbers are accepted, so each index has
+/// a single key.
+fn parse_index(segment: &str) -> Option<usize> {
+    if segment.len() > 1 && segment.starts_with('0') {
+        return None;
+    }
+    if !segment.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    segment.parse().ok()
+}
+
+impl<'de, K, S, BH> CompoundDeserializer<'de, K, S, BH>
+where
+    K: Borrow<Key> + Eq + Hash,
+    S: AsRef<str>,
+    BH: std::hash::BuildHasher,
+{
+    /// Groups our keys by the list index in their first segment, and returns the keys of each
+    /// element with the index removed, in order.  Fails if any first segment isn't an index, or if
+    /// the indexes aren't 0 through n.
+    fn list_elements(&self) -> Result<Vec<(String, HashSet<Key>)>> {
+        let mut elements: BTreeMap<usize, (String, HashSet<Key>)> = BTreeMap::new();
+        for key in &self.keys {
+            // As in deserialize_struct, keys at the top level still include the path.
+            let key = match self.path {
+                Some(ref path) => {
+                    key.strip_prefix_segments(path.segments())
+                        .context(error::StripPrefix {
+                            prefix: path.name(),
+                            name: key.name(),
+                        })?
+                }
+                None => key.clone(),
+            };
+            let (first, rest) = key.segments().split_first().expect("Key has no segments?!");
+            let index = parse_index(first).with_context(|| error::Message {
+                msg: format!(
+                    "key '{}' of list '{}' is not an index",
+                    first,
+                    self.path_name()
+                ),
+            })?;
+            let element = elements
+                .entry(index)
+                .or_insert_with(|| (first.clone(), HashSet::new()));
+            if !rest.is_empty() {
+                let element_key =
+                    Key::from_segments(KeyType::Data, rest).context(error::StripPrefix {
+                        prefix: first,
+                        name: key.name(),
+                    })?;
+                element.1.insert(element_key);
+            }
+        }
+
+        for (expected, index) in elements.keys().enumerate() {
+            ensure!(
+                *index == expected,
+                error::Message {
+                    msg: format!(
+                        "list '{}' is missing element {}",
+                        self.path_name(),
+                        expected
+                    ),
+                }
+            );
+        }
+        Ok(elements.into_values().collect())
+    }
+
+    /// Returns whether our keys look like a list of structures: the first segment of each key is
+    /// an index, the indexes are 0 through n, and each element is a structure.
+    fn looks_like_list(&self) -> bool {
+        match self.list_elements() {
+            Ok(elements) => elements.iter().all(|(_, keys)| !keys.is_empty()),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the name of our path, for use in error messages.
+    fn path_name(&self) -> String {
+        match self.path {
+            Some(ref path) => path.name().clone(),
+            None => String::new(),
+        }
+    }
+}
+
 impl<'de, K, S, BH> serde::de::Deserializer<'de> for CompoundDeserializer<'de, K, S, BH>
 where
     K: Borrow<Key> + Eq + Hash,
@@ -381,6 +495,52 @@ where
         }
     }
 
+    /// We use deserialize_seq for lists of structures, whose keys start with the index of each
+    /// element.  Like maps, lists need a prefix at the top level.
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return bad_root(),
+        };
+
+        let mut elements = Vec::new();
+        for (index, keys) in self.list_elements()? {
+            let element_path = path
+                .append_segments(&[&index])
+                .context(error::InvalidPrefix {
+                    prefix: format!("{}.{}", path, index),
+                })?;
+            if keys.is_empty() {
+                // The element is stored directly under its index, so it's a scalar.
+                trace!("List element '{}' is scalar", element_path);
+                let val = self
+                    .map
+                    .get(&element_path)
+                    .with_context(|| error::Message {
+                        msg: format!("list '{}' is missing element {}", path, index),
+                    })?;
+                elements.push(ValueDeserializer::Scalar(deserializer_for_scalar(
+                    val.as_ref(),
+                )));
+            } else {
+                trace!(
+                    "Recursing for list element '{}' with keys: {:?}",
+                    element_path,
+                    keys
+                );
+                elements.push(ValueDeserializer::Compound(CompoundDeserializer::new(
+                    self.map,
+                    keys,
+                    Some(element_path),
+                )));
+            }
+        }
+        visitor.visit_seq(SeqDeserializer::new(elements.into_iter()))
+    }
+
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
@@ -388,20 +548,29 @@ where
         visitor.visit_some(self)
     }
 
-    /// Scalar types, and compound types we can't use at the root, are forwarded here to be
-    /// rejected.  (Compound types need to have a name to serve at the root level.)
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    /// When the output type doesn't tell us what it expects, for example a generic JSON Value, we
+    /// deserialize structures that look like a list of structures as a list, and anything else as
+    /// a map.  Scalar types are forwarded here too, and rejected by the visitor.  At the root, we
+    /// reject everything, because compound types need to have a name to serve at the root level.
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        bad_root()
+        if self.path.is_none() {
+            return bad_root();
+        }
+        if self.looks_like_list() {
+            self.deserialize_seq(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
     }
 
     // This gives us the rest of the implementations needed to compile, and forwards them to the
-    // function above that will reject them.
+    // function above.
     forward_to_deserialize_any! {
         bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
-        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        bytes byte_buf unit unit_struct newtype_struct tuple
         tuple_struct enum identifier ignored_any
     }
 }
@@ -508,6 +677,74 @@ mod test {
         );
     }
 
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Listy {
+        list: Vec<C>,
+        nested: Option<Vec<Vec<C>>>,
+    }
+
+    #[test]
+    fn list_of_structs_works() {
+        let listy: Listy = from_map(&hashmap! {
+            key!("listy.list.0.boolean") => "true".to_string(),
+            key!("listy.list.1.boolean") => "false".to_string(),
+            key!("listy.nested.0.0.boolean") => "false".to_string(),
+        })
+        .unwrap();
+        assert_eq!(
+            listy,
+            Listy {
+                list: vec![C { boolean: true }, C { boolean: false }],
+                nested: Some(vec![vec![C { boolean: false }]]),
+            }
+        );
+    }
+
+    #[test]
+    fn list_of_structs_works_at_root_with_prefix() {
+        let map = &hashmap! {
+            key!("x.0.boolean") => "true".to_string(),
+            key!("x.1.boolean") => "false".to_string(),
+        };
+        let x: Vec<C> = from_map_with_prefix(Some("x".to_string()), map).unwrap();
+        assert_eq!(x, vec![C { boolean: true }, C { boolean: false }]);
+    }
+
+    #[test]
+    fn list_of_structs_into_value() {
+        let map = &hashmap! {
+            key!("x.list.0.boolean") => "true".to_string(),
+            key!("x.list.1.boolean") => "false".to_string(),
+            key!("x.map.0") => "1".to_string(),
+        };
+        let x: serde_json::Value = from_map_with_prefix(Some("x".to_string()), map).unwrap();
+        assert_eq!(
+            x,
+            serde_json::json!({
+                "list": [{"boolean": true}, {"boolean": false}],
+                "map": {"0": 1},
+            })
+        );
+    }
+
+    #[test]
+    fn list_missing_element() {
+        let listy: Result<Listy, Error> = from_map(&hashmap! {
+            key!("listy.list.0.boolean") => "true".to_string(),
+            key!("listy.list.2.boolean") => "false".to_string(),
+        });
+        listy.unwrap_err();
+    }
+
+    #[test]
+    fn list_bad_index() {
+        let listy: Result<Listy, Error> = from_map(&hashmap! {
+            key!("listy.list.0.boolean") => "true".to_string(),
+            key!("listy.list.01.boolean") => "false".to_string(),
+        });
+        listy.unwrap_err();
+    }
+
     #[derive(Debug, Deserialize, PartialEq)]
     struct Bad {
         id: u64,
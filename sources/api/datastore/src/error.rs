@@ -59,6 +59,33 @@ pub enum Error {
 
     #[snafu(display("Key name beyond maximum length {}: {}", name, max))]
     KeyTooLong { name: String, max: usize },
+
+    #[snafu(display("Unable to serialize staged removals: {}", source))]
+    RemovalsSerialization { source: serde_json::Error },
+
+    #[snafu(display("Staged removals at '{}' are invalid: {}", path.display(), source))]
+    RemovalsDeserialization {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Unable to serialize snapshot: {}", source))]
+    SnapshotSerialization { source: serde_json::Error },
+
+    #[snafu(display("Snapshot at '{}' is invalid: {}", path.display(), source))]
+    SnapshotDeserialization {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Unable to serialize commit journal: {}", source))]
+    JournalSerialization { source: serde_json::Error },
+
+    #[snafu(display("Commit journal at '{}' is invalid: {}", path.display(), source))]
+    JournalDeserialization {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -3,20 +3,72 @@
 //!
 //! Data is kept in files with paths resembling the keys, e.g. a/b/c for a.b.c, and metadata is
 //! kept in a suffixed file next to the data, e.g. a/b/c.meta for metadata "meta" about a.b.c
-
-use log::{debug, error, trace};
+//!
+//! Snapshots of live data taken before each commit are kept as JSON files in the "snapshots"
+//! directory, named with an increasing sequence number so they sort from oldest to newest.
+//!
+//! Revisions are kept in the "revisions" directory, in a "live" file for live data and in files
+//! named by the encoded transaction name under "pending" for pending transactions.  The "latest"
+//! file holds the last revision handed out; each change takes the next one, so revisions are
+//! never reused.  The revision files of committed and deleted transactions are kept for the same
+//! reason.
+//!
+//! Keys staged for removal in a pending transaction are kept as a JSON list of key names in the
+//! "removals" directory, in a file named by the encoded transaction name.
+//!
+//! Metadata staged in a pending transaction is kept next to the pending data, the same way as
+//! live metadata, and is copied to live when the transaction is committed.
+//!
+//! Commits are made atomic with a journal.  Before live data is changed, everything the commit
+//! will write is recorded in the "journal" file, which is written to a temporary file and renamed
+//! into place, and flushed to disk.  The commit then changes live data, flushes it to disk, and
+//! removes the journal.  If the commit is interrupted, for example by a power loss, the journal is
+//! still there the next time the data store is used, and `recover` replays it to finish the
+//! commit; if the journal wasn't in place yet, the commit never happened.  Replaying the journal
+//! has the same result no matter how far the commit got.
+
+use log::{debug, error, trace, warn};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{self, Path, PathBuf};
 use walkdir::{DirEntry, WalkDir};
 
 use super::key::{Key, KeyType};
-use super::{error, Committed, DataStore, Result};
-
-const METADATA_KEY_PREFIX: &str = ".";
+use super::{
+    error, Committed, DataStore, Result, Snapshot, MAX_SNAPSHOTS, PROVENANCE_METADATA_KEY,
+};
+
+/// Separates the name of a data key's file from the metadata key in the name of a metadata file.
+pub const METADATA_KEY_PREFIX: &str = ".";
+
+// The layout of the data store under its base path, for tools that inspect it directly.
+/// The directory holding live data and metadata.
+pub const LIVE_DIR: &str = "live";
+/// The directory holding a directory of data and metadata for each pending transaction.
+pub const PENDING_DIR: &str = "pending";
+/// The directory holding revisions: a file for live data, a directory with a file for each
+/// pending transaction, and the file recording the last revision handed out.
+pub const REVISIONS_DIR: &str = "revisions";
+/// The name of the file under the revisions directory recording the last revision handed out.
+pub const LATEST_REVISION_FILE: &str = "latest";
+/// The directory holding the keys staged for removal by each pending transaction.
+pub const REMOVALS_DIR: &str = "removals";
+/// The directory holding snapshots of live data taken before each commit.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+/// The file recording the changes of a commit in progress.
+pub const JOURNAL_FILE: &str = "journal";
+/// The file in which the API server logs changes to live settings.  It isn't part of the data,
+/// so migrations don't see it; the migrator copies it to the migrated data store.
+pub const HISTORY_FILE: &str = "history.jsonl";
+/// The suffix of snapshot files.
+pub const SNAPSHOT_SUFFIX: &str = ".json";
+/// The suffix of temporary files, which are renamed into place once they're written.
+pub const TMP_SUFFIX: &str = ".tmp";
 
 // This describes the set of characters we encode when making the filesystem path for a given key.
 // Any non-ASCII characters, plus these ones, will be encoded.
@@ -29,14 +81,187 @@ const ENCODE_CHARACTERS: &AsciiSet = &NON_ALPHANUMERIC.remove(b'_').remove(b'-')
 pub struct FilesystemDataStore {
     live_path: PathBuf,
     pending_base_path: PathBuf,
+    snapshots_path: PathBuf,
+    revisions_path: PathBuf,
+    removals_path: PathBuf,
+    journal_path: PathBuf,
+    // The number of commit steps to allow before simulating a crash, if any.
+    #[cfg(test)]
+    crash_after: Option<usize>,
+}
+
+/// Journal records everything a commit writes to live data, so that an interrupted commit can be
+/// finished by replaying it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// The name of the committed transaction, which is removed once the commit is applied.
+    transaction: String,
+    /// The snapshot of live data from before the commit, and the sequence number to save it with.
+    snapshot: Snapshot,
+    snapshot_sequence: u64,
+    /// The revision of live data and of the transaction after the commit.
+    revision: u64,
+    /// Names of the data keys to remove from live data.
+    removals: Vec<String>,
+    /// Mapping of data key names to the values to write to live data.
+    data: HashMap<String, String>,
+    /// Mapping of data key names to the metadata, by metadata key name, to write to live data.
+    metadata: HashMap<String, HashMap<String, String>>,
 }
 
 impl FilesystemDataStore {
     pub fn new<P: AsRef<Path>>(base_path: P) -> FilesystemDataStore {
         FilesystemDataStore {
-            live_path: base_path.as_ref().join("live"),
-            pending_base_path: base_path.as_ref().join("pending"),
+            live_path: base_path.as_ref().join(LIVE_DIR),
+            pending_base_path: base_path.as_ref().join(PENDING_DIR),
+            snapshots_path: base_path.as_ref().join(SNAPSHOTS_DIR),
+            revisions_path: base_path.as_ref().join(REVISIONS_DIR),
+            removals_path: base_path.as_ref().join(REMOVALS_DIR),
+            journal_path: base_path.as_ref().join(JOURNAL_FILE),
+            #[cfg(test)]
+            crash_after: None,
+        }
+    }
+
+    /// Finishes a commit that was interrupted, for example by a crash or power loss, by replaying
+    /// its journal.  This should be called before using a data store that may have been in use
+    /// when the system went down; commits also call it first.  Returns the name of the
+    /// transaction whose commit was finished, if there was one.
+    pub fn recover(&mut self) -> Result<Option<String>> {
+        // A temporary journal means we were interrupted before the commit took effect.
+        let tmp_path = tmp_path(&self.journal_path);
+        match fs::remove_file(&tmp_path) {
+            Ok(()) => warn!("Discarded journal of an interrupted commit that hadn't started"),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).context(error::DeleteKey { path: tmp_path }),
+        }
+
+        let data = match fs::read_to_string(&self.journal_path) {
+            Ok(data) => data,
+            // No commit was interrupted.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).context(error::Io {
+                    path: &self.journal_path,
+                })
+            }
+        };
+        let journal: Journal =
+            serde_json::from_str(&data).context(error::JournalDeserialization {
+                path: &self.journal_path,
+            })?;
+
+        warn!(
+            "Finishing interrupted commit of transaction '{}'",
+            journal.transaction
+        );
+        self.apply_journal(&journal)?;
+        Ok(Some(journal.transaction))
+    }
+
+    /// Writes the journal of a commit and flushes it to disk.  Once this returns, the commit
+    /// will happen, even if we're interrupted.
+    fn write_journal(&mut self, journal: &Journal) -> Result<()> {
+        let data = serde_json::to_string(journal).context(error::JournalSerialization)?;
+
+        // Write to a temporary file and flush it first, so the journal is never seen partially
+        // written.
+        let tmp_path = tmp_path(&self.journal_path);
+        if let Some(parent) = tmp_path.parent() {
+            fs::create_dir_all(parent).context(error::Io { path: parent })?;
+        }
+        let mut file = fs::File::create(&tmp_path).context(error::Io { path: &tmp_path })?;
+        file.write_all(data.as_bytes())
+            .context(error::Io { path: &tmp_path })?;
+        file.sync_all().context(error::Io { path: &tmp_path })?;
+        self.step()?;
+
+        fs::rename(&tmp_path, &self.journal_path).context(error::Io {
+            path: &self.journal_path,
+        })?;
+        sync_parent_directory(&self.journal_path)?;
+        self.step()
+    }
+
+    /// Applies the changes recorded in a commit journal, then removes the journal.  Each change
+    /// sets things to the state recorded in the journal, so if we're interrupted, this can be
+    /// called again to finish the job.
+    fn apply_journal(&mut self, journal: &Journal) -> Result<()> {
+        debug!("Saving snapshot of live keys");
+        self.write_snapshot(journal.snapshot_sequence, &journal.snapshot)?;
+        self.step()?;
+
+        // Removals go first, because a removed key can be in the way of a new one, for example
+        // when the elements of a list of structures are replaced by an empty list, which is a
+        // single key.
+        debug!("Removing keys staged for removal from live");
+        let provenance_key = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY)?;
+        for name in &journal.removals {
+            let key = Key::new(KeyType::Data, name)?;
+            self.remove_key(&key, &Committed::Live)?;
+            self.step()?;
+            // The provenance of a removed key no longer applies.
+            self.unset_metadata(&provenance_key, &key)?;
+            self.step()?;
+        }
+
+        debug!("Writing pending keys to live");
+        for (name, value) in &journal.data {
+            let key = Key::new(KeyType::Data, name)?;
+            self.write_key(&key, value, &Committed::Live)?;
+            self.step()?;
+        }
+
+        debug!("Writing pending metadata to live");
+        for (data_name, metadata) in &journal.metadata {
+            let data_key = Key::new(KeyType::Data, data_name)?;
+            for (metadata_name, value) in metadata {
+                let metadata_key = Key::new(KeyType::Meta, metadata_name)?;
+                self.set_metadata(&metadata_key, &data_key, value)?;
+                self.step()?;
+            }
+        }
+
+        // Live data and the transaction both changed, and take the same new revision.
+        let pending = Committed::Pending {
+            tx: journal.transaction.clone(),
+        };
+        self.write_revision(&self.latest_revision_path(), journal.revision)?;
+        self.write_revision(&self.revision_path(&Committed::Live), journal.revision)?;
+        self.write_revision(&self.revision_path(&pending), journal.revision)?;
+        self.step()?;
+
+        debug!("Removing old pending keys");
+        self.remove_transaction(&journal.transaction)?;
+        self.step()?;
+
+        // Everything has to be on disk before the journal is removed, or we couldn't finish the
+        // commit if we're interrupted now.
+        sync_filesystem(&self.journal_path)?;
+        self.step()?;
+
+        fs::remove_file(&self.journal_path).context(error::DeleteKey {
+            path: &self.journal_path,
+        })?;
+        // Make sure the journal stays removed, so it isn't replayed over later changes.
+        sync_parent_directory(&self.journal_path)
+    }
+
+    /// Marks the end of a step of a commit, where tests can simulate a crash.
+    fn step(&mut self) -> Result<()> {
+        #[cfg(test)]
+        {
+            if let Some(remaining) = self.crash_after.as_mut() {
+                if *remaining == 0 {
+                    return error::Internal {
+                        msg: "simulated crash",
+                    }
+                    .fail();
+                }
+                *remaining -= 1;
+            }
         }
+        Ok(())
     }
 
     /// Returns the appropriate filesystem path for pending or live data.
@@ -102,6 +327,138 @@ impl FilesystemDataStore {
         Ok(path_str.into())
     }
 
+    /// Returns the path of the file storing the last revision handed out.
+    fn latest_revision_path(&self) -> PathBuf {
+        self.revisions_path.join(LATEST_REVISION_FILE)
+    }
+
+    /// Returns the revision to give live data or a pending transaction when it changes: one
+    /// higher than the last revision handed out, and than the data's own revision, in case the
+    /// data store predates the "latest" file.
+    fn next_revision(&self, committed: &Committed) -> Result<u64> {
+        let latest = read_revision(&self.latest_revision_path())?;
+        Ok(latest.max(self.revision(committed)?) + 1)
+    }
+
+    /// Returns the path of the file storing the revision of live data or of a pending transaction.
+    fn revision_path(&self, committed: &Committed) -> PathBuf {
+        match committed {
+            Committed::Pending { tx } => self
+                .revisions_path
+                .join(PENDING_DIR)
+                .join(encode_path_component(tx)),
+            Committed::Live => self.revisions_path.join(LIVE_DIR),
+        }
+    }
+
+    /// Removes everything stored for a pending transaction: its directory of data and metadata,
+    /// and its staged removals.  Its revision is kept, so the transaction doesn't start over at a
+    /// revision that someone may have seen before.
+    fn remove_transaction(&mut self, transaction: &str) -> Result<()> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let path = self.base_path(&pending);
+        debug!("Removing transaction directory {}", path.display());
+        if let Err(e) = fs::remove_dir_all(&path) {
+            // If the transaction only had removals, or nothing at all, it has no directory.
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e).context(error::Io { path });
+            }
+        }
+        self.set_pending_removals(transaction, &HashSet::new())
+    }
+
+    /// Writes the value of a data key, without changing revisions.
+    fn write_key<S: AsRef<str>>(&self, key: &Key, value: S, committed: &Committed) -> Result<()> {
+        let path = self.data_path(key, committed)?;
+        write_file_mkdir(path, value)
+    }
+
+    /// Removes a data key, and in a pending transaction its pending metadata, without changing
+    /// revisions.
+    fn remove_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
+        let path = self.data_path(key, committed)?;
+        if let Committed::Pending { .. } = committed {
+            delete_metadata_files(&path)?;
+        }
+        self.delete_key_path(path, committed)
+    }
+
+    /// Returns the path of the file storing the keys staged for removal in a pending transaction.
+    fn removals_file_path(&self, transaction: &str) -> PathBuf {
+        self.removals_path.join(encode_path_component(transaction))
+    }
+
+    /// Returns the paths of saved snapshots, sorted from oldest to newest, along with their
+    /// sequence numbers.
+    fn snapshot_paths(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let entries = match fs::read_dir(&self.snapshots_path) {
+            Ok(entries) => entries,
+            // No snapshots have been saved yet.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).context(error::Io {
+                    path: &self.snapshots_path,
+                })
+            }
+        };
+
+        let mut paths = Vec::new();
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.snapshots_path,
+            })?;
+            let path = entry.path();
+            let sequence = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(SNAPSHOT_SUFFIX))
+                .and_then(|name| name.parse::<u64>().ok())
+                .context(error::Corruption {
+                    msg: "unexpected file in snapshot directory",
+                    path: &path,
+                })?;
+            paths.push((sequence, path));
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// Returns the sequence number for a new snapshot, one higher than the newest existing
+    /// snapshot.
+    fn next_snapshot_sequence(&self) -> Result<u64> {
+        let paths = self.snapshot_paths()?;
+        Ok(paths.last().map(|(sequence, _)| sequence + 1).unwrap_or(0))
+    }
+
+    /// Saves a snapshot with the given sequence number, replacing any snapshot with the same
+    /// number, then removes the oldest snapshots beyond our limit.
+    fn write_snapshot(&mut self, sequence: u64, snapshot: &Snapshot) -> Result<()> {
+        let data = serde_json::to_string(snapshot).context(error::SnapshotSerialization)?;
+        // Zero-pad the sequence number so snapshots are also in order when listed by name.
+        let path = self
+            .snapshots_path
+            .join(format!("{:020}{}", sequence, SNAPSHOT_SUFFIX));
+        write_file_mkdir(path, data)?;
+
+        let paths = self.snapshot_paths()?;
+        let excess = paths.len().saturating_sub(MAX_SNAPSHOTS);
+        for (_, path) in paths.into_iter().take(excess) {
+            debug!("Removing old snapshot {}", path.display());
+            fs::remove_file(&path).context(error::DeleteKey { path })?;
+        }
+        Ok(())
+    }
+
+    /// Writes a revision to the given path.  The revision is written to a temporary file and
+    /// renamed into place, so readers never see a partially written revision.
+    fn write_revision(&self, path: &Path, revision: u64) -> Result<()> {
+        let tmp_path = tmp_path(path);
+        write_file_mkdir(tmp_path.clone(), revision.to_string())?;
+        fs::rename(&tmp_path, &path).context(error::Io { path })
+    }
+
     /// Deletes the given path from the filesystem.  Also removes the parent directory if empty
     /// (repeatedly, up to the base path), so as to have consistent artifacts on the filesystem
     /// after adding and removing keys.
@@ -118,6 +475,23 @@ impl FilesystemDataStore {
         P: AsRef<Path>,
     {
         let path = path.as_ref();
+        let base = self.base_path(committed);
+
+        // If a parent is a key file, for example because a list of structures was replaced with a
+        // single value, the key can't exist, and there's nothing to clean up.
+        if path
+            .ancestors()
+            .skip(1)
+            .take_while(|parent| *parent != base)
+            .any(Path::is_file)
+        {
+            return Ok(());
+        }
+        // Likewise, if the path is a directory, the key is the prefix of other keys, for example
+        // after an empty list was replaced with a list of structures.
+        if path.is_dir() {
+            return Ok(());
+        }
 
         // Remove the file.  If it doesn't exist, we're still OK.
         match fs::remove_file(path) {
@@ -132,7 +506,6 @@ impl FilesystemDataStore {
         // Remove the directory if it's empty, i.e. if the setting we removed was the last setting
         // in that prefix.  Continue up the tree until the base, in case it was the only thing in
         // that subtree.
-        let base = self.base_path(committed);
         if let Some(parent) = path.parent() {
             // Note: ancestors() includes 'parent' itself
             for parent in parent.ancestors() {
@@ -172,13 +545,13 @@ impl FilesystemDataStore {
 // Filesystem helpers
 
 /// Encodes a string so that it's safe to use as a filesystem path component.
-fn encode_path_component<S: AsRef<str>>(segment: S) -> String {
+pub fn encode_path_component<S: AsRef<str>>(segment: S) -> String {
     let encoded = utf8_percent_encode(segment.as_ref(), ENCODE_CHARACTERS);
     encoded.to_string()
 }
 
 /// Decodes a path component, removing the encoding that's applied to make it filesystem-safe.
-fn decode_path_component<S, P>(segment: S, path: P) -> Result<String>
+pub fn decode_path_component<S, P>(segment: S, path: P) -> Result<String>
 where
     S: AsRef<str>,
     P: AsRef<Path>,
@@ -204,7 +577,12 @@ fn read_file_for_key(key: &Key, path: &Path) -> Result<Option<String>> {
     match fs::read_to_string(path) {
         Ok(s) => Ok(Some(s)),
         Err(e) => {
-            if e.kind() == io::ErrorKind::NotFound {
+            // The path can also be a directory of other keys, or be under another key's file,
+            // for example after a list of structures is replaced with a single value.
+            if e.kind() == io::ErrorKind::NotFound
+                || path.is_dir()
+                || path.ancestors().skip(1).any(Path::is_file)
+            {
                 return Ok(None);
             }
 
@@ -228,6 +606,87 @@ fn write_file_mkdir<S: AsRef<str>>(path: PathBuf, data: S) -> Result<()> {
     fs::write(&path, data.as_ref().as_bytes()).context(error::Io { path: &path })
 }
 
+/// Finds the metadata files stored next to the given data key path, i.e. all files named with the
+/// data key's file name, the metadata prefix, and a metadata key.  Returns the path of each file
+/// with the encoded name of its metadata key.
+fn find_metadata_files(data_path: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let (parent, name) = match (data_path.parent(), data_path.file_name()) {
+        (Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+        _ => return Ok(Vec::new()),
+    };
+    let prefix = format!("{}{}", name, METADATA_KEY_PREFIX);
+
+    // No directory, no metadata; the parent can also be a key file if the data key isn't set
+    if !parent.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(parent).context(error::Io { path: parent })? {
+        let entry = entry.context(error::Io { path: parent })?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(metadata_name) = file_name.strip_prefix(&prefix) {
+            files.push((entry.path(), metadata_name.to_string()));
+        }
+    }
+    Ok(files)
+}
+
+/// Removes the metadata files stored next to the given data key path.
+fn delete_metadata_files(data_path: &Path) -> Result<()> {
+    for (path, _) in find_metadata_files(data_path)? {
+        fs::remove_file(&path).context(error::DeleteKey { path: &path })?;
+    }
+    Ok(())
+}
+
+/// Returns the path of the temporary file used to write the given path.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.to_path_buf().into_os_string();
+    tmp_path.push(TMP_SUFFIX);
+    tmp_path.into()
+}
+
+/// Flushes the directory containing the given path to disk, so that a file created, renamed, or
+/// removed at the path stays that way.
+fn sync_parent_directory(path: &Path) -> Result<()> {
+    let parent = path.parent().with_context(|| error::Internal {
+        msg: format!("Given path to sync without parent: {}", path.display()),
+    })?;
+    let dir = fs::File::open(parent).context(error::Io { path: parent })?;
+    dir.sync_all().context(error::Io { path: parent })
+}
+
+/// Flushes everything written to the filesystem containing the given path to disk.  We change
+/// many files in a commit, so this is simpler than flushing each one and its directory.
+fn sync_filesystem(path: &Path) -> Result<()> {
+    let file = fs::File::open(path).context(error::Io { path })?;
+    // Safe because the file descriptor is valid until the file is dropped.
+    if unsafe { libc::syncfs(file.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error()).context(error::Io { path });
+    }
+    Ok(())
+}
+
+/// Helper for reading a revision written by write_revision.  Data that hasn't been changed yet
+/// has no revision file, and revision 0.
+fn read_revision(path: &Path) -> Result<u64> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).context(error::Io { path }),
+    };
+    data.trim().parse().ok().context(error::Corruption {
+        msg: "invalid revision",
+        path,
+    })
+}
+
+/// Helper for reading a snapshot saved by save_snapshot.
+fn read_snapshot(path: &Path) -> Result<Snapshot> {
+    let data = fs::read_to_string(path).context(error::Io { path })?;
+    serde_json::from_str(&data).context(error::SnapshotDeserialization { path })
+}
+
 /// KeyPath represents the filesystem path to a data or metadata key, relative to the base path of
 /// the live or pending data store.  For example, the data key "settings.a.b" would be
 /// "settings/a/b" and the metadata key "meta1" for "settings.a.b" would be "settings/a/b.meta1".
@@ -442,19 +901,37 @@ impl DataStore for FilesystemDataStore {
         Ok(result)
     }
 
+    fn list_key_metadata(&self, data_key: &Key) -> Result<HashSet<Key>> {
+        let data_path = self.data_path(data_key, &Committed::Live)?;
+        let mut result = HashSet::new();
+        for (path, metadata_name) in find_metadata_files(&data_path)? {
+            let metadata_name = decode_path_component(metadata_name, &path)?;
+            result.insert(Key::new(KeyType::Meta, metadata_name)?);
+        }
+        Ok(result)
+    }
+
     fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
         let path = self.data_path(key, committed)?;
         read_file_for_key(&key, &path)
     }
 
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
-        let path = self.data_path(key, committed)?;
-        write_file_mkdir(path, value)
+        self.write_key(key, value, committed)?;
+        if let Committed::Pending { tx } = committed {
+            let mut removals = self.pending_removals(tx)?;
+            if removals.remove(key) {
+                self.set_pending_removals(tx, &removals)?;
+            }
+        }
+        self.bump_revision(committed)?;
+        Ok(())
     }
 
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
-        let path = self.data_path(key, committed)?;
-        self.delete_key_path(path, committed)
+        self.remove_key(key, committed)?;
+        self.bump_revision(committed)?;
+        Ok(())
     }
 
     fn get_metadata_raw(&self, metadata_key: &Key, data_key: &Key) -> Result<Option<String>> {
@@ -477,34 +954,126 @@ impl DataStore for FilesystemDataStore {
         self.delete_key_path(path, &Committed::Live)
     }
 
-    /// We commit by copying pending keys to live, then removing pending.  Something smarter (lock,
-    /// atomic flip, etc.) will be required to make the server concurrent.
+    fn set_pending_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        transaction: &str,
+    ) -> Result<()> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let path = self.metadata_path(metadata_key, data_key, &pending)?;
+        write_file_mkdir(path, value)
+    }
+
+    fn pending_metadata(&self, transaction: &str) -> Result<HashMap<Key, HashMap<Key, String>>> {
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        let mut metadata = HashMap::new();
+        for key_path in find_populated_key_paths(self, KeyType::Meta, "", &pending)? {
+            let metadata_key = key_path.metadata_key.context(error::Internal {
+                msg: format!("Found meta key path with no dot: {}", key_path.data_key),
+            })?;
+            let path = self.metadata_path(&metadata_key, &key_path.data_key, &pending)?;
+            if let Some(value) = read_file_for_key(&metadata_key, &path)? {
+                metadata
+                    .entry(key_path.data_key)
+                    .or_insert_with(HashMap::new)
+                    .insert(metadata_key, value);
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// We commit by writing a journal of the changes to live data, then applying it, so that an
+    /// interrupted commit can be finished by `recover`.  Something smarter (lock, atomic flip,
+    /// etc.) will be required to make the server concurrent.
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
+        // Finish any interrupted commit first, so we see the live data it leaves.
+        self.recover()?;
+
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
         // Get data for changed keys
         let pending_data = self.get_prefix("settings.", &pending)?;
+        // Removing keys that aren't set in live data doesn't change anything
+        let mut removals = HashSet::new();
+        for key in self.pending_removals(&transaction)? {
+            if self.key_populated(&key, &Committed::Live)? {
+                removals.insert(key);
+            }
+        }
 
-        // Nothing to do if no keys are present in pending
-        if pending_data.is_empty() {
+        // Nothing to do if no keys are present in pending; any pending metadata is discarded
+        if pending_data.is_empty() && removals.is_empty() {
+            self.remove_transaction(&transaction)?;
+            if self.revision(&pending)? > 0 {
+                self.bump_revision(&pending)?;
+            }
             return Ok(Default::default());
         }
 
         // Save Keys for return value
-        let pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
+        let mut pending_keys: HashSet<Key> = pending_data.keys().cloned().collect();
+        pending_keys.extend(removals.iter().cloned());
 
-        // Apply changes to live
-        debug!("Writing pending keys to live");
-        self.set_keys(&pending_data, &Committed::Live)?;
+        let mut metadata = HashMap::new();
+        for (data_key, data_metadata) in self.pending_metadata(&transaction)? {
+            // Metadata is only kept for keys the transaction writes.
+            if !pending_data.contains_key(&data_key) {
+                continue;
+            }
+            let data_metadata = data_metadata
+                .into_iter()
+                .map(|(metadata_key, value)| (metadata_key.name().clone(), value))
+                .collect::<HashMap<_, _>>();
+            metadata.insert(data_key.name().clone(), data_metadata);
+        }
 
-        // Remove pending
-        debug!("Removing old pending keys");
-        let path = self.base_path(&pending);
-        fs::remove_dir_all(&path).context(error::Io { path })?;
+        // Save the live values and metadata we're about to change, so the commit can be rolled
+        // back
+        let provenance_key = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY)?;
+        let mut metadata_keys: HashMap<Key, HashSet<Key>> = HashMap::new();
+        for key in &removals {
+            metadata_keys
+                .entry(key.clone())
+                .or_default()
+                .insert(provenance_key.clone());
+        }
+        for (data_name, data_metadata) in &metadata {
+            let entry = metadata_keys
+                .entry(Key::new(KeyType::Data, data_name)?)
+                .or_default();
+            for metadata_name in data_metadata.keys() {
+                entry.insert(Key::new(KeyType::Meta, metadata_name)?);
+            }
+        }
+
+        let journal = Journal {
+            snapshot: self.snapshot_keys(transaction.as_str(), &pending_keys, &metadata_keys)?,
+            snapshot_sequence: self.next_snapshot_sequence()?,
+            revision: self
+                .next_revision(&Committed::Live)?
+                .max(self.next_revision(&pending)?),
+            removals: removals.iter().map(|key| key.name().clone()).collect(),
+            data: pending_data
+                .iter()
+                .map(|(key, value)| (key.name().clone(), value.clone()))
+                .collect(),
+            metadata,
+            transaction,
+        };
+        debug!("Writing commit journal");
+        self.write_journal(&journal)?;
+        self.apply_journal(&journal)?;
 
         Ok(pending_keys)
     }
@@ -513,31 +1082,32 @@ impl DataStore for FilesystemDataStore {
     where
         S: Into<String> + AsRef<str>,
     {
+        let transaction = transaction.into();
         let pending = Committed::Pending {
-            tx: transaction.into(),
+            tx: transaction.clone(),
         };
         // Get changed keys so we can return the list
         let pending_data = self.get_prefix("settings.", &pending)?;
 
         // Pull out just the keys so we can log them and return them
-        let pending_keys = pending_data.into_iter().map(|(key, _val)| key).collect();
+        let mut pending_keys: HashSet<Key> =
+            pending_data.into_iter().map(|(key, _val)| key).collect();
+        pending_keys.extend(self.pending_removals(&transaction)?);
         debug!("Found pending keys: {:?}", &pending_keys);
 
-        // Delete pending from the filesystem, same as a commit
-        let path = self.base_path(&pending);
-        debug!("Removing transaction directory {}", path.display());
-        if let Err(e) = fs::remove_dir_all(&path) {
-            // If path doesn't exist, it's fine, we'll just return an empty list.
-            if e.kind() != io::ErrorKind::NotFound {
-                return Err(e).context(error::Io { path });
-            }
+        // Delete pending from the filesystem, same as a commit, and move the transaction past
+        // any revision someone may have seen
+        self.remove_transaction(&transaction)?;
+        if self.revision(&pending)? > 0 {
+            self.bump_revision(&pending)?;
         }
 
         Ok(pending_keys)
     }
 
     /// We store transactions as subdirectories of the pending data store, so to list them we list
-    /// the names of the subdirectories.
+    /// the names of the subdirectories.  Transactions that only stage removals have no
+    /// subdirectory, so we also list the names of the files in the removals directory.
     fn list_transactions(&self) -> Result<HashSet<String>> {
         // Any directory under pending should be a transaction name.
         let walker = WalkDir::new(&self.pending_base_path)
@@ -576,13 +1146,126 @@ impl DataStore for FilesystemDataStore {
             }
         }
 
+        let entries = match fs::read_dir(&self.removals_path) {
+            Ok(entries) => entries,
+            // No removals have been staged.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(transactions),
+            Err(e) => {
+                return Err(e).context(error::Io {
+                    path: &self.removals_path,
+                })
+            }
+        };
+        for entry in entries {
+            let entry = entry.context(error::Io {
+                path: &self.removals_path,
+            })?;
+            let path = entry.path();
+            let file_name =
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .context(error::Corruption {
+                        msg: "Non-UTF8 path",
+                        path: &path,
+                    })?;
+            // Skip temporary files from an interrupted write.
+            if file_name.ends_with(TMP_SUFFIX) {
+                continue;
+            }
+            transactions.insert(decode_path_component(file_name, &path)?);
+        }
+
         Ok(transactions)
     }
+
+    fn pending_removals(&self, transaction: &str) -> Result<HashSet<Key>> {
+        let path = self.removals_file_path(transaction);
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            // No removals have been staged.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+            Err(e) => return Err(e).context(error::Io { path }),
+        };
+        let names: Vec<String> =
+            serde_json::from_str(&data).context(error::RemovalsDeserialization { path: &path })?;
+        names
+            .iter()
+            .map(|name| Key::new(KeyType::Data, name))
+            .collect()
+    }
+
+    /// The removals are written to a temporary file and renamed into place, like revisions.
+    fn set_pending_removals(&mut self, transaction: &str, keys: &HashSet<Key>) -> Result<()> {
+        let path = self.removals_file_path(transaction);
+        if keys.is_empty() {
+            return match fs::remove_file(&path) {
+                Ok(()) => Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                Err(e) => Err(e).context(error::DeleteKey { path }),
+            };
+        }
+
+        // Sort the names so the file is easier to read.
+        let mut names: Vec<_> = keys.iter().map(|key| key.name()).collect();
+        names.sort();
+        let data = serde_json::to_string(&names).context(error::RemovalsSerialization)?;
+
+        let tmp_path = tmp_path(&path);
+        write_file_mkdir(tmp_path.clone(), data)?;
+        fs::rename(&tmp_path, &path).context(error::Io { path })
+    }
+
+    fn revision(&self, committed: &Committed) -> Result<u64> {
+        read_revision(&self.revision_path(committed))
+    }
+
+    /// The latest revision is written first, so if we're interrupted, the next bump still
+    /// moves past it.
+    fn bump_revision(&mut self, committed: &Committed) -> Result<u64> {
+        let revision = self.next_revision(committed)?;
+        self.write_revision(&self.latest_revision_path(), revision)?;
+        self.write_revision(&self.revision_path(committed), revision)?;
+        Ok(revision)
+    }
+
+    /// Snapshots are numbered one higher than the newest existing snapshot.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.is_empty() {
+            return Ok(());
+        }
+        let sequence = self.next_snapshot_sequence()?;
+        self.write_snapshot(sequence, snapshot)
+    }
+
+    fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        self.snapshot_paths()?
+            .into_iter()
+            .map(|(_, path)| read_snapshot(&path))
+            .collect()
+    }
+
+    fn newest_snapshot(&self) -> Result<Option<Snapshot>> {
+        match self.snapshot_paths()?.pop() {
+            Some((_, path)) => Ok(Some(read_snapshot(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn remove_newest_snapshot(&mut self) -> Result<Option<Snapshot>> {
+        let path = match self.snapshot_paths()?.pop() {
+            Some((_, path)) => path,
+            None => return Ok(None),
+        };
+        let newest = read_snapshot(&path)?;
+        fs::remove_file(&path).context(error::DeleteKey { path })?;
+        Ok(Some(newest))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use maplit::{hashmap, hashset};
 
     #[test]
     fn data_path() {
@@ -644,4 +1327,420 @@ mod test {
         // Invalid UTF-8
         decode_path_component("%C3%28", "").unwrap_err();
     }
+
+    #[test]
+    fn rollback() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        f.set_key(&k1, "1", &Committed::Live).unwrap();
+
+        // Commit enough transactions to go past our limit
+        for i in 0..MAX_SNAPSHOTS + 1 {
+            let tx = format!("tx{}", i);
+            let pending = Committed::Pending { tx: tx.clone() };
+            f.set_key(&k1, format!("{}", i + 2), &pending).unwrap();
+            f.commit_transaction(tx).unwrap();
+        }
+        let pending = Committed::Pending { tx: "last".into() };
+        f.set_key(&k2, "new", &pending).unwrap();
+        f.commit_transaction("last").unwrap();
+
+        let snapshots = f.list_snapshots().unwrap();
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        assert_eq!(snapshots[MAX_SNAPSHOTS - 1].transaction, "last");
+
+        // The newest commit is undone first
+        assert_eq!(f.rollback().unwrap(), Some(hashset!(k2.clone())));
+        assert_eq!(f.get_key(&k2, &Committed::Live).unwrap(), None);
+        assert_eq!(f.rollback().unwrap(), Some(hashset!(k1.clone())));
+        assert_eq!(
+            f.get_key(&k1, &Committed::Live).unwrap(),
+            // The value set by the second-to-last "tx" commit
+            Some(format!("{}", MAX_SNAPSHOTS + 1)),
+        );
+        assert_eq!(f.list_snapshots().unwrap().len(), MAX_SNAPSHOTS - 2);
+    }
+
+    #[test]
+    fn rollback_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let md = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+        f.set_key(&k1, "1", &Committed::Live).unwrap();
+        f.set_key(&k2, "1", &Committed::Live).unwrap();
+        f.set_metadata(&md, &k1, "\"old\"").unwrap();
+        f.set_metadata(&md, &k2, "\"old\"").unwrap();
+
+        // Change one key's provenance and remove the other key
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_key(&k1, "2", &pending).unwrap();
+        f.set_pending_metadata(&md, &k1, "\"new\"", "tx").unwrap();
+        f.stage_removals(&hashset!(k2.clone()), "tx").unwrap();
+        f.commit_transaction("tx").unwrap();
+        assert_eq!(f.get_metadata_raw(&md, &k2).unwrap(), None);
+
+        f.rollback().unwrap().unwrap();
+        assert_eq!(
+            f.get_metadata_raw(&md, &k1).unwrap(),
+            Some("\"old\"".into())
+        );
+        assert_eq!(
+            f.get_metadata_raw(&md, &k2).unwrap(),
+            Some("\"old\"".into())
+        );
+        assert!(f.rollback().unwrap().is_none());
+    }
+
+    #[test]
+    fn revisions() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        assert_eq!(f.revision(&Committed::Live).unwrap(), 0);
+        assert_eq!(f.revision(&pending).unwrap(), 0);
+
+        f.set_keys(&hashmap!(k.clone() => "1"), &pending).unwrap();
+        f.set_key(&k, "2", &pending).unwrap();
+        assert_eq!(f.revision(&pending).unwrap(), 2);
+        assert_eq!(f.revision(&Committed::Live).unwrap(), 0);
+
+        // Committing moves live data and the transaction to the next revision; the transaction
+        // doesn't go back to 0, where someone's old revision could match it
+        f.commit_transaction("tx").unwrap();
+        assert_eq!(f.revision(&Committed::Live).unwrap(), 3);
+        assert_eq!(f.revision(&pending).unwrap(), 3);
+
+        f.unset_keys(&hashset!(k.clone()), &pending).unwrap();
+        assert_eq!(f.revision(&pending).unwrap(), 4);
+        f.delete_transaction("tx").unwrap();
+        assert_eq!(f.revision(&pending).unwrap(), 5);
+
+        f.rollback().unwrap();
+        assert_eq!(f.revision(&Committed::Live).unwrap(), 6);
+
+        // Data stores from before the latest revision was kept still move past their revisions
+        fs::remove_file(f.latest_revision_path()).unwrap();
+        f.set_key(&k, "3", &pending).unwrap();
+        assert_eq!(f.revision(&pending).unwrap(), 6);
+    }
+
+    #[test]
+    fn removals() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.\"b.c\"").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_keys(
+            &hashmap!(k1.clone() => "1", k2.clone() => "2"),
+            &Committed::Live,
+        )
+        .unwrap();
+
+        // A transaction that only stages removals is still listed
+        f.stage_removals(&hashset!(k1.clone(), k2.clone()), "tx")
+            .unwrap();
+        assert_eq!(f.revision(&pending).unwrap(), 4);
+        assert_eq!(f.list_transactions().unwrap(), hashset!("tx".to_string()));
+        assert_eq!(
+            f.pending_removals("tx").unwrap(),
+            hashset!(k1.clone(), k2.clone())
+        );
+
+        let changed = f.commit_transaction("tx").unwrap();
+        assert_eq!(changed, hashset!(k1.clone(), k2.clone()));
+        assert!(!f.key_populated(&k1, &Committed::Live).unwrap());
+        assert!(!f.key_populated(&k2, &Committed::Live).unwrap());
+        assert!(f.list_transactions().unwrap().is_empty());
+
+        // Deleting a transaction clears its removals
+        f.stage_removals(&hashset!(k1.clone()), "tx").unwrap();
+        assert_eq!(f.delete_transaction("tx").unwrap(), hashset!(k1.clone()));
+        assert!(f.pending_removals("tx").unwrap().is_empty());
+    }
+
+    #[test]
+    fn set_key_cancels_removal() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_keys(
+            &hashmap!(k1.clone() => "1", k2.clone() => "2"),
+            &Committed::Live,
+        )
+        .unwrap();
+
+        f.stage_removals(&hashset!(k1.clone(), k2.clone()), "tx")
+            .unwrap();
+        f.set_key(&k1, "3", &pending).unwrap();
+        assert_eq!(f.pending_removals("tx").unwrap(), hashset!(k2.clone()));
+
+        f.commit_transaction("tx").unwrap();
+        assert_eq!(
+            f.get_key(&k1, &Committed::Live).unwrap(),
+            Some("3".to_string())
+        );
+        assert!(!f.key_populated(&k2, &Committed::Live).unwrap());
+    }
+
+    #[test]
+    fn pending_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let md = Key::new(KeyType::Meta, "source").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        // Pending metadata isn't visible until it's committed
+        f.set_key(&k, "1", &pending).unwrap();
+        f.set_pending_metadata(&md, &k, "\"first\"", "tx").unwrap();
+        assert_eq!(f.get_metadata_raw(&md, &k).unwrap(), None);
+        assert_eq!(
+            f.list_populated_keys("", &pending).unwrap(),
+            hashset!(k.clone())
+        );
+        assert_eq!(
+            f.pending_metadata("tx").unwrap(),
+            hashmap!(k.clone() => hashmap!(md.clone() => "\"first\"".to_string()))
+        );
+        f.commit_transaction("tx").unwrap();
+        assert!(f.pending_metadata("tx").unwrap().is_empty());
+        assert_eq!(
+            f.get_metadata_raw(&md, &k).unwrap(),
+            Some("\"first\"".to_string())
+        );
+        assert!(f.list_transactions().unwrap().is_empty());
+
+        // Deleting a transaction discards its metadata
+        f.set_key(&k, "2", &pending).unwrap();
+        f.set_pending_metadata(&md, &k, "\"second\"", "tx").unwrap();
+        f.delete_transaction("tx").unwrap();
+        assert_eq!(
+            f.get_metadata_raw(&md, &k).unwrap(),
+            Some("\"first\"".to_string())
+        );
+    }
+
+    #[test]
+    fn replace_list_of_structures() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let element = Key::new(KeyType::Data, "settings.list.0.id").unwrap();
+        let md = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        f.set_key(&element, "1", &Committed::Live).unwrap();
+        f.set_metadata(&md, &element, "\"api\"").unwrap();
+
+        // The element's file and metadata are removed before the list is written in their place
+        // (An element staged earlier in the transaction is unset along with its metadata)
+        f.set_key(&element, "2", &pending).unwrap();
+        f.set_pending_metadata(&md, &element, "\"api\"", "tx")
+            .unwrap();
+        f.unset_key(&element, &pending).unwrap();
+        f.set_key(&list, "[]", &pending).unwrap();
+        f.set_pending_metadata(&md, &list, "\"api\"", "tx").unwrap();
+        f.stage_removals(&hashset!(element.clone()), "tx").unwrap();
+        f.commit_transaction("tx").unwrap();
+        assert_eq!(
+            f.get_key(&list, &Committed::Live).unwrap(),
+            Some("[]".into())
+        );
+        assert_eq!(f.get_key(&element, &Committed::Live).unwrap(), None);
+        assert_eq!(f.get_metadata_raw(&md, &element).unwrap(), None);
+
+        // Rolling back removes the list before restoring the element
+        f.rollback().unwrap();
+        assert_eq!(f.get_key(&list, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            f.get_key(&element, &Committed::Live).unwrap(),
+            Some("1".into())
+        );
+    }
+
+    #[test]
+    fn list_key_metadata() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let ab = Key::new(KeyType::Data, "settings.ab").unwrap();
+        let md1 = Key::new(KeyType::Meta, "md1").unwrap();
+        let md2 = Key::new(KeyType::Meta, "md2").unwrap();
+        f.set_key(&a, "1", &Committed::Live).unwrap();
+        f.set_key(&ab, "2", &Committed::Live).unwrap();
+        f.set_metadata(&md1, &a, "\"x\"").unwrap();
+        f.set_metadata(&md2, &ab, "\"y\"").unwrap();
+
+        // Only the metadata of the given key is listed, not that of keys sharing its name
+        assert_eq!(f.list_key_metadata(&a).unwrap(), hashset!(md1));
+        assert_eq!(f.list_key_metadata(&ab).unwrap(), hashset!(md2));
+        let unset = Key::new(KeyType::Data, "settings.c").unwrap();
+        assert!(f.list_key_metadata(&unset).unwrap().is_empty());
+    }
+
+    #[test]
+    fn read_errors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let directory = Key::new(KeyType::Data, "settings.a").unwrap();
+        let file = Key::new(KeyType::Data, "settings.b").unwrap();
+        let looped = Key::new(KeyType::Data, "settings.loop").unwrap();
+
+        // Directories of other keys, and paths under another key's file, aren't set
+        let child = Key::new(KeyType::Data, "settings.a.b").unwrap();
+        f.set_key(&child, "1", &Committed::Live).unwrap();
+        f.set_key(&file, "2", &Committed::Live).unwrap();
+        assert_eq!(f.get_key(&directory, &Committed::Live).unwrap(), None);
+        let under_file = Key::new(KeyType::Data, "settings.b.c").unwrap();
+        assert_eq!(f.get_key(&under_file, &Committed::Live).unwrap(), None);
+
+        // Other problems reading a key are reported
+        let path = f.data_path(&looped, &Committed::Live).unwrap();
+        std::os::unix::fs::symlink(&path, &path).unwrap();
+        assert!(f.get_key(&looped, &Committed::Live).is_err());
+    }
+
+    /// Sets up a data store with live data and a pending transaction that changes it in each way
+    /// a commit can: setting, adding, and removing keys, with metadata, and replacing a list.
+    fn setup_commit(path: &Path) -> FilesystemDataStore {
+        let mut f = FilesystemDataStore::new(path);
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.b").unwrap();
+        let c = Key::new(KeyType::Data, "settings.c").unwrap();
+        let list = Key::new(KeyType::Data, "settings.list").unwrap();
+        let element = Key::new(KeyType::Data, "settings.list.0.id").unwrap();
+        let md = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        f.set_keys(
+            &hashmap!(a.clone() => "1", b.clone() => "2", list.clone() => "[]"),
+            &Committed::Live,
+        )
+        .unwrap();
+        f.set_metadata(&md, &a, "\"defaults\"").unwrap();
+        f.set_metadata(&md, &b, "\"defaults\"").unwrap();
+
+        f.set_keys(
+            &hashmap!(a.clone() => "10", c.clone() => "3", element.clone() => "1"),
+            &pending,
+        )
+        .unwrap();
+        for key in &[&a, &c, &element] {
+            f.set_pending_metadata(&md, key, "\"api\"", "tx").unwrap();
+        }
+        f.stage_removals(&hashset!(b, list), "tx").unwrap();
+        f
+    }
+
+    /// Returns the contents of each file in the data store, by path.  Snapshots are normalized,
+    /// because the order of their values isn't fixed.
+    fn read_tree(path: &Path) -> HashMap<PathBuf, String> {
+        let mut tree = HashMap::new();
+        for entry in WalkDir::new(path) {
+            let entry = entry.unwrap();
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let mut data = fs::read_to_string(entry.path()).unwrap();
+            if entry.path().starts_with(path.join("snapshots")) {
+                let snapshot: serde_json::Value = serde_json::from_str(&data).unwrap();
+                data = snapshot.to_string();
+            }
+            tree.insert(entry.path().strip_prefix(path).unwrap().into(), data);
+        }
+        tree
+    }
+
+    /// Returns the data store contents from before and after an uninterrupted commit.
+    fn commit_trees() -> (HashMap<PathBuf, String>, HashMap<PathBuf, String>) {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = setup_commit(tmp.path());
+        let old = read_tree(tmp.path());
+        f.commit_transaction("tx").unwrap();
+        let new = read_tree(tmp.path());
+        assert_ne!(old, new);
+        assert!(f.list_transactions().unwrap().is_empty());
+        (old, new)
+    }
+
+    #[test]
+    fn interrupted_commit() {
+        let (old, new) = commit_trees();
+
+        // Crash at each step of the commit in turn, until it gets through every step
+        let (mut saw_old, mut saw_new) = (false, false);
+        for crash_after in 0.. {
+            let tmp = tempfile::tempdir().unwrap();
+            let mut f = setup_commit(tmp.path());
+            f.crash_after = Some(crash_after);
+            if f.commit_transaction("tx").is_ok() {
+                break;
+            }
+
+            // Start over, as after a reboot
+            let mut f = FilesystemDataStore::new(tmp.path());
+            let recovered = f.recover().unwrap();
+            let tree = read_tree(tmp.path());
+            if tree == old {
+                assert_eq!(recovered, None);
+                saw_old = true;
+            } else if tree == new {
+                assert_eq!(recovered, Some("tx".to_string()));
+                saw_new = true;
+            } else {
+                panic!("Partial commit after crash at step {}", crash_after);
+            }
+        }
+        assert!(saw_old && saw_new);
+    }
+
+    #[test]
+    fn interrupted_recovery() {
+        let (_, new) = commit_trees();
+
+        // Crash right after the journal is written, then crash at each step of recovery in turn
+        for crash_after in 0.. {
+            let tmp = tempfile::tempdir().unwrap();
+            let mut f = setup_commit(tmp.path());
+            f.crash_after = Some(2);
+            f.commit_transaction("tx").unwrap_err();
+            assert!(f.journal_path.exists());
+
+            let mut f = FilesystemDataStore::new(tmp.path());
+            f.crash_after = Some(crash_after);
+            let done = f.recover().is_ok();
+
+            let mut f = FilesystemDataStore::new(tmp.path());
+            f.recover().unwrap();
+            assert_eq!(read_tree(tmp.path()), new);
+            if done {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn commit_after_interrupted_commit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut f = setup_commit(tmp.path());
+        f.crash_after = Some(4);
+        f.commit_transaction("tx").unwrap_err();
+
+        // The next commit finishes the interrupted one first
+        let mut f = FilesystemDataStore::new(tmp.path());
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "next".into() };
+        f.set_key(&k, "11", &pending).unwrap();
+        f.commit_transaction("next").unwrap();
+        assert_eq!(f.list_snapshots().unwrap().len(), 2);
+        assert_eq!(f.rollback().unwrap(), Some(hashset!(k.clone())));
+        assert_eq!(f.get_key(&k, &Committed::Live).unwrap(), Some("10".into()));
+    }
 }
@@ -9,6 +9,9 @@ For more detail about their usage, see [apiserver](../apiserver).
 
 This library provides a trait defining the exact requirements, along with basic implementations for filesystem and memory data stores.
 
+`CachedDataStore` wraps another data store and keeps its live data and metadata in memory, so reads don't have to walk the underlying data store.
+It's updated only for the keys that are written through it, so it assumes nothing else writes live data while it's in use.
+
 There's also a common error type and some methods that implementations of DataStore should generally share, like scalar serialization.
 
 We represent scalars -- the actual values stored under a datastore key -- using JSON, just to have a convenient human-readable form.
@@ -20,13 +23,54 @@ The `serialization` module provides code to serialize Rust types into a mapping
 
 The `deserialization` module provides code to deserialize datastore-acceptable keys (a.b.c) and values into Rust types.
 
+Lists of scalars are stored as a single value, like `["a","b"]`.
+Lists of structures are stored with the index of each element as a key segment, like "a.b.0.c" and "a.b.1.c", so each element is stored like any other structure.
+When you replace a list of structures with a shorter one, the keys of the extra elements are left behind; `serialization::to_list_keys` gives you the lists in a value, so you can find and remove them.
+
+# Rollback
+
+When a transaction is committed, the data store first saves a `Snapshot` of the live values of the keys being changed, and of the metadata the commit changes, like provenance.
+The most recent `MAX_SNAPSHOTS` snapshots are kept, and `rollback` restores the newest one, undoing the last commit.
+Rolling back repeatedly undoes earlier commits, in reverse order.
+Commits that change nothing don't save a snapshot, so they don't use up a rollback.
+
+# Revisions
+
+Live data and each pending transaction have a revision number, starting at 0, that changes each time their data is changed, by setting or unsetting keys, or by a commit or rollback.
+Users can remember the revision they saw and check it before making changes, to detect changes made by others in the meantime.
+
+Revisions are taken from a counter shared by live data and all transactions that only ever goes up, so a revision is never reused.
+Committing or deleting a transaction also gives it a new revision, so a user holding a revision from before can't mistake a new transaction with the same name for the one they saw.
+
+# Removals
+
+Setting a key in a pending transaction can only add or change values in live data.
+To remove keys from live data, stage their removal in a pending transaction with `stage_removals`.
+When the transaction is committed, the keys are unset in live data before the other changes are made, and can be restored with a rollback like any other change.
+Setting a key in the transaction after staging its removal cancels the removal.
+
+# Pending metadata
+
+Metadata is normally written straight to live data, but it can also be staged in a pending transaction with `set_pending_metadata`.
+Staged metadata can be read back with `pending_metadata`, so it can be carried forward when migrating the data store.
+It's made live along with the data when the transaction is committed, and discarded if the transaction is deleted.
+Unsetting a key in the transaction discards its pending metadata, only metadata for keys the transaction writes is made live, and committing the removal of a key also removes its provenance.
+This is used to record the `Provenance` of each setting, so it describes the committed value rather than one that may never be committed.
+
+# Crash safety
+
+`FilesystemDataStore` makes each commit atomic: it records the changes in a journal before changing live data, and removes the journal once they're all on disk.
+If the commit is interrupted, for example by a power loss, `FilesystemDataStore::recover` replays the journal to finish it; if the journal hadn't been written yet, the commit didn't happen, and the transaction is still pending.
+Users should call `recover` before using a data store that may have been in use when the system went down; commits also call it first.
+Rollbacks aren't journaled, but a rollback only removes its snapshot once it's done, so an interrupted rollback can be retried.
+
 # Current limitations
 
 * The user (e.g. apiserver) needs to handle locking.
-* There's no support for rolling back transactions.
-* The `serialization` module can't handle complex types under lists; it assumes lists can be serialized as scalars.
+* A list can't mix structures with other values, and an empty list is stored as a single value, like a list of scalars.
 */
 
+pub mod cache;
 pub mod deserialization;
 pub mod error;
 pub mod filesystem;
@@ -34,15 +78,20 @@ pub mod key;
 pub mod memory;
 pub mod serialization;
 
+pub use cache::CachedDataStore;
 pub use error::{Error, Result};
 pub use filesystem::FilesystemDataStore;
 pub use key::{Key, KeyType, KEY_SEPARATOR, KEY_SEPARATOR_STR};
 
-use log::trace;
+use log::{debug, trace};
 use serde::{Deserialize, Serialize};
 use snafu::OptionExt;
 use std::collections::{HashMap, HashSet};
 
+/// The number of commits we keep snapshots of, and so the number of commits that can be rolled
+/// back.  Older snapshots are removed as new commits are made.
+pub const MAX_SNAPSHOTS: usize = 10;
+
 /// Committed represents whether we want to look at pending (uncommitted) or live (committed) data
 /// in the datastore.
 #[derive(Debug, Clone)]
@@ -54,6 +103,50 @@ pub enum Committed {
     },
 }
 
+/// Snapshot holds the live values of a set of data keys from before a commit, so that the commit
+/// can be rolled back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The name of the committed transaction.
+    pub transaction: String,
+    /// Mapping of data key names to their serialized live values before the commit, or None if
+    /// the key wasn't set.
+    pub values: HashMap<String, Option<String>>,
+    /// Mapping of data key names to the live values of the metadata the commit changed, by
+    /// metadata key name, or None if the metadata key wasn't set.
+    #[serde(default)]
+    pub metadata: HashMap<String, HashMap<String, Option<String>>>,
+}
+
+impl Snapshot {
+    /// Returns whether the snapshot has nothing to restore.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.metadata.is_empty()
+    }
+}
+
+/// The name of the metadata key under which the `Provenance` of a data key is stored.
+pub const PROVENANCE_METADATA_KEY: &str = "provenance";
+
+/// The name of the metadata key that marks a data key as sensitive, with a value of true.  The
+/// values of sensitive data keys, and keys under them, are redacted when they're read through the
+/// API unless they're explicitly requested.
+pub const SENSITIVE_METADATA_KEY: &str = "sensitive";
+
+/// Provenance records where the live value of a data key came from.  It's stored in the metadata
+/// of each data key when its value is written, and so describes the most recent write.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The source of the write, for example "defaults", "user-data", "generator", or "api".
+    pub source: String,
+    /// The name of the transaction in which the value was written.
+    pub transaction: String,
+}
+
+/// The API request header that names the source of a settings change, for example "user-data",
+/// which the API server records in the `Provenance` of the changed settings.
+pub const SETTING_SOURCE_HEADER: &str = "Setting-Source";
+
 pub trait DataStore {
     /// Returns whether a key is present (has a value) in the datastore.
     fn key_populated(&self, key: &Key, committed: &Committed) -> Result<bool>;
@@ -77,14 +170,20 @@ pub trait DataStore {
     where
         S1: AsRef<str>,
         S2: AsRef<str>;
+    /// Finds the metadata keys that are currently populated for the given data key, without
+    /// looking at other data keys.  Metadata inherited from earlier in the tree isn't included.
+    fn list_key_metadata(&self, data_key: &Key) -> Result<HashSet<Key>>;
 
     /// Retrieve the value for a single data key from the datastore.
     fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>>;
-    /// Set the value of a single data key in the datastore.
+    /// Set the value of a single data key in the datastore.  In a pending transaction, this
+    /// cancels any staged removal of the key.  Implementations should bump the revision of the
+    /// data.
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()>;
     /// Removes the given data key from the datastore.  If we succeeded, we return Ok(()); if
     /// the key didn't exist, we also return Ok(()); we return Err only if we failed to check
-    /// or remove the key.
+    /// or remove the key.  In a pending transaction, any pending metadata for the key is removed
+    /// too.  Implementations should bump the revision of the data.
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()>;
 
     /// Retrieve the value for a single metadata key from the datastore.  Values will inherit from
@@ -122,6 +221,19 @@ pub trait DataStore {
     /// succeeded, we return Ok(()); if the data or metadata key didn't exist, we also return
     /// Ok(()); we return Err only if we failed to check or remove the key.
     fn unset_metadata(&mut self, metadata_key: &Key, data_key: &Key) -> Result<()>;
+    /// Set the value of a single metadata key in the given pending transaction.  Implementations
+    /// should make the metadata live when committing the transaction, and discard it when deleting
+    /// the transaction.
+    fn set_pending_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        transaction: &str,
+    ) -> Result<()>;
+    /// Returns the metadata staged in the given pending transaction, as a mapping of data keys to
+    /// their metadata keys and values.  If the transaction doesn't exist, returns an empty map.
+    fn pending_metadata(&self, transaction: &str) -> Result<HashMap<Key, HashMap<Key, String>>>;
 
     /// Applies pending changes from the given transaction to the live datastore.  Returns the
     /// list of changed keys.
@@ -138,7 +250,134 @@ pub trait DataStore {
     /// Returns a list of the names of any pending transactions in the data store.
     fn list_transactions(&self) -> Result<HashSet<String>>;
 
-    /// Set multiple data keys at once in the data store.
+    /// Returns the data keys staged for removal in the given pending transaction.
+    fn pending_removals(&self, transaction: &str) -> Result<HashSet<Key>>;
+
+    /// Replaces the data keys staged for removal in the given pending transaction; an empty set
+    /// clears them.  Implementations should unset the staged keys in live data when committing a
+    /// transaction, and clear them when committing or deleting a transaction.
+    fn set_pending_removals(&mut self, transaction: &str, keys: &HashSet<Key>) -> Result<()>;
+
+    /// Stages removal of the given data keys in the given pending transaction, so they're unset
+    /// in live data when the transaction is committed.  Any pending values for the keys are
+    /// removed.
+    fn stage_removals(&mut self, keys: &HashSet<Key>, transaction: &str) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let pending = Committed::Pending {
+            tx: transaction.to_string(),
+        };
+        for key in keys {
+            trace!("Staging removal of data key {}", key.name());
+            self.unset_key(key, &pending)?;
+        }
+        let mut removals = self.pending_removals(transaction)?;
+        removals.extend(keys.iter().cloned());
+        self.set_pending_removals(transaction, &removals)
+    }
+
+    /// Returns the revision of live data or of the given pending transaction.  Data that has
+    /// never been changed has revision 0.
+    fn revision(&self, committed: &Committed) -> Result<u64>;
+
+    /// Gives live data or the given pending transaction the next revision from the data store's
+    /// revision counter, returning it.  The counter is shared by live data and all transactions,
+    /// and is never reset, so revisions only go up.  Implementations should call this when
+    /// setting or unsetting a key, for live data and the transaction when committing a
+    /// transaction, and for the transaction when deleting it.
+    fn bump_revision(&mut self, committed: &Committed) -> Result<u64>;
+
+    /// Saves the given snapshot as the newest, removing the oldest snapshots if there are more
+    /// than MAX_SNAPSHOTS.  Implementations should call this when committing a transaction that
+    /// changes something, before changing live data; see `snapshot_keys`.
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()>;
+
+    /// Returns the saved snapshots, oldest first.
+    fn list_snapshots(&self) -> Result<Vec<Snapshot>>;
+
+    /// Returns the newest snapshot, if there are any.
+    fn newest_snapshot(&self) -> Result<Option<Snapshot>>;
+
+    /// Removes the newest snapshot, returning it.  If there are no snapshots, returns Ok(None).
+    fn remove_newest_snapshot(&mut self) -> Result<Option<Snapshot>>;
+
+    /// Builds a snapshot of the current live values of the given data keys, and of the given
+    /// metadata keys of each data key, to be saved before committing the given transaction.
+    fn snapshot_keys<S: Into<String>>(
+        &self,
+        transaction: S,
+        keys: &HashSet<Key>,
+        metadata_keys: &HashMap<Key, HashSet<Key>>,
+    ) -> Result<Snapshot> {
+        let mut values = HashMap::new();
+        for key in keys {
+            let value = self.get_key(key, &Committed::Live)?;
+            values.insert(key.name().clone(), value);
+        }
+        let mut metadata = HashMap::new();
+        for (data_key, metadata_keys) in metadata_keys {
+            let mut data_metadata = HashMap::new();
+            for metadata_key in metadata_keys {
+                let value = self.get_metadata_raw(metadata_key, data_key)?;
+                data_metadata.insert(metadata_key.name().clone(), value);
+            }
+            metadata.insert(data_key.name().clone(), data_metadata);
+        }
+        Ok(Snapshot {
+            transaction: transaction.into(),
+            values,
+            metadata,
+        })
+    }
+
+    /// Undoes the most recent commit by restoring live data and metadata from the newest
+    /// snapshot, then removes the snapshot.  Returns the list of changed keys, or None if there
+    /// are no snapshots.
+    fn rollback(&mut self) -> Result<Option<HashSet<Key>>> {
+        let snapshot = match self.newest_snapshot()? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        debug!(
+            "Rolling back commit of transaction '{}'",
+            snapshot.transaction
+        );
+
+        // Like a commit, remove keys before setting any, so removed keys aren't in the way.
+        let mut changed = HashSet::new();
+        for (name, value) in &snapshot.values {
+            if value.is_none() {
+                let key = Key::new(KeyType::Data, name)?;
+                self.unset_key(&key, &Committed::Live)?;
+                changed.insert(key);
+            }
+        }
+        for (name, value) in &snapshot.values {
+            if let Some(value) = value {
+                let key = Key::new(KeyType::Data, name)?;
+                self.set_key(&key, value, &Committed::Live)?;
+                changed.insert(key);
+            }
+        }
+        for (data_name, metadata) in &snapshot.metadata {
+            let data_key = Key::new(KeyType::Data, data_name)?;
+            for (metadata_name, value) in metadata {
+                let metadata_key = Key::new(KeyType::Meta, metadata_name)?;
+                match value {
+                    Some(value) => self.set_metadata(&metadata_key, &data_key, value)?,
+                    None => self.unset_metadata(&metadata_key, &data_key)?,
+                }
+            }
+        }
+
+        // Only remove the snapshot once it's restored, so a failed rollback can be retried.
+        self.remove_newest_snapshot()?;
+        Ok(Some(changed))
+    }
+
+    /// Set multiple data keys at once in the data store.  Like set_key, this cancels any staged
+    /// removal of the keys in a pending transaction.
     ///
     /// Implementers can replace the default implementation if there's a faster way than setting
     /// each key individually.
@@ -5,7 +5,9 @@
 
 use std::collections::{HashMap, HashSet};
 
-use super::{Committed, DataStore, Key, Result};
+use super::{
+    Committed, DataStore, Key, KeyType, Result, Snapshot, MAX_SNAPSHOTS, PROVENANCE_METADATA_KEY,
+};
 
 #[derive(Debug)]
 pub struct MemoryDataStore {
@@ -16,6 +18,17 @@ pub struct MemoryDataStore {
     // Map of data keys to their metadata, which in turn is a mapping of metadata keys to
     // arbitrary (string/serialized) values.
     metadata: HashMap<Key, HashMap<Key, String>>,
+    // Snapshots of live data from before each commit, oldest first.
+    snapshots: Vec<Snapshot>,
+    // The last revision handed out, and the revision of live data and of each transaction that's
+    // been changed, including committed and deleted ones.
+    latest_revision: u64,
+    live_revision: u64,
+    pending_revisions: HashMap<String, u64>,
+    // Transaction name -> keys staged for removal.
+    pending_removals: HashMap<String, HashSet<Key>>,
+    // Transaction name -> (data key -> (metadata key -> value)) staged in the transaction.
+    pending_metadata: HashMap<String, HashMap<Key, HashMap<Key, String>>>,
 }
 
 impl MemoryDataStore {
@@ -24,6 +37,12 @@ impl MemoryDataStore {
             pending: HashMap::new(),
             live: HashMap::new(),
             metadata: HashMap::new(),
+            snapshots: Vec::new(),
+            latest_revision: 0,
+            live_revision: 0,
+            pending_revisions: HashMap::new(),
+            pending_removals: HashMap::new(),
+            pending_metadata: HashMap::new(),
         }
     }
 
@@ -93,6 +112,14 @@ impl DataStore for MemoryDataStore {
         Ok(result)
     }
 
+    fn list_key_metadata(&self, data_key: &Key) -> Result<HashSet<Key>> {
+        Ok(self
+            .metadata
+            .get(data_key)
+            .map(|meta_map| meta_map.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
     fn get_key(&self, key: &Key, committed: &Committed) -> Result<Option<String>> {
         let empty = HashMap::new();
         let dataset = self.dataset(committed).unwrap_or(&empty);
@@ -102,11 +129,26 @@ impl DataStore for MemoryDataStore {
     fn set_key<S: AsRef<str>>(&mut self, key: &Key, value: S, committed: &Committed) -> Result<()> {
         self.dataset_mut(committed)
             .insert(key.clone(), value.as_ref().to_owned());
+        if let Committed::Pending { tx } = committed {
+            if let Some(removals) = self.pending_removals.get_mut(tx) {
+                removals.remove(key);
+                if removals.is_empty() {
+                    self.pending_removals.remove(tx);
+                }
+            }
+        }
+        self.bump_revision(committed)?;
         Ok(())
     }
 
     fn unset_key(&mut self, key: &Key, committed: &Committed) -> Result<()> {
         self.dataset_mut(committed).remove(key);
+        if let Committed::Pending { tx } = committed {
+            if let Some(metadata) = self.pending_metadata.get_mut(tx) {
+                metadata.remove(key);
+            }
+        }
+        self.bump_revision(committed)?;
         Ok(())
     }
 
@@ -150,44 +192,201 @@ impl DataStore for MemoryDataStore {
         Ok(())
     }
 
+    fn set_pending_metadata<S: AsRef<str>>(
+        &mut self,
+        metadata_key: &Key,
+        data_key: &Key,
+        value: S,
+        transaction: &str,
+    ) -> Result<()> {
+        self.pending_metadata
+            .entry(transaction.to_string())
+            .or_default()
+            .entry(data_key.clone())
+            .or_default()
+            .insert(metadata_key.clone(), value.as_ref().to_owned());
+        Ok(())
+    }
+
+    fn pending_metadata(&self, transaction: &str) -> Result<HashMap<Key, HashMap<Key, String>>> {
+        Ok(self
+            .pending_metadata
+            .get(transaction)
+            .cloned()
+            .unwrap_or_default())
+    }
+
     fn commit_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
+        let tx = Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        };
+        if self.revision(&tx)? > 0 {
+            self.bump_revision(&tx)?;
+        }
+        let pending_metadata = self
+            .pending_metadata
+            .remove(transaction.as_ref())
+            .unwrap_or_default();
         // Remove anything pending for this transaction
-        if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Apply pending changes to live
-            self.set_keys(&pending, &Committed::Live)?;
-            // Return keys that were committed
-            Ok(pending.keys().cloned().collect())
-        } else {
-            Ok(HashSet::new())
+        let pending = self
+            .pending
+            .remove(transaction.as_ref())
+            .unwrap_or_default();
+        // Removing keys that aren't set in live data doesn't change anything
+        let removals: HashSet<Key> = self
+            .pending_removals
+            .remove(transaction.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|key| self.live.contains_key(key))
+            .collect();
+        if pending.is_empty() && removals.is_empty() {
+            return Ok(HashSet::new());
         }
+
+        // Save the live values and metadata we're about to change, so the commit can be rolled
+        // back
+        let mut keys: HashSet<Key> = pending.keys().cloned().collect();
+        keys.extend(removals.iter().cloned());
+        let provenance_key = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY)?;
+        let mut metadata_keys: HashMap<Key, HashSet<Key>> = HashMap::new();
+        for key in &removals {
+            metadata_keys
+                .entry(key.clone())
+                .or_default()
+                .insert(provenance_key.clone());
+        }
+        for (data_key, metadata) in &pending_metadata {
+            if pending.contains_key(data_key) {
+                metadata_keys
+                    .entry(data_key.clone())
+                    .or_default()
+                    .extend(metadata.keys().cloned());
+            }
+        }
+        let snapshot = self.snapshot_keys(transaction, &keys, &metadata_keys)?;
+        self.save_snapshot(&snapshot)?;
+        // Apply pending changes to live, removals first to match FilesystemDataStore
+        self.unset_keys(&removals, &Committed::Live)?;
+        for key in &removals {
+            self.unset_metadata(&provenance_key, key)?;
+        }
+        self.set_keys(&pending, &Committed::Live)?;
+        for (data_key, metadata) in pending_metadata {
+            if !pending.contains_key(&data_key) {
+                continue;
+            }
+            for (metadata_key, value) in metadata {
+                self.set_metadata(&metadata_key, &data_key, value)?;
+            }
+        }
+        // Return keys that were committed
+        Ok(keys)
     }
 
     fn delete_transaction<S>(&mut self, transaction: S) -> Result<HashSet<Key>>
     where
         S: Into<String> + AsRef<str>,
     {
-        // Remove anything pending for this transaction
-        if let Some(pending) = self.pending.remove(transaction.as_ref()) {
-            // Return the old pending keys
-            Ok(pending.keys().cloned().collect())
-        } else {
-            Ok(HashSet::new())
+        let tx = Committed::Pending {
+            tx: transaction.as_ref().to_string(),
+        };
+        if self.revision(&tx)? > 0 {
+            self.bump_revision(&tx)?;
         }
+        self.pending_metadata.remove(transaction.as_ref());
+        // Remove anything pending for this transaction, and return the old pending keys
+        let mut keys: HashSet<Key> = self
+            .pending
+            .remove(transaction.as_ref())
+            .map(|pending| pending.keys().cloned().collect())
+            .unwrap_or_default();
+        keys.extend(
+            self.pending_removals
+                .remove(transaction.as_ref())
+                .unwrap_or_default(),
+        );
+        Ok(keys)
     }
 
     fn list_transactions(&self) -> Result<HashSet<String>> {
-        Ok(self.pending.keys().cloned().collect())
+        Ok(self
+            .pending
+            .keys()
+            .chain(self.pending_removals.keys())
+            .cloned()
+            .collect())
+    }
+
+    fn pending_removals(&self, transaction: &str) -> Result<HashSet<Key>> {
+        Ok(self
+            .pending_removals
+            .get(transaction)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn set_pending_removals(&mut self, transaction: &str, keys: &HashSet<Key>) -> Result<()> {
+        if keys.is_empty() {
+            self.pending_removals.remove(transaction);
+        } else {
+            self.pending_removals
+                .insert(transaction.to_string(), keys.clone());
+        }
+        Ok(())
+    }
+
+    fn revision(&self, committed: &Committed) -> Result<u64> {
+        Ok(match committed {
+            Committed::Live => self.live_revision,
+            Committed::Pending { tx } => self.pending_revisions.get(tx).copied().unwrap_or(0),
+        })
+    }
+
+    fn bump_revision(&mut self, committed: &Committed) -> Result<u64> {
+        self.latest_revision += 1;
+        let revision = match committed {
+            Committed::Live => &mut self.live_revision,
+            Committed::Pending { tx } => self.pending_revisions.entry(tx.clone()).or_default(),
+        };
+        *revision = self.latest_revision;
+        Ok(*revision)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.is_empty() {
+            return Ok(());
+        }
+        self.snapshots.push(snapshot.clone());
+        let excess = self.snapshots.len().saturating_sub(MAX_SNAPSHOTS);
+        self.snapshots.drain(..excess);
+        Ok(())
+    }
+
+    fn list_snapshots(&self) -> Result<Vec<Snapshot>> {
+        Ok(self.snapshots.clone())
+    }
+
+    fn newest_snapshot(&self) -> Result<Option<Snapshot>> {
+        Ok(self.snapshots.last().cloned())
+    }
+
+    fn remove_newest_snapshot(&mut self) -> Result<Option<Snapshot>> {
+        Ok(self.snapshots.pop())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::super::{Committed, DataStore, Key, KeyType};
+    use super::super::{
+        Committed, DataStore, Key, KeyType, Snapshot, MAX_SNAPSHOTS, PROVENANCE_METADATA_KEY,
+    };
     use super::MemoryDataStore;
-    use maplit::hashset;
+    use maplit::{hashmap, hashset};
+    use std::collections::HashMap;
 
     #[test]
     fn get_set_unset() {
@@ -273,4 +472,198 @@ mod test {
         // Assure other transactions were not deleted
         assert!(m.key_populated(&k2, &pending2).unwrap());
     }
+
+    #[test]
+    fn rollback() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        m.set_key(&k1, "1", &Committed::Live).unwrap();
+
+        // Change one existing key and add a new one
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k1, "2", &pending).unwrap();
+        m.set_key(&k2, "3", &pending).unwrap();
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(m.list_snapshots().unwrap().len(), 1);
+
+        let changed = m.rollback().unwrap().unwrap();
+        assert_eq!(changed, hashset!(k1.clone(), k2.clone()));
+        assert_eq!(
+            m.get_key(&k1, &Committed::Live).unwrap(),
+            Some("1".to_string())
+        );
+        assert_eq!(m.get_key(&k2, &Committed::Live).unwrap(), None);
+
+        // Nothing left to roll back
+        assert!(m.list_snapshots().unwrap().is_empty());
+        assert!(m.rollback().unwrap().is_none());
+    }
+
+    #[test]
+    fn rollback_metadata() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let md = Key::new(KeyType::Meta, PROVENANCE_METADATA_KEY).unwrap();
+        m.set_key(&k1, "1", &Committed::Live).unwrap();
+        m.set_key(&k2, "1", &Committed::Live).unwrap();
+        m.set_metadata(&md, &k1, "\"old\"").unwrap();
+        m.set_metadata(&md, &k2, "\"old\"").unwrap();
+
+        // Change one key's provenance and remove the other key
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k1, "2", &pending).unwrap();
+        m.set_pending_metadata(&md, &k1, "\"new\"", "tx").unwrap();
+        m.stage_removals(&hashset!(k2.clone()), "tx").unwrap();
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&md, &k1).unwrap(),
+            Some("\"new\"".into())
+        );
+        assert_eq!(m.get_metadata_raw(&md, &k2).unwrap(), None);
+
+        m.rollback().unwrap().unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&md, &k1).unwrap(),
+            Some("\"old\"".into())
+        );
+        assert_eq!(
+            m.get_metadata_raw(&md, &k2).unwrap(),
+            Some("\"old\"".into())
+        );
+    }
+
+    #[test]
+    fn rollback_empty_snapshot() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_key(&k, "1", &pending).unwrap();
+        m.commit_transaction("tx").unwrap();
+
+        // Snapshots with nothing to restore aren't saved, so they don't hide earlier commits
+        m.save_snapshot(&Snapshot {
+            transaction: "empty".into(),
+            values: HashMap::new(),
+            metadata: HashMap::new(),
+        })
+        .unwrap();
+        assert_eq!(m.newest_snapshot().unwrap().unwrap().transaction, "tx");
+        assert_eq!(m.rollback().unwrap(), Some(hashset!(k)));
+    }
+
+    #[test]
+    fn snapshots_bounded() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        for i in 0..MAX_SNAPSHOTS + 2 {
+            let tx = format!("tx{}", i);
+            m.set_key(&k, i.to_string(), &Committed::Pending { tx: tx.clone() })
+                .unwrap();
+            m.commit_transaction(tx).unwrap();
+        }
+
+        let snapshots = m.list_snapshots().unwrap();
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS);
+        // The oldest snapshots were removed
+        assert_eq!(snapshots[0].transaction, "tx2");
+        assert_eq!(snapshots[0].values["settings.a"], Some("1".to_string()));
+    }
+
+    #[test]
+    fn revisions() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        m.set_keys(&hashmap!(k.clone() => "1"), &pending).unwrap();
+        assert_eq!(m.revision(&pending).unwrap(), 1);
+        m.set_key(&k, "2", &pending).unwrap();
+        assert_eq!(m.revision(&pending).unwrap(), 2);
+
+        // Committing changes live data and the transaction, which doesn't go back to 0
+        m.commit_transaction("tx").unwrap();
+        let committed = m.revision(&pending).unwrap();
+        assert!(committed > 2);
+        assert!(m.revision(&Committed::Live).unwrap() > 2);
+
+        // Staging changes again moves on from there, so an old revision can't match
+        m.set_key(&k, "3", &pending).unwrap();
+        assert!(m.revision(&pending).unwrap() > committed);
+        m.delete_transaction("tx").unwrap();
+        assert!(m.revision(&pending).unwrap() > committed + 1);
+    }
+
+    #[test]
+    fn removals() {
+        let mut m = MemoryDataStore::new();
+        let k1 = Key::new(KeyType::Data, "settings.a").unwrap();
+        let k2 = Key::new(KeyType::Data, "settings.b").unwrap();
+        let unset = Key::new(KeyType::Data, "settings.c").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+        m.set_keys(
+            &hashmap!(k1.clone() => "1", k2.clone() => "2"),
+            &Committed::Live,
+        )
+        .unwrap();
+
+        m.set_keys(&hashmap!(k1.clone() => "3"), &pending).unwrap();
+        m.stage_removals(&hashset!(k1.clone(), k2.clone(), unset), "tx")
+            .unwrap();
+        // Staging a removal drops the pending value
+        assert_eq!(m.get_key(&k1, &pending).unwrap(), None);
+        // Setting a key cancels its removal
+        m.set_key(&k2, "4", &pending).unwrap();
+        assert_eq!(m.list_transactions().unwrap(), hashset!("tx".to_string()));
+
+        // Only keys that were set in live data are reported as changed
+        let changed = m.commit_transaction("tx").unwrap();
+        assert_eq!(changed, hashset!(k1.clone(), k2.clone()));
+        assert_eq!(m.get_key(&k1, &Committed::Live).unwrap(), None);
+        assert_eq!(
+            m.get_key(&k2, &Committed::Live).unwrap(),
+            Some("4".to_string())
+        );
+        assert!(m.pending_removals("tx").unwrap().is_empty());
+
+        // Removals can be rolled back
+        m.rollback().unwrap();
+        assert_eq!(
+            m.get_key(&k1, &Committed::Live).unwrap(),
+            Some("1".to_string())
+        );
+    }
+
+    #[test]
+    fn pending_metadata() {
+        let mut m = MemoryDataStore::new();
+        let k = Key::new(KeyType::Data, "settings.a").unwrap();
+        let md = Key::new(KeyType::Meta, "source").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        m.set_key(&k, "1", &pending).unwrap();
+        m.set_pending_metadata(&md, &k, "first", "tx").unwrap();
+        assert_eq!(m.get_metadata_raw(&md, &k).unwrap(), None);
+        assert_eq!(
+            m.pending_metadata("tx").unwrap(),
+            hashmap!(k.clone() => hashmap!(md.clone() => "first".to_string()))
+        );
+        m.commit_transaction("tx").unwrap();
+        assert!(m.pending_metadata("tx").unwrap().is_empty());
+        assert_eq!(
+            m.get_metadata_raw(&md, &k).unwrap(),
+            Some("first".to_string())
+        );
+
+        // Deleting a transaction discards its metadata
+        m.set_key(&k, "2", &pending).unwrap();
+        m.set_pending_metadata(&md, &k, "second", "tx").unwrap();
+        m.delete_transaction("tx").unwrap();
+        m.commit_transaction("tx").unwrap();
+        assert_eq!(
+            m.get_metadata_raw(&md, &k).unwrap(),
+            Some("first".to_string())
+        );
+    }
 }
@@ -27,14 +27,14 @@ pub enum Error {
     #[snafu(display("Error serializing {}: {} ", given, source))]
     Serialization { given: String, source: ScalarError },
 
-    #[snafu(display("Error deserializing {}: {} ", given, source))]
-    Deserialization { given: String, source: ScalarError },
-
     #[snafu(display("'{}' not allowed by Serializer", typename))]
     InvalidType { typename: String },
 
     #[snafu(display("'{}' not allowed as map key", typename))]
     BadMapKey { typename: String },
+
+    #[snafu(display("Invalid list: {}", msg))]
+    InvalidList { msg: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
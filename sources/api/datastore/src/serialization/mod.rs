@@ -5,7 +5,7 @@ mod error;
 mod pairs;
 
 pub use error::{Error, Result};
-pub use pairs::{to_pairs, to_pairs_with_prefix};
+pub use pairs::{to_list_keys, to_pairs, to_pairs_with_prefix};
 
 use log::{debug, trace};
 use serde::{ser, Serialize};
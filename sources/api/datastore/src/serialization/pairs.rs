@@ -10,8 +10,8 @@
 
 use log::trace;
 use serde::{ser, Serialize};
-use snafu::{IntoError, NoneError as NoSource, OptionExt, ResultExt};
-use std::collections::HashMap;
+use snafu::{ensure, IntoError, NoneError as NoSource, OptionExt, ResultExt};
+use std::collections::{HashMap, HashSet};
 
 use super::{error, Error, MapKeySerializer, Result};
 use crate::{serialize_scalar, Key, KeyType, ScalarError};
@@ -21,11 +21,16 @@ use crate::{serialize_scalar, Key, KeyType, ScalarError};
 ///    Settings -> DockerSettings -> bridge_ip = u64
 /// would turn into a key of "settings.docker-settings.bridge-ip" and a serialized String
 /// representing the u64 data.
+///
+/// Lists of scalars are serialized as a single value, like "[1,2,3]".  Lists of structures are
+/// serialized with a key per element, using the element's index as a key segment; for example, a
+/// list of structs with an 'id' field would turn into keys like "settings.list.0.id" and
+/// "settings.list.1.id".
 pub fn to_pairs<T: Serialize>(value: &T) -> Result<HashMap<Key, String>> {
-    let mut output = HashMap::new();
+    let mut output = Output::default();
     let serializer = Serializer::new(&mut output, None);
     value.serialize(serializer)?;
-    Ok(output)
+    Ok(output.pairs)
 }
 
 /// Like to_pairs, but lets you add an arbitrary prefix to the resulting keys.  A separator will
@@ -43,10 +48,30 @@ where
         .into_error(NoSource)
     })?;
 
-    let mut output = HashMap::new();
+    let mut output = Output::default();
     let serializer = Serializer::new(&mut output, Some(prefix_key));
     value.serialize(serializer)?;
-    Ok(output)
+    Ok(output.pairs)
+}
+
+/// Returns the keys of the lists in the given value, named the same way as the keys from
+/// to_pairs.  The elements of a list of structures are stored under the list's key, so when you
+/// replace a list, you can use this to find the elements of the old list that are no longer
+/// present.
+pub fn to_list_keys<T: Serialize>(value: &T) -> Result<HashSet<Key>> {
+    let mut output = Output::default();
+    let serializer = Serializer::new(&mut output, None);
+    value.serialize(serializer)?;
+    Ok(output.lists)
+}
+
+/// Output collects the results of serialization.
+#[derive(Debug, Default)]
+struct Output {
+    /// The serialized values, keyed by their location in the structure.
+    pairs: HashMap<Key, String>,
+    /// The keys of any lists we found.
+    lists: HashSet<Key>,
 }
 
 /////
@@ -54,17 +79,14 @@ where
 /// Serializer does most of the work by recursively serializing compound structures, and trivially
 /// serializing scalars.
 ///
-/// Caveat: for a list/tuple, the elements inside only have indexes, which doesn't work well with
-/// the data store.  Lists are common enough that we need some answer, so we say that lists can
-/// only contain scalars, not further compound objects.  That way we can serialize the list
-/// directly (see FlatSerializer) rather than as a compound.
-///
-/// (We could handle lists as proper compound structures by improving the data store such that it
-/// can store unnamed sub-components, perhaps by using a visible index ("a.b.c[0]", "a.b.c[1]").
-/// It's more common to use a HashMap in the model, and then to use named keys instead of indexes,
-/// which works fine.)
+/// Caveat: for a list/tuple, the elements inside only have indexes, not names.  Lists of scalars
+/// are serialized directly as a single value (see ListSerializer), which keeps them simple to read
+/// and write.  Lists of compound structures use the index of each element as a key segment, like
+/// "a.b.c.0.d", so each element can be stored like any other structure.  It's still more common
+/// to use a HashMap in the model, and then to use named keys instead of indexes, which are easier
+/// to refer to.
 struct Serializer<'a> {
-    output: &'a mut HashMap<Key, String>,
+    output: &'a mut Output,
     prefix: Option<Key>,
     // This is temporary storage for serializing maps, because serde gives us keys and values
     // separately.  See the SerializeMap implementation below.
@@ -72,7 +94,7 @@ struct Serializer<'a> {
 }
 
 impl<'a> Serializer<'a> {
-    fn new(output: &'a mut HashMap<Key, String>, prefix: Option<Key>) -> Self {
+    fn new(output: &'a mut Output, prefix: Option<Key>) -> Self {
         Self {
             output,
             prefix,
@@ -96,7 +118,7 @@ macro_rules! concrete_output {
                 given: format!("concrete value '{}'", $value),
             })?;
         let prefix = expect_prefix($self.prefix, &value)?;
-        $self.output.insert(prefix, value);
+        $self.output.pairs.insert(prefix, value);
         return Ok(());
     };
 }
@@ -113,7 +135,7 @@ impl<'a> ser::Serializer for Serializer<'a> {
     type Error = Error;
 
     // See the docs on Serializer for reasoning about this.
-    type SerializeSeq = FlatSerializer<'a>;
+    type SerializeSeq = ListSerializer<'a>;
     type SerializeTuple = ser::Impossible<(), Error>;
     type SerializeTupleStruct = ser::Impossible<(), Error>;
     type SerializeTupleVariant = ser::Impossible<(), Error>;
@@ -143,7 +165,7 @@ impl<'a> ser::Serializer for Serializer<'a> {
 
     // Compound types
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Ok(FlatSerializer::new(self.output, expect_prefix(self.prefix, "seq")?))
+        Ok(ListSerializer::new(self.output, expect_prefix(self.prefix, "seq")?))
     }
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
         Ok(Serializer::new(self.output, self.prefix))
@@ -321,33 +343,46 @@ impl<'a> ser::SerializeStruct for Serializer<'a> {
 
 /////
 
-/// This serializes compound structures into a flat blob, for cases where recursively serializing
-/// compound structures doesn't make sense.  See Serializer for detail on why it uses this.
+/// This serializes lists.  Lists of scalars are serialized into a single value, because their
+/// elements have no names to use as keys, and the whole list is usually changed at once anyway.
+/// Lists of compound structures are serialized with the index of each element as the next key
+/// segment, because their contents need to be stored as separate keys like any other structure.
 ///
-/// Warning; this requires hacks.  serde gives you three callbacks during serialization - starting
-/// the structure, for each element, and ending the structure.  There's no option to handle an
-/// entire structure at once, which is exactly what we'd want.  We can't clone the elements, since
-/// we only have a Serialize bound, and I couldn't figure out how to store the references, so we do
-/// the unthinkable - serialize each element to a String, store those in a list during the
-/// serialization steps, and then at the end, deserialize the strings back into a list of the
-/// original type, and serialize the entire list.  Sorry.
-struct FlatSerializer<'a> {
-    output: &'a mut HashMap<Key, String>,
+/// serde gives us the elements one at a time, and we don't know what kind of list we have until
+/// we see them.  We can't store the elements themselves, since we only have a reference with a
+/// Serialize bound, so we serialize each element to a generic JSON value to check what it is.  If
+/// it's a structure, we serialize it right away under its index; otherwise we keep the JSON value
+/// so we can serialize the entire list at the end.
+struct ListSerializer<'a> {
+    output: &'a mut Output,
     prefix: Key,
-    list: Vec<String>,
+    list: Vec<serde_json::Value>,
+    // The number of elements serialized as structures under their index.
+    structures: usize,
 }
 
-impl<'a> FlatSerializer<'a> {
-    fn new(output: &'a mut HashMap<Key, String>, prefix: Key) -> Self {
-        FlatSerializer {
+impl<'a> ListSerializer<'a> {
+    fn new(output: &'a mut Output, prefix: Key) -> Self {
+        ListSerializer {
             output,
             prefix,
             list: Vec::new(),
+            structures: 0,
         }
     }
 }
 
-impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
+/// Returns whether the given list element has to be serialized as a structure with its own keys,
+/// meaning it's a map or struct, or a list containing them.
+fn is_structure(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Object(_) => true,
+        serde_json::Value::Array(list) => list.iter().any(is_structure),
+        _ => false,
+    }
+}
+
+impl<'a> ser::SerializeSeq for ListSerializer<'a> {
     type Ok = ();
     type Error = Error;
 
@@ -356,27 +391,59 @@ impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
         T: ?Sized + Serialize,
     {
         trace!("Serializing element of list");
-        self.list
-            .push(serde_json::to_string(value).context(error::Serialization {
-                given: "list element",
-            })?);
+        let json = serde_json::to_value(value).context(error::Serialization {
+            given: "list element",
+        })?;
+
+        if is_structure(&json) {
+            let index = self.list.len().to_string();
+            let key = self.prefix.append_segments(&[&index]).map_err(|e| {
+                error::InvalidKey {
+                    msg: format!(
+                        "appending index {} to '{}' is invalid as Key: {}",
+                        index, self.prefix, e
+                    ),
+                }
+                .into_error(NoSource)
+            })?;
+            trace!(
+                "Recursively serializing list element with new root '{}'",
+                key
+            );
+            let before = self.output.pairs.len();
+            value.serialize(Serializer::new(self.output, Some(key)))?;
+            // An element with no values would leave a gap in the indexes, and the list would
+            // come back shorter.
+            ensure!(
+                self.output.pairs.len() > before,
+                error::InvalidList {
+                    msg: format!("element {} of '{}' has no values", index, self.prefix),
+                }
+            );
+            self.structures += 1;
+        }
+        self.list.push(json);
         Ok(())
     }
 
     fn end(self) -> Result<()> {
-        let mut originals: Vec<serde_json::Value> = Vec::new();
-        trace!("Deserializing elements of list");
-        for original in self.list {
-            originals.push(original.parse().context(error::Deserialization {
-                given: "list element",
-            })?);
+        if self.structures == 0 {
+            trace!("Serializing list");
+            self.output.pairs.insert(
+                self.prefix.clone(),
+                serde_json::to_string(&self.list)
+                    .context(error::Serialization { given: "list" })?,
+            );
+        } else {
+            // We can't represent a scalar next to structures, or deserialize it into the same type.
+            ensure!(
+                self.structures == self.list.len(),
+                error::InvalidList {
+                    msg: format!("'{}' mixes structures with other values", self.prefix),
+                }
+            );
         }
-
-        trace!("Serializing list");
-        self.output.insert(
-            self.prefix,
-            serde_json::to_string(&originals).context(error::Serialization { given: "list" })?,
-        );
+        self.output.lists.insert(self.prefix);
 
         Ok(())
     }
@@ -384,7 +451,7 @@ impl<'a> ser::SerializeSeq for FlatSerializer<'a> {
 
 #[cfg(test)]
 mod test {
-    use super::{to_pairs, to_pairs_with_prefix};
+    use super::{to_list_keys, to_pairs, to_pairs_with_prefix};
     use crate::{Key, KeyType};
     use maplit::hashmap;
     use serde::Serialize;
@@ -490,4 +557,79 @@ mod test {
         let i = 42;
         to_pairs(&i).unwrap_err();
     }
+
+    #[derive(PartialEq, Serialize)]
+    struct Listy {
+        list: Vec<B>,
+    }
+
+    #[test]
+    fn list_of_structs() {
+        let listy = Listy {
+            list: vec![
+                B {
+                    list: vec![1],
+                    boolean: true,
+                },
+                B {
+                    list: vec![],
+                    boolean: false,
+                },
+            ],
+        };
+        let keys = to_pairs(&listy).unwrap();
+        assert_eq!(
+            keys,
+            hashmap!(
+                key!("Listy.list.0.list") => "[1]".to_string(),
+                key!("Listy.list.0.boolean") => "true".to_string(),
+                key!("Listy.list.1.list") => "[]".to_string(),
+                key!("Listy.list.1.boolean") => "false".to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn nested_list_of_structs() {
+        let m = hashmap!(
+            key!("x") => vec![vec![hashmap!(key!("id") => 1)], vec![hashmap!(key!("id") => 2)]],
+        );
+        let keys = to_pairs_with_prefix("map", &m).unwrap();
+        assert_eq!(
+            keys,
+            hashmap!(
+                key!("map.x.0.0.id") => "1".to_string(),
+                key!("map.x.1.0.id") => "2".to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn list_keys() {
+        let listy = Listy {
+            list: vec![B {
+                list: vec![1],
+                boolean: true,
+            }],
+        };
+        let lists = to_list_keys(&listy).unwrap();
+        assert_eq!(
+            lists,
+            vec![key!("Listy.list"), key!("Listy.list.0.list")]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn mixed_list_fails() {
+        let val: toml::Value = toml::from_str("list = [[1], [{a = 1}]]").unwrap();
+        to_pairs_with_prefix("x", &val).unwrap_err();
+    }
+
+    #[test]
+    fn empty_list_element_fails() {
+        let val: toml::Value = toml::from_str("list = [{a = 1}, {}]").unwrap();
+        to_pairs_with_prefix("x", &val).unwrap_err();
+    }
 }
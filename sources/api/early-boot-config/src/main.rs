@@ -34,6 +34,8 @@ const DEFAULT_API_SOCKET: &str = "/run/api.sock";
 const API_SETTINGS_URI: &str = "/settings";
 // We change settings in the shared transaction used by boot-time services.
 const TRANSACTION: &str = "bottlerocket-launch";
+// The API records this as the source of our settings, since our settings come from user data.
+const SETTING_SOURCE: &str = "user-data";
 
 // We only want to run early-boot-config once, at first boot.  Our systemd unit file has a
 // ConditionPathExists that will prevent it from running again if this file exists.
@@ -126,10 +128,15 @@ async fn run() -> Result<()> {
 
         info!("Sending {} to API", settings_json.desc);
         trace!("Request body: {}", settings_json.json);
-        let (code, response_body) =
-            apiclient::raw_request(&args.socket_path, uri, method, Some(settings_json.json))
-                .await
-                .context(error::APIRequest { method, uri })?;
+        let (code, response_body) = apiclient::raw_request_with_headers(
+            &args.socket_path,
+            uri,
+            method,
+            Some(settings_json.json),
+            &[(apiclient::SETTING_SOURCE_HEADER, SETTING_SOURCE)],
+        )
+        .await
+        .context(error::APIRequest { method, uri })?;
         ensure!(
             code.is_success(),
             error::Response {
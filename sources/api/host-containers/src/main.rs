@@ -32,7 +32,8 @@ use model::modeled_types::Identifier;
 
 // FIXME Get from configuration in the future
 const DEFAULT_API_SOCKET: &str = "/run/api.sock";
-const API_SETTINGS_URI: &str = "/settings";
+// Ask for secrets too; we need the user data of the containers.
+const API_SETTINGS_URI: &str = "/settings?secrets=true";
 const ENV_FILE_DIR: &str = "/etc/host-containers";
 const PERSISTENT_STORAGE_BASE_DIR: &str = "/local/host-containers";
 
@@ -0,0 +1,106 @@
+/// This build script generates a unified TOML file representing the default settings for the
+/// variant being built, the same way storewolf does.  Migrations are built as part of the new
+/// image, so these are the defaults of the image we're migrating to; migrations can read them
+/// through `defaults_for` rather than hardcoding new default values.
+use merge_toml::merge_values;
+use snafu::ResultExt;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{map::Map, Value};
+use walkdir::WalkDir;
+
+/// Each variant has a directory of its model under this one, and stores its default settings in
+/// .toml files in a defaults.d directory there.  It can link to shared files if desired.  Entries
+/// are sorted by filename, and later entries take precedence.
+const MODELS_DIR: &str = "../../../models/src";
+
+/// Returns the defaults.d directory of the variant being built.
+fn defaults_dir() -> PathBuf {
+    // The models build requires VARIANT as well, and checks that it names a model directory.
+    let variant = env::var("VARIANT").expect("VARIANT not set; it's needed to pick the model");
+    Path::new(MODELS_DIR).join(variant).join("defaults.d")
+}
+
+fn main() -> Result<()> {
+    generate_defaults_toml()?;
+
+    // Reflect that we need to rerun if variant has changed to pick up the new default settings.
+    println!("cargo:rerun-if-env-changed=VARIANT");
+
+    Ok(())
+}
+
+/// Merge the variant's default settings files into a single TOML value.  The result is serialized
+/// to a file in OUT_DIR for the defaults module to read.
+fn generate_defaults_toml() -> Result<()> {
+    // Find TOML config files specified by the variant.
+    let defaults_dir = defaults_dir();
+    let walker = WalkDir::new(&defaults_dir)
+        .follow_links(true) // we expect users to link to shared files
+        .min_depth(1) // only read files in defaults.d, not doing inheritance yet
+        .max_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name())) // allow ordering by prefix
+        .into_iter()
+        .filter_entry(|e| e.file_name().to_string_lossy().ends_with(".toml")); // looking for TOML config
+
+    // Merge the files into a single TOML value, in order.
+    let mut defaults = Value::Table(Map::new());
+    for entry in walker {
+        let entry = entry.context(error::ListFiles { dir: &defaults_dir })?;
+
+        // Reflect that we need to rerun if any of the default settings files have changed.
+        println!("cargo:rerun-if-changed={}", entry.path().display());
+
+        let data = fs::read_to_string(entry.path()).context(error::File {
+            op: "read",
+            path: entry.path(),
+        })?;
+        let value = toml::from_str(&data).context(error::TomlDeserialize { path: entry.path() })?;
+        merge_values(&mut defaults, &value).context(error::TomlMerge)?;
+    }
+
+    // Serialize to disk for the defaults module to read.
+    let data = toml::to_string(&defaults).context(error::TomlSerialize)?;
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR not set; are you not using cargo?");
+    let path = Path::new(&out_dir).join("defaults.toml");
+    fs::write(&path, data).context(error::File { op: "write", path })?;
+
+    Ok(())
+}
+
+mod error {
+    use snafu::Snafu;
+    use std::path::PathBuf;
+
+    #[derive(Debug, Snafu)]
+    #[snafu(visibility = "pub(super)")]
+    pub(super) enum Error {
+        #[snafu(display("Failed to {} {}: {}", op, path.display(), source))]
+        File {
+            op: String,
+            path: PathBuf,
+            source: std::io::Error,
+        },
+
+        #[snafu(display("Failed to list files in {}: {}", dir.display(), source))]
+        ListFiles {
+            dir: PathBuf,
+            source: walkdir::Error,
+        },
+
+        #[snafu(display("{} is not valid TOML: {}", path.display(), source))]
+        TomlDeserialize {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Failed to merge TOML: {}", source))]
+        TomlMerge { source: merge_toml::Error },
+
+        #[snafu(display("Failed to serialize default settings: {}", source))]
+        TomlSerialize { source: toml::ser::Error },
+    }
+}
+
+type Result<T> = std::result::Result<T, error::Error>;
@@ -1,8 +1,41 @@
 use crate::{error, Metadata, Migration, MigrationData, Result};
+use datastore::Value;
 use serde::Serialize;
 use snafu::{OptionExt, ResultExt};
 use std::collections::HashMap;
 
+/// Returns the keys of the elements of the given setting if it's a list of structures, which are
+/// stored with a key per element value, like "setting.0.field".
+fn list_element_keys(data: &HashMap<String, Value>, setting: &str) -> Vec<String> {
+    let prefix = format!("{}.", setting);
+    data.keys()
+        .filter(|key| match key.strip_prefix(&prefix) {
+            Some(rest) => {
+                let index = rest.split('.').next().unwrap_or_default();
+                !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit())
+            }
+            None => false,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Removes the given setting, including the keys of its elements if it's a list of structures.
+fn remove_setting(data: &mut HashMap<String, Value>, setting: &str) {
+    let mut keys = list_element_keys(data, setting);
+    keys.push(setting.to_string());
+    let mut found = false;
+    for key in keys {
+        if let Some(value) = data.remove(&key) {
+            println!("Removed {}, which was set to '{}'", key, value);
+            found = true;
+        }
+    }
+    if !found {
+        println!("Found no {} to remove", setting);
+    }
+}
+
 /// We use this migration when we add settings and want to make sure they're removed before we go
 /// back to old versions that don't understand them.
 pub struct AddSettingsMigration<'a>(pub &'a [&'static str]);
@@ -23,11 +56,7 @@ impl Migration for AddSettingsMigration<'_> {
     /// and safe to remove.)
     fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
         for setting in self.0 {
-            if let Some(data) = input.data.remove(*setting) {
-                println!("Removed {}, which was set to '{}'", setting, data);
-            } else {
-                println!("Found no {} to remove", setting);
-            }
+            remove_setting(&mut input.data, setting);
         }
         Ok(input)
     }
@@ -93,6 +122,7 @@ impl Migration for AddPrefixesMigration {
 #[cfg(test)]
 mod test_add_prefixes_migration {
     use super::AddPrefixesMigration;
+    use crate::test_harness::{datastore_fixture, MigrationTest};
     use crate::{Migration, MigrationData};
     use maplit::hashmap;
     use std::collections::HashMap;
@@ -170,6 +200,50 @@ mod test_add_prefixes_migration {
             }
         );
     }
+
+    #[test]
+    fn round_trip() {
+        // The prefixed settings are kept on upgrade, and removed on downgrade; their metadata is
+        // left alone.
+        let live = MigrationData {
+            data: hashmap! {
+                "keep.me.a".into() => 0.into(),
+                "remove.me.b".into() => 0.into(),
+                "remove.me.d.e".into() => 0.into(),
+            },
+            metadata: hashmap! {
+                "keep.me.a".into() => hashmap! {"affected-services".into() => vec!["a"].into()},
+                "remove.me.b".into() => hashmap! {"affected-services".into() => vec!["b"].into()},
+            },
+        };
+        let pending = MigrationData {
+            data: hashmap! {
+                "keep.me.a".into() => 1.into(),
+                "remove.me.b".into() => 1.into(),
+            },
+            metadata: HashMap::new(),
+        };
+        let fixture = datastore_fixture(&live, &[("tx", &pending)]);
+        let expected_forward = datastore_fixture(&live, &[("tx", &pending)]);
+
+        let live = MigrationData {
+            data: hashmap! {"keep.me.a".into() => 0.into()},
+            metadata: hashmap! {
+                "keep.me.a".into() => hashmap! {"affected-services".into() => vec!["a"].into()},
+                "remove.me.b".into() => hashmap! {"affected-services".into() => vec!["b"].into()},
+            },
+        };
+        let pending = MigrationData {
+            data: hashmap! {"keep.me.a".into() => 1.into()},
+            metadata: HashMap::new(),
+        };
+        let expected_backward = datastore_fixture(&live, &[("tx", &pending)]);
+
+        MigrationTest::new(AddPrefixesMigration(vec!["remove.me"]), fixture)
+            .expect_forward(expected_forward)
+            .expect_backward(expected_backward)
+            .run();
+    }
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -184,11 +258,7 @@ impl Migration for RemoveSettingsMigration<'_> {
     /// and safe to remove.)
     fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
         for setting in self.0 {
-            if let Some(data) = input.data.remove(*setting) {
-                println!("Removed {}, which was set to '{}'", setting, data);
-            } else {
-                println!("Found no {} to remove", setting);
-            }
+            remove_setting(&mut input.data, setting);
         }
         Ok(input)
     }
@@ -204,6 +274,39 @@ impl Migration for RemoveSettingsMigration<'_> {
     }
 }
 
+#[cfg(test)]
+mod test_remove_settings {
+    use super::RemoveSettingsMigration;
+    use crate::{Migration, MigrationData};
+    use maplit::hashmap;
+    use std::collections::HashMap;
+
+    #[test]
+    fn list_of_structures() {
+        let data = MigrationData {
+            data: hashmap! {
+                "a.list".into() => vec!["x"].into(),
+                "a.structs.0.name".into() => "x".into(),
+                "a.structs.1.name".into() => "y".into(),
+                "a.structs-extra".into() => "z".into(),
+                "a.structs.map.name".into() => "w".into(),
+            },
+            metadata: HashMap::new(),
+        };
+        let result = RemoveSettingsMigration(&["a.list", "a.structs"])
+            .forward(data)
+            .unwrap();
+        // Only the elements of the list are removed, not other keys with the same prefix
+        assert_eq!(
+            result.data,
+            hashmap! {
+                "a.structs-extra".into() => "z".into(),
+                "a.structs.map.name".into() => "w".into(),
+            }
+        );
+    }
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 /// Similar to the above, this migration is for when we need to remove a single setting.
@@ -297,6 +400,8 @@ impl Migration for ReplaceStringMigration {
 
 /// We use this migration when we need to replace settings that contain lists of string values;
 /// for example, when a release changes the list of configuration-files associated with a service.
+/// Lists of structures are stored with a key per element value rather than as a list, and are left
+/// alone.
 // String is the only type we use today, and handling multiple value types is more complicated than
 // we need at the moment.  Allowing &[serde_json::Value] seems nice, but it would allow arbitrary
 // data transformations that the API model would then fail to load.
@@ -346,6 +451,11 @@ impl Migration for ReplaceListsMigration {
                         );
                     }
                 }
+            } else if !list_element_keys(&input.data, replacement.setting).is_empty() {
+                println!(
+                    "'{}' is a list of structures; ReplaceListsMigration only handles lists of strings",
+                    replacement.setting
+                );
             } else {
                 println!("Found no '{}' to change on upgrade", replacement.setting);
             }
@@ -389,6 +499,11 @@ impl Migration for ReplaceListsMigration {
                     );
                     }
                 }
+            } else if !list_element_keys(&input.data, replacement.setting).is_empty() {
+                println!(
+                    "'{}' is a list of structures; ReplaceListsMigration only handles lists of strings",
+                    replacement.setting
+                );
             } else {
                 println!("Found no '{}' to change on downgrade", replacement.setting);
             }
@@ -550,6 +665,30 @@ mod test_replace_list {
         .forward(data)
         .unwrap_err();
     }
+
+    #[test]
+    fn list_of_structures() {
+        let data = MigrationData {
+            data: hashmap! {
+                "hi.0.name".into() => "there".into(),
+            },
+            metadata: HashMap::new(),
+        };
+        let result = ReplaceListsMigration(vec![ListReplacement {
+            setting: "hi",
+            old_vals: &["there"],
+            new_vals: &["sup"],
+        }])
+        .forward(data)
+        .unwrap();
+        // No change
+        assert_eq!(
+            result.data,
+            hashmap! {
+                "hi.0.name".into() => "there".into(),
+            }
+        );
+    }
 }
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
@@ -740,6 +879,70 @@ impl Migration for ReplaceTemplateMigration {
     }
 }
 
+#[cfg(test)]
+mod test_replace_template {
+    use super::ReplaceTemplateMigration;
+    use crate::test_harness::{datastore_fixture, MigrationTest};
+    use crate::MigrationData;
+    use maplit::hashmap;
+    use std::collections::HashMap;
+
+    const OLD_TEMPLATE: &str = "{{ settings.aws.region }}/admin:v1";
+    const NEW_TEMPLATE: &str = "{{ settings.aws.region }}/admin:v2";
+
+    fn migration() -> ReplaceTemplateMigration {
+        ReplaceTemplateMigration {
+            setting: "settings.admin",
+            old_template: OLD_TEMPLATE,
+            new_template: NEW_TEMPLATE,
+        }
+    }
+
+    fn live(value: &str, template: &str) -> MigrationData {
+        MigrationData {
+            data: hashmap! {
+                "settings.aws.region".into() => "us-west-2".into(),
+                "settings.admin".into() => value.into(),
+            },
+            metadata: hashmap! {
+                "settings.admin".into() => hashmap! {"template".into() => template.into()},
+            },
+        }
+    }
+
+    #[test]
+    fn replaced() {
+        // Pending transactions have no metadata, so their values aren't changed.
+        let pending = MigrationData {
+            data: hashmap! {"settings.admin".into() => "us-east-1/admin:v1".into()},
+            metadata: HashMap::new(),
+        };
+        let fixture = datastore_fixture(
+            &live("us-west-2/admin:v1", OLD_TEMPLATE),
+            &[("tx", &pending)],
+        );
+        let expected = datastore_fixture(
+            &live("us-west-2/admin:v2", NEW_TEMPLATE),
+            &[("tx", &pending)],
+        );
+
+        MigrationTest::new(migration(), fixture)
+            .expect_forward(expected)
+            .run();
+    }
+
+    #[test]
+    fn user_changed_value() {
+        // The user set their own value, so we leave it alone, but still update the template.
+        let fixture = datastore_fixture(&live("custom", OLD_TEMPLATE), &[]);
+        let expected = datastore_fixture(&live("custom", NEW_TEMPLATE), &[]);
+
+        MigrationTest::new(migration(), fixture)
+            .expect_forward(expected)
+            .run();
+    }
+}
+
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
 /// We use this migration when we add metadata and want to make sure they're removed before we go
@@ -19,6 +19,26 @@ use datastore::{
 pub(crate) fn get_input_data<D: DataStore>(
     datastore: &D,
     committed: &Committed,
+) -> Result<MigrationData> {
+    let mut input = get_datastore_data(datastore, committed)?;
+
+    // We also want to make "os.*" values, like variant and arch, available to migrations.
+    let release = BottlerocketRelease::new().context(error::BottlerocketRelease)?;
+    let os_pairs = to_pairs_with_prefix("os", &release).context(error::SerializeRelease)?;
+    for (data_key, value_str) in os_pairs.into_iter() {
+        let value =
+            deserialize_scalar(&value_str).context(error::Deserialize { input: value_str })?;
+        input.data.insert(data_key.name().clone(), value);
+    }
+
+    Ok(input)
+}
+
+/// Retrieves data and metadata from the specified data store, without the "os.*" values that
+/// get_input_data adds from the running system.
+pub(crate) fn get_datastore_data<D: DataStore>(
+    datastore: &D,
+    committed: &Committed,
 ) -> Result<MigrationData> {
     let raw_data = datastore
         .get_prefix("", committed)
@@ -39,15 +59,6 @@ pub(crate) fn get_input_data<D: DataStore>(
         data.insert(key_name.clone(), value);
     }
 
-    // We also want to make "os.*" values, like variant and arch, available to migrations.
-    let release = BottlerocketRelease::new().context(error::BottlerocketRelease)?;
-    let os_pairs = to_pairs_with_prefix("os", &release).context(error::SerializeRelease)?;
-    for (data_key, value_str) in os_pairs.into_iter() {
-        let value =
-            deserialize_scalar(&value_str).context(error::Deserialize { input: value_str })?;
-        data.insert(data_key.name().clone(), value);
-    }
-
     // Metadata isn't committed, it goes live immediately, so we only populate the metadata
     // output for Committed::Live.
     let mut metadata = HashMap::new();
@@ -121,3 +132,65 @@ pub(crate) fn set_output_data<D: DataStore>(
 
     Ok(())
 }
+
+/// Copies the keys staged for removal and the metadata staged in the given pending transaction of
+/// the source data store to the target data store.  Migrations only see the data of a pending
+/// transaction, so these are carried forward as they are.
+pub(crate) fn copy_pending_changes<D: DataStore>(
+    source: &D,
+    target: &mut D,
+    transaction: &str,
+) -> Result<()> {
+    let removals = source
+        .pending_removals(transaction)
+        .context(error::GetPendingChanges { transaction })?;
+    target
+        .set_pending_removals(transaction, &removals)
+        .context(error::DataStoreWrite)?;
+
+    let metadata = source
+        .pending_metadata(transaction)
+        .context(error::GetPendingChanges { transaction })?;
+    for (data_key, meta_map) in metadata {
+        for (metadata_key, value) in meta_map {
+            target
+                .set_pending_metadata(&metadata_key, &data_key, value, transaction)
+                .context(error::DataStoreWrite)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::copy_pending_changes;
+    use datastore::memory::MemoryDataStore;
+    use datastore::{Committed, DataStore, Key, KeyType};
+    use maplit::{hashmap, hashset};
+
+    #[test]
+    fn pending_changes_copied() {
+        let a = Key::new(KeyType::Data, "settings.a").unwrap();
+        let b = Key::new(KeyType::Data, "settings.b").unwrap();
+        let md = Key::new(KeyType::Meta, "source").unwrap();
+        let pending = Committed::Pending { tx: "tx".into() };
+
+        let mut source = MemoryDataStore::new();
+        source.set_key(&a, "\"x\"", &pending).unwrap();
+        source
+            .set_pending_metadata(&md, &a, "\"api\"", "tx")
+            .unwrap();
+        source.stage_removals(&hashset!(b.clone()), "tx").unwrap();
+
+        let mut target = MemoryDataStore::new();
+        target.set_key(&a, "\"x\"", &pending).unwrap();
+        copy_pending_changes(&source, &mut target, "tx").unwrap();
+
+        assert_eq!(target.pending_removals("tx").unwrap(), hashset!(b));
+        assert_eq!(
+            target.pending_metadata("tx").unwrap(),
+            hashmap!(a => hashmap!(md => "\"api\"".to_string()))
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! This module gives migrations access to the default settings of the image we're migrating to,
+//! so they can reset part of the datastore to its new defaults instead of hardcoding values.
+//!
+//! The defaults are merged from the variant's `defaults.d` directory at build time, the same way
+//! storewolf does it, so they match what storewolf would populate on a new system.
+
+use snafu::{OptionExt, ResultExt};
+use std::cell::RefCell;
+
+use crate::{error, Result};
+use datastore::{Key, KeyType, Value};
+
+/// The merged default settings for the variant, written by build.rs.
+const DEFAULTS_TOML: &str = include_str!(concat!(env!("OUT_DIR"), "/defaults.toml"));
+
+thread_local! {
+    /// Defaults to use instead of DEFAULTS_TOML, if set by a test.
+    static DEFAULTS_FIXTURE: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Returns the default settings for a given path so you can easily replace a given section of the
+/// datastore with new defaults.  For example, you could request "settings" to get all new default
+/// settings, or "settings.serviceX.subsection" to scope it down.  You can also request other
+/// sections of the defaults, like "metadata.settings.serviceX.subsection" or "services.serviceX".
+///
+/// Paths are datastore key names, so segments containing dots must be quoted.  Returns an error
+/// if the defaults don't contain the given path.
+pub fn defaults_for<S: AsRef<str>>(path: S) -> Result<Value> {
+    let path = path.as_ref();
+    let key = Key::new(KeyType::Data, path).context(error::InvalidKey {
+        key_type: KeyType::Data,
+        key: path,
+    })?;
+
+    let defaults = DEFAULTS_FIXTURE.with(|fixture| match &*fixture.borrow() {
+        Some(defaults_str) => toml::from_str::<toml::Value>(defaults_str),
+        None => toml::from_str::<toml::Value>(DEFAULTS_TOML),
+    });
+    let mut value = &defaults.context(error::DefaultsFormatting)?;
+
+    for segment in key.segments() {
+        value = value
+            .get(segment)
+            .context(error::MissingDefault { path: key.name() })?;
+    }
+
+    serde_json::to_value(value).context(error::DefaultsConversion { path: key.name() })
+}
+
+/// Makes `defaults_for` use the given TOML string as the default settings, rather than the
+/// variant's real defaults, until `clear_defaults_fixture` is called.  The string should have the
+/// same structure as the files in `defaults.d`.
+///
+/// This is intended for unit tests of migrations, so they don't depend on the defaults of the
+/// variant being built.  The fixture only applies to the current thread, so tests running in
+/// parallel don't interfere with each other.
+pub fn set_defaults_fixture<S: Into<String>>(defaults: S) {
+    DEFAULTS_FIXTURE.with(|fixture| *fixture.borrow_mut() = Some(defaults.into()));
+}
+
+/// Makes `defaults_for` use the variant's real defaults again after `set_defaults_fixture`.
+pub fn clear_defaults_fixture() {
+    DEFAULTS_FIXTURE.with(|fixture| *fixture.borrow_mut() = None);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clear_defaults_fixture, defaults_for, set_defaults_fixture};
+    use serde_json::json;
+
+    const FIXTURE: &str = r#"
+        [settings.host-containers.admin]
+        enabled = false
+        superpowered = true
+
+        [settings.kernel.sysctl]
+        "net.ipv4.ip_forward" = "1"
+
+        [metadata.settings.host-containers.admin.source]
+        template = "{{ ecr-prefix settings.aws.region }}/bottlerocket-admin:v0.7.1"
+    "#;
+
+    #[test]
+    fn subtree() {
+        set_defaults_fixture(FIXTURE);
+        assert_eq!(
+            defaults_for("settings.host-containers").unwrap(),
+            json!({"admin": {"enabled": false, "superpowered": true}})
+        );
+    }
+
+    #[test]
+    fn scalar() {
+        set_defaults_fixture(FIXTURE);
+        assert_eq!(
+            defaults_for("settings.host-containers.admin.superpowered").unwrap(),
+            json!(true)
+        );
+        assert_eq!(
+            defaults_for("metadata.settings.host-containers.admin.source.template").unwrap(),
+            json!("{{ ecr-prefix settings.aws.region }}/bottlerocket-admin:v0.7.1")
+        );
+    }
+
+    #[test]
+    fn quoted_segment() {
+        set_defaults_fixture(FIXTURE);
+        assert_eq!(
+            defaults_for(r#"settings.kernel.sysctl."net.ipv4.ip_forward""#).unwrap(),
+            json!("1")
+        );
+    }
+
+    #[test]
+    fn missing() {
+        set_defaults_fixture(FIXTURE);
+        defaults_for("settings.host-containers.control").unwrap_err();
+        defaults_for("settings.host-containers.admin.enabled.nope").unwrap_err();
+    }
+
+    #[test]
+    fn bad_fixture() {
+        set_defaults_fixture("[not toml");
+        defaults_for("settings").unwrap_err();
+    }
+
+    #[test]
+    fn real_defaults() {
+        // Every variant has default settings; make sure we can read them without a fixture.
+        clear_defaults_fixture();
+        assert!(defaults_for("settings").unwrap().is_object());
+    }
+}
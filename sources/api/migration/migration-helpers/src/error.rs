@@ -19,6 +19,19 @@ pub enum Error {
     #[snafu(display("Unable to get metadata for migration: {}", source))]
     GetMetadata { source: datastore::Error },
 
+    #[snafu(display(
+        "Unable to get staged changes of pending transaction '{}' for migration: {}",
+        transaction,
+        source
+    ))]
+    GetPendingChanges {
+        transaction: String,
+        source: datastore::Error,
+    },
+
+    #[snafu(display("Unable to finish interrupted commit before migration: {}", source))]
+    DataStoreRecovery { source: datastore::Error },
+
     #[snafu(display("Unable to deserialize to Value from '{}': {}", input, source))]
     Deserialize {
         input: String,
@@ -42,6 +55,18 @@ pub enum Error {
         source: datastore::Error,
     },
 
+    #[snafu(display("Merged default settings are not valid TOML: {}", source))]
+    DefaultsFormatting { source: toml::de::Error },
+
+    #[snafu(display("Default settings have no entry for '{}'", path))]
+    MissingDefault { path: String },
+
+    #[snafu(display("Unable to convert default settings for '{}': {}", path, source))]
+    DefaultsConversion {
+        path: String,
+        source: serde_json::Error,
+    },
+
     #[snafu(display("Migrated data failed validation: {}", msg))]
     Validation { msg: String },
 
@@ -14,7 +14,9 @@
 mod args;
 pub mod common_migrations;
 mod datastore_helper;
+mod defaults;
 pub mod error;
+pub mod test_harness;
 
 use snafu::ResultExt;
 use std::collections::HashMap;
@@ -25,7 +27,8 @@ use datastore::{Committed, Value};
 pub use datastore::{DataStore, FilesystemDataStore};
 
 use args::{parse_args, Args};
-use datastore_helper::{get_input_data, set_output_data};
+use datastore_helper::{copy_pending_changes, get_input_data, set_output_data};
+pub use defaults::{clear_defaults_fixture, defaults_for, set_defaults_fixture};
 pub use error::Result;
 
 /// The data store implementation currently in use.  Used by the simpler `migrate` interface; can
@@ -43,6 +46,9 @@ type DataStoreImplementation = FilesystemDataStore;
 /// must not add a key in all cases if it's missing, because you could be adding the key to an
 /// unrelated pending transaction.  Instead, make sure you're adding a key to an existing
 /// structure.
+///
+/// Migrations only see the data of a pending transaction; the keys it stages for removal and the
+/// metadata it stages are carried forward unchanged.
 pub trait Migration {
     /// Migrates data forward from the prior version to the version specified in the migration
     /// name.
@@ -60,7 +66,7 @@ pub type Metadata = HashMap<String, Value>;
 /// MigrationData holds all data that can be migrated in a migration, and serves as the input and
 /// output format of migrations.  A serde Value type is used to hold the arbitrary data of each
 /// key because we can't represent types when they could change in the migration.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MigrationData {
     /// Mapping of data key names to their arbitrary values.
     pub data: HashMap<String, Value>,
@@ -68,13 +74,6 @@ pub struct MigrationData {
     pub metadata: HashMap<String, Metadata>,
 }
 
-/// Returns the default settings for a given path so you can easily replace a given section of the
-/// datastore with new defaults.  For example, you could request "settings" to get all new default
-/// settings, or "settings.serviceX.subsection" to scope it down.
-pub fn defaults_for<S: AsRef<str>>(_path: S) -> Result<Value> {
-    unimplemented!()
-}
-
 /// Ensures we can use the migrated data in the new data store.  Can use this result to stop the
 /// migration process before saving any data.
 fn validate_migrated_data(_migrated: &MigrationData) -> Result<()> {
@@ -87,9 +86,12 @@ fn validate_migrated_data(_migrated: &MigrationData) -> Result<()> {
 /// module as a library, you can call run_migration directly with the arguments that would
 /// normally be parsed from the migration binary's command line.
 pub fn run_migration(mut migration: impl Migration, args: &Args) -> Result<()> {
-    let source = DataStoreImplementation::new(&args.source_datastore);
+    let mut source = DataStoreImplementation::new(&args.source_datastore);
     let mut target = DataStoreImplementation::new(&args.target_datastore);
 
+    // Finish any commit that was interrupted, so we migrate all of its data.
+    source.recover().context(error::DataStoreRecovery)?;
+
     // Run for live data and for each pending transaction
     let mut committeds = vec![Committed::Live];
     let transactions = source
@@ -109,6 +111,9 @@ pub fn run_migration(mut migration: impl Migration, args: &Args) -> Result<()> {
         validate_migrated_data(&migrated)?;
 
         set_output_data(&mut target, &migrated, &committed)?;
+        if let Committed::Pending { tx } = &committed {
+            copy_pending_changes(&source, &mut target, tx)?;
+        }
     }
     Ok(())
 }
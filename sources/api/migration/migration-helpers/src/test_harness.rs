@@ -0,0 +1,267 @@
+//! This module provides a harness for unit testing migrations without a real data store.
+//!
+//! Give `MigrationTest` your migration and a `MemoryDataStore` fixture representing data from
+//! the prior version; `datastore_fixture` makes it easy to build one from the same format your
+//! migration sees.  `MigrationTest::run` migrates live data and each pending transaction forward,
+//! then backward, and checks that we get back the original data.  You can also declare the data
+//! you expect after migrating forward with `MigrationTest::expect_forward`, and, for migrations
+//! that intentionally drop data on the way back, the data you expect after migrating backward
+//! with `MigrationTest::expect_backward`.
+//!
+//! Data is written to and read from a data store between each step, like the migrator does
+//! between migrations, so the test also covers serialization of your migration's output.
+//!
+//! Note that "os.*" values aren't added for you like they are when running migrations on a
+//! system; include them in your fixture if your migration needs them.
+//!
+//! ```
+//! use maplit::hashmap;
+//! use migration_helpers::common_migrations::AddSettingsMigration;
+//! use migration_helpers::test_harness::{datastore_fixture, MigrationTest};
+//! use migration_helpers::MigrationData;
+//! use std::collections::HashMap;
+//!
+//! let live = MigrationData {
+//!     data: hashmap! { "settings.motd".into() => "hi".into() },
+//!     metadata: HashMap::new(),
+//! };
+//! let pending = MigrationData {
+//!     data: hashmap! { "settings.motd".into() => "hello".into() },
+//!     metadata: HashMap::new(),
+//! };
+//! let fixture = datastore_fixture(&live, &[("bottlerocket-launch", &pending)]);
+//!
+//! MigrationTest::new(AddSettingsMigration(&["settings.new"]), fixture).run();
+//! ```
+
+use std::collections::BTreeSet;
+
+use crate::datastore_helper::{get_datastore_data, set_output_data};
+use crate::{Migration, MigrationData};
+use datastore::memory::MemoryDataStore;
+use datastore::{Committed, DataStore};
+
+/// Builds a MemoryDataStore containing the given live data and metadata, and the data of each
+/// given pending transaction, for use as the input or expected output of a MigrationTest.
+/// Metadata is only stored for live data, so the metadata of pending transactions must be empty.
+///
+/// Panics if the data can't be stored, for example if a key name is invalid.
+pub fn datastore_fixture(
+    live: &MigrationData,
+    pending: &[(&str, &MigrationData)],
+) -> MemoryDataStore {
+    let mut datastore = MemoryDataStore::new();
+    write_data(&mut datastore, live, &Committed::Live);
+    for (tx, data) in pending {
+        assert!(
+            data.metadata.is_empty(),
+            "Metadata can't be pending, but was given for transaction '{}'",
+            tx
+        );
+        let committed = Committed::Pending { tx: tx.to_string() };
+        write_data(&mut datastore, data, &committed);
+    }
+    datastore
+}
+
+/// MigrationTest runs a migration over a fixture data store and checks the results.  See the
+/// module docs for an example.
+pub struct MigrationTest<M: Migration> {
+    migration: M,
+    input: MemoryDataStore,
+    expected_forward: Option<MemoryDataStore>,
+    expected_backward: Option<MemoryDataStore>,
+}
+
+impl<M: Migration> MigrationTest<M> {
+    /// Creates a test of the given migration using the given data from the prior version.
+    pub fn new(migration: M, input: MemoryDataStore) -> Self {
+        Self {
+            migration,
+            input,
+            expected_forward: None,
+            expected_backward: None,
+        }
+    }
+
+    /// Declares the data we expect after migrating the input forward.  Live data, metadata, and
+    /// each pending transaction must match exactly.
+    pub fn expect_forward(mut self, expected: MemoryDataStore) -> Self {
+        self.expected_forward = Some(expected);
+        self
+    }
+
+    /// Declares the data we expect after migrating forward and then backward, for migrations that
+    /// don't return the original data, like ones that remove settings the old version doesn't
+    /// understand.  Live data, metadata, and each pending transaction must match exactly.
+    pub fn expect_backward(mut self, expected: MemoryDataStore) -> Self {
+        self.expected_backward = Some(expected);
+        self
+    }
+
+    /// Migrates live data and each pending transaction forward, checks the result against the
+    /// expected forward data, if any, then migrates backward and checks that we got back the
+    /// expected backward data, or the original data if none was given.
+    ///
+    /// Panics with a description of the problem if the migration fails or doesn't produce the
+    /// expected data.
+    pub fn run(mut self) {
+        let mut committeds = vec![Committed::Live];
+        let mut transactions = list_transactions(&self.input);
+        // Include any transactions we only expect in the output, so we report them missing.
+        for expected in self.expected_forward.iter().chain(&self.expected_backward) {
+            transactions.extend(list_transactions(expected));
+        }
+        committeds.extend(transactions.into_iter().map(|tx| Committed::Pending { tx }));
+
+        let mut forward_store = MemoryDataStore::new();
+        let mut backward_store = MemoryDataStore::new();
+        for committed in &committeds {
+            let input = read_data(&self.input, committed);
+
+            let forward = self
+                .migration
+                .forward(input.clone())
+                .unwrap_or_else(|e| panic!("Forward migration of {:?} failed: {}", committed, e));
+            write_data(&mut forward_store, &forward, committed);
+            let forward = read_data(&forward_store, committed);
+
+            if let Some(expected) = &self.expected_forward {
+                assert_eq!(
+                    forward,
+                    read_data(expected, committed),
+                    "Forward migration of {:?} didn't produce the expected data",
+                    committed
+                );
+            }
+
+            let backward = self
+                .migration
+                .backward(forward)
+                .unwrap_or_else(|e| panic!("Backward migration of {:?} failed: {}", committed, e));
+            write_data(&mut backward_store, &backward, committed);
+            let backward = read_data(&backward_store, committed);
+
+            match &self.expected_backward {
+                Some(expected) => assert_eq!(
+                    backward,
+                    read_data(expected, committed),
+                    "Backward migration of {:?} didn't produce the expected data",
+                    committed
+                ),
+                None => assert_eq!(
+                    backward, input,
+                    "Migrating {:?} forward and backward didn't return the original data",
+                    committed
+                ),
+            }
+        }
+    }
+}
+
+fn list_transactions(datastore: &MemoryDataStore) -> BTreeSet<String> {
+    datastore
+        .list_transactions()
+        .unwrap_or_else(|e| panic!("Unable to list transactions: {}", e))
+        .into_iter()
+        .collect()
+}
+
+fn read_data(datastore: &MemoryDataStore, committed: &Committed) -> MigrationData {
+    get_datastore_data(datastore, committed)
+        .unwrap_or_else(|e| panic!("Unable to read {:?} data: {}", committed, e))
+}
+
+fn write_data(datastore: &mut MemoryDataStore, data: &MigrationData, committed: &Committed) {
+    set_output_data(datastore, data, committed)
+        .unwrap_or_else(|e| panic!("Unable to write {:?} data: {}", committed, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{datastore_fixture, MigrationTest};
+    use crate::{Migration, MigrationData, Result};
+    use maplit::hashmap;
+    use std::collections::HashMap;
+
+    /// Renames "settings.old" to "settings.new", optionally forgetting to rename it back.
+    struct Rename {
+        reversible: bool,
+    }
+
+    impl Migration for Rename {
+        fn forward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+            if let Some(value) = input.data.remove("settings.old") {
+                input.data.insert("settings.new".into(), value);
+            }
+            Ok(input)
+        }
+
+        fn backward(&mut self, mut input: MigrationData) -> Result<MigrationData> {
+            if self.reversible {
+                if let Some(value) = input.data.remove("settings.new") {
+                    input.data.insert("settings.old".into(), value);
+                }
+            }
+            Ok(input)
+        }
+    }
+
+    fn data(data: HashMap<String, serde_json::Value>) -> MigrationData {
+        MigrationData {
+            data,
+            metadata: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let live = data(hashmap! {"settings.old".into() => 1.into()});
+        let pending = data(hashmap! {"settings.old".into() => 2.into()});
+        let fixture = datastore_fixture(&live, &[("tx", &pending)]);
+
+        let expected_live = data(hashmap! {"settings.new".into() => 1.into()});
+        let expected_pending = data(hashmap! {"settings.new".into() => 2.into()});
+        let expected = datastore_fixture(&expected_live, &[("tx", &expected_pending)]);
+
+        MigrationTest::new(Rename { reversible: true }, fixture)
+            .expect_forward(expected)
+            .run();
+    }
+
+    #[test]
+    #[should_panic(expected = "didn't return the original data")]
+    fn not_reversible() {
+        let live = data(hashmap! {"settings.old".into() => 1.into()});
+        let fixture = datastore_fixture(&live, &[]);
+        MigrationTest::new(Rename { reversible: false }, fixture).run();
+    }
+
+    #[test]
+    fn expected_backward() {
+        let live = data(hashmap! {"settings.old".into() => 1.into()});
+        let fixture = datastore_fixture(&live, &[]);
+        let expected = datastore_fixture(&data(hashmap! {"settings.new".into() => 1.into()}), &[]);
+
+        MigrationTest::new(Rename { reversible: false }, fixture)
+            .expect_backward(expected)
+            .run();
+    }
+
+    #[test]
+    #[should_panic(expected = "didn't produce the expected data")]
+    fn unexpected_forward() {
+        let live = data(hashmap! {"settings.old".into() => 1.into()});
+        let pending = data(hashmap! {"settings.old".into() => 2.into()});
+        let fixture = datastore_fixture(&live, &[("tx", &pending)]);
+
+        // The pending transaction is wrong
+        let expected_live = data(hashmap! {"settings.new".into() => 1.into()});
+        let expected_pending = data(hashmap! {"settings.old".into() => 2.into()});
+        let expected = datastore_fixture(&expected_live, &[("tx", &expected_pending)]);
+
+        MigrationTest::new(Rename { reversible: true }, fixture)
+            .expect_forward(expected)
+            .run();
+    }
+}
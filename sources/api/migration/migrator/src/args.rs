@@ -19,6 +19,7 @@ fn usage() -> ! {
             --root-path PATH
             --metadata-directory PATH
             (--migrate-to-version x.y | --migrate-to-version-from-os-release)
+            [ --dry-run [ --round-trip ] ]
             [ --no-color ]
             [ --log-level trace|debug|info|warn|error ]",
         program_name
@@ -40,6 +41,8 @@ pub(crate) struct Args {
     pub(crate) migrate_to_version: Version,
     pub(crate) root_path: PathBuf,
     pub(crate) metadata_directory: PathBuf,
+    pub(crate) dry_run: bool,
+    pub(crate) round_trip: bool,
 }
 
 impl Args {
@@ -52,6 +55,8 @@ impl Args {
         let mut migrate_to_version = None;
         let mut root_path = None;
         let mut metadata_path = None;
+        let mut dry_run = false;
+        let mut round_trip = false;
 
         let mut iter = args.skip(1);
         while let Some(arg) = iter.next() {
@@ -131,10 +136,19 @@ impl Args {
                     trace!("Given --metadata-directory: {}", path_str);
                     metadata_path = Some(PathBuf::from(path_str));
                 }
+
+                "--dry-run" => dry_run = true,
+
+                "--round-trip" => round_trip = true,
+
                 _ => usage_msg(format!("Unable to parse input '{}'", arg)),
             }
         }
 
+        if round_trip && !dry_run {
+            usage_msg("--round-trip can only be used with --dry-run");
+        }
+
         Self {
             datastore_path: datastore_path
                 .unwrap_or_else(|| usage_msg("--datastore-path must be specified")),
@@ -150,6 +164,8 @@ impl Args {
             root_path: root_path.unwrap_or_else(|| usage_msg("--root-path must be specified")),
             metadata_directory: metadata_path
                 .unwrap_or_else(|| usage_msg("--metadata-directory must be specified")),
+            dry_run,
+            round_trip,
         }
     }
 }
@@ -0,0 +1,300 @@
+//! This module compares two data stores key by key, so we can show what a set of migrations did
+//! to a data store without flipping to it.
+
+use datastore::{Committed, DataStore, FilesystemDataStore};
+use snafu::ResultExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+
+use crate::error::{self, Result};
+
+/// Change represents the difference in a single key between two data stores.  Values are shown
+/// in their serialized data store form.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Change {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { key, value } => write!(f, "+ {} = {}", key, value),
+            Change::Removed { key, value } => write!(f, "- {} = {}", key, value),
+            Change::Changed { key, old, new } => write!(f, "~ {}: {} -> {}", key, old, new),
+        }
+    }
+}
+
+/// DataStoreDiff holds the changes between two data stores, grouped by section: live data, live
+/// metadata, and each pending transaction.  Sections without changes are omitted.
+#[derive(Debug, Default)]
+pub(crate) struct DataStoreDiff {
+    sections: BTreeMap<String, Vec<Change>>,
+}
+
+impl DataStoreDiff {
+    /// Compares the data store at `old_path` with the data store at `new_path`.
+    pub(crate) fn new<P1, P2>(old_path: P1, new_path: P2) -> Result<Self>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let old_path = old_path.as_ref();
+        let new_path = new_path.as_ref();
+        let old = FilesystemDataStore::new(old_path);
+        let new = FilesystemDataStore::new(new_path);
+
+        let mut diff = Self::default();
+        diff.add_section(
+            "live data",
+            get_data(&old, old_path, &Committed::Live)?,
+            get_data(&new, new_path, &Committed::Live)?,
+        );
+        diff.add_section(
+            "live metadata",
+            get_metadata(&old, old_path)?,
+            get_metadata(&new, new_path)?,
+        );
+
+        let mut transactions = list_transactions(&old, old_path)?;
+        transactions.extend(list_transactions(&new, new_path)?);
+        for tx in transactions {
+            let pending = Committed::Pending { tx: tx.clone() };
+            diff.add_section(
+                format!("pending transaction '{}'", tx),
+                get_data(&old, old_path, &pending)?,
+                get_data(&new, new_path, &pending)?,
+            );
+        }
+
+        Ok(diff)
+    }
+
+    /// Returns true if the data stores had no differences.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Compares the old and new mappings of key to value and stores the changes under the given
+    /// section name, if there are any.
+    fn add_section<S>(
+        &mut self,
+        section: S,
+        old: BTreeMap<String, String>,
+        mut new: BTreeMap<String, String>,
+    ) where
+        S: Into<String>,
+    {
+        let mut changes = Vec::new();
+        for (key, old_value) in old {
+            match new.remove(&key) {
+                None => changes.push(Change::Removed {
+                    key,
+                    value: old_value,
+                }),
+                Some(new_value) if new_value != old_value => changes.push(Change::Changed {
+                    key,
+                    old: old_value,
+                    new: new_value,
+                }),
+                Some(_) => {}
+            }
+        }
+        // Anything left over wasn't in the old data store.
+        changes.extend(
+            new.into_iter()
+                .map(|(key, value)| Change::Added { key, value }),
+        );
+
+        if !changes.is_empty() {
+            // Show changes in key order regardless of their type.
+            changes.sort_by(|a, b| change_key(a).cmp(change_key(b)));
+            self.sections.insert(section.into(), changes);
+        }
+    }
+}
+
+impl fmt::Display for DataStoreDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sections.is_empty() {
+            return writeln!(f, "(no changes)");
+        }
+        for (section, changes) in &self.sections {
+            writeln!(f, "{}:", section)?;
+            for change in changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn change_key(change: &Change) -> &str {
+    match change {
+        Change::Added { key, .. } | Change::Removed { key, .. } | Change::Changed { key, .. } => {
+            key
+        }
+    }
+}
+
+/// Returns a sorted mapping of data key name to serialized value.
+fn get_data(
+    datastore: &FilesystemDataStore,
+    path: &Path,
+    committed: &Committed,
+) -> Result<BTreeMap<String, String>> {
+    let data = datastore
+        .get_prefix("", committed)
+        .context(error::ReadDataStore { path })?;
+    Ok(data
+        .into_iter()
+        .map(|(key, value)| (key.name().clone(), value))
+        .collect())
+}
+
+/// Returns a sorted mapping of "data key [metadata key]" to serialized value, so metadata can be
+/// compared the same way as data.
+fn get_metadata(datastore: &FilesystemDataStore, path: &Path) -> Result<BTreeMap<String, String>> {
+    let metadata = datastore
+        .get_metadata_prefix("", &None as &Option<&str>)
+        .context(error::ReadDataStore { path })?;
+    let mut result = BTreeMap::new();
+    for (data_key, meta_map) in metadata {
+        for (metadata_key, value) in meta_map {
+            result.insert(format!("{} [{}]", data_key, metadata_key), value);
+        }
+    }
+    Ok(result)
+}
+
+fn list_transactions(datastore: &FilesystemDataStore, path: &Path) -> Result<BTreeSet<String>> {
+    Ok(datastore
+        .list_transactions()
+        .context(error::ReadDataStore { path })?
+        .into_iter()
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Change, DataStoreDiff};
+    use datastore::{Committed, DataStore, FilesystemDataStore, Key, KeyType};
+    use maplit::hashmap;
+    use tempfile::TempDir;
+
+    fn data_key(name: &str) -> Key {
+        Key::new(KeyType::Data, name).unwrap()
+    }
+
+    #[test]
+    fn same() {
+        let tmp = TempDir::new().unwrap();
+        let mut old = FilesystemDataStore::new(tmp.path().join("old"));
+        let mut new = FilesystemDataStore::new(tmp.path().join("new"));
+        for datastore in &mut [&mut old, &mut new] {
+            datastore
+                .set_key(&data_key("settings.motd"), "\"hi\"", &Committed::Live)
+                .unwrap();
+        }
+
+        let diff = DataStoreDiff::new(tmp.path().join("old"), tmp.path().join("new")).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "(no changes)\n");
+    }
+
+    #[test]
+    fn changes() {
+        let tmp = TempDir::new().unwrap();
+        let mut old = FilesystemDataStore::new(tmp.path().join("old"));
+        let mut new = FilesystemDataStore::new(tmp.path().join("new"));
+        let pending = Committed::Pending {
+            tx: "bottlerocket-launch".into(),
+        };
+        let meta = Key::new(KeyType::Meta, "affected-services").unwrap();
+
+        old.set_keys(
+            &hashmap! {
+                data_key("settings.a") => "1",
+                data_key("settings.b") => "2",
+                data_key("settings.c") => "3",
+            },
+            &Committed::Live,
+        )
+        .unwrap();
+        old.set_metadata(&meta, &data_key("settings.a"), "[\"x\"]")
+            .unwrap();
+        new.set_keys(
+            &hashmap! {
+                data_key("settings.b") => "2",
+                data_key("settings.c") => "4",
+                data_key("settings.d") => "5",
+            },
+            &Committed::Live,
+        )
+        .unwrap();
+        new.set_metadata(&meta, &data_key("settings.a"), "[\"y\"]")
+            .unwrap();
+        new.set_key(&data_key("settings.e"), "6", &pending).unwrap();
+
+        let diff = DataStoreDiff::new(tmp.path().join("old"), tmp.path().join("new")).unwrap();
+        assert!(!diff.is_empty());
+        assert_eq!(
+            diff.sections["live data"],
+            vec![
+                Change::Removed {
+                    key: "settings.a".into(),
+                    value: "1".into()
+                },
+                Change::Changed {
+                    key: "settings.c".into(),
+                    old: "3".into(),
+                    new: "4".into()
+                },
+                Change::Added {
+                    key: "settings.d".into(),
+                    value: "5".into()
+                },
+            ]
+        );
+        assert_eq!(
+            diff.sections["live metadata"],
+            vec![Change::Changed {
+                key: "settings.a [affected-services]".into(),
+                old: "[\"x\"]".into(),
+                new: "[\"y\"]".into()
+            }]
+        );
+        assert_eq!(
+            diff.sections["pending transaction 'bottlerocket-launch'"],
+            vec![Change::Added {
+                key: "settings.e".into(),
+                value: "6".into()
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            r#"live data:
+  - settings.a = 1
+  ~ settings.c: 3 -> 4
+  + settings.d = 5
+live metadata:
+  ~ settings.a [affected-services]: ["x"] -> ["y"]
+pending transaction 'bottlerocket-launch':
+  + settings.e = 6
+"#
+        );
+    }
+}
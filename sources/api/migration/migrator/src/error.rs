@@ -114,6 +114,26 @@ pub(crate) enum Error {
     #[snafu(display("Failed setting permissions of '{}': {}", path.display(), source))]
     SetPermissions { path: PathBuf, source: io::Error },
 
+    #[snafu(display("Failed reading data store at '{}': {}", path.display(), source))]
+    ReadDataStore {
+        path: PathBuf,
+        source: datastore::Error,
+    },
+
+    #[snafu(display(
+        "Migrated data store at '{}' is not valid for the new model: {}",
+        path.display(),
+        problems
+    ))]
+    InvalidMigratedData { path: PathBuf, problems: String },
+
+    #[snafu(display(
+        "Migrating to {} and back to {} changed the data store; see the changes above",
+        to,
+        from
+    ))]
+    RoundTripChanged { from: Version, to: Version },
+
     #[snafu(display("Migration path '{}' contains invalid UTF-8", path.display()))]
     MigrationNameNotUTF8 { path: PathBuf },
 }
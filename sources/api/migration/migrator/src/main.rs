@@ -11,10 +11,21 @@
 //! * find migrations between the two versions
 //! * if there are migrations:
 //!   * run the migrations; the transformed data becomes the new data store
+//!   * if migrating forward, check that the new data store is valid for the model of this image
+//!   * copy the API server's history of settings changes to the new data store
 //! * if there are *no* migrations:
 //!   * just symlink to the old data store
 //! * do symlink flips so the new version takes the place of the original
 //!
+//! If given `--dry-run`, it runs the migrations into a new data store next to the given one, but
+//! doesn't flip to it.  Instead, it prints the changes between the two data stores, key by key,
+//! for live data, live metadata, and each pending transaction.  This is useful for checking
+//! migrations against a copy of a real data store.  The migrated data store is left in place for
+//! inspection.
+//!
+//! If also given `--round-trip`, it then migrates the new data store back to the original
+//! version, prints any changes from the original data store, and fails if there are any.
+//!
 //! To understand motivation and more about the overall process, look at the migration system
 //! documentation, one level up.
 
@@ -24,6 +35,8 @@
 extern crate log;
 
 use args::Args;
+use datastore::filesystem::HISTORY_FILE;
+use diff::DataStoreDiff;
 use direction::Direction;
 use error::Result;
 use nix::{dir::Dir, fcntl::OFlag, sys::stat::Mode, unistd::fsync};
@@ -43,10 +56,12 @@ use update_metadata::Manifest;
 use url::Url;
 
 mod args;
+mod diff;
 mod direction;
 mod error;
 #[cfg(test)]
 mod test;
+mod validation;
 
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
@@ -160,6 +175,17 @@ pub(crate) fn run(args: &Args) -> Result<()> {
         update_metadata::find_migrations(&current_version, &args.migrate_to_version, &manifest)
             .context(error::FindMigrations)?;
 
+    if args.dry_run {
+        return dry_run(
+            &repo,
+            &manifest,
+            direction,
+            &migrations,
+            &current_version,
+            args,
+        );
+    }
+
     if migrations.is_empty() {
         // Not all new OS versions need to change the data store format.  If there's been no
         // change, we can just link to the last version rather than making a copy.
@@ -219,7 +245,8 @@ where
 /// migration so it knows which direction we're migrating.
 ///
 /// The given data store is used as a starting point; each migration is given the output of the
-/// previous migration, and the final output becomes the new data store.
+/// previous migration, and the final output becomes the new data store.  When migrating forward,
+/// the final output is checked against the model before it's returned.
 fn run_migrations<P, S>(
     repository: &tough::Repository,
     direction: Direction,
@@ -233,7 +260,8 @@ where
 {
     // We start with the given source_datastore, updating this after each migration to point to the
     // output of the previous one.
-    let mut source_datastore = source_datastore.as_ref();
+    let original_datastore = source_datastore.as_ref();
+    let mut source_datastore = original_datastore;
     // We create a new data store (below) to serve as the target of each migration.  (Start at
     // source just to have the right type; we know we have migrations at this point.)
     let mut target_datastore = source_datastore.to_owned();
@@ -296,6 +324,14 @@ where
         source_datastore = &target_datastore;
     }
 
+    // Intermediate data stores may hold keys that a later migration removes, so we only check
+    // the final one.  We only have the model of the image we're in, so we can't check the output
+    // of backward migrations.
+    if let Direction::Forward = direction {
+        validation::validate_migrated_datastore(&target_datastore)?;
+    }
+    copy_history(original_datastore, &target_datastore);
+
     // Remove the intermediate data stores
     intermediate_datastores.remove(&target_datastore);
     for intermediate_datastore in intermediate_datastores {
@@ -318,6 +354,106 @@ where
     Ok(target_datastore)
 }
 
+/// Copies the API server's log of changes to live settings from the given data store to the
+/// migrated one.  Migrations only carry data and metadata, so the log would otherwise be lost.
+fn copy_history(from_datastore: &Path, to_datastore: &Path) {
+    let from = from_datastore.join(HISTORY_FILE);
+    if !from.exists() {
+        debug!("No settings history to copy from {}", from.display());
+        return;
+    }
+    let to = to_datastore.join(HISTORY_FILE);
+    // Even if we fail to copy the history, the migrated data store is fine, and we don't want to
+    // fail the update over it - just let someone know.
+    if let Err(e) = fs::copy(&from, &to) {
+        error!(
+            "Failed to copy settings history from '{}' to '{}': {}",
+            from.display(),
+            to.display(),
+            e
+        );
+    }
+}
+
+/// Runs the given migrations into a new data store, without flipping to it, and prints the
+/// changes from the given data store.  If the user asked for a round trip, then migrates the new
+/// data store back to the current version and fails if it doesn't match the given data store.
+fn dry_run<S>(
+    repository: &tough::Repository,
+    manifest: &Manifest,
+    direction: Direction,
+    migrations: &[S],
+    current_version: &Version,
+    args: &Args,
+) -> Result<()>
+where
+    S: AsRef<str>,
+{
+    if migrations.is_empty() {
+        info!(
+            "No migrations from {} to {}; the data store would be linked unchanged",
+            current_version, args.migrate_to_version
+        );
+        return Ok(());
+    }
+
+    let migrated = run_migrations(
+        repository,
+        direction,
+        migrations,
+        &args.datastore_path,
+        &args.migrate_to_version,
+    )?;
+    info!(
+        "Dry run: migrated data store left at {} without flipping to it",
+        migrated.display()
+    );
+    let diff = DataStoreDiff::new(&args.datastore_path, &migrated)?;
+    println!(
+        "Changes from migrating {} to {}:\n{}",
+        current_version, args.migrate_to_version, diff
+    );
+
+    if args.round_trip {
+        let back_direction = Direction::from_versions(&args.migrate_to_version, current_version)
+            .context(error::Internal {
+                msg: "Versions matched after finding migrations",
+            })?;
+        let back_migrations =
+            update_metadata::find_migrations(&args.migrate_to_version, current_version, manifest)
+                .context(error::FindMigrations)?;
+        let round_tripped = if back_migrations.is_empty() {
+            migrated
+        } else {
+            run_migrations(
+                repository,
+                back_direction,
+                &back_migrations,
+                &migrated,
+                current_version,
+            )?
+        };
+        info!(
+            "Dry run: round-tripped data store left at {}",
+            round_tripped.display()
+        );
+        let diff = DataStoreDiff::new(&args.datastore_path, &round_tripped)?;
+        println!(
+            "Changes from migrating back to {}:\n{}",
+            current_version, diff
+        );
+        ensure!(
+            diff.is_empty(),
+            error::RoundTripChanged {
+                from: current_version.clone(),
+                to: args.migrate_to_version.clone(),
+            }
+        );
+    }
+
+    Ok(())
+}
+
 /// Atomically flips version symlinks to point to the given "to" datastore so that it becomes live.
 ///
 /// This includes:
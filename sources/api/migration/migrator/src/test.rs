@@ -1,8 +1,10 @@
 //! Provides an end-to-end test of `migrator` via the `run` function. This module is conditionally
 //! compiled for cfg(test) only.
 use crate::args::Args;
-use crate::run;
+use crate::{get_current_version, run};
 use chrono::{DateTime, Utc};
+use datastore::filesystem::HISTORY_FILE;
+use datastore::{Committed, DataStore, FilesystemDataStore, Key, KeyType};
 use semver::Version;
 use std::fs;
 use std::fs::File;
@@ -40,7 +42,8 @@ const SECOND_MIGRATION: &str = "a-second-migration";
 /// Creates a script that will serve as a migration during testing. The script writes its migrations
 /// name to a file named `result.txt` in the parent directory of the datastore. `pentacle` does not
 /// retain the name of the executing binary or script, so we take the `migration_name` as input,
-/// and 'hardcode' it into the script.
+/// and 'hardcode' it into the script.  Like a real migration, it only writes data to the target
+/// datastore; it copies live data unchanged, so the migrator has a valid datastore to check.
 fn create_test_migration<S: AsRef<str>>(migration_name: S) -> String {
     format!(
         r#"#!/usr/bin/env bash
@@ -49,11 +52,59 @@ migration_name="{}"
 datastore_parent_dir="$(dirname "${{3}}")"
 outfile="${{datastore_parent_dir}}/result.txt"
 echo "${{migration_name}}:" "${{@}}" >> "${{outfile}}"
+mkdir -p "${{5}}"
+cp -r "${{3}}/live" "${{5}}/live"
 "#,
         migration_name.as_ref()
     )
 }
 
+/// Creates a script that will serve as a migration during dry run testing.  Like
+/// `create_test_migration`, the script copies the source datastore to the target datastore.  On
+/// the way forward, it adds a host container named after the migration; on the way back, it
+/// removes the host container, unless `reversible` is false.
+fn create_copying_migration<S: AsRef<str>>(migration_name: S, reversible: bool) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+set -eo pipefail
+migration_name="{}"
+reversible="{}"
+cp -r "${{3}}" "${{5}}"
+container="${{5}}/live/settings/host-containers/${{migration_name}}"
+if [ "${{1}}" = "--forward" ]; then
+    mkdir -p "${{container}}"
+    echo -n 'true' > "${{container}}/enabled"
+elif [ "${{reversible}}" = "true" ]; then
+    rm -rf "${{container}}"
+fi
+"#,
+        migration_name.as_ref(),
+        reversible
+    )
+}
+
+/// Creates a script that will serve as a migration during validation testing.  It copies the
+/// source datastore to the target datastore; the first migration adds a setting that isn't in the
+/// model, and the second migration removes it, unless `remove` is false.
+fn create_obsolete_setting_migration<S: AsRef<str>>(migration_name: S, remove: bool) -> String {
+    format!(
+        r#"#!/usr/bin/env bash
+set -eo pipefail
+migration_name="{}"
+remove="{}"
+cp -r "${{3}}" "${{5}}"
+if [ "${{migration_name}}" = "{}" ]; then
+    echo -n '"obsolete"' > "${{5}}/live/settings/no-such-setting"
+elif [ "${{remove}}" = "true" ]; then
+    rm -f "${{5}}/live/settings/no-such-setting"
+fi
+"#,
+        migration_name.as_ref(),
+        remove,
+        FIRST_MIGRATION
+    )
+}
+
 /// Holds the lifetime of a `TempDir` inside which a datastore directory and links are held for
 /// testing.
 struct TestDatastore {
@@ -95,6 +146,15 @@ fn compress(source: &[u8], destination: &Path) {
 /// Creates a test repository with a couple of versions defined in the manifest and a couple of
 /// migrations. See the test description for for more info.
 fn create_test_repo() -> TestRepo {
+    create_test_repo_with(|name| create_test_migration(name))
+}
+
+/// Creates a test repository like `create_test_repo`, using the given function to create the
+/// script for each migration from its name.
+fn create_test_repo_with<F>(create_migration: F) -> TestRepo
+where
+    F: Fn(&str) -> String,
+{
     // This is where the signed TUF repo will exist when we are done. It is the
     // root directory of the `TestRepo` we will return when we are done.
     let test_repo_dir = TempDir::new().unwrap();
@@ -123,8 +183,8 @@ fn create_test_repo() -> TestRepo {
     // order. Note that tests are sensitive to the order and number of arguments passed. If
     // --source-datastore is given at a different position then the tests will fail and the script
     // will need to be updated.
-    let migration_a = create_test_migration(FIRST_MIGRATION);
-    let migration_b = create_test_migration(SECOND_MIGRATION);
+    let migration_a = create_migration(FIRST_MIGRATION);
+    let migration_b = create_migration(SECOND_MIGRATION);
 
     // Save lz4 compressed copies of the migration script into the tuftool_indir.
     compress(migration_a.as_bytes(), &tuf_indir.join(FIRST_MIGRATION));
@@ -201,6 +261,9 @@ fn migrate_forward() {
     let from_version = Version::parse("0.99.0").unwrap();
     let to_version = Version::parse("0.99.1").unwrap();
     let test_datastore = TestDatastore::new(from_version);
+    populate_datastore(&test_datastore);
+    let history = "{\"transaction\":\"default\"}\n";
+    fs::write(test_datastore.datastore.join(HISTORY_FILE), history).unwrap();
     let test_repo = create_test_repo();
     let args = Args {
         datastore_path: test_datastore.datastore.clone(),
@@ -209,6 +272,8 @@ fn migrate_forward() {
         migrate_to_version: to_version,
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: false,
+        round_trip: false,
     };
     run(&args).unwrap();
     // the migrations should write to a file named result.txt.
@@ -224,6 +289,17 @@ fn migrate_forward() {
     let want = format!("{}: --forward", SECOND_MIGRATION);
     let got: String = second_line.chars().take(want.len()).collect();
     assert_eq!(got, want);
+
+    // The settings history was carried forward to the migrated datastore.
+    let migrated = fs::canonicalize(test_datastore.tmp.path().join("current")).unwrap();
+    assert_ne!(
+        migrated,
+        fs::canonicalize(&test_datastore.datastore).unwrap()
+    );
+    assert_eq!(
+        fs::read_to_string(migrated.join(HISTORY_FILE)).unwrap(),
+        history
+    );
 }
 
 /// This test ensures that migrations run when migrating from a newer to an older version.
@@ -241,6 +317,8 @@ fn migrate_backward() {
         migrate_to_version: to_version,
         root_path: root(),
         metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: false,
+        round_trip: false,
     };
     run(&args).unwrap();
     let output_file = test_datastore.tmp.path().join("result.txt");
@@ -256,3 +334,170 @@ fn migrate_backward() {
     let got: String = second_line.chars().take(want.len()).collect();
     assert_eq!(got, want);
 }
+
+/// Sets a live setting in the test datastore so dry runs have some data to compare, and migrated
+/// datastores have live data to check.
+fn populate_datastore(test_datastore: &TestDatastore) {
+    let mut datastore = FilesystemDataStore::new(&test_datastore.datastore);
+    let key = Key::new(KeyType::Data, "settings.motd").unwrap();
+    datastore.set_key(&key, "\"hi\"", &Committed::Live).unwrap();
+}
+
+/// Returns the paths of the datastores for the given version, other than the original.
+fn migrated_datastores(test_datastore: &TestDatastore, version: &Version) -> Vec<PathBuf> {
+    let prefix = format!("v{}_", version);
+    fs::read_dir(test_datastore.tmp.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path != &test_datastore.datastore)
+        .filter(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(&prefix)
+        })
+        .collect()
+}
+
+/// This test ensures that a dry run migrates into a new datastore without flipping to it.
+#[test]
+fn dry_run() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    populate_datastore(&test_datastore);
+    let test_repo = create_test_repo_with(|name| create_copying_migration(name, true));
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version.clone(),
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: true,
+        round_trip: false,
+    };
+    run(&args).unwrap();
+
+    // The datastore links still point to the original version.
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        from_version
+    );
+
+    // The final migrated datastore was left in place, with both migrations applied; the
+    // intermediate datastore was removed.
+    let migrated = migrated_datastores(&test_datastore, &to_version);
+    assert_eq!(migrated.len(), 1);
+    let datastore = FilesystemDataStore::new(&migrated[0]);
+    let mut names = vec!["settings.motd".to_string()];
+    for name in &[FIRST_MIGRATION, SECOND_MIGRATION] {
+        names.push(format!("settings.host-containers.{}.enabled", name));
+    }
+    for name in names {
+        let key = Key::new(KeyType::Data, name).unwrap();
+        assert!(datastore.key_populated(&key, &Committed::Live).unwrap());
+    }
+}
+
+/// This test ensures that a round trip succeeds when migrations are reversible.
+#[test]
+fn dry_run_round_trip() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    populate_datastore(&test_datastore);
+    let test_repo = create_test_repo_with(|name| create_copying_migration(name, true));
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: true,
+        round_trip: true,
+    };
+    run(&args).unwrap();
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        from_version
+    );
+}
+
+/// This test ensures that a round trip fails when a migration isn't reversible.
+#[test]
+fn dry_run_round_trip_changed() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    populate_datastore(&test_datastore);
+    let test_repo = create_test_repo_with(|name| create_copying_migration(name, false));
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: true,
+        round_trip: true,
+    };
+    run(&args).unwrap_err();
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        from_version
+    );
+}
+
+/// This test ensures that only the final datastore is checked against the model, so a migration
+/// can rely on a later one to remove a setting the model doesn't have.
+#[test]
+fn migrate_forward_validates_final_datastore() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version);
+    populate_datastore(&test_datastore);
+    let test_repo = create_test_repo_with(|name| create_obsolete_setting_migration(name, true));
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version.clone(),
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: false,
+        round_trip: false,
+    };
+    run(&args).unwrap();
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        to_version
+    );
+}
+
+/// This test ensures that we don't flip to a migrated datastore that isn't valid for the model.
+#[test]
+fn migrate_forward_invalid() {
+    let from_version = Version::parse("0.99.0").unwrap();
+    let to_version = Version::parse("0.99.1").unwrap();
+    let test_datastore = TestDatastore::new(from_version.clone());
+    populate_datastore(&test_datastore);
+    let test_repo = create_test_repo_with(|name| create_obsolete_setting_migration(name, false));
+    let args = Args {
+        datastore_path: test_datastore.datastore.clone(),
+        log_level: log::LevelFilter::Info,
+        migration_directory: test_repo.targets_path.clone(),
+        migrate_to_version: to_version,
+        root_path: root(),
+        metadata_directory: test_repo.metadata_path.clone(),
+        dry_run: false,
+        round_trip: false,
+    };
+    let err = run(&args).unwrap_err().to_string();
+    assert!(err.contains("settings.no-such-setting"), "{}", err);
+    assert_eq!(
+        get_current_version(test_datastore.tmp.path()).unwrap(),
+        from_version
+    );
+}
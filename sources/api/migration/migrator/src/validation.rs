@@ -0,0 +1,261 @@
+//! This module checks a migrated data store against the model of the image we're migrating to, so
+//! a broken chain of migrations fails before we flip to a data store the new apiserver can't read.
+
+use datastore::deserialization::from_map_with_prefix;
+use datastore::{
+    deserialize_scalar, Committed, DataStore, FilesystemDataStore, Key, KeyType, ScalarError, Value,
+};
+use model::{ConfigurationFiles, Services, Settings};
+use serde::de::DeserializeOwned;
+use snafu::{ensure, ResultExt};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::error::{self, Result};
+
+/// Ensures the apiserver of the new image can use the migrated data store at the given path.
+///
+/// This is checked once, after the last migration, because intermediate data stores are allowed
+/// to hold keys that a later migration removes.  The migrator is built with the model of the
+/// image it ships in, so we can only check data stores we migrated forward; backward migrations
+/// produce data for an older model that we don't have.  Data is deserialized the same way the
+/// apiserver does it, and the types of well-known metadata are checked.  All problems are
+/// reported at once.
+pub(crate) fn validate_migrated_datastore<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let datastore = FilesystemDataStore::new(path);
+    let problems = datastore_problems(&datastore).context(error::ReadDataStore { path })?;
+    ensure!(
+        problems.is_empty(),
+        error::InvalidMigratedData {
+            path,
+            problems: problems.join("; "),
+        }
+    );
+    Ok(())
+}
+
+/// Returns a description of each problem found in live data, live metadata, and each pending
+/// transaction of the given data store.
+fn datastore_problems<D: DataStore>(datastore: &D) -> datastore::Result<Vec<String>> {
+    let mut committeds = vec![Committed::Live];
+    let mut transactions: Vec<String> = datastore.list_transactions()?.into_iter().collect();
+    transactions.sort();
+    committeds.extend(transactions.into_iter().map(|tx| Committed::Pending { tx }));
+
+    let mut problems = Vec::new();
+    for committed in committeds {
+        let section = match &committed {
+            Committed::Live => "live data".to_string(),
+            Committed::Pending { tx } => format!("pending transaction '{}'", tx),
+        };
+        let data = datastore.get_prefix("", &committed)?;
+        for problem in data_problems(&data) {
+            problems.push(format!("{}: {}", section, problem));
+        }
+    }
+
+    // Metadata isn't committed, it goes live immediately.
+    let metadata = datastore.get_metadata_prefix("", &None as &Option<&str>)?;
+    for problem in metadata_problems(&metadata) {
+        problems.push(format!("live metadata: {}", problem));
+    }
+    Ok(problems)
+}
+
+/// Splits the given data into the sections the apiserver deserializes separately, and returns a
+/// description of each problem found deserializing them.
+fn data_problems(data: &HashMap<Key, String>) -> Vec<String> {
+    let mut settings = HashMap::new();
+    let mut services = HashMap::new();
+    let mut configuration_files = HashMap::new();
+    for (key, value) in data {
+        let section = match key.segments()[0].as_ref() {
+            "settings" => &mut settings,
+            "services" => &mut services,
+            "configuration-files" => &mut configuration_files,
+            // Other keys aren't served by the apiserver and aren't part of the model.
+            _ => continue,
+        };
+        section.insert(key.clone(), value.clone());
+    }
+
+    let mut problems = Vec::new();
+    // Settings are all optional, so we can check keys individually to find the bad ones.
+    problems.extend(find_problems::<Settings>(&settings, None, usize::MAX));
+    // Services and configuration files have required fields, so we check each one as a whole.
+    problems.extend(find_problems::<Services>(&services, Some("services"), 2));
+    problems.extend(find_problems::<ConfigurationFiles>(
+        &configuration_files,
+        Some("configuration-files"),
+        2,
+    ));
+    problems.sort();
+    problems
+}
+
+/// Tries to deserialize the given data into T.  If that fails, checks groups of keys sharing the
+/// first `group_segments` segments individually, so we can report each group that's at fault.
+/// Returns a description of each problem found.
+fn find_problems<T: DeserializeOwned>(
+    data: &HashMap<Key, String>,
+    map_prefix: Option<&str>,
+    group_segments: usize,
+) -> Vec<String> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let map_prefix = map_prefix.map(str::to_string);
+    let all_err = match from_map_with_prefix::<_, _, T, _>(map_prefix.clone(), data) {
+        Ok(_) => return Vec::new(),
+        Err(e) => e,
+    };
+
+    let mut groups: BTreeMap<String, HashMap<Key, String>> = BTreeMap::new();
+    for (key, value) in data {
+        let segments = key.segments();
+        let group_len = segments.len().min(group_segments);
+        let group = Key::from_segments(KeyType::Data, &segments[..group_len])
+            .map(|k| k.name().clone())
+            .unwrap_or_else(|_| key.name().clone());
+        groups
+            .entry(group)
+            .or_default()
+            .insert(key.clone(), value.clone());
+    }
+
+    let mut problems = Vec::new();
+    for (group, group_data) in groups {
+        if let Err(e) = from_map_with_prefix::<_, _, T, _>(map_prefix.clone(), &group_data) {
+            problems.push(format!("{}: {}", group, e));
+        }
+    }
+
+    // The data could be invalid in combination even if each group is fine on its own; make sure
+    // we still report something.
+    if problems.is_empty() {
+        problems.push(all_err.to_string());
+    }
+    problems
+}
+
+/// Checks that well-known metadata has the types its users expect.  Returns a description of each
+/// problem found.
+fn metadata_problems(metadata: &HashMap<Key, HashMap<Key, String>>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (data_key, meta_map) in metadata {
+        for (metadata_key, value_str) in meta_map {
+            let value: Value = match deserialize_scalar::<_, ScalarError>(value_str) {
+                Ok(value) => value,
+                Err(e) => {
+                    problems.push(format!("{} for {}: {}", metadata_key, data_key, e));
+                    continue;
+                }
+            };
+
+            let well_formed = match metadata_key.name().as_ref() {
+                "affected-services" => value
+                    .as_array()
+                    .map(|list| list.iter().all(Value::is_string))
+                    .unwrap_or(false),
+                "setting-generator" | "template" => value.is_string(),
+                _ => true,
+            };
+            if !well_formed {
+                problems.push(format!(
+                    "{} for {}: unexpected value {}",
+                    metadata_key, data_key, value
+                ));
+            }
+        }
+    }
+    problems.sort();
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::datastore_problems;
+    use datastore::memory::MemoryDataStore;
+    use datastore::{Committed, DataStore, Key, KeyType};
+
+    fn set(ds: &mut MemoryDataStore, key: &str, value: &str, committed: &Committed) {
+        ds.set_key(&Key::new(KeyType::Data, key).unwrap(), value, committed)
+            .unwrap();
+    }
+
+    fn set_metadata(ds: &mut MemoryDataStore, key: &str, metadata_key: &str, value: &str) {
+        ds.set_metadata(
+            &Key::new(KeyType::Meta, metadata_key).unwrap(),
+            &Key::new(KeyType::Data, key).unwrap(),
+            value,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn valid() {
+        let mut ds = MemoryDataStore::new();
+        let live = Committed::Live;
+        set(&mut ds, "settings.motd", "\"hi\"", &live);
+        set(
+            &mut ds,
+            "settings.host-containers.admin.enabled",
+            "true",
+            &live,
+        );
+        set(
+            &mut ds,
+            "services.motd.configuration-files",
+            "[\"motd\"]",
+            &live,
+        );
+        set(&mut ds, "services.motd.restart-commands", "[]", &live);
+        set_metadata(&mut ds, "settings.motd", "affected-services", "[\"motd\"]");
+        let pending = Committed::Pending { tx: "tx".into() };
+        set(&mut ds, "settings.motd", "\"bye\"", &pending);
+
+        assert_eq!(datastore_problems(&ds).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_keys_reported() {
+        let mut ds = MemoryDataStore::new();
+        let live = Committed::Live;
+        set(&mut ds, "settings.motd", "\"hi\"", &live);
+        set(&mut ds, "settings.no-such-setting", "\"x\"", &live);
+        // Missing restart-commands
+        set(
+            &mut ds,
+            "services.motd.configuration-files",
+            "[\"motd\"]",
+            &live,
+        );
+        let pending = Committed::Pending { tx: "tx".into() };
+        set(
+            &mut ds,
+            "settings.host-containers.admin.enabled",
+            "\"not a bool\"",
+            &pending,
+        );
+
+        let problems = datastore_problems(&ds).unwrap().join("\n");
+        assert!(problems.contains("live data: settings.no-such-setting"));
+        assert!(problems.contains("live data: services.motd"));
+        assert!(
+            problems.contains("pending transaction 'tx': settings.host-containers.admin.enabled")
+        );
+        assert!(!problems.contains("settings.motd"));
+    }
+
+    #[test]
+    fn invalid_metadata_reported() {
+        let mut ds = MemoryDataStore::new();
+        set_metadata(&mut ds, "settings.motd", "affected-services", "\"motd\"");
+        set_metadata(&mut ds, "settings.motd", "template", "42");
+
+        let problems = datastore_problems(&ds).unwrap().join("\n");
+        assert!(problems.contains("affected-services"));
+        assert!(problems.contains("template"));
+    }
+}
@@ -11,6 +11,7 @@ use handlebars::Handlebars;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 use serde::de::DeserializeOwned;
 use snafu::ResultExt;
+use std::fs;
 use std::path::Path;
 
 // https://url.spec.whatwg.org/#query-percent-encode-set
@@ -19,6 +20,8 @@ const ENCODE_QUERY_CHARS: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'#').ad
 pub mod error {
     use http::StatusCode;
     use snafu::Snafu;
+    use std::io;
+    use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
@@ -49,6 +52,24 @@ pub mod error {
             uri: String,
             source: serde_json::Error,
         },
+
+        #[snafu(display("Unable to read settings file '{}': {}", path.display(), source))]
+        SettingsFileRead { path: PathBuf, source: io::Error },
+
+        #[snafu(display("Invalid TOML in settings file '{}': {}", path.display(), source))]
+        SettingsFileToml {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+
+        #[snafu(display("Invalid JSON in settings file '{}': {}", path.display(), source))]
+        SettingsFileJson {
+            path: PathBuf,
+            source: serde_json::Error,
+        },
+
+        #[snafu(display("Unable to read os-release file: {}", source))]
+        OsRelease { source: bottlerocket_release::Error },
     }
 }
 pub use error::Error;
@@ -98,19 +119,66 @@ where
 }
 
 /// Requests all settings from the API so they can be used as the data source for a handlebars
-/// templating call.
+/// templating call.  Sensitive settings are included, since templates may need them.
 pub async fn get_settings<P>(socket_path: P) -> Result<model::Model>
 where
     P: AsRef<Path>,
 {
     debug!("Querying API for settings data");
-    let settings: model::Model =
-        get_json(&socket_path, "/", None as Option<(String, String)>).await?;
+    let settings: model::Model = get_json(&socket_path, "/", Some(("secrets", "true"))).await?;
+    trace!("Model values: {:?}", settings);
+
+    Ok(settings)
+}
+
+/// Reads settings from a file so they can be used as the data source for a handlebars templating
+/// call without the API, for example to test templates.  The file is shaped like the response to
+/// a GET of "/", e.g. `{"settings": {"motd": "hi"}}`, and is read as TOML if its name ends in
+/// ".toml" and as JSON otherwise.  If an os-release file is given, it stands in for the `os`
+/// fields.
+pub fn get_settings_from_file<P1, P2>(
+    settings_path: P1,
+    os_release_path: Option<P2>,
+) -> Result<model::Model>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let path = settings_path.as_ref();
+    debug!("Reading settings data from {}", path.display());
+    let data = fs::read_to_string(path).context(error::SettingsFileRead { path })?;
+    let mut settings: model::Model = if path.extension().map_or(false, |ext| ext == "toml") {
+        toml::from_str(&data).context(error::SettingsFileToml { path })?
+    } else {
+        serde_json::from_str(&data).context(error::SettingsFileJson { path })?
+    };
+
+    if let Some(os_release_path) = os_release_path {
+        settings.os = Some(
+            bottlerocket_release::BottlerocketRelease::from_file(os_release_path)
+                .context(error::OsRelease)?,
+        );
+    }
     trace!("Model values: {:?}", settings);
 
     Ok(settings)
 }
 
+/// The helpers registered by `build_template_registry`, with the number of parameters each one
+/// requires.  Templates can be checked against this without rendering them.
+pub const HELPER_PARAMS: &[(&str, usize)] = &[
+    ("base64_decode", 1),
+    ("join_map", 4),
+    ("default", 2),
+    ("ecr-prefix", 1),
+    ("pause-prefix", 1),
+    ("host", 1),
+    ("goarch", 1),
+    ("join_array", 2),
+    ("kube_reserve_cpu", 1),
+    ("kube_reserve_memory", 2),
+];
+
 /// Build a handlebars template registry with our common helper functions.
 pub fn build_template_registry() -> Result<handlebars::Handlebars<'static>> {
     let mut template_registry = Handlebars::new();
@@ -134,3 +202,82 @@ pub fn build_template_registry() -> Result<handlebars::Handlebars<'static>> {
 
     Ok(template_registry)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn helper_params_registered() {
+        let registry = build_template_registry().unwrap();
+        for (name, _) in HELPER_PARAMS {
+            assert!(
+                registry.get_helper(name).is_some(),
+                "{} not registered",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn registered_helpers_have_params() {
+        // Handlebars can't list its helpers, so we find the names build_template_registry
+        // registers in its source.
+        let source = include_str!("lib.rs");
+        let start = source.find("pub fn build_template_registry").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+        let names: Vec<&str> = source[start..end]
+            .split("register_helper(")
+            .skip(1)
+            .filter_map(|call| call.split('"').nth(1))
+            .collect();
+        assert_eq!(names.len(), HELPER_PARAMS.len());
+        for name in names {
+            assert!(
+                HELPER_PARAMS
+                    .iter()
+                    .any(|(param_name, _)| *param_name == name),
+                "{} not in HELPER_PARAMS",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn settings_from_toml_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings_path = dir.path().join("settings.toml");
+        let os_release_path = dir.path().join("os-release");
+        fs::write(&settings_path, "[settings]\nmotd = \"hi\"\n").unwrap();
+        let os_release = r#"PRETTY_NAME="Bottlerocket OS 1.2.3"
+VARIANT_ID=aws-dev
+VERSION_ID=1.2.3
+BUILD_ID=abcdef
+"#;
+        fs::write(&os_release_path, os_release).unwrap();
+
+        let model = get_settings_from_file(&settings_path, Some(&os_release_path)).unwrap();
+        let registry = build_template_registry().unwrap();
+        let rendered = registry
+            .render_template("{{settings.motd}} {{os.variant_id}}", &model)
+            .unwrap();
+        assert_eq!(rendered, "hi aws-dev");
+    }
+
+    #[test]
+    fn settings_from_json_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings_path = dir.path().join("settings.json");
+        fs::write(&settings_path, r#"{"settings": {"motd": "hi"}}"#).unwrap();
+
+        let model = get_settings_from_file(&settings_path, None as Option<&Path>).unwrap();
+        assert!(model.os.is_none());
+        let registry = build_template_registry().unwrap();
+        let rendered = registry
+            .render_template("{{settings.motd}}", &model)
+            .unwrap();
+        assert_eq!(rendered, "hi");
+        // Without an os-release file, templates can't use os fields.
+        assert!(registry.render_template("{{os.arch}}", &model).is_err());
+    }
+}
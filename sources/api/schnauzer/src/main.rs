@@ -11,12 +11,26 @@ For example, if we're generating "settings.x" and we have template "foo-{{ setti
 If the returned value is "baz", our generated value will be "foo-baz".
 
 (The name "schnauzer" comes from the fact that Schnauzers are search and rescue dogs (similar to this search and replace task) and because they have mustaches.)
+
+# Rendering templates offline
+
+schnauzer can also render a template file without the API, which makes it easy to test changes to the configuration file templates under `packages`:
+
+```shell
+schnauzer --render packages/os/updog-toml --settings settings.toml --os-release os-release
+```
+
+The settings file is shaped like the response to a GET of "/" from the API, e.g. `{"settings": {"motd": "hi"}}`, and is read as TOML if its name ends in ".toml" and as JSON otherwise, so a user data file works too.
+If given, the os-release file stands in for the `os` fields.
+The rendered file is printed to stdout, so it can be compared to an expected file.
+Templates are rendered with the same helpers as they are on a host, in strict mode, so a reference to a setting that isn't in the settings file is an error.
 */
 
 #![deny(rust_2018_idioms)]
 
 use snafu::{ensure, OptionExt, ResultExt};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::string::String;
 use std::{env, process};
 
@@ -27,6 +41,7 @@ const API_METADATA_URI_BASE: &str = "/metadata/";
 mod error {
     use http::StatusCode;
     use snafu::Snafu;
+    use std::path::PathBuf;
 
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub(super)")]
@@ -71,6 +86,21 @@ mod error {
         #[snafu(display("Failed to get settings from API: {}", source))]
         GetSettings { source: schnauzer::Error },
 
+        #[snafu(display("Failed to get settings from file: {}", source))]
+        GetSettingsFile { source: schnauzer::Error },
+
+        #[snafu(display("Failed to register template '{}': {}", path.display(), source))]
+        RegisterTemplate {
+            path: PathBuf,
+            source: handlebars::TemplateFileError,
+        },
+
+        #[snafu(display("Failed to render template '{}': {}", path.display(), source))]
+        RenderTemplateFile {
+            path: PathBuf,
+            source: handlebars::RenderError,
+        },
+
         #[snafu(display(
             "Failed to render setting '{}' from template '{}': {}",
             setting_name,
@@ -126,25 +156,98 @@ async fn get_metadata(key: &str, meta: &str) -> Result<String> {
     Ok(response_str.to_string())
 }
 
+/// The ways schnauzer can run.
+enum Mode {
+    /// Generate the value of a setting from its template metadata, using settings from the API.
+    Generate { setting_name: String },
+    /// Render a template file with settings from a file.
+    Render {
+        template_path: PathBuf,
+        settings_path: PathBuf,
+        os_release_path: Option<PathBuf>,
+    },
+}
+
 /// Print usage message.
 fn usage() -> ! {
     let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
-    eprintln!("Usage: {} SETTING_KEY", program_name);
+    eprintln!(
+        r"Usage: {0} SETTING_KEY
+       {0} --render TEMPLATE_FILE --settings SETTINGS_FILE [ --os-release OS_RELEASE_FILE ]
+
+    With SETTING_KEY, generates the setting from its template metadata and settings in the API.
+
+    With --render, renders the template file with the settings in the given TOML or JSON file,
+    and prints the result.  The os-release file stands in for the os fields.",
+        program_name
+    );
     process::exit(2);
 }
 
-/// Parses args for the setting key name.
-fn parse_args(mut args: env::Args) -> String {
-    let arg = args.nth(1).unwrap_or_else(|| usage());
-    if arg == "--help" || arg == "-h" {
-        usage()
-    }
-    arg
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
 }
 
-async fn run() -> Result<()> {
-    let setting_name = parse_args(env::args());
+/// Parses args for the setting key name, or the files to render.
+fn parse_args(args: env::Args) -> Mode {
+    let mut setting_name = None;
+    let mut template_path = None;
+    let mut settings_path = None;
+    let mut os_release_path = None;
 
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--help" | "-h" => usage(),
+            "--render" => {
+                template_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --render"))
+                        .into(),
+                )
+            }
+            "--settings" => {
+                settings_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --settings"))
+                        .into(),
+                )
+            }
+            "--os-release" => {
+                os_release_path = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --os-release"))
+                        .into(),
+                )
+            }
+            x if x.starts_with('-') => usage_msg(format!("Unknown argument '{}'", x)),
+            _ if setting_name.is_some() => usage_msg("Only one setting key can be given"),
+            x => setting_name = Some(x.to_string()),
+        }
+    }
+
+    match (setting_name, template_path) {
+        (Some(setting_name), None) => {
+            if settings_path.is_some() || os_release_path.is_some() {
+                usage_msg("--settings and --os-release are only used with --render");
+            }
+            Mode::Generate { setting_name }
+        }
+        (None, Some(template_path)) => Mode::Render {
+            template_path,
+            settings_path: settings_path
+                .unwrap_or_else(|| usage_msg("--settings is required with --render")),
+            os_release_path,
+        },
+        (Some(_), Some(_)) => usage_msg("Give either a setting key or --render, not both"),
+        (None, None) => usage(),
+    }
+}
+
+/// Generates the value of a setting from its template metadata and prints it.
+async fn generate(setting_name: String) -> Result<()> {
     let registry = schnauzer::build_template_registry().context(error::BuildTemplateRegistry)?;
     let template = get_metadata(&setting_name, "templates").await?;
     let settings = schnauzer::get_settings(DEFAULT_API_SOCKET)
@@ -168,6 +271,45 @@ async fn run() -> Result<()> {
     Ok(())
 }
 
+/// Renders a template file with settings from a file and prints the result as-is.
+fn render(
+    template_path: PathBuf,
+    settings_path: PathBuf,
+    os_release_path: Option<PathBuf>,
+) -> Result<()> {
+    let mut registry =
+        schnauzer::build_template_registry().context(error::BuildTemplateRegistry)?;
+    let settings = schnauzer::get_settings_from_file(&settings_path, os_release_path.as_ref())
+        .context(error::GetSettingsFile)?;
+
+    // The template is registered under its path so errors name the file.
+    let name = template_path.display().to_string();
+    registry
+        .register_template_file(&name, &template_path)
+        .context(error::RegisterTemplate {
+            path: &template_path,
+        })?;
+    let rendered = registry
+        .render(&name, &settings)
+        .context(error::RenderTemplateFile {
+            path: &template_path,
+        })?;
+
+    print!("{}", rendered);
+    Ok(())
+}
+
+async fn run() -> Result<()> {
+    match parse_args(env::args()) {
+        Mode::Generate { setting_name } => generate(setting_name).await,
+        Mode::Render {
+            template_path,
+            settings_path,
+            os_release_path,
+        } => render(template_path, settings_path, os_release_path),
+    }
+}
+
 // Returning a Result from main makes it print a Debug representation of the error, but with Snafu
 // we have nice Display representations of the error, so we wrap "main" (run) and print any error.
 // https://github.com/shepmaster/snafu/issues/110
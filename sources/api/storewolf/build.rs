@@ -6,8 +6,17 @@
 /// groups of default settings, without having to ship those files in the OS image.  Specifically,
 /// we read any number of files from a defaults.d directory in the variant's model directory and
 /// merge later entries into earlier entries, so later files take precedence.
+///
+/// Before writing the defaults, we check the defaults of every variant against its model, so that
+/// a mistake fails the build instead of keeping storewolf from populating the data store on a
+/// booted host, even if it's in a variant other than the one being built.
 use merge_toml::merge_values;
-use snafu::ResultExt;
+use model::schema::Value as JsonValue;
+use model::validation::Scope;
+use model::variants::{self, Variant};
+use model::{ConfigurationFiles, Services};
+use snafu::{ensure, ResultExt};
+use std::collections::HashSet;
 use std::env;
 use std::fs::{self, File};
 use std::io::Write;
@@ -15,12 +24,19 @@ use std::path::{Path, PathBuf};
 use toml::{map::Map, Value};
 use walkdir::WalkDir;
 
-/// A variant stores its default settings in .toml files in this directory.  It can link to shared
-/// files if desired.  Entries are sorted by filename, and later entries take precedence.
-const DEFAULTS_DIR: &str = "../../models/src/variant/current/defaults.d";
+/// Each variant has a directory of its model under this one, and stores its default settings in
+/// .toml files in a defaults.d directory there.  It can link to shared files if desired.  Entries
+/// are sorted by filename, and later entries take precedence.
+const MODELS_DIR: &str = "../../models/src";
+
+/// Returns the defaults.d directory of the given variant.
+fn defaults_dir(variant: &str) -> PathBuf {
+    Path::new(MODELS_DIR).join(variant).join("defaults.d")
+}
 
 fn main() -> Result<()> {
     generate_readme();
+    check_all_defaults()?;
     generate_defaults_toml()?;
 
     // Reflect that we need to rerun if variant has changed to pick up the new default settings.
@@ -55,11 +71,11 @@ fn generate_readme() {
     readme.write_all(content.as_bytes()).unwrap();
 }
 
-/// Merge the variant's default settings files into a single TOML value.  The result is serialized
-/// to a file in OUT_DIR for storewolf to read.
-fn generate_defaults_toml() -> Result<()> {
+/// Merge the given variant's default settings files into a single TOML value.
+fn merge_defaults(variant: &str) -> Result<Value> {
     // Find TOML config files specified by the variant.
-    let walker = WalkDir::new(DEFAULTS_DIR)
+    let defaults_dir = defaults_dir(variant);
+    let walker = WalkDir::new(&defaults_dir)
         .follow_links(true) // we expect users to link to shared files
         .min_depth(1) // only read files in defaults.d, not doing inheritance yet
         .max_depth(1)
@@ -70,7 +86,7 @@ fn generate_defaults_toml() -> Result<()> {
     // Merge the files into a single TOML value, in order.
     let mut defaults = Value::Table(Map::new());
     for entry in walker {
-        let entry = entry.context(error::ListFiles { dir: DEFAULTS_DIR })?;
+        let entry = entry.context(error::ListFiles { dir: &defaults_dir })?;
 
         // Reflect that we need to rerun if any of the default settings files have changed.
         println!("cargo:rerun-if-changed={}", entry.path().display());
@@ -82,6 +98,15 @@ fn generate_defaults_toml() -> Result<()> {
         let value = toml::from_str(&data).context(error::TomlDeserialize { path: entry.path() })?;
         merge_values(&mut defaults, &value).context(error::TomlMerge)?;
     }
+    Ok(defaults)
+}
+
+/// Merge the default settings of the variant being built, and serialize them to a file in OUT_DIR
+/// for storewolf to read.
+fn generate_defaults_toml() -> Result<()> {
+    // The models build requires VARIANT as well, and checks that it names a registered variant.
+    let variant = env::var("VARIANT").expect("VARIANT not set; it's needed to pick the model");
+    let defaults = merge_defaults(&variant)?;
 
     // Serialize to disk for storewolf to read.
     let data = toml::to_string(&defaults).context(error::TomlSerialize)?;
@@ -92,6 +117,163 @@ fn generate_defaults_toml() -> Result<()> {
     Ok(())
 }
 
+/// Checks the merged defaults of every variant against its model.  The problems of each variant
+/// are printed together, and returned as a single error.
+fn check_all_defaults() -> Result<()> {
+    let mut invalid = Vec::new();
+    let mut count = 0;
+    for variant in variants::all() {
+        let defaults = merge_defaults(variant.name())?;
+        let problems = check_defaults(variant, &defaults);
+        if !problems.is_empty() {
+            eprintln!(
+                "Default settings for variant '{}' are invalid:",
+                variant.name()
+            );
+            for problem in &problems {
+                eprintln!("  {}", problem);
+            }
+            invalid.push(variant.name());
+            count += problems.len();
+        }
+    }
+
+    ensure!(
+        invalid.is_empty(),
+        error::InvalidDefaults {
+            variants: invalid.join(", "),
+            count
+        }
+    );
+    Ok(())
+}
+
+/// Checks the merged defaults against the given variant's model: the settings, services, and
+/// configuration files have to deserialize into their model types, and the services named in
+/// `affected-services` metadata and the configuration files named by services have to exist.
+/// The settings are checked as they'll be once committed, except for the structs containing
+/// settings whose values are generated on the host.  Returns the problems found.
+fn check_defaults(variant: &Variant, defaults: &Value) -> Vec<String> {
+    let empty = Value::Table(Map::new());
+    let mut problems = Vec::new();
+
+    let settings = defaults
+        .get("settings")
+        .unwrap_or(&empty)
+        .clone()
+        .try_into::<JsonValue>();
+    let generated = generated_settings(defaults);
+    match settings.map(|settings| variant.validate_settings(settings, Scope::Merged)) {
+        Ok(Ok(violations)) => {
+            for violation in violations {
+                // Rules can't be checked until the generated settings have their values.
+                if generated
+                    .iter()
+                    .any(|setting| setting.starts_with(&violation.path))
+                {
+                    continue;
+                }
+                problems.push(violation.to_string());
+            }
+        }
+        Ok(Err(e)) => problems.push(format!("settings don't match the model: {}", e)),
+        Err(e) => problems.push(format!("settings don't match the model: {}", e)),
+    }
+
+    let services = match defaults
+        .get("services")
+        .unwrap_or(&empty)
+        .clone()
+        .try_into::<Services>()
+    {
+        Ok(services) => services,
+        Err(e) => {
+            problems.push(format!("services don't match the model: {}", e));
+            Services::new()
+        }
+    };
+    let configuration_files = match defaults
+        .get("configuration-files")
+        .unwrap_or(&empty)
+        .clone()
+        .try_into::<ConfigurationFiles>()
+    {
+        Ok(configuration_files) => configuration_files,
+        Err(e) => {
+            problems.push(format!("configuration-files don't match the model: {}", e));
+            ConfigurationFiles::new()
+        }
+    };
+
+    // Every configuration file a service names needs an entry to say how to render it.
+    let mut service_names: Vec<_> = services.keys().collect();
+    service_names.sort();
+    for name in service_names {
+        for file in &services[name].configuration_files {
+            if !configuration_files.contains_key(&**file) {
+                problems.push(format!(
+                    "services.{} uses configuration file '{}', which isn't in configuration-files",
+                    name, file
+                ));
+            }
+        }
+    }
+
+    // Every service named in metadata needs to exist, or changes to the setting won't be applied.
+    let service_names: HashSet<&str> = services.keys().map(String::as_str).collect();
+    let mut to_check = vec![(Vec::new(), defaults.get("metadata").unwrap_or(&empty))];
+    while let Some((path, value)) = to_check.pop() {
+        let table = match value.as_table() {
+            Some(table) => table,
+            None => continue,
+        };
+        for (key, value) in table {
+            if key == "affected-services" {
+                for service in value.as_array().into_iter().flatten() {
+                    let service = service.as_str().unwrap_or_default();
+                    if !service_names.contains(service) {
+                        problems.push(format!(
+                            "metadata.{}.affected-services names service '{}', which isn't in services",
+                            path.join("."),
+                            service
+                        ));
+                    }
+                }
+            } else {
+                let mut path = path.clone();
+                path.push(key.as_str());
+                to_check.push((path, value));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Returns the paths of the settings that have a setting generator or a template in the metadata
+/// of the given defaults, like ["settings", "host-containers", "admin", "source"].  Their values
+/// are generated on the host, after the defaults are committed.
+fn generated_settings(defaults: &Value) -> Vec<Vec<String>> {
+    let mut generated = Vec::new();
+    let mut to_check = vec![(Vec::new(), defaults.get("metadata"))];
+    while let Some((path, value)) = to_check.pop() {
+        let table = match value.and_then(Value::as_table) {
+            Some(table) => table,
+            None => continue,
+        };
+        for (key, value) in table {
+            if key == "setting-generator" || key == "template" {
+                generated.push(path.clone());
+            } else {
+                let mut path = path.clone();
+                path.push(key.to_string());
+                to_check.push((path, Some(value)));
+            }
+        }
+    }
+    generated
+}
+
 mod error {
     use snafu::Snafu;
     use std::path::PathBuf;
@@ -106,6 +288,13 @@ mod error {
             source: std::io::Error,
         },
 
+        #[snafu(display(
+            "Default settings for variant(s) {} have {} problem(s)",
+            variants,
+            count
+        ))]
+        InvalidDefaults { variants: String, count: usize },
+
         #[snafu(display("Failed to list files in {}: {}", dir.display(), source))]
         ListFiles {
             dir: PathBuf,
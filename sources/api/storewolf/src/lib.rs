@@ -1,10 +1,13 @@
 #![deny(rust_2018_idioms)]
 
 use bottlerocket_release::BottlerocketRelease;
+use datastore::serialization::to_pairs_with_prefix;
+use datastore::Key;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use semver::Version;
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
+use std::collections::HashMap;
 use std::fs;
 use std::os::unix::fs::symlink;
 use std::path::{Path, PathBuf};
@@ -19,6 +22,17 @@ pub mod error {
     #[derive(Debug, Snafu)]
     #[snafu(visibility = "pub")]
     pub enum Error {
+        #[snafu(display("Default settings are not valid TOML: {}", source))]
+        DefaultsFormatting { source: toml::de::Error },
+
+        #[snafu(display("'settings' key in defaults is not a TOML table"))]
+        DefaultSettingsNotTable,
+
+        #[snafu(display("Unable to serialize default settings: {}", source))]
+        DefaultSettingsSerialization {
+            source: datastore::serialization::Error,
+        },
+
         #[snafu(display("Unable to create directory at '{}': {}", path.display(), source))]
         DirectoryCreation { path: PathBuf, source: io::Error },
 
@@ -32,6 +46,25 @@ pub mod error {
 
 type Result<T> = std::result::Result<T, error::Error>;
 
+/// The variant's default settings and other data, merged from the TOML files in its `defaults.d`
+/// directory at build time.
+pub const DEFAULTS_TOML: &str = include_str!(concat!(env!("OUT_DIR"), "/defaults.toml"));
+
+/// Returns the variant's default settings as a mapping of data keys, like "settings.motd", to
+/// serialized values, in the form they're written to the datastore.
+pub fn default_settings() -> Result<HashMap<Key, String>> {
+    let defaults: toml::Value = toml::from_str(DEFAULTS_TOML).context(error::DefaultsFormatting)?;
+    match defaults.get("settings") {
+        Some(settings) => {
+            let settings = settings
+                .as_table()
+                .context(error::DefaultSettingsNotTable)?;
+            to_pairs_with_prefix("settings", settings).context(error::DefaultSettingsSerialization)
+        }
+        None => Ok(HashMap::new()),
+    }
+}
+
 /// Given a base path, create a brand new datastore with the appropriate
 /// symlink structure for the desired datastore version.
 ///
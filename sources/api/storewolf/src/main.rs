@@ -25,10 +25,14 @@ use datastore::key::{Key, KeyType};
 use datastore::serialization::{to_pairs, to_pairs_with_prefix};
 use datastore::{self, DataStore, FilesystemDataStore, ScalarError};
 use model::modeled_types::SingleLineString;
+use model::sensitive::SensitivePaths;
 
 // Shared transaction used by boot-time services.
 const TRANSACTION: &str = "bottlerocket-launch";
 
+// The source recorded in the provenance of default settings.
+const PROVENANCE_SOURCE: &str = "defaults";
+
 mod error {
     use std::io;
     use std::path::PathBuf;
@@ -63,6 +67,9 @@ mod error {
         #[snafu(display("Default settings' metadata has unexpected types"))]
         DefaultsMetadataUnexpectedFormat {},
 
+        #[snafu(display("Unable to finish interrupted commit in datastore: {}", source))]
+        DatastoreRecovery { source: datastore::Error },
+
         #[snafu(display("Error querying datstore for populated keys: {}", source))]
         QueryData { source: datastore::Error },
 
@@ -220,10 +227,21 @@ fn populate_default_datastore<P: AsRef<Path>>(
     let mut existing_data = HashSet::new();
     let mut existing_metadata = HashMap::new();
 
+    // Settings marked sensitive in the model are marked in metadata as they're written, so they're
+    // kept out of API responses.
+    let sensitive = SensitivePaths::of::<model::Settings>("settings");
+    let sensitive_md_key =
+        Key::new(KeyType::Meta, datastore::SENSITIVE_METADATA_KEY).context(error::InvalidKey {
+            key_type: KeyType::Meta,
+            key: datastore::SENSITIVE_METADATA_KEY,
+        })?;
+
     // If the "live" path of the datastore exists, query it for populated
     // meta/data.  Otherwise, create the datastore path.
     let live_path = &datastore_path.join("live");
     if live_path.exists() {
+        // Finish any interrupted commit, so we see all of its data.
+        datastore.recover().context(error::DatastoreRecovery)?;
         debug!("Gathering existing data from the datastore");
         existing_metadata = datastore
             .list_populated_metadata("", &None as &Option<&str>)
@@ -237,9 +255,8 @@ fn populate_default_datastore<P: AsRef<Path>>(
     }
 
     // Here we read in the merged settings file built by build.rs.
-    let defaults_str = include_str!(concat!(env!("OUT_DIR"), "/defaults.toml"));
     let mut defaults_val: toml::Value =
-        toml::from_str(defaults_str).context(error::DefaultsFormatting {
+        toml::from_str(storewolf::DEFAULTS_TOML).context(error::DefaultsFormatting {
             path: concat!(env!("OUT_DIR"), "/defaults.toml"),
         })?;
 
@@ -290,6 +307,48 @@ fn populate_default_datastore<P: AsRef<Path>>(
         datastore
             .set_keys(&settings_to_write, &pending)
             .context(error::WriteKeys)?;
+
+        // Record where the settings came from once they're committed, so the default values can
+        // be told apart from values given later, e.g. in user data.
+        let md_key = Key::new(KeyType::Meta, datastore::PROVENANCE_METADATA_KEY).context(
+            error::InvalidKey {
+                key_type: KeyType::Meta,
+                key: datastore::PROVENANCE_METADATA_KEY,
+            },
+        )?;
+        let provenance = datastore::Provenance {
+            source: PROVENANCE_SOURCE.to_string(),
+            transaction: TRANSACTION.to_string(),
+        };
+        let value = datastore::serialize_scalar::<_, ScalarError>(&provenance).context(
+            error::SerializeScalar {
+                given: "provenance of default settings",
+            },
+        )?;
+        for key in settings_to_write.keys() {
+            datastore
+                .set_pending_metadata(&md_key, key, &value, TRANSACTION)
+                .context(error::WriteMetadata)?;
+            if sensitive.is_sensitive(key.segments()) {
+                datastore
+                    .set_pending_metadata(&sensitive_md_key, key, "true", TRANSACTION)
+                    .context(error::WriteMetadata)?;
+            }
+        }
+    }
+
+    // Mark sensitive settings that are already live, in case they were written before the model
+    // marked them as sensitive.
+    for key in &existing_data {
+        let marked = existing_metadata
+            .get(key)
+            .map_or(false, |md_keys| md_keys.contains(&sensitive_md_key));
+        if !marked && sensitive.is_sensitive(key.segments()) {
+            debug!("Marking existing setting '{}' as sensitive", key);
+            datastore
+                .set_metadata(&sensitive_md_key, key, "true")
+                .context(error::WriteMetadata)?;
+        }
     }
 
     // If we have metadata, write it out to the datastore in Live state
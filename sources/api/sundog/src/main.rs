@@ -29,6 +29,8 @@ const API_SETTINGS_URI: &str = "/settings";
 const API_SETTING_GENERATORS_URI: &str = "/metadata/setting-generators";
 // We change settings in the shared transaction used by boot-time services.
 const TRANSACTION: &str = "bottlerocket-launch";
+// The API records this as the source of our settings, since our settings come from setting generators.
+const SETTING_SOURCE: &str = "generator";
 
 /// Potential errors during Sundog execution
 mod error {
@@ -365,10 +367,15 @@ where
     let uri = &format!("{}?tx={}", API_SETTINGS_URI, TRANSACTION);
     let method = "PATCH";
     trace!("Settings to {} to {}: {}", method, uri, &request_body);
-    let (code, response_body) =
-        apiclient::raw_request(socket_path.as_ref(), uri, method, Some(request_body))
-            .await
-            .context(error::APIRequest { method, uri })?;
+    let (code, response_body) = apiclient::raw_request_with_headers(
+        socket_path.as_ref(),
+        uri,
+        method,
+        Some(request_body),
+        &[(apiclient::SETTING_SOURCE_HEADER, SETTING_SOURCE)],
+    )
+    .await
+    .context(error::APIRequest { method, uri })?;
     ensure!(
         code.is_success(),
         error::APIResponse {
@@ -0,0 +1,37 @@
+//! This module owns the error type used by template-lint.
+
+use snafu::Snafu;
+use std::io;
+use std::path::PathBuf;
+
+/// Error contains the errors that keep us from checking templates.  Problems found in the
+/// templates themselves are reported as a `Problem` instead.
+#[derive(Debug, Snafu)]
+#[snafu(visibility = "pub(crate)")]
+pub(crate) enum Error {
+    #[snafu(display("Default settings in '{}' are not valid TOML: {}", path.display(), source))]
+    DefaultsFormatting {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Unable to merge default settings of variant {}: {}", variant, source))]
+    DefaultsMerge {
+        variant: String,
+        source: merge_toml::Error,
+    },
+
+    #[snafu(display("Unable to read '{}': {}", path.display(), source))]
+    ReadFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Unable to list directory '{}': {}", path.display(), source))]
+    ListDirectory { path: PathBuf, source: io::Error },
+
+    #[snafu(display("'{}' is not valid TOML: {}", path.display(), source))]
+    Manifest {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+}
+
+pub(crate) type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,626 @@
+//! This module checks the text of a template.  It finds each handlebars expression in the
+//! template, checks the paths it uses against the JSON Schema of the model, and checks the
+//! helpers it calls against the ones we register, and Handlebars' own helpers.
+
+use handlebars::Template;
+use serde_json::Value;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Handlebars' own helpers, with the least and most number of parameters they take.
+const BUILTIN_HELPERS: &[(&str, usize, Option<usize>)] = &[
+    ("if", 1, Some(1)),
+    ("unless", 1, Some(1)),
+    ("each", 1, Some(1)),
+    ("with", 1, Some(1)),
+    ("lookup", 2, Some(2)),
+    ("log", 1, None),
+    ("eq", 2, Some(2)),
+    ("ne", 2, Some(2)),
+    ("gt", 2, Some(2)),
+    ("gte", 2, Some(2)),
+    ("lt", 2, Some(2)),
+    ("lte", 2, Some(2)),
+    ("and", 2, Some(2)),
+    ("or", 2, Some(2)),
+    ("not", 1, Some(1)),
+    ("len", 1, Some(1)),
+];
+
+/// Blocks that change the context of the paths inside them, so that those paths are no longer
+/// relative to the model.
+const CONTEXT_HELPERS: &[&str] = &["each", "with"];
+
+/// Helpers that test their parameter, which is false if it's missing rather than an error.
+const CONDITION_HELPERS: &[&str] = &["if", "unless"];
+
+/// A problem found in a template.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Problem {
+    pub(crate) line: usize,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Checks the given template against the given JSON Schema of the model, and the given helpers
+/// with the number of parameters they require, and returns any problems found.
+///
+/// Templates can be shared by variants with different models, so paths that are only used when
+/// they're set, like `{{#if settings.x}}{{settings.x}}{{/if}}`, can be missing from the model;
+/// they only have to be in one of the given shared schemas, such as those of all variants.
+pub(crate) fn lint(
+    template: &str,
+    schema: &Value,
+    shared_schemas: &[Value],
+    helpers: &[(&str, usize)],
+) -> Vec<Problem> {
+    // Let handlebars find syntax errors; we can't make sense of the expressions without valid
+    // syntax.
+    if let Err(e) = Template::compile(template) {
+        return vec![Problem {
+            line: e.line_no.unwrap_or(1),
+            message: format!("invalid template: {}", e),
+        }];
+    }
+
+    let mut linter = Linter {
+        schema,
+        shared_schemas,
+        helpers,
+        blocks: Vec::new(),
+        line: 1,
+        problems: Vec::new(),
+    };
+    for (line, expression) in expressions(template) {
+        linter.line = line;
+        linter.check_expression(expression);
+    }
+    linter.problems
+}
+
+/// Returns the text inside each `{{...}}` in the template, with the line it starts on.
+/// Comments, raw blocks, and escaped expressions are skipped.
+fn expressions(template: &str) -> Vec<(usize, &str)> {
+    let mut expressions = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = template[pos..].find("{{") {
+        let start = pos + found;
+        let rest = &template[start..];
+        let (open, close) = if rest.starts_with("{{!--") {
+            (5, "--}}")
+        } else if rest.starts_with("{{{{") {
+            (4, "}}}}")
+        } else if rest.starts_with("{{{") {
+            (3, "}}}")
+        } else {
+            (2, "}}")
+        };
+        let end = match rest[open..].find(close) {
+            Some(end) => start + open + end,
+            None => break,
+        };
+        pos = end + close.len();
+
+        let escaped = template[..start].ends_with('\\');
+        if (open == 2 || open == 3) && !escaped {
+            let line = template[..start].matches('\n').count() + 1;
+            expressions.push((line, &template[start + open..end]));
+        }
+    }
+    expressions
+}
+
+/// The parts of a handlebars expression.
+#[derive(Debug)]
+enum Token {
+    /// A string, number, boolean, or null.
+    Literal,
+    /// A path into the data, or the name of a helper.
+    Path(String),
+    /// A helper call in parentheses.
+    SubExpression(Vec<Token>),
+    /// A hash parameter, like `includeZero=true`.
+    Hash(Box<Token>),
+}
+
+/// A block we're inside, like `{{#if settings.motd}}...{{/if}}`.
+struct Block {
+    helper: String,
+    /// The path that has to be set for the current part of the block to render, like
+    /// `settings.motd` before the `else` of an `if` block.
+    guard: Option<String>,
+}
+
+struct Linter<'a> {
+    schema: &'a Value,
+    shared_schemas: &'a [Value],
+    helpers: &'a [(&'a str, usize)],
+    /// The blocks we're inside, innermost last.
+    blocks: Vec<Block>,
+    line: usize,
+    problems: Vec<Problem>,
+}
+
+impl Linter<'_> {
+    fn problem(&mut self, message: String) {
+        self.problems.push(Problem {
+            line: self.line,
+            message,
+        });
+    }
+
+    /// Checks the text inside a `{{...}}`.
+    fn check_expression(&mut self, expression: &str) {
+        let expression = expression.trim_matches('~').trim();
+        let mut chars = expression.chars();
+        match chars.next() {
+            // Comments and partials.
+            Some('!') | Some('>') | None => {}
+
+            Some('/') => {
+                self.blocks.pop();
+            }
+
+            // Block helpers, and inverse blocks like `{{^if x}}`; `{{^}}` is the same as else.
+            Some(open) if open == '#' || open == '^' => {
+                let rest = chars.as_str().trim();
+                // Decorators, like `{{#*inline}}`, don't refer to data.
+                if rest.starts_with('*') {
+                    self.blocks.push(Block {
+                        helper: String::new(),
+                        guard: None,
+                    });
+                    return;
+                }
+                if rest.is_empty() {
+                    self.set_guard(None);
+                    return;
+                }
+                let tokens = tokenize(rest);
+                let helper = match tokens.first() {
+                    Some(Token::Path(name)) => name.clone(),
+                    _ => String::new(),
+                };
+                self.check_call(&tokens);
+                let guard = if open == '#' { guard(&tokens) } else { None };
+                self.blocks.push(Block { helper, guard });
+            }
+
+            // Unescaped output.
+            Some('&') => self.check_call(&tokenize(chars.as_str())),
+
+            _ => {
+                if expression == "else" {
+                    self.set_guard(None);
+                    return;
+                }
+                // Chained blocks, like `{{else if x}}`.
+                if let Some(rest) = expression.strip_prefix("else ") {
+                    let tokens = tokenize(rest);
+                    self.check_call(&tokens);
+                    self.set_guard(guard(&tokens));
+                    return;
+                }
+                self.check_call(&tokenize(expression));
+            }
+        }
+    }
+
+    /// Sets the guard of the innermost block, when it reaches an `else`.
+    fn set_guard(&mut self, guard: Option<String>) {
+        if let Some(block) = self.blocks.last_mut() {
+            block.guard = guard;
+        }
+    }
+
+    /// Returns the least and most number of parameters the named helper takes, if it's a helper.
+    fn arity(&self, name: &str) -> Option<(usize, Option<usize>)> {
+        if let Some((_, count)) = self.helpers.iter().find(|(h, _)| *h == name) {
+            return Some((*count, Some(*count)));
+        }
+        BUILTIN_HELPERS
+            .iter()
+            .find(|(h, _, _)| *h == name)
+            .map(|(_, min, max)| (*min, *max))
+    }
+
+    /// Checks an expression, which is either a helper call or a path.
+    fn check_call(&mut self, tokens: &[Token]) {
+        let name = match tokens.first() {
+            Some(Token::Path(name)) => name,
+            Some(token) => return self.check_value(token),
+            None => return,
+        };
+        let args = &tokens[1..];
+        let params = args
+            .iter()
+            .filter(|token| !matches!(token, Token::Hash(_)))
+            .count();
+
+        match self.arity(name) {
+            Some((min, max)) => {
+                if params < min || max.map_or(false, |max| params > max) {
+                    let expected = match max {
+                        Some(max) if max == min => min.to_string(),
+                        Some(max) => format!("{} to {}", min, max),
+                        None => format!("at least {}", min),
+                    };
+                    self.problem(format!(
+                        "helper '{}' takes {} parameter(s) but was given {}",
+                        name, expected, params
+                    ));
+                }
+            }
+            None => {
+                // Without parameters, the name is a path, whether it's output or used as a
+                // section, like `{{#settings.motd}}`.
+                if params == 0 {
+                    self.check_path(name, false);
+                } else {
+                    self.problem(format!("unknown helper '{}'", name));
+                }
+            }
+        }
+
+        for arg in args {
+            match arg {
+                Token::Path(path) if CONDITION_HELPERS.contains(&name.as_str()) => {
+                    self.check_path(path, true)
+                }
+                _ => self.check_value(arg),
+            }
+        }
+    }
+
+    /// Checks a parameter of a helper.
+    fn check_value(&mut self, token: &Token) {
+        match token {
+            Token::Literal => {}
+            Token::Path(path) => self.check_path(path, false),
+            Token::SubExpression(tokens) => self.check_call(tokens),
+            Token::Hash(value) => self.check_value(value),
+        }
+    }
+
+    /// Checks that the given path exists in the model.  Conditions, like the parameter of `if`,
+    /// and paths inside a block that's guarded by them, are only used if they're set, so they
+    /// only have to exist in one of the shared schemas.
+    fn check_path(&mut self, path: &str, condition: bool) {
+        let path = match path.strip_prefix("@root.") {
+            Some(path) => path,
+            None => {
+                // Paths inside blocks like `each` are relative to the current item, and paths
+                // like `@key`, `this`, and `../x` are relative to the current context, so we
+                // can't check them against the model.
+                let in_context = self
+                    .blocks
+                    .iter()
+                    .any(|block| CONTEXT_HELPERS.contains(&block.helper.as_str()));
+                if in_context
+                    || path.starts_with('@')
+                    || path.starts_with('.')
+                    || path == "this"
+                    || path.starts_with("this.")
+                    || path.starts_with("this/")
+                {
+                    return;
+                }
+                path
+            }
+        };
+
+        let path_segments = segments(path);
+        if resolves(self.schema, &path_segments) {
+            return;
+        }
+        let guarded = condition
+            || self.blocks.iter().any(|block| match &block.guard {
+                Some(guard) => path_segments.starts_with(&segments(guard)),
+                None => false,
+            });
+        if guarded
+            && self
+                .shared_schemas
+                .iter()
+                .any(|schema| resolves(schema, &path_segments))
+        {
+            return;
+        }
+        self.problem(format!("unknown key '{}'", path));
+    }
+}
+
+/// Returns the path that an `if` block with the given tokens requires to be set, if it tests a
+/// path.
+fn guard(tokens: &[Token]) -> Option<String> {
+    match tokens {
+        [Token::Path(helper), Token::Path(path), ..] if helper == "if" => {
+            Some(path.strip_prefix("@root.").unwrap_or(path).to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Splits a handlebars expression into tokens.
+fn tokenize(expression: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || c == ')' || c == '=' {
+            chars.next();
+            continue;
+        }
+        let word = read_word(&mut chars);
+        // Block parameters, like `as |item|`, name values rather than use them.
+        if word == "as" {
+            break;
+        }
+        if word.is_empty() {
+            tokens.push(value_token(&mut chars));
+        } else if chars.peek() == Some(&'=') {
+            chars.next();
+            tokens.push(Token::Hash(Box::new(value_token(&mut chars))));
+        } else {
+            tokens.push(word_token(&word));
+        }
+    }
+    tokens
+}
+
+/// Reads an unquoted word, up to whitespace, parentheses, or the `=` of a hash parameter.
+fn read_word(chars: &mut Peekable<Chars<'_>>) -> String {
+    let mut word = String::new();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() || ['(', ')', '=', '"', '\''].contains(&c) {
+            break;
+        }
+        word.push(c);
+        chars.next();
+    }
+    word
+}
+
+/// Reads a value: a quoted string, a sub-expression, or an unquoted word.
+fn value_token(chars: &mut Peekable<Chars<'_>>) -> Token {
+    match chars.peek() {
+        Some(&quote) if quote == '"' || quote == '\'' => {
+            chars.next();
+            for c in chars {
+                if c == quote {
+                    break;
+                }
+            }
+            Token::Literal
+        }
+        Some(&'(') => {
+            chars.next();
+            Token::SubExpression(tokenize(&sub_expression(chars)))
+        }
+        _ => word_token(&read_word(chars)),
+    }
+}
+
+/// Returns the text of a sub-expression up to its closing parenthesis, consuming it.
+fn sub_expression<I: Iterator<Item = char>>(chars: &mut I) -> String {
+    let mut text = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    for c in chars {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') if depth == 0 => break,
+            (None, ')') => depth -= 1,
+            _ => {}
+        }
+        text.push(c);
+    }
+    text
+}
+
+/// Returns the token for an unquoted word, which is a literal or a path.
+fn word_token(word: &str) -> Token {
+    if word.parse::<f64>().is_ok() || ["true", "false", "null", "undefined"].contains(&word) {
+        Token::Literal
+    } else {
+        Token::Path(word.to_string())
+    }
+}
+
+/// Splits a handlebars path into its segments.  Segments are separated by dots or slashes, and
+/// can be given in brackets to include those characters, like `settings.[a.b]`.
+fn segments(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                for c in &mut chars {
+                    if c == ']' {
+                        break;
+                    }
+                    current.push(c);
+                }
+            }
+            '.' | '/' => segments.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+/// Returns whether the given path segments can be found in data described by the given JSON
+/// Schema document.
+fn resolves(schema: &Value, segments: &[String]) -> bool {
+    let defs = &schema["$defs"];
+    let mut node = schema;
+    for segment in segments {
+        while let Some(reference) = node.get("$ref").and_then(Value::as_str) {
+            node = &defs[reference.trim_start_matches("#/$defs/")];
+        }
+
+        let property = node.get("properties").and_then(|p| p.get(segment));
+        let entry = node.get("additionalProperties").filter(|a| a.is_object());
+        let item = node
+            .get("items")
+            .filter(|_| segment.parse::<usize>().is_ok());
+        node = match property.or(entry).or(item) {
+            Some(child) => child,
+            // A schema without a type, like that of a TOML value, allows any value.
+            None => return node.get("type").is_none() && node.get("properties").is_none(),
+        };
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    const HELPERS: &[(&str, usize)] = &[("default", 2), ("join_map", 4)];
+
+    fn schema() -> Value {
+        json!({
+            "$ref": "#/$defs/Model",
+            "$defs": {
+                "Model": {
+                    "type": "object",
+                    "properties": {
+                        "settings": { "$ref": "#/$defs/Settings" },
+                        "os": { "type": "object", "properties": { "arch": { "type": "string" } } },
+                    },
+                    "additionalProperties": false,
+                },
+                "Settings": {
+                    "type": "object",
+                    "properties": {
+                        "motd": { "type": "string" },
+                        "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+                        "servers": { "type": "array", "items": { "type": "string" } },
+                        "metadata": {},
+                    },
+                    "additionalProperties": false,
+                },
+            },
+        })
+    }
+
+    fn messages(template: &str) -> Vec<String> {
+        lint(template, &schema(), &[schema()], HELPERS)
+            .into_iter()
+            .map(|p| p.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn valid_template() {
+        let template = r#"{{! a comment }}{{!-- {{settings.nothing}} --}}
+{{settings.motd}} {{os.arch}} {{{settings.labels.a}}}
+{{settings.servers.[0]}} {{settings.metadata.anything.at.all}}
+{{~#if settings.motd includeZero=true~}}
+{{default "x" settings.motd}}
+{{else}}
+{{#if (eq settings.motd "hi")}}{{/if}}
+{{join_map "=" "," "fail-if-missing" settings.labels}}
+{{/if}}
+{{#each settings.servers}}{{this}} {{unknown}} {{@root.settings.motd}}{{/each}}
+"#;
+        assert_eq!(messages(template), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let template = "{{settings.motd}}\n{{settings.mtod}}\n{{#if settings.motd.x}}{{/if}}\n\
+            {{default \"x\" (lookup settings.labels settings.nope)}}";
+        assert_eq!(
+            messages(template),
+            vec![
+                "line 2: unknown key 'settings.mtod'",
+                "line 3: unknown key 'settings.motd.x'",
+                "line 4: unknown key 'settings.nope'",
+            ]
+        );
+    }
+
+    #[test]
+    fn guarded_keys() {
+        let shared = vec![json!({
+            "properties": {
+                "settings": {
+                    "properties": {
+                        "region": { "type": "string" },
+                        "cluster": { "type": "object", "properties": { "name": {} } },
+                    },
+                },
+            },
+        })];
+        let template =
+            "{{#if settings.region}}{{settings.region}}{{else}}{{settings.region}}{{/if}}\n\
+            {{#unless settings.region}}{{settings.region}}{{/unless}}\n\
+            {{#if @root.settings.cluster}}{{default \"x\" settings.cluster.name}}{{/if}}\n\
+            {{#if settings.motd}}{{else if settings.region}}{{settings.region}}{{/if}}\n\
+            {{#if settings.regoin}}{{settings.regoin}}{{/if}}";
+        let messages: Vec<String> = lint(template, &schema(), &shared, HELPERS)
+            .into_iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "line 1: unknown key 'settings.region'",
+                "line 2: unknown key 'settings.region'",
+                "line 5: unknown key 'settings.regoin'",
+                "line 5: unknown key 'settings.regoin'",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_keys_in_each() {
+        let template = "{{#each settings.labels}}{{@key}}{{@root.settings.bad}}{{/each}}";
+        assert_eq!(
+            messages(template),
+            vec!["line 1: unknown key 'settings.bad'"]
+        );
+    }
+
+    #[test]
+    fn helper_arity() {
+        let template = "{{default settings.motd}}\n{{#if settings.motd settings.motd}}{{/if}}\n\
+            {{join_map \"=\" \",\" settings.labels}}";
+        assert_eq!(
+            messages(template),
+            vec![
+                "line 1: helper 'default' takes 2 parameter(s) but was given 1",
+                "line 2: helper 'if' takes 1 parameter(s) but was given 2",
+                "line 3: helper 'join_map' takes 4 parameter(s) but was given 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_helper() {
+        assert_eq!(
+            messages("{{defualt \"x\" settings.motd}}"),
+            vec!["line 1: unknown helper 'defualt'"]
+        );
+    }
+
+    #[test]
+    fn invalid_syntax() {
+        let problems = lint("{{#if settings.motd}}\n", &schema(), &[schema()], HELPERS);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.starts_with("invalid template"));
+    }
+}
@@ -0,0 +1,221 @@
+/*!
+# Introduction
+
+template-lint checks the templates of every variant's configuration files before they're built into an image.
+A mistake in a template, like a misspelled setting, otherwise only shows up at runtime, when thar-be-settings fails to render the file in strict mode.
+
+For each variant registered in the model, it finds the templates through the `configuration-files` in the variant's default settings, and finds their source files in the packages the variant includes, by reading the `install` commands in their spec files.
+The default settings are merged from the variant's `defaults.d` directory the way storewolf's build merges them.
+For each template, it reports:
+* syntax errors
+* paths, like `settings.kubernetes.cluster-name`, that aren't in the variant's model
+* calls to helpers that aren't registered by schnauzer or built into Handlebars
+* helper calls with the wrong number of parameters
+* configuration files whose template isn't installed by any of the variant's packages
+
+Paths inside blocks that change the context, like `each` and `with`, are relative to the current item, so they're only checked if they start with `@root`.
+
+Templates are checked against the model of each variant that uses them, so a template shared by variants has to suit each of their models.
+Paths that are only used when they're set, like the parameter of `if` and paths inside an `if` block on them, only have to be in one variant's model.
+It prints the problems of each variant, each with the template's source file and line, and exits with status 1 if it found any.
+
+# Example usage
+
+`template-lint --root /path/to/bottlerocket`
+*/
+
+#![deny(rust_2018_idioms)]
+
+use merge_toml::merge_values;
+use model::variants::{self, Variant};
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{env, process};
+
+use error::Result;
+
+mod error;
+mod lint;
+mod packages;
+
+/// Each variant's default settings are in .toml files in a defaults.d directory under this one,
+/// relative to the root of the repository.  Later files, by name, take precedence.
+const MODELS_DIR: &str = "sources/models/src";
+
+/// Stores user-supplied arguments.
+struct Args {
+    root: PathBuf,
+}
+
+/// Informs the user about proper usage of the program and exits.
+fn usage() -> ! {
+    let program_name = env::args().next().unwrap_or_else(|| "program".to_string());
+    eprintln!(
+        r"Usage: {}
+            [ --root PATH ]
+
+    Default root: the repository template-lint was built from",
+        program_name,
+    );
+    process::exit(2);
+}
+
+/// Prints a more specific message before exiting through usage().
+fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
+    eprintln!("{}\n", msg.as_ref());
+    usage();
+}
+
+/// Parses the args to the program and returns an Args struct.
+fn parse_args(args: env::Args) -> Args {
+    let mut root = None;
+
+    let mut iter = args.skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_ref() {
+            "--root" => {
+                root = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --root")),
+                )
+            }
+
+            _ => usage(),
+        }
+    }
+
+    Args {
+        root: root
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("../../..")),
+    }
+}
+
+/// Returns the default settings of the given variant, merged from the files in its defaults.d
+/// directory in order of name, like storewolf's build does.
+fn variant_defaults(root: &Path, variant: &Variant) -> Result<toml::Value> {
+    let dir = root
+        .join(MODELS_DIR)
+        .join(variant.name())
+        .join("defaults.d");
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(&dir).context(error::ListDirectory { path: &dir })? {
+        let entry = entry.context(error::ListDirectory { path: &dir })?;
+        if entry.file_name().to_string_lossy().ends_with(".toml") {
+            paths.push(entry.path());
+        }
+    }
+    paths.sort();
+
+    let mut defaults = toml::Value::Table(toml::map::Map::new());
+    for path in paths {
+        let data = fs::read_to_string(&path).context(error::ReadFile { path: &path })?;
+        let value = toml::from_str(&data).context(error::DefaultsFormatting { path: &path })?;
+        merge_values(&mut defaults, &value).context(error::DefaultsMerge {
+            variant: variant.name(),
+        })?;
+    }
+    Ok(defaults)
+}
+
+/// Returns the template path of each configuration file in the given default settings, with the
+/// names of the configuration files that use it.
+fn template_paths(defaults: &toml::Value) -> BTreeMap<String, Vec<String>> {
+    let mut paths: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    if let Some(files) = defaults
+        .get("configuration-files")
+        .and_then(toml::Value::as_table)
+    {
+        for (name, file) in files {
+            if let Some(path) = file.get("template-path").and_then(toml::Value::as_str) {
+                paths
+                    .entry(path.to_string())
+                    .or_default()
+                    .push(name.to_string());
+            }
+        }
+    }
+    paths
+}
+
+/// Checks every template used by the given variant, returning its problems, each with the source
+/// file and line of the template.  Paths that are only used when they're set only have to be in
+/// one of the given shared schemas.
+fn lint_variant(root: &Path, variant: &Variant, shared_schemas: &[Value]) -> Result<Vec<String>> {
+    let schema = variant.schema();
+    let defaults = variant_defaults(root, variant)?;
+    let sources = packages::template_sources(&root.join("variants").join(variant.name()))?;
+
+    let mut problems = Vec::new();
+    for (template_path, files) in template_paths(&defaults) {
+        let source = match sources.get(&template_path) {
+            Some(source) => source,
+            None => {
+                problems.push(format!(
+                    "{}: no package in variant {} installs this template, used by {}",
+                    template_path,
+                    variant.name(),
+                    files.join(", ")
+                ));
+                continue;
+            }
+        };
+
+        let template = fs::read_to_string(source).context(error::ReadFile { path: source })?;
+        let display_path = source.strip_prefix(root).unwrap_or(source);
+        for problem in lint::lint(&template, &schema, shared_schemas, schnauzer::HELPER_PARAMS) {
+            problems.push(format!(
+                "{}:{}: {}",
+                display_path.display(),
+                problem.line,
+                problem.message
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+/// Checks every template used by every variant, printing problems.  Returns whether the templates
+/// are free of problems.
+fn run(args: &Args) -> Result<bool> {
+    let root = fs::canonicalize(&args.root).context(error::ReadFile { path: &args.root })?;
+
+    // Templates are shared by variants, so paths that are only used when they're set can come
+    // from any variant's model.
+    let shared_schemas: Vec<Value> = variants::all().iter().map(Variant::schema).collect();
+
+    let mut count = 0;
+    for variant in variants::all() {
+        let problems = lint_variant(&root, variant, &shared_schemas)?;
+        if !problems.is_empty() {
+            println!("Templates for variant '{}' have problems:", variant.name());
+            for problem in &problems {
+                println!("  {}", problem);
+            }
+        }
+        count += problems.len();
+    }
+
+    if count > 0 {
+        println!("Found {} problem(s) in templates", count);
+    }
+    Ok(count == 0)
+}
+
+// Returning a Result from main makes it print a Debug representation of the error, but with Snafu
+// we have nice Display representations of the error, so we wrap "main" (run) and print any error.
+// https://github.com/shepmaster/snafu/issues/110
+fn main() {
+    let args = parse_args(env::args());
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! This module finds the source files of the templates a variant installs, by following the
+//! package dependencies in the variant's Cargo.toml and reading the spec files of those packages.
+
+use crate::error::{self, Result};
+use snafu::ResultExt;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The directory templates are installed to in the OS image.
+const TEMPLATE_DIR: &str = "/usr/share/templates";
+
+/// The spec file macro that refers to TEMPLATE_DIR.
+const TEMPLATE_DIR_MACRO: &str = "%{_cross_templatedir}";
+
+/// Returns the source file of each template installed by the packages the given variant depends
+/// on, directly or through other packages, keyed by the path it's installed to.
+pub(crate) fn template_sources(variant_dir: &Path) -> Result<HashMap<String, PathBuf>> {
+    let mut sources = HashMap::new();
+    let mut seen = HashSet::new();
+    let mut queue = vec![variant_dir.to_path_buf()];
+
+    while let Some(dir) = queue.pop() {
+        for dep in package_deps(&dir)? {
+            if seen.insert(dep.clone()) {
+                queue.push(dep);
+            }
+        }
+
+        for spec in spec_files(&dir)? {
+            let data = fs::read_to_string(&spec).context(error::ReadFile { path: &spec })?;
+            for (installed, source) in spec_templates(&data) {
+                sources.insert(installed, dir.join(source));
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+/// Returns the directories of the path dependencies listed in the Cargo.toml in the given
+/// directory.  Variants list their packages as build-dependencies, and packages list the packages
+/// they need as dependencies.
+fn package_deps(dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = dir.join("Cargo.toml");
+    let data = fs::read_to_string(&path).context(error::ReadFile { path: &path })?;
+    let manifest: toml::Value = toml::from_str(&data).context(error::Manifest { path: &path })?;
+
+    let mut deps = Vec::new();
+    for section in &["dependencies", "build-dependencies"] {
+        let table = match manifest.get(section).and_then(toml::Value::as_table) {
+            Some(table) => table,
+            None => continue,
+        };
+        for dep in table.values() {
+            if let Some(dep_path) = dep.get("path").and_then(toml::Value::as_str) {
+                let dep_dir = dir.join(dep_path);
+                let dep_dir =
+                    fs::canonicalize(&dep_dir).context(error::ReadFile { path: dep_dir })?;
+                deps.push(dep_dir);
+            }
+        }
+    }
+    Ok(deps)
+}
+
+/// Returns the spec files in the given directory.
+fn spec_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir).context(error::ListDirectory { path: dir })?;
+    let mut specs = Vec::new();
+    for entry in entries {
+        let path = entry.context(error::ListDirectory { path: dir })?.path();
+        if path.extension().map_or(false, |ext| ext == "spec") {
+            specs.push(path);
+        }
+    }
+    Ok(specs)
+}
+
+/// Returns the templates installed by the given spec file, as pairs of the installed path and the
+/// name of the source file, relative to the spec.
+fn spec_templates(spec: &str) -> Vec<(String, String)> {
+    // Commands can be continued on the next line with a backslash.
+    let spec = spec.replace("\\\n", " ");
+
+    // Find the local source files, like "Source2: kubelet-env", by number.
+    let mut sources = HashMap::new();
+    for line in spec.lines() {
+        let mut parts = line.splitn(2, ':');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        if !key.starts_with("Source") || value.contains('/') {
+            continue;
+        }
+        let number = match &key["Source".len()..] {
+            "" => 0,
+            number => match number.parse::<u32>() {
+                Ok(number) => number,
+                Err(_) => continue,
+            },
+        };
+        sources.insert(number, value.to_string());
+    }
+
+    // Find the install commands that copy sources into the template directory, either to a given
+    // name, like `install %{S:2} %{buildroot}%{_cross_templatedir}/kubelet-env`, or under their
+    // own names, like `install %{S:2} %{S:3} %{buildroot}%{_cross_templatedir}`.
+    let mut templates = Vec::new();
+    for line in spec.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.first() != Some(&"install") {
+            continue;
+        }
+        let dest = match words.last() {
+            Some(dest) if dest.contains(TEMPLATE_DIR_MACRO) => dest,
+            _ => continue,
+        };
+        let dest_name = dest
+            .splitn(2, TEMPLATE_DIR_MACRO)
+            .nth(1)
+            .unwrap_or_default()
+            .trim_matches('/');
+
+        for word in &words[1..words.len() - 1] {
+            let source = match source_number(word).and_then(|n| sources.get(&n)) {
+                Some(source) => source,
+                None => continue,
+            };
+            let name = if dest_name.is_empty() {
+                source.as_str()
+            } else {
+                dest_name
+            };
+            templates.push((format!("{}/{}", TEMPLATE_DIR, name), source.clone()));
+        }
+    }
+    templates
+}
+
+/// Returns the number of the source referred to by a spec file macro like `%{S:2}` or
+/// `%{SOURCE2}`, if it is one.
+fn source_number(word: &str) -> Option<u32> {
+    let inner = word.strip_prefix("%{")?.strip_suffix('}')?;
+    let number = inner
+        .strip_prefix("S:")
+        .or_else(|| inner.strip_prefix("SOURCE"))?;
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn installed_templates() {
+        let spec = r#"
+Source0: https://example.com/archive/v1.0/kubernetes-1.0.tar.gz
+Source2: kubelet-env
+Source3: containerd-config-toml_k8s
+Source4: containerd-config-toml_basic
+Source5: kubelet.service
+
+%install
+install -d %{buildroot}%{_cross_templatedir}
+install -m 0644 %{S:2} %{buildroot}%{_cross_templatedir}/kubelet-env-template
+install -p -m 0644 \
+  %{S:3} %{SOURCE4} \
+  %{buildroot}%{_cross_templatedir}
+install -p -m 0644 %{S:5} %{buildroot}%{_cross_unitdir}
+"#;
+        assert_eq!(
+            spec_templates(spec),
+            vec![
+                (
+                    "/usr/share/templates/kubelet-env-template".to_string(),
+                    "kubelet-env".to_string()
+                ),
+                (
+                    "/usr/share/templates/containerd-config-toml_k8s".to_string(),
+                    "containerd-config-toml_k8s".to_string()
+                ),
+                (
+                    "/usr/share/templates/containerd-config-toml_basic".to_string(),
+                    "containerd-config-toml_basic".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use crate::service::Services;
+use crate::status::{ApplyRun, ConfigFileResult};
 use crate::{error, Result};
 use itertools::join;
 use snafu::ResultExt;
@@ -42,7 +43,7 @@ pub fn get_config_file_names(services: &Services) -> HashSet<String> {
     config_file_set
 }
 
-/// Render the configuration files
+/// Render the configuration files, recording the result of each in the given ApplyRun.
 // If strict is True, return an error if we fail to render any template.
 // If strict is False, ignore failures, always returning an Ok value
 // containing any successfully rendered templates.
@@ -51,6 +52,7 @@ pub fn render_config_files(
     config_files: model::ConfigurationFiles,
     settings: model::Model,
     strict: bool,
+    apply_run: &mut ApplyRun,
 ) -> Result<Vec<RenderedConfigFile>> {
     // Go write all the configuration files from template
     let mut rendered_configs = Vec::new();
@@ -58,6 +60,11 @@ pub fn render_config_files(
         debug!("Rendering {}", &name);
 
         let try_rendered = registry.render(&name, &settings);
+        apply_run.config_files.push(ConfigFileResult {
+            name: name.clone(),
+            path: metadata.path.to_string(),
+            error: try_rendered.as_ref().err().map(|e| e.to_string()),
+        });
         if strict {
             let rendered = try_rendered.context(error::TemplateRender { template: name })?;
             rendered_configs.push(RenderedConfigFile::new(&metadata.path, rendered));
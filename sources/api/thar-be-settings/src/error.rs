@@ -74,4 +74,22 @@ pub enum Error {
         uri: String,
         source: schnauzer::Error,
     },
+
+    #[snafu(display("Failed to lock apply status at {}: {}", path.display(), source))]
+    StatusLock { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to read apply status from {}: {}", path.display(), source))]
+    StatusRead { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Failed to parse apply status from {}: {}", path.display(), source))]
+    StatusParse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Failed to serialize apply status: {}", source))]
+    StatusSerialize { source: serde_json::Error },
+
+    #[snafu(display("Failed to write apply status to {}: {}", path.display(), source))]
+    StatusWrite { path: PathBuf, source: io::Error },
 }
@@ -12,6 +12,10 @@ It then renders the templates and rewrites the affected configuration files.
 Service data from the API includes any commands needed to restart services affected by configuration file changes, which are run here.
 
 In the standalone ("all keys") mode, it queries the API for all services and configuration files, then renders and rewrites all configuration files and restarts all services.
+
+The results of each run are recorded in a status file, `/run/cache/thar-be-settings/status.json`, which the API server makes available at `/actions/apply-status`.
+Each run has an ID, which can be given with `--apply-id`; the API server uses this so it can tell callers which run is applying their changes.
+For each run, the status includes whether it succeeded, the error that stopped it if not, each configuration file it rendered, and the exit code and stderr of each restart command it ran.
 */
 
 #![deny(rust_2018_idioms)]
@@ -26,6 +30,7 @@ use std::io::{self, Read};
 pub mod config;
 pub mod error;
 pub mod service;
+pub mod status;
 
 pub use error::Error;
 type Result<T> = std::result::Result<T, Error>;
@@ -10,6 +10,7 @@ use std::process;
 use std::str::FromStr;
 use tokio::runtime::Runtime;
 
+use thar_be_settings::status::{self, ApplyRun};
 use thar_be_settings::{config, get_changed_settings, service};
 
 // FIXME Get from configuration in the future
@@ -45,6 +46,7 @@ enum RunMode {
 
 /// Store the args we receive on the command line
 struct Args {
+    apply_id: Option<String>,
     daemon: bool,
     log_level: LevelFilter,
     mode: RunMode,
@@ -57,6 +59,7 @@ fn usage() -> ! {
     eprintln!(
         r"Usage: {}
             [ --all ]
+            [ --apply-id ID ]
             [ --daemon ]
             [ --socket-path PATH ]
             [ --log-level trace|debug|info|warn|error ]
@@ -69,8 +72,13 @@ fn usage() -> ! {
     If --daemon is given, thar-be-settings will fork and do its work in a new
     process; this is useful to prevent blocking an API call.
 
+    The results are recorded in {} under the given apply ID, or a
+    generated one if none is given.
+
     Socket path defaults to {}",
-        program_name, DEFAULT_API_SOCKET,
+        program_name,
+        status::APPLY_STATUS_FILE,
+        DEFAULT_API_SOCKET,
     );
     process::exit(2);
 }
@@ -83,6 +91,7 @@ fn usage_msg<S: AsRef<str>>(msg: S) -> ! {
 
 /// Parse the args to the program and return an Args struct
 fn parse_args(args: env::Args) -> Args {
+    let mut apply_id = None;
     let mut daemon = false;
     let mut log_level = None;
     let mut mode = RunMode::SpecificKeys;
@@ -93,6 +102,13 @@ fn parse_args(args: env::Args) -> Args {
         match arg.as_ref() {
             "--all" => mode = RunMode::All,
 
+            "--apply-id" => {
+                apply_id = Some(
+                    iter.next()
+                        .unwrap_or_else(|| usage_msg("Did not give argument to --apply-id")),
+                )
+            }
+
             "--daemon" => daemon = true,
 
             "--log-level" => {
@@ -116,6 +132,7 @@ fn parse_args(args: env::Args) -> Args {
     }
 
     Args {
+        apply_id,
         daemon,
         mode,
         log_level: log_level.unwrap_or_else(|| LevelFilter::Info),
@@ -128,6 +145,7 @@ fn parse_args(args: env::Args) -> Args {
 async fn write_config_files(
     args: &Args,
     files_limit: Option<HashSet<String>>,
+    apply_run: &mut ApplyRun,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Create a vec of ConfigFile structs from the list of changed services
     info!("Requesting configuration file data for affected services");
@@ -160,7 +178,13 @@ async fn write_config_files(
         RunMode::SpecificKeys => true,
         RunMode::All => false,
     };
-    let rendered = config::render_config_files(&template_registry, config_files, settings, strict)?;
+    let rendered = config::render_config_files(
+        &template_registry,
+        config_files,
+        settings,
+        strict,
+        apply_run,
+    )?;
 
     // If all the config renders properly, write it to disk
     info!("Writing config files to disk...");
@@ -173,13 +197,33 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
     // SimpleLogger will send errors to stderr and anything less to stdout.
     SimpleLogger::init(args.log_level, LogConfig::default()).context(error::Logger)?;
 
-    info!("thar-be-settings started");
+    let mut apply_run = ApplyRun::new(args.apply_id.clone().unwrap_or_else(status::new_apply_id));
+    info!("thar-be-settings started, apply ID {}", apply_run.id);
+    record_status(&apply_run);
+
+    let result = apply(&args, &mut apply_run).await;
+    apply_run.finish(result.as_ref().err().map(|e| e.to_string()));
+    record_status(&apply_run);
+    result
+}
+
+/// Records the given run in the status file.  Failing to record status shouldn't stop us from
+/// applying settings, so we only log any problem.
+fn record_status(apply_run: &ApplyRun) {
+    if let Err(e) = status::record_apply_run(status::APPLY_STATUS_FILE, apply_run) {
+        warn!("Unable to record apply status: {}", e);
+    }
+}
 
+/// Writes config files and restarts services based on the requested mode, recording the results
+/// in the given ApplyRun.
+async fn apply(args: &Args, apply_run: &mut ApplyRun) -> Result<(), Box<dyn std::error::Error>> {
     match args.mode {
         RunMode::SpecificKeys => {
             // Get the settings that changed via stdin
             info!("Parsing stdin for updated settings");
             let changed_settings = get_changed_settings()?;
+            apply_run.keys = Some(changed_settings.iter().cloned().collect());
 
             // Create a HashSet of affected services
             info!(
@@ -191,27 +235,27 @@ async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
             trace!("Found services: {:?}", services);
             if services.0.is_empty() {
                 info!("No services are affected, exiting...");
-                process::exit(0)
+                return Ok(());
             }
 
             // Create a HashSet of configuration file names
             let config_file_names = config::get_config_file_names(&services);
 
             if !config_file_names.is_empty() {
-                write_config_files(&args, Some(config_file_names)).await?;
+                write_config_files(args, Some(config_file_names), apply_run).await?;
             }
 
             // Now go bounce the affected services
             info!("Restarting affected services...");
-            service::restart_services(services)?;
+            service::restart_services(services, apply_run)?;
         }
         RunMode::All => {
-            write_config_files(&args, None).await?;
+            write_config_files(args, None, apply_run).await?;
 
             info!("Restarting all services...");
             let services = service::get_affected_services(&args.socket_path, None).await?;
             trace!("Found services: {:?}", services);
-            service::restart_services(services)?;
+            service::restart_services(services, apply_run)?;
         }
     }
 
@@ -1,3 +1,4 @@
+use crate::status::{ApplyRun, RestartCommandResult};
 use crate::{error, Result};
 use itertools::join;
 use snafu::{ensure, OptionExt, ResultExt};
@@ -133,11 +134,12 @@ where
     Ok(service_map)
 }
 
-/// Call the `restart()` method on each Service in a Services object
-pub fn restart_services(services: Services) -> Result<()> {
+/// Call the `restart()` method on each Service in a Services object, recording the result of each
+/// restart command in the given ApplyRun.
+pub fn restart_services(services: Services, apply_run: &mut ApplyRun) -> Result<()> {
     for (name, service) in services.0 {
         debug!("Checking for restart-commands for {}", name);
-        service.restart()?;
+        service.restart(&name, apply_run)?;
     }
     Ok(())
 }
@@ -145,12 +147,12 @@ pub fn restart_services(services: Services) -> Result<()> {
 /// This trait is primarily meant to extend the Service model.  It uses the metadata
 /// inside the Service struct to restart the service.
 trait ServiceRestart {
-    /// Restart the service
-    fn restart(&self) -> Result<()>;
+    /// Restart the service with the given name, recording the result of each restart command
+    fn restart(&self, name: &str, apply_run: &mut ApplyRun) -> Result<()>;
 }
 
 impl ServiceRestart for Service {
-    fn restart(&self) -> Result<()> {
+    fn restart(&self, name: &str, apply_run: &mut ApplyRun) -> Result<()> {
         let restart_commands = &self.model.restart_commands;
         info!("restart commands {:?}", restart_commands);
         for restart_command in restart_commands {
@@ -179,6 +181,12 @@ impl ServiceRestart for Service {
                 .context(error::CommandExecutionFailure {
                     command: restart_command.as_str(),
                 })?;
+            apply_run.restart_commands.push(RestartCommandResult {
+                service: name.to_string(),
+                command: restart_command.clone(),
+                exit_code: result.status.code(),
+                stderr: String::from_utf8_lossy(&result.stderr).to_string(),
+            });
 
             // If the restart command exited nonzero, call it a failure
             ensure!(
@@ -0,0 +1,204 @@
+//! The status module records the results of each run of thar-be-settings, so callers can learn
+//! whether configuration files rendered and restart commands succeeded.
+//!
+//! Each run has an ID, either given by the caller (the API server passes one so it can tell the
+//! user which run applied their changes) or generated at startup.  The most recent runs are kept in
+//! a JSON status file; a run is recorded when it starts, and again when it finishes.
+
+use crate::{error, Result};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const APPLY_STATUS_FILE: &str = "/run/cache/thar-be-settings/status.json";
+
+/// The number of runs we keep in the status file; older runs are removed as new ones are added.
+pub const MAX_APPLY_RUNS: usize = 20;
+
+/// Returns a new ID for an apply run.  IDs are unique across processes because they include the
+/// process ID, and within a process because of a counter.
+pub fn new_apply_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{}-{}-{}",
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ"),
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// The state of an apply run.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApplyState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// ApplyRun describes one run of thar-be-settings and what it did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApplyRun {
+    pub id: String,
+    pub state: ApplyState,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+    /// The changed settings we were asked to apply, or None if we were asked to apply all
+    /// settings.
+    pub keys: Option<BTreeSet<String>>,
+    /// The configuration files we tried to render, in order.
+    pub config_files: Vec<ConfigFileResult>,
+    /// The restart commands we ran, in order.
+    pub restart_commands: Vec<RestartCommandResult>,
+    /// The error that stopped the run, if it failed.
+    pub error: Option<String>,
+}
+
+/// ConfigFileResult describes the rendering of one configuration file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigFileResult {
+    pub name: String,
+    pub path: String,
+    /// The reason the template failed to render, if it did.
+    pub error: Option<String>,
+}
+
+/// RestartCommandResult describes one restart command run for a service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RestartCommandResult {
+    pub service: String,
+    pub command: String,
+    /// The exit code of the command, or None if it was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+impl ApplyRun {
+    /// Starts a new run with the given ID.
+    pub fn new<S: Into<String>>(id: S) -> Self {
+        Self {
+            id: id.into(),
+            state: ApplyState::Running,
+            started: Utc::now(),
+            finished: None,
+            keys: None,
+            config_files: Vec::new(),
+            restart_commands: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Marks the run as finished, successfully or with the given error.
+    pub fn finish(&mut self, error: Option<String>) {
+        self.state = match error {
+            Some(_) => ApplyState::Failed,
+            None => ApplyState::Succeeded,
+        };
+        self.finished = Some(Utc::now());
+        self.error = error;
+    }
+}
+
+/// ApplyStatus is the format of the status file: the most recent runs, oldest first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApplyStatus {
+    pub runs: Vec<ApplyRun>,
+}
+
+/// Loads and returns the apply status from the given status file.  If no runs have been recorded,
+/// returns an empty status.
+///
+/// The status file is replaced atomically, so no lock is needed to read it.
+pub fn get_apply_status<P: AsRef<Path>>(path: P) -> Result<ApplyStatus> {
+    let path = path.as_ref();
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ApplyStatus::default()),
+        Err(e) => return Err(e).context(error::StatusRead { path }),
+    };
+    serde_json::from_str(&data).context(error::StatusParse { path })
+}
+
+/// Records the given run in the given status file, replacing any earlier record of the same run,
+/// and removing the oldest runs if there are more than MAX_APPLY_RUNS.
+///
+/// Runs can happen concurrently, so we hold a lock on a file next to the status file while
+/// updating it.
+pub fn record_apply_run<P: AsRef<Path>>(path: P, run: &ApplyRun) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context(error::StatusWrite { path: dir })?;
+    }
+    let lock_path = path.with_extension("lock");
+    let lockfile = File::create(&lock_path).context(error::StatusLock { path: &lock_path })?;
+    lockfile
+        .lock_exclusive()
+        .context(error::StatusLock { path: &lock_path })?;
+
+    let mut status = get_apply_status(path)?;
+    match status
+        .runs
+        .iter_mut()
+        .find(|existing| existing.id == run.id)
+    {
+        Some(existing) => *existing = run.clone(),
+        None => status.runs.push(run.clone()),
+    }
+    let excess = status.runs.len().saturating_sub(MAX_APPLY_RUNS);
+    status.runs.drain(..excess);
+
+    // Write to a temporary file and rename it into place, so readers always see a complete file.
+    let data = serde_json::to_string_pretty(&status).context(error::StatusSerialize)?;
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data).context(error::StatusWrite { path: &tmp_path })?;
+    fs::rename(&tmp_path, path).context(error::StatusWrite { path })
+    // The lock is released when lockfile is dropped.
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ids_unique() {
+        assert_ne!(new_apply_id(), new_apply_id());
+    }
+
+    #[test]
+    fn record_and_replace() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("status").join("status.json");
+        assert!(get_apply_status(&path).unwrap().runs.is_empty());
+
+        let mut first = ApplyRun::new("first");
+        record_apply_run(&path, &first).unwrap();
+        let second = ApplyRun::new("second");
+        record_apply_run(&path, &second).unwrap();
+
+        // Finishing a run replaces its earlier record
+        first.finish(Some("oops".to_string()));
+        record_apply_run(&path, &first).unwrap();
+        let status = get_apply_status(&path).unwrap();
+        assert_eq!(status.runs, vec![first, second]);
+        assert_eq!(status.runs[0].state, ApplyState::Failed);
+    }
+
+    #[test]
+    fn bounded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("status.json");
+        for i in 0..MAX_APPLY_RUNS + 2 {
+            record_apply_run(&path, &ApplyRun::new(i.to_string())).unwrap();
+        }
+        let status = get_apply_status(&path).unwrap();
+        assert_eq!(status.runs.len(), MAX_APPLY_RUNS);
+        assert_eq!(status.runs[0].id, "2");
+    }
+}
@@ -19,6 +19,8 @@ For the log requests used to gather logs, please see the following:
 * And the variant-specific files in [conf](conf/), one of which is selected by [build.rs](build.rs)
 based on the value of the `VARIANT` environment variable at build time.
 
+Settings are gathered from the API without `secrets=true`, so sensitive settings, like the Kubernetes bootstrap token and container user data, are left out of `settings.json`.
+
 */
 
 #![deny(rust_2018_idioms)]
@@ -1,33 +1,30 @@
-// src/variant/current is a link to the API model we actually want to build; this build.rs creates
-// that symlink based on the VARIANT environment variable, which either comes from the build
-// system or the user, if doing a local `cargo build`.
+// Every variant's model is compiled into this crate, and the one for the variant being built is
+// re-exported; this build.rs tells the compiler which one that is, through the `variant` cfg,
+// based on the VARIANT environment variable, which either comes from the build system or the
+// user, if doing a local `cargo build`.
 //
-// See README.md to understand the symlink setup.
+// See README.md to understand the variant setup.
 
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use std::env;
 use std::fs::{self, File};
-use std::io::{self, Write};
-use std::os::unix::fs::symlink;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process;
 
-const VARIANT_LINK: &str = "src/variant/current";
-const MOD_LINK: &str = "src/variant/mod.rs";
 const VARIANT_ENV: &str = "VARIANT";
+const VARIANTS_FILE: &str = "src/variants.rs";
 
 fn main() {
-    // Tell cargo when we have to rerun; we always want variant links to be correct, especially
-    // after changing the variant we're building for.
+    // Tell cargo when we have to rerun; we always want the current variant to be correct,
+    // especially after changing the variant we're building for.
     println!("cargo:rerun-if-env-changed={}", VARIANT_ENV);
-    println!("cargo:rerun-if-changed={}", VARIANT_LINK);
-    println!("cargo:rerun-if-changed={}", MOD_LINK);
+    println!("cargo:rerun-if-changed={}", VARIANTS_FILE);
 
     generate_readme();
-    link_current_variant();
+    set_current_variant();
 }
 
-fn link_current_variant() {
+fn set_current_variant() {
     // The VARIANT variable is originally BUILDSYS_VARIANT, set in the top-level Makefile.toml,
     // and is passed through as VARIANT by the top-level Dockerfile.  It represents which OS
     // variant we're building, and therefore which API model to use.
@@ -36,9 +33,6 @@ fn link_current_variant() {
         process::exit(1);
     });
 
-    // Point to the source for the requested variant
-    let variant_target = format!("../{}", variant);
-
     // Make sure requested variant exists
     let variant_path = format!("src/{}", variant);
     if !Path::new(&variant_path).exists() {
@@ -46,19 +40,30 @@ fn link_current_variant() {
         process::exit(1);
     }
 
-    // Create the symlink for the following `cargo build` to use for its source code
-    symlink_safe(&variant_target, VARIANT_LINK).unwrap_or_else(|e| {
-        eprintln!("Failed to create symlink at '{}' pointing to '{}' - we need this to support different API models for different variants.  Error: {}", VARIANT_LINK, variant_target, e);
+    // Make sure it's registered, or there'd be no `current` module to pick
+    let registered = registered_variants();
+    if !registered.contains(&variant) {
+        eprintln!("The variant '{}' from the environment variable {} isn't registered in the variants! list in {}; registered variants are: {}", variant, VARIANT_ENV, VARIANTS_FILE, registered.join(", "));
         process::exit(1);
-    });
+    }
 
-    // Also create the link for mod.rs so Rust can import source from the "current" link
-    // created above.
-    let mod_target = "../variant_mod.rs";
-    symlink_safe(&mod_target, MOD_LINK).unwrap_or_else(|e| {
-        eprintln!("Failed to create symlink at '{}' pointing to '{}' - we need this to build a Rust module structure through the `current` link.  Error: {}", MOD_LINK, mod_target, e);
+    // src/variants.rs re-exports the requested variant's model as `current` based on this.
+    println!("cargo:rustc-cfg=variant=\"{}\"", variant);
+}
+
+/// Returns the names of the variants registered in the `variants!` list, whose entries look like
+/// `"aws-k8s-1.21" => aws_k8s_1_19,`.
+fn registered_variants() -> Vec<String> {
+    let source = fs::read_to_string(VARIANTS_FILE).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", VARIANTS_FILE, e);
         process::exit(1);
     });
+    source
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('"'))
+        .filter_map(|line| line.split_once("\" =>"))
+        .map(|(name, _)| name.to_string())
+        .collect()
 }
 
 fn generate_readme() {
@@ -86,33 +91,3 @@ fn generate_readme() {
     let mut readme = File::create("README.md").unwrap();
     readme.write_all(content.as_bytes()).unwrap();
 }
-
-// Creates the requested symlink through an atomic swap, so it doesn't matter if the link path
-// already exists or not; like --force but fewer worries about reentrancy and retries.
-fn symlink_safe<P1, P2>(target: P1, link: P2) -> io::Result<()>
-where
-    P1: AsRef<Path>,
-    P2: AsRef<Path>,
-{
-    // Create the link at a temporary path.
-    let temp_link = link.as_ref().with_file_name(format!(".{}", rando()));
-    symlink(&target, &temp_link)?;
-
-    // Swap the temporary link into the real location
-    if let Err(e) = fs::rename(&temp_link, &link) {
-        // If we couldn't, for whatever reason, clean up the temporary path and return the error.
-        let _ = fs::remove_file(&temp_link);
-        return Err(e);
-    }
-
-    Ok(())
-}
-
-// Generates a random ID, affectionately known as a 'rando'.
-fn rando() -> String {
-    thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
-}
@@ -35,16 +35,35 @@ This can be disabled by specifying the argument `add_option = false`.
 
 Fields are all wrapped in `Option<...>`.
 Similar to the `serde` attribute added to fields, this is because we don't want users to have to specify fields they aren't changing, and can be disabled the same way, by specifying `add_option = false`.
+
+## JSON Schema
+
+The struct implements the models crate's `schema::JsonSchema` trait, so the model can be described in JSON Schema.
+The struct is an object with a property for each field, using the kebab-case field name and the schema of the field's type, and doesn't allow other properties.
+Fields are only required if `add_option = false` is specified.
+The schema is named after the struct, and the schemas of field types are referenced by name, so each field type must implement `JsonSchema` too; modeled types do.
+
+## Sensitive fields
+
+Fields whose values are secret can be marked with a `#[sensitive]` attribute, which is removed from the output.
+Sensitive fields are `writeOnly` in the JSON Schema, meaning their values are accepted but not returned; the models crate's `sensitive` module finds them in the schema, so the API can keep their values out of responses.
+
+## Validation
+
+The struct implements the models crate's `validation::Validate` trait, which checks rules involving more than one field, like a setting that's required when another setting has a certain value.
+Specify the argument `validate = "function"` to name a function that checks the rules of the struct.
+It's called with the struct and the `validation::Scope` of the check, and returns a message for each broken rule.
+Each field is validated too, so the rules of nested structs are checked as well; each field type must implement `Validate`, and modeled types do.
 */
 
 extern crate proc_macro;
 
 use darling::FromMeta;
 use proc_macro::TokenStream;
-use quote::ToTokens;
+use quote::{quote, ToTokens};
 use syn::visit_mut::{self, VisitMut};
 use syn::{
-    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, ItemStruct, Visibility,
+    parse_macro_input, parse_quote, Attribute, AttributeArgs, Field, Fields, ItemStruct, Visibility,
 };
 
 /// Define a `#[model]` attribute that can be placed on structs to be used in an API model.
@@ -62,8 +81,15 @@ pub fn model(args: TokenStream, input: TokenStream) -> TokenStream {
     // Parse and modify source
     let mut ast: ItemStruct =
         syn::parse(input).expect("Unable to parse item `model` was placed on - is it a struct?");
+    // The schema describes the fields as given, before they're wrapped in `Option`.
+    let schema_impl = json_schema_impl(&ast, helper.add_option);
+    let validate_impl = validate_impl(&ast, helper.validate.as_ref());
     helper.visit_item_struct_mut(&mut ast);
-    ast.into_token_stream().into()
+
+    let mut output = ast.into_token_stream();
+    output.extend(schema_impl);
+    output.extend(validate_impl);
+    output.into()
 }
 
 /// Store any args given by the user inside `#[model(...)]`.
@@ -73,6 +99,7 @@ struct ParsedArgs {
     rename: Option<String>,
     impl_default: Option<bool>,
     add_option: Option<bool>,
+    validate: Option<String>,
 }
 
 /// Stores the user's requested options, plus any defaults for unspecified options.
@@ -81,6 +108,7 @@ struct ModelHelper {
     rename: Option<String>,
     impl_default: bool,
     add_option: bool,
+    validate: Option<syn::Path>,
 }
 
 /// Takes the user's requested options and sets default values for anything unspecified.
@@ -91,6 +119,9 @@ impl From<ParsedArgs> for ModelHelper {
             rename: args.rename,
             impl_default: args.impl_default.unwrap_or(false),
             add_option: args.add_option.unwrap_or(true),
+            validate: args.validate.map(|validate| {
+                syn::parse_str(&validate).expect("`validate` must name a function")
+            }),
         }
     }
 }
@@ -149,6 +180,10 @@ impl VisitMut for ModelHelper {
             _ => {}
         }
 
+        // Our `sensitive` attribute isn't known to the compiler, so remove it once it's been used
+        // in the schema.
+        node.attrs.retain(|attr| !attr.path.is_ident("sensitive"));
+
         // Add our serde attribute, if the user hasn't set one
         if self.add_option {
             if !is_attr_set("serde", &node.attrs) {
@@ -167,6 +202,94 @@ impl VisitMut for ModelHelper {
     }
 }
 
+/// Generates an implementation of `JsonSchema` for the given struct, describing it as an object
+/// with a property for each field.  Fields are only required if they aren't made optional.
+fn json_schema_impl(node: &ItemStruct, add_option: bool) -> proc_macro2::TokenStream {
+    let fields = match &node.fields {
+        Fields::Named(fields) => &fields.named,
+        _ => panic!("`model` can only be placed on structs with named fields"),
+    };
+
+    // Fields are renamed to kebab-case by serde, like the attribute we add to the struct.
+    let names: Vec<String> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .map(|ident| ident.to_string().replace('_', "-"))
+        .collect();
+    // Sensitive fields are write-only; their values are accepted but not returned.
+    let schemas = fields.iter().map(|field| {
+        let ty = &field.ty;
+        let schema = quote!(<#ty as crate::schema::JsonSchema>::json_schema(gen));
+        if is_attr_set("sensitive", &field.attrs) {
+            quote!(crate::schema::write_only(#schema))
+        } else {
+            schema
+        }
+    });
+    let required = if add_option {
+        Vec::new()
+    } else {
+        names.clone()
+    };
+
+    let ident = &node.ident;
+    let schema_name = ident.to_string();
+    quote! {
+        impl crate::schema::JsonSchema for #ident {
+            fn json_schema(
+                gen: &mut crate::schema::SchemaGenerator,
+            ) -> crate::schema::Value {
+                gen.named(#schema_name, |gen| {
+                    crate::schema::object(
+                        vec![#((#names, #schemas)),*],
+                        &[#(#required),*],
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Generates an implementation of `Validate` for the given struct, which validates each field and
+/// then calls the struct's validation function, if one was given.
+fn validate_impl(node: &ItemStruct, validate: Option<&syn::Path>) -> proc_macro2::TokenStream {
+    let fields = match &node.fields {
+        Fields::Named(fields) => &fields.named,
+        _ => panic!("`model` can only be placed on structs with named fields"),
+    };
+    let idents: Vec<_> = fields
+        .iter()
+        .filter_map(|field| field.ident.as_ref())
+        .collect();
+    let names = idents
+        .iter()
+        .map(|ident| ident.to_string().replace('_', "-"));
+    let validate = validate.map(|validate| {
+        quote! {
+            crate::validation::add_violations(path, #validate(self, scope), violations);
+        }
+    });
+
+    let ident = &node.ident;
+    quote! {
+        impl crate::validation::Validate for #ident {
+            fn validate_at(
+                &self,
+                path: &[String],
+                scope: crate::validation::Scope,
+                violations: &mut Vec<crate::validation::Violation>,
+            ) {
+                #(
+                    crate::validation::validate_field(
+                        &self.#idents, path, #names, scope, violations,
+                    );
+                )*
+                #validate
+            }
+        }
+    }
+}
+
 /// Checks whether an attribute named `attr_name` (e.g. "serde") is set in the given list of
 /// `syn::Attribute`s.
 fn is_attr_set(attr_name: &'static str, attrs: &[Attribute]) -> bool {
@@ -13,9 +13,21 @@ This `Settings` essentially becomes the schema for the variant's data store.
 
 At the field level, standard Rust types can be used, or ["modeled types"](src/modeled_types) that add input validation.
 
+The model can describe itself in [JSON Schema](https://json-schema.org/); `schema::schema_for::<Model>()` returns a document with a schema for each model struct and modeled type.
+Modeled types include the checks they make on input, as far as JSON Schema can express them.
+The API server serves this document, and uses the schemas to generate its OpenAPI document.
+
+Rules that involve more than one field of a struct are checked by a validation function named in its `#[model]` attribute; see the [validation](src/validation.rs) module.
+The API server checks them when settings are changed and committed.
+
+Fields whose values are secret, like tokens and user data, are marked `#[sensitive]`; see the [sensitive](src/sensitive.rs) module.
+The API server leaves them out of responses unless they're requested explicitly.
+
 Default values are specified in .toml files in each variant's `defaults.d` directory under [src](src).
 (For example, see the [aws-ecs-1 defaults](src/aws-ecs-1/defaults.d/).)
 Entries are sorted by filename, and later entries take precedence.
+When the variant is built, storewolf checks the merged defaults of every variant against that variant's model, including that the services named in `affected-services` metadata and the configuration files named by services exist.
+Settings are checked as they'll be once committed, so the rules of the model apply to them, except in structs with settings that have a `setting-generator` or `template`, whose values are generated on the host.
 
 The `#[model]` attribute on Settings and its sub-structs reduces duplication and adds some required metadata; see [its docs](model-derive/) for details.
 
@@ -76,36 +88,41 @@ The `#[model]` attribute on Settings and its sub-structs reduces duplication and
 
 # This directory
 
-We use `build.rs` to symlink the proper API model source code for Cargo to build.
-We determine the "proper" model by using the `VARIANT` environment variable.
+Every variant's model is compiled into this crate, each as its own module under `variants`, named for the directory that holds its `mod.rs`.
+Tools that work with more than one variant can look up a variant's model by name with `variants::get`, or list them with `variants::all`, to get its JSON Schema or check settings against its `Settings`.
+A new variant needs an entry in the list at the bottom of `src/variants.rs`, and a module there if it doesn't link to another variant's model.
+`build.rs` rejects a `VARIANT` that isn't in the list, and the tests check that each variant's `mod.rs` is the file its module is compiled from.
+
+On-host binaries use the model of the variant being built, which the crate re-exports, so they can use `model::Settings` as before.
+We determine that variant by using the `VARIANT` environment variable; `build.rs` passes it to the compiler as the `variant` cfg, which picks the `variants::current` module.
 
 If a developer is doing a local `cargo build`, they need to set `VARIANT`.
 
 When building with the Bottlerocket build system, `VARIANT` is based on `BUILDSYS_VARIANT` from the top-level `Makefile.toml`, which can be overridden on the command line with `cargo make -e BUILDSYS_VARIANT=bla`.
 
-Note: when building with the build system, we can't create the symlink in the source directory during a build - the directories are owned by `root`, but we're `builder`.
-We can't use a read/write bind mount with current Docker syntax.
-To get around this, in the top-level `Dockerfile`, we mount a "cache" directory at `src/variant` that we can modify, and create a `current` symlink inside.
-The code in `src/lib.rs` then imports the requested model using `variant/current`.
-
-Note: for the same reason, we symlink `variant/mod.rs` to `variant_mod.rs`.
-Rust needs a `mod.rs` file to understand that a directory is part of the module structure, so we have to have `variant/mod.rs`.
-`variant/` is the cache mount that starts empty, so we have to store the file elsewhere and link it in.
-
 Note: all models share the same `Cargo.toml`.
 */
 
 // "Modeled types" are types with special ser/de behavior used for validation.
 pub mod modeled_types;
 
-// The "variant" module is just a directory where we symlink in the user's requested build
-// variant; each variant defines a top-level Settings structure and we re-export the current one.
-mod variant;
-pub use variant::*;
+// JSON Schema descriptions of the model, used for API documentation and client-side validation.
+pub mod schema;
+
+// Checks of rules that involve more than one field of a model struct.
+pub mod validation;
+
+// The settings marked sensitive, whose values are kept out of API responses and logs.
+pub mod sensitive;
+
+// Each variant defines a top-level Settings structure in its own module, and we re-export the one
+// for the variant being built.
+pub mod variants;
+pub use variants::current::*;
 
 // Below, we define common structures used in the API surface; specific variants build a Settings
 // structure based on these, and that's what gets exposed via the API.  (Specific variants' models
-// are in subdirectories and linked into place by build.rs at variant/current.)
+// are in subdirectories and compiled as modules of `variants`.)
 
 use model_derive::model;
 use serde::{Deserialize, Serialize};
@@ -120,17 +137,34 @@ use crate::modeled_types::{
     KubernetesTaintValue, KubernetesThresholdValue, Lockdown, SingleLineString, SysctlKey, Url,
     ValidBase64,
 };
+use crate::validation::Scope;
 
 // Kubernetes static pod manifest settings
-#[model]
+#[model(validate = "validate_static_pod")]
 struct StaticPod {
     enabled: bool,
     manifest: ValidBase64,
 }
 
+fn validate_static_pod(pod: &StaticPod, scope: Scope) -> Vec<String> {
+    let mut violations = Vec::new();
+    if pod.enabled == Some(true) {
+        match &pod.manifest {
+            Some(manifest) if manifest.is_empty() => {
+                violations.push("manifest must not be empty when enabled".to_string())
+            }
+            None if scope == Scope::Merged => {
+                violations.push("manifest is required when enabled".to_string())
+            }
+            _ => {}
+        }
+    }
+    violations
+}
+
 // Kubernetes related settings. The dynamic settings are retrieved from
 // IMDS via Sundog's child "Pluto".
-#[model]
+#[model(validate = "validate_kubernetes")]
 struct KubernetesSettings {
     // Settings that must be specified via user data or through API requests.  Not all settings are
     // useful for all modes. For example, in standalone mode the user does not need to specify any
@@ -142,6 +176,7 @@ struct KubernetesSettings {
     node_taints: HashMap<KubernetesLabelKey, KubernetesTaintValue>,
     static_pods: HashMap<Identifier, StaticPod>,
     authentication_mode: KubernetesAuthenticationMode,
+    #[sensitive]
     bootstrap_token: KubernetesBootstrapToken,
     standalone_mode: bool,
     eviction_hard: HashMap<KubernetesEvictionHardKey, KubernetesThresholdValue>,
@@ -170,6 +205,21 @@ struct KubernetesSettings {
     pod_infra_container_image: SingleLineString,
 }
 
+fn validate_kubernetes(kubernetes: &KubernetesSettings, scope: Scope) -> Vec<String> {
+    let mut violations = Vec::new();
+    // The bootstrap token is how the kubelet joins the cluster in TLS authentication mode; in
+    // standalone mode there's no cluster to join.
+    if scope == Scope::Merged
+        && kubernetes.authentication_mode.as_deref() == Some("tls")
+        && kubernetes.standalone_mode != Some(true)
+        && kubernetes.bootstrap_token.is_none()
+    {
+        violations
+            .push("bootstrap-token is required when authentication-mode is \"tls\"".to_string());
+    }
+    violations
+}
+
 // ECS settings.
 #[model]
 struct ECSSettings {
@@ -193,14 +243,23 @@ struct UpdatesSettings {
     ignore_waves: bool,
 }
 
-#[model]
+#[model(validate = "validate_host_container")]
 struct HostContainer {
     source: Url,
     enabled: bool,
     superpowered: bool,
+    #[sensitive]
     user_data: ValidBase64,
 }
 
+fn validate_host_container(container: &HostContainer, scope: Scope) -> Vec<String> {
+    let mut violations = Vec::new();
+    if scope == Scope::Merged && container.enabled == Some(true) && container.source.is_none() {
+        violations.push("source is required when enabled".to_string());
+    }
+    violations
+}
+
 // Network settings. These settings will affect host service components' network behavior
 #[model]
 struct NetworkSettings {
@@ -273,10 +332,20 @@ struct Metadata {
 
 ///// Bootstrap Containers
 
-#[model]
+#[model(validate = "validate_bootstrap_container")]
 struct BootstrapContainer {
     source: Url,
     mode: BootstrapContainerMode,
+    #[sensitive]
     user_data: ValidBase64,
     essential: bool,
 }
+
+fn validate_bootstrap_container(container: &BootstrapContainer, scope: Scope) -> Vec<String> {
+    let mut violations = Vec::new();
+    let runs = matches!(container.mode.as_deref(), Some("once") | Some("always"));
+    if scope == Scope::Merged && runs && container.source.is_none() {
+        violations.push("source is required when mode is \"once\" or \"always\"".to_string());
+    }
+    violations
+}
@@ -49,6 +49,9 @@ impl TryFrom<&str> for ECSAttributeKey {
 
 string_impls_for!(ECSAttributeKey, "ECSAttributeKey");
 
+// JSON Schema patterns can't use the extended syntax of the regex above.
+json_schema_for!(ECSAttributeKey, { "pattern": r"^[a-zA-Z0-9._/-]{1,128}$" });
+
 #[cfg(test)]
 mod test_ecs_attribute_key {
     use super::ECSAttributeKey;
@@ -134,6 +137,10 @@ impl TryFrom<&str> for ECSAttributeValue {
 
 string_impls_for!(ECSAttributeValue, "ECSAttributeValue");
 
+json_schema_for!(ECSAttributeValue, {
+    "pattern": r"^[a-zA-Z0-9.@:_/\\-]([a-zA-Z0-9.@: _/\\-]{0,126}[a-zA-Z0-9.@:_/\\-])?$",
+});
+
 #[cfg(test)]
 mod test_ecs_attribute_value {
     use super::ECSAttributeValue;
@@ -195,6 +202,9 @@ enum ECSLogLevel {
 }
 
 string_impls_for!(ECSAgentLogLevel, "ECSAgentLogLevel");
+json_schema_for!(ECSAgentLogLevel, {
+    "enum": ["debug", "info", "warn", "error", "crit"],
+});
 
 impl TryFrom<&str> for ECSAgentLogLevel {
     type Error = error::Error;
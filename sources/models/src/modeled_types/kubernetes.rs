@@ -42,6 +42,8 @@ impl TryFrom<&str> for KubernetesName {
 
 string_impls_for!(KubernetesName, "KubernetesName");
 
+json_schema_for!(KubernetesName, { "pattern": KUBERNETES_NAME.as_str() });
+
 #[cfg(test)]
 mod test_kubernetes_name {
     use super::KubernetesName;
@@ -107,6 +109,11 @@ impl TryFrom<&str> for KubernetesLabelKey {
 
 string_impls_for!(KubernetesLabelKey, "KubernetesLabelKey");
 
+// JSON Schema patterns can't use the extended syntax or character classes of the regex above.
+json_schema_for!(KubernetesLabelKey, {
+    "pattern": r"^([A-Za-z0-9.-]{1,253}/)?[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?$",
+});
+
 #[cfg(test)]
 mod test_kubernetes_label_key {
     use super::KubernetesLabelKey;
@@ -183,6 +190,10 @@ impl TryFrom<&str> for KubernetesLabelValue {
 
 string_impls_for!(KubernetesLabelValue, "KubernetesLabelValue");
 
+json_schema_for!(KubernetesLabelValue, {
+    "pattern": r"^([A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?)?$",
+});
+
 #[cfg(test)]
 mod test_kubernetes_label_value {
     use super::KubernetesLabelValue;
@@ -256,6 +267,10 @@ impl TryFrom<&str> for KubernetesTaintValue {
 
 string_impls_for!(KubernetesTaintValue, "KubernetesTaintValue");
 
+json_schema_for!(KubernetesTaintValue, {
+    "pattern": r"^([A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?)?:[A-Za-z0-9]{1,253}$",
+});
+
 #[cfg(test)]
 mod test_kubernetes_taint_value {
     use super::KubernetesTaintValue;
@@ -331,6 +346,10 @@ impl TryFrom<&str> for KubernetesClusterName {
 
 string_impls_for!(KubernetesClusterName, "KubernetesClusterName");
 
+json_schema_for!(KubernetesClusterName, {
+    "pattern": r"^[A-Za-z0-9](([A-Za-z0-9._-]{0,61})?[A-Za-z0-9])?$",
+});
+
 #[cfg(test)]
 mod test_kubernetes_cluster_name {
     use super::KubernetesClusterName;
@@ -376,6 +395,8 @@ impl TryFrom<&str> for KubernetesAuthenticationMode {
 
 string_impls_for!(KubernetesAuthenticationMode, "KubernetesAuthenticationMode");
 
+json_schema_for!(KubernetesAuthenticationMode, { "enum": ["aws", "tls"] });
+
 #[cfg(test)]
 mod test_kubernetes_authentication_mode {
     use super::KubernetesAuthenticationMode;
@@ -431,6 +452,10 @@ impl TryFrom<&str> for KubernetesBootstrapToken {
 
 string_impls_for!(KubernetesBootstrapToken, "KubernetesBootstrapToken");
 
+json_schema_for!(KubernetesBootstrapToken, {
+    "pattern": KUBERNETES_BOOTSTRAP_TOKEN.as_str(),
+});
+
 #[cfg(test)]
 mod test_kubernetes_bootstrap_token {
     use super::KubernetesBootstrapToken;
@@ -491,6 +516,16 @@ impl TryFrom<&str> for KubernetesEvictionHardKey {
     }
 }
 string_impls_for!(KubernetesEvictionHardKey, "KubernetesEvictionHardKey");
+json_schema_for!(KubernetesEvictionHardKey, {
+    "enum": [
+        "memory.available",
+        "nodefs.available",
+        "nodefs.inodesFree",
+        "imagefs.available",
+        "imagefs.inodesFree",
+        "pid.available",
+    ],
+});
 
 #[cfg(test)]
 mod test_kubernetes_eviction_hard_key {
@@ -544,6 +579,13 @@ lazy_static! {
     .unwrap();
 }
 
+/// The regex above, in the syntax of JSON Schema patterns.
+const KUBERNETES_QUANTITY_PATTERN: &str = concat!(
+    r"^([+-]?[0-9.]+)((e)?[0-9]*)$",
+    r"|^([+-]?[0-9.]+)((E|P|T|G|M|K)i?)?$",
+    r"|^([+-]?[0-9.]+)(n|u|m|k)?$",
+);
+
 impl TryFrom<&str> for KubernetesThresholdValue {
     type Error = error::Error;
 
@@ -574,6 +616,15 @@ impl TryFrom<&str> for KubernetesThresholdValue {
 }
 string_impls_for!(KubernetesThresholdValue, "KubernetesThresholdValue");
 
+// JSON Schema can't compare numbers in strings, so the percentage pattern only allows values below
+// 100, and is stricter than the check above about how they're written.
+json_schema_for!(KubernetesThresholdValue, {
+    "anyOf": [
+        { "pattern": r"^([0-9]{1,2}(\.[0-9]*)?|\.[0-9]+)%$" },
+        { "pattern": KUBERNETES_QUANTITY_PATTERN },
+    ],
+});
+
 #[cfg(test)]
 mod test_kubernetes_threshold_value {
     use super::KubernetesThresholdValue;
@@ -641,6 +692,9 @@ string_impls_for!(
     KubernetesReservedResourceKey,
     "KubernetesReservedResourceKey"
 );
+json_schema_for!(KubernetesReservedResourceKey, {
+    "enum": ["cpu", "memory", "ephemeral-storage"],
+});
 
 #[cfg(test)]
 mod test_reserved_resources_key {
@@ -692,6 +746,8 @@ impl TryFrom<&str> for KubernetesQuantityValue {
 }
 string_impls_for!(KubernetesQuantityValue, "KubernetesQuantityValue");
 
+json_schema_for!(KubernetesQuantityValue, { "pattern": KUBERNETES_QUANTITY_PATTERN });
+
 #[cfg(test)]
 mod test_kubernetes_quantity_value {
     use super::KubernetesQuantityValue;
@@ -749,6 +805,8 @@ impl TryFrom<&str> for KubernetesCloudProvider {
 
 string_impls_for!(KubernetesCloudProvider, "KubernetesCloudProvider");
 
+json_schema_for!(KubernetesCloudProvider, { "enum": ["aws", "external"] });
+
 #[cfg(test)]
 mod test_kubernetes_cloud_provider {
     use super::KubernetesCloudProvider;
@@ -797,6 +855,7 @@ impl TryFrom<&str> for CpuManagerPolicy {
     }
 }
 string_impls_for!(CpuManagerPolicy, "CpuManagerPolicy");
+json_schema_for!(CpuManagerPolicy, { "enum": ["static", "none"] });
 
 #[cfg(test)]
 mod test_cpu_manager_policy {
@@ -853,6 +912,11 @@ impl TryFrom<&str> for KubernetesDurationValue {
 
 string_impls_for!(KubernetesDurationValue, "KubernetesDurationValue");
 
+json_schema_for!(KubernetesDurationValue, {
+    "pattern": KUBERNETES_DURATION_VALUE.as_str(),
+    "minLength": 1,
+});
+
 #[cfg(test)]
 mod test_kubernetes_duration_value {
     use super::KubernetesDurationValue;
@@ -176,10 +176,37 @@ macro_rules! string_impls_for {
                 &self.inner == other
             }
         }
+
+        /// The value was checked when it was created, so there are no other rules to check.
+        impl $crate::validation::Validate for $for {
+            fn validate_at(
+                &self,
+                _: &[String],
+                _: $crate::validation::Scope,
+                _: &mut Vec<$crate::validation::Violation>,
+            ) {
+            }
+        }
+    };
+}
+
+/// Helper macro for implementing JsonSchema for a modeled type.  Pass the name of the type, and
+/// the keywords of a string schema that accepts the same input as the type's TryFrom<&str>, as
+/// closely as JSON Schema allows.  Patterns must use the regular expression syntax common to JSON
+/// Schema (ECMA 262) and the regex crate, so they can be tested against the type's checks.
+macro_rules! json_schema_for {
+    ($for:ident, { $($keywords:tt)* }) => {
+        impl $crate::schema::JsonSchema for $for {
+            fn json_schema(gen: &mut $crate::schema::SchemaGenerator) -> $crate::schema::Value {
+                gen.named(stringify!($for), |_| {
+                    serde_json::json!({ "type": "string", $($keywords)* })
+                })
+            }
+        }
     };
 }
 
-// Must be after macro definition
+// Must be after macro definitions
 mod ecs;
 mod kubernetes;
 mod shared;
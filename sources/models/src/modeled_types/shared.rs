@@ -36,6 +36,12 @@ impl TryFrom<&str> for ValidBase64 {
 }
 
 string_impls_for!(ValidBase64, "ValidBase64");
+// Groups of four characters, then a final group of two or three characters whose unused bits are
+// zero; the padding of the final group is optional.
+json_schema_for!(ValidBase64, {
+    "contentEncoding": "base64",
+    "pattern": r"^(?:[A-Za-z0-9+/]{4})*(?:[A-Za-z0-9+/][AQgw](?:==)?|[A-Za-z0-9+/]{2}[AEIMQUYcgkosw048]=?)?$",
+});
 
 #[cfg(test)]
 mod test_valid_base64 {
@@ -97,6 +103,10 @@ impl TryFrom<&str> for SingleLineString {
 
 string_impls_for!(SingleLineString, "SingleLineString");
 
+json_schema_for!(SingleLineString, {
+    "pattern": r"^[^\n\r\u000B\u000C\u0085\u2028\u2029]*$",
+});
+
 #[cfg(test)]
 mod test_single_line_string {
     use super::SingleLineString;
@@ -159,6 +169,10 @@ impl TryFrom<&str> for Identifier {
 
 string_impls_for!(Identifier, "Identifier");
 
+json_schema_for!(Identifier, {
+    "pattern": format!("^[A-Za-z0-9-]{{0,{}}}$", CONTAINERD_ID_LENGTH),
+});
+
 #[cfg(test)]
 mod test_valid_identifier {
     use super::{Identifier, CONTAINERD_ID_LENGTH};
@@ -223,6 +237,14 @@ impl TryFrom<&str> for Url {
 
 string_impls_for!(Url, "Url");
 
+// Unlike a JSON Schema "uri", the scheme can be left out.  The pattern requires an optional scheme,
+// optional user info, a host or bracketed IPv6 address, and an optional port, none of them
+// containing whitespace; any path, query, or fragment follows.
+json_schema_for!(Url, {
+    "description": "A URL, like \"https://example.com/path\"; the scheme may be left out",
+    "pattern": r"^(?:[A-Za-z][A-Za-z0-9+.-]*://)?(?:[^\s/?#@]*@)?(?:[^\s/?#@:\[\]]+|\[[0-9A-Fa-f:.]+\])(?::[0-9]*)?(?:[/?#].*)?$",
+});
+
 #[cfg(test)]
 mod test_url {
     use super::Url;
@@ -310,6 +332,14 @@ impl TryFrom<FriendlyVersion> for semver::Version {
 
 string_impls_for!(FriendlyVersion, "FriendlyVersion");
 
+json_schema_for!(FriendlyVersion, {
+    "pattern": concat!(
+        r"^(latest|v?(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)\.(0|[1-9][0-9]*)",
+        r"(-(0|[1-9][0-9]*|[0-9]*[A-Za-z-][0-9A-Za-z-]*)(\.(0|[1-9][0-9]*|[0-9]*[A-Za-z-][0-9A-Za-z-]*))*)?",
+        r"(\+[0-9A-Za-z-]+(\.[0-9A-Za-z-]+)*)?)$",
+    ),
+});
+
 #[cfg(test)]
 mod test_version {
     use super::FriendlyVersion;
@@ -409,6 +439,14 @@ impl TryFrom<&str> for DNSDomain {
 
 string_impls_for!(DNSDomain, "DNSDomain");
 
+// Host names can't contain the characters URLs use as delimiters, and names made only of digits
+// and dots are parsed as IPv4 addresses.
+json_schema_for!(DNSDomain, {
+    "description": "A DNS domain name, like \"cluster.local\", that isn't an IP address",
+    "pattern": r"^[^.\s/?#@:%<>\[\]\\^|][^\s/?#@:%<>\[\]\\^|]*$",
+    "not": { "pattern": r"^[0-9.]+$" },
+});
+
 #[cfg(test)]
 mod test_dns_domain {
     use super::DNSDomain;
@@ -484,6 +522,11 @@ impl TryFrom<&str> for SysctlKey {
 
 string_impls_for!(SysctlKey, "SysctlKey");
 
+json_schema_for!(SysctlKey, {
+    "pattern": r"^[a-zA-Z0-9_-][a-zA-Z0-9./_-]{0,127}$",
+    "not": { "pattern": r"\.\." },
+});
+
 #[cfg(test)]
 mod test_sysctl_key {
     use super::SysctlKey;
@@ -569,6 +612,7 @@ impl TryFrom<&str> for Lockdown {
 }
 
 string_impls_for!(Lockdown, "Lockdown");
+json_schema_for!(Lockdown, { "enum": ["none", "integrity", "confidentiality"] });
 
 // =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=   =^..^=
 
@@ -600,6 +644,7 @@ impl Default for BootstrapContainerMode {
 }
 
 string_impls_for!(BootstrapContainerMode, "BootstrapContainerMode");
+json_schema_for!(BootstrapContainerMode, { "enum": ["off", "once", "always"] });
 
 #[cfg(test)]
 mod test_valid_container_mode {
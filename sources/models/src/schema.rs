@@ -0,0 +1,550 @@
+//! This module describes the model in [JSON Schema](https://json-schema.org/), so clients can
+//! check their input before sending it to the API, and so the API documentation can be generated
+//! from the model rather than written by hand.
+//!
+//! Types describe themselves by implementing `JsonSchema`.  The `#[model]` attribute implements it
+//! for model structs, and each modeled type implements it with the checks it makes on input, as
+//! far as JSON Schema can express them.  Model structs and modeled types are named types; their
+//! schemas are collected as definitions by a `SchemaGenerator`, and referenced where they're used.
+
+use bottlerocket_release::BottlerocketRelease;
+use serde_json::{json, Map};
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+
+pub use serde_json::Value;
+
+/// The JSON Schema dialect used by documents from `schema_for`.
+pub const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A type whose serialized form can be described by a JSON Schema.
+pub trait JsonSchema {
+    /// Returns the schema for the type.  Named types add their schema to the generator's
+    /// definitions and return a reference to it.
+    fn json_schema(gen: &mut SchemaGenerator) -> Value;
+}
+
+/// SchemaGenerator collects the definitions of the named types used in a schema.
+#[derive(Debug)]
+pub struct SchemaGenerator {
+    ref_prefix: String,
+    definitions: BTreeMap<String, Value>,
+}
+
+impl SchemaGenerator {
+    /// Creates a generator whose references point to definitions under the given prefix, for
+    /// example "#/$defs/" for a JSON Schema document, or "#/components/schemas/" for an OpenAPI
+    /// document.
+    pub fn new<S: Into<String>>(ref_prefix: S) -> Self {
+        Self {
+            ref_prefix: ref_prefix.into(),
+            definitions: BTreeMap::new(),
+        }
+    }
+
+    /// Returns a reference to the definition with the given name, calling `schema` to create the
+    /// definition the first time the name is used.
+    pub fn named<F>(&mut self, name: &str, schema: F) -> Value
+    where
+        F: FnOnce(&mut Self) -> Value,
+    {
+        if !self.definitions.contains_key(name) {
+            // Reserve the name first, so a type that contains itself doesn't recurse forever.
+            self.definitions.insert(name.to_string(), Value::Null);
+            let schema = schema(self);
+            self.definitions.insert(name.to_string(), schema);
+        }
+        json!({ "$ref": format!("{}{}", self.ref_prefix, name) })
+    }
+
+    /// Returns the definitions collected so far, by name.
+    pub fn definitions(&self) -> &BTreeMap<String, Value> {
+        &self.definitions
+    }
+
+    /// Consumes the generator, returning the definitions it collected, by name.
+    pub fn into_definitions(self) -> BTreeMap<String, Value> {
+        self.definitions
+    }
+}
+
+/// Returns a JSON Schema document for the given type, with the named types it uses in `$defs`.
+pub fn schema_for<T: JsonSchema>() -> Value {
+    let mut gen = SchemaGenerator::new("#/$defs/");
+    let root = T::json_schema(&mut gen);
+
+    let mut schema = Map::new();
+    schema.insert("$schema".to_string(), DIALECT.into());
+    if let Value::Object(root) = root {
+        schema.extend(root);
+    }
+    schema.insert("$defs".to_string(), json!(gen.into_definitions()));
+    Value::Object(schema)
+}
+
+/// Returns the schema of a model struct with the given properties, of which those named in
+/// `required` must be given.  This is used by the `#[model]` attribute.
+pub fn object(properties: Vec<(&str, Value)>, required: &[&str]) -> Value {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .map(|(name, schema)| (name.to_string(), schema))
+        .collect();
+    let mut schema = json!({
+        "type": "object",
+        "properties": properties,
+        "additionalProperties": false,
+    });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+/// Marks the given schema of a field as `writeOnly`, meaning its value is accepted but not
+/// returned; this is how sensitive fields are described.  This is used by the `#[model]`
+/// attribute.
+pub fn write_only(schema: Value) -> Value {
+    let mut schema = match schema {
+        Value::Object(schema) => schema,
+        _ => Map::new(),
+    };
+    schema.insert("writeOnly".to_string(), true.into());
+    Value::Object(schema)
+}
+
+// Schemas for the standard types used in the model.
+
+impl JsonSchema for bool {
+    fn json_schema(_gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+macro_rules! integer_schema_for {
+    ($($for:ty),*) => {
+        $(
+            impl JsonSchema for $for {
+                fn json_schema(_gen: &mut SchemaGenerator) -> Value {
+                    json!({ "type": "integer", "minimum": <$for>::MIN, "maximum": <$for>::MAX })
+                }
+            }
+        )*
+    };
+}
+
+integer_schema_for!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl JsonSchema for String {
+    fn json_schema(_gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for Ipv4Addr {
+    fn json_schema(_gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "string", "format": "ipv4" })
+    }
+}
+
+/// Fields that are `None` are left out when serialized rather than given as null, so an `Option`
+/// has the schema of its contents; `#[model]` leaves optional fields out of `required`.
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        T::json_schema(gen)
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({ "type": "array", "items": T::json_schema(gen) })
+    }
+}
+
+/// Maps are objects whose property names are described by the schema of the key type.
+impl<K: JsonSchema, V: JsonSchema> JsonSchema for HashMap<K, V> {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        json!({
+            "type": "object",
+            "propertyNames": K::json_schema(gen),
+            "additionalProperties": V::json_schema(gen),
+        })
+    }
+}
+
+/// TOML values, like metadata values, can be any JSON value.
+impl JsonSchema for toml::Value {
+    fn json_schema(_gen: &mut SchemaGenerator) -> Value {
+        json!({})
+    }
+}
+
+impl JsonSchema for BottlerocketRelease {
+    fn json_schema(gen: &mut SchemaGenerator) -> Value {
+        gen.named("BottlerocketRelease", |_| {
+            json!({
+                "type": "object",
+                "properties": {
+                    "pretty_name": { "type": "string" },
+                    "variant_id": { "type": "string" },
+                    "version_id": { "type": "string" },
+                    "build_id": { "type": "string" },
+                    "arch": { "type": "string" },
+                },
+                "required": ["pretty_name", "variant_id", "version_id", "build_id", "arch"],
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modeled_types::*;
+    use crate::Model;
+    use regex::Regex;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn model_document() {
+        let schema = schema_for::<Model>();
+        assert_eq!(schema["$schema"], DIALECT);
+        assert_eq!(schema["$ref"], "#/$defs/Model");
+
+        // Every reference points to a definition.
+        fn check_refs(value: &Value, defs: &Value) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::String(reference)) = map.get("$ref") {
+                        let name = reference.trim_start_matches("#/$defs/");
+                        assert!(defs[name].is_object(), "Missing definition {}", reference);
+                    }
+                    map.values().for_each(|v| check_refs(v, defs));
+                }
+                Value::Array(values) => values.iter().for_each(|v| check_refs(v, defs)),
+                _ => {}
+            }
+        }
+        check_refs(&schema, &schema["$defs"]);
+
+        // Settings are optional, with kebab-case names, and unknown names are rejected.
+        let settings = &schema["$defs"]["Settings"];
+        assert_eq!(settings["type"], "object");
+        assert_eq!(settings["additionalProperties"], false);
+        assert!(settings.get("required").is_none());
+        assert_eq!(
+            settings["properties"]["host-containers"]["additionalProperties"]["$ref"],
+            "#/$defs/HostContainer"
+        );
+
+        // Services are all given.
+        let service = &schema["$defs"]["Service"];
+        assert_eq!(
+            service["required"],
+            json!(["configuration-files", "restart-commands"])
+        );
+    }
+
+    #[test]
+    fn named_once() {
+        let mut gen = SchemaGenerator::new("#/components/schemas/");
+        let first = Url::json_schema(&mut gen);
+        let second = Url::json_schema(&mut gen);
+        assert_eq!(first, json!({ "$ref": "#/components/schemas/Url" }));
+        assert_eq!(first, second);
+        assert_eq!(gen.definitions().len(), 1);
+    }
+
+    /// Checks a string against the string keywords used by modeled types.
+    fn accepts(schema: &Value, input: &str) -> bool {
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            if !Regex::new(pattern).unwrap().is_match(input) {
+                return false;
+            }
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            if !values.iter().any(|v| v == input) {
+                return false;
+            }
+        }
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if (input.chars().count() as u64) < min {
+                return false;
+            }
+        }
+        if let Some(schemas) = schema.get("anyOf").and_then(Value::as_array) {
+            if !schemas.iter().any(|s| accepts(s, input)) {
+                return false;
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if accepts(not, input) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Makes sure the schema of each modeled type accepts the same inputs as the type itself.
+    macro_rules! check_schema {
+        ($for:ident, [$($input:expr),* $(,)?]) => {
+            let mut gen = SchemaGenerator::new("#/$defs/");
+            $for::json_schema(&mut gen);
+            let schema = &gen.definitions()[stringify!($for)];
+            assert_eq!(schema["type"], "string");
+            for input in &[$($input),*] {
+                let input: &str = input;
+                assert_eq!(
+                    accepts(schema, input),
+                    $for::try_from(input).is_ok(),
+                    "{} schema disagrees about '{}'",
+                    stringify!($for),
+                    input
+                );
+            }
+        };
+    }
+
+    #[test]
+    fn modeled_types_match_schemas() {
+        check_schema!(
+            KubernetesName,
+            ["abc", "a.b-c", "", "ABC", &"a".repeat(254)]
+        );
+        check_schema!(
+            KubernetesLabelKey,
+            [
+                "no-prefix",
+                "have.a/prefix",
+                "more-chars_here.now",
+                &"a".repeat(63),
+                &"a".repeat(64),
+                "",
+                ".bad",
+                "bad.",
+                "a/b/c",
+                "-bad",
+            ]
+        );
+        check_schema!(
+            KubernetesLabelValue,
+            [
+                "",
+                "more-chars_here.now",
+                &"a".repeat(63),
+                &"a".repeat(64),
+                ".bad",
+                "bad."
+            ]
+        );
+        check_schema!(
+            KubernetesTaintValue,
+            [
+                "no-prefix:NoSchedule",
+                ":NoSchedule",
+                "have.a/prefix:NoSchedule",
+                "",
+                "NoSchedule",
+                "bad.:NoSchedule",
+            ]
+        );
+        check_schema!(
+            KubernetesClusterName,
+            ["", "cluster", "a.b_c-d", ".bad", &"a".repeat(64)]
+        );
+        check_schema!(
+            KubernetesAuthenticationMode,
+            ["aws", "tls", "", "AWS", "x509"]
+        );
+        check_schema!(
+            KubernetesBootstrapToken,
+            [
+                "abcdef.0123456789abcdef",
+                "",
+                "ABCDEF.0123456789ABCDEF",
+                "abcdef0123456789abcdef"
+            ]
+        );
+        check_schema!(
+            KubernetesEvictionHardKey,
+            [
+                "memory.available",
+                "nodefs.inodesFree",
+                "pid.available",
+                "",
+                "storage.available"
+            ]
+        );
+        check_schema!(
+            KubernetesThresholdValue,
+            ["10%", "0%", "99.9%", ".5%", "100%", "-1%", "1Gi", "1.5e9", "100m", "", "1Gb"]
+        );
+        check_schema!(
+            KubernetesReservedResourceKey,
+            ["cpu", "memory", "ephemeral-storage", "", "storage", "CPU"]
+        );
+        check_schema!(
+            KubernetesQuantityValue,
+            [
+                "128974848",
+                "129e6",
+                "129M",
+                "123Mi",
+                "-1.5",
+                "",
+                "1Gb",
+                "Gi"
+            ]
+        );
+        check_schema!(KubernetesCloudProvider, ["aws", "external", "", "gce"]);
+        check_schema!(CpuManagerPolicy, ["static", "none", "", "dynamic"]);
+        check_schema!(
+            KubernetesDurationValue,
+            [
+                "9h",
+                "1.5h30m",
+                "60s",
+                "100ms",
+                "1h2m3s4ms",
+                "",
+                "1d",
+                "1s1h",
+                "ms"
+            ]
+        );
+        check_schema!(
+            ECSAttributeKey,
+            [
+                "a",
+                "a.b_c/d-e",
+                &"a".repeat(128),
+                "",
+                &"a".repeat(129),
+                "a b",
+                "a@b"
+            ]
+        );
+        check_schema!(
+            ECSAttributeValue,
+            [
+                "a",
+                "with space",
+                "a@b:c/d\\e",
+                &"a".repeat(128),
+                "",
+                " leading",
+                "trailing ",
+                &"a".repeat(129),
+            ]
+        );
+        check_schema!(ECSAgentLogLevel, ["debug", "crit", "", "warning", " "]);
+        check_schema!(
+            SingleLineString,
+            [
+                "",
+                "one line",
+                "two\nlines",
+                "a\rb",
+                "a\u{000B}b",
+                "a\u{0085}b",
+                "a\u{2029}b"
+            ]
+        );
+        check_schema!(
+            Identifier,
+            [
+                "",
+                "abc-123",
+                &"a".repeat(76),
+                &"a".repeat(77),
+                "a_b",
+                "a.b",
+                "ü"
+            ]
+        );
+        check_schema!(
+            FriendlyVersion,
+            [
+                "latest",
+                "1.0.0",
+                "v1.0.0",
+                "1.0.0-beta.1+build.01",
+                "Latest",
+                "1.0",
+                "v",
+                "01.0.0",
+                "1.0.0-beta.01",
+                "vv1.0.0",
+            ]
+        );
+        check_schema!(
+            ValidBase64,
+            [
+                "",
+                "aGk=",
+                "aGk",
+                "aGVsbG8=",
+                "aA==",
+                "aA",
+                "+/+/",
+                "aGl=",
+                "aB==",
+                "a",
+                "aGk==",
+                "invalid base64",
+                "aGk=aGk=",
+            ]
+        );
+        check_schema!(
+            Url,
+            [
+                "https://example.com/path",
+                "example.com",
+                "ntp://127.0.0.1",
+                "localhost:8080",
+                "http://user@localhost/path?query#fragment",
+                "[::1]:8080",
+                ".internal",
+                "how are you",
+                "weird@",
+                "",
+                "http://",
+                "http://exa mple.com",
+            ]
+        );
+        check_schema!(
+            DNSDomain,
+            [
+                "cluster.local",
+                "dev.eks",
+                "localhost",
+                "1.example",
+                "foo/com",
+                ".a",
+                "",
+                "123.123.123.123",
+                "123",
+                "[2001:db8::ff00:42:8329]",
+                "a b",
+                "a:80",
+            ]
+        );
+        check_schema!(
+            SysctlKey,
+            [
+                "net.ipv4.ip_forward",
+                "fs/aio-nr",
+                "a..b",
+                ".a",
+                "/a",
+                "",
+                &"a".repeat(129),
+                "a b",
+            ]
+        );
+        check_schema!(
+            Lockdown,
+            ["none", "integrity", "confidentiality", "", "all"]
+        );
+        check_schema!(BootstrapContainerMode, ["off", "once", "always", "", "on"]);
+    }
+}
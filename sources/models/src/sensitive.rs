@@ -0,0 +1,131 @@
+//! This module finds the settings that are marked sensitive in the model, like secrets and user
+//! data, so their values can be kept out of API responses and logs.
+//!
+//! A field of a model struct is marked with `#[sensitive]`, and `#[model]` describes it as
+//! `writeOnly` in the struct's JSON Schema.  `SensitivePaths` walks the schema of a type to find
+//! the paths of its sensitive fields, as data store keys.  Fields under maps and lists don't have
+//! fixed names, like the user data of each host container, so their paths have wildcard segments.
+
+use crate::schema::{JsonSchema, SchemaGenerator, Value};
+use std::collections::BTreeMap;
+
+/// A path segment that matches any segment, like the key of a map entry or the index of a list
+/// element.
+pub const WILDCARD: &str = "*";
+
+/// The references of the schemas we walk point to definitions under this prefix.
+const REF_PREFIX: &str = "#/$defs/";
+
+/// The paths of the sensitive fields of a type, as the segments of data store keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SensitivePaths {
+    paths: Vec<Vec<String>>,
+}
+
+impl SensitivePaths {
+    /// Finds the sensitive fields of the given type, which is stored at the given path, for
+    /// example `SensitivePaths::of::<Settings>("settings")`.
+    pub fn of<T: JsonSchema>(path: &str) -> Self {
+        let mut gen = SchemaGenerator::new(REF_PREFIX);
+        let root = T::json_schema(&mut gen);
+        let mut paths = Vec::new();
+        find_paths(
+            &root,
+            gen.definitions(),
+            &mut vec![path.to_string()],
+            &mut paths,
+        );
+        Self { paths }
+    }
+
+    /// Returns the paths of the sensitive fields, which may contain `WILDCARD` segments.
+    pub fn paths(&self) -> &[Vec<String>] {
+        &self.paths
+    }
+
+    /// Returns whether the key with the given segments is a sensitive field, or is under one.
+    pub fn is_sensitive<S: AsRef<str>>(&self, segments: &[S]) -> bool {
+        self.paths.iter().any(|path| {
+            path.len() <= segments.len()
+                && path
+                    .iter()
+                    .zip(segments)
+                    .all(|(p, s)| p == WILDCARD || p == s.as_ref())
+        })
+    }
+}
+
+/// Adds the paths of the sensitive fields in the given schema, which is at the given path, to
+/// `paths`.
+fn find_paths(
+    schema: &Value,
+    definitions: &BTreeMap<String, Value>,
+    path: &mut Vec<String>,
+    paths: &mut Vec<Vec<String>>,
+) {
+    // Everything under a sensitive field is sensitive, so there's no need to look further.
+    if schema["writeOnly"] == true {
+        paths.push(path.clone());
+        return;
+    }
+
+    if let Some(name) = schema["$ref"]
+        .as_str()
+        .and_then(|reference| reference.strip_prefix(REF_PREFIX))
+    {
+        if let Some(definition) = definitions.get(name) {
+            find_paths(definition, definitions, path, paths);
+        }
+    }
+    if let Some(properties) = schema["properties"].as_object() {
+        for (name, property) in properties {
+            path.push(name.clone());
+            find_paths(property, definitions, path, paths);
+            path.pop();
+        }
+    }
+    for entries in &[&schema["additionalProperties"], &schema["items"]] {
+        if entries.is_object() {
+            path.push(WILDCARD.to_string());
+            find_paths(entries, definitions, path, paths);
+            path.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BootstrapContainer, HostContainer, KubernetesSettings};
+    use std::collections::HashMap;
+
+    #[test]
+    fn nested_fields() {
+        let sensitive = SensitivePaths::of::<HashMap<String, HostContainer>>("host-containers");
+        assert_eq!(
+            sensitive.paths(),
+            &[vec!["host-containers", "*", "user-data"]]
+        );
+
+        assert!(sensitive.is_sensitive(&["host-containers", "admin", "user-data"]));
+        assert!(!sensitive.is_sensitive(&["host-containers", "admin", "source"]));
+        assert!(!sensitive.is_sensitive(&["host-containers", "admin"]));
+        assert!(!sensitive.is_sensitive(&["host-containers", "user-data"]));
+    }
+
+    #[test]
+    fn under_sensitive_fields() {
+        let sensitive = SensitivePaths::of::<KubernetesSettings>("kubernetes");
+        assert!(sensitive.is_sensitive(&["kubernetes", "bootstrap-token"]));
+        assert!(sensitive.is_sensitive(&["kubernetes", "bootstrap-token", "x"]));
+        assert!(!sensitive.is_sensitive(&["kubernetes", "cluster-name"]));
+        assert!(!sensitive.is_sensitive(&["kubernetes"]));
+    }
+
+    #[test]
+    fn lists() {
+        let sensitive = SensitivePaths::of::<Vec<BootstrapContainer>>("containers");
+        assert!(sensitive.is_sensitive(&["containers", "0", "user-data"]));
+        assert!(!sensitive.is_sensitive(&["containers", "0", "mode"]));
+    }
+}
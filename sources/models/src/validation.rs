@@ -0,0 +1,233 @@
+//! This module checks rules about model structs that involve more than one field, like a setting
+//! that's required when another setting has a certain value.  Rules about a single value are
+//! checked by its type, for example by modeled types, when it's deserialized.
+//!
+//! A struct names a function that checks its rules with `#[model(validate = "function")]`.  The
+//! function takes the struct and the `Scope` of the validation, and returns a message for each
+//! rule that's broken.  The `#[model]` attribute implements `Validate` for the struct, which runs
+//! the function, if any, and validates each field, so problems are found anywhere in the model.
+//!
+//! All problems are returned at once as `Violation`s, with the path to the struct that has them.
+
+use bottlerocket_release::BottlerocketRelease;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// What the data being validated represents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// A change to settings, which only includes the fields being changed; a missing field isn't a
+    /// problem, because it may be given elsewhere.
+    Change,
+    /// Settings as they'd be after a commit, with the changes merged into live data; a missing
+    /// field really is missing.
+    Merged,
+}
+
+/// A rule broken by a model struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// The path to the struct, as segments of a data store key, like ["settings", "kubernetes"].
+    pub path: Vec<String>,
+    /// A description of the problem.
+    pub message: String,
+}
+
+/// Shows the path in the form of a data store key, quoting segments that contain dots.
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path: Vec<String> = self
+            .path
+            .iter()
+            .map(|segment| {
+                if segment.contains('.') {
+                    format!("\"{}\"", segment)
+                } else {
+                    segment.clone()
+                }
+            })
+            .collect();
+        write!(f, "{}: {}", path.join("."), self.message)
+    }
+}
+
+/// A type that can check the rules of the model structs it contains.
+pub trait Validate {
+    /// Adds a violation for each broken rule found in self, which is at the given path.
+    fn validate_at(&self, path: &[String], scope: Scope, violations: &mut Vec<Violation>);
+}
+
+/// Returns all violations found in the given value, which is at the given top-level path, like
+/// "settings".
+pub fn validate<T: Validate>(value: &T, path: &str, scope: Scope) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    value.validate_at(&[path.to_string()], scope, &mut violations);
+    violations
+}
+
+/// Validates a field of a struct at the given path; used by the `#[model]` attribute.
+pub fn validate_field<T: Validate>(
+    value: &T,
+    path: &[String],
+    name: &str,
+    scope: Scope,
+    violations: &mut Vec<Violation>,
+) {
+    let mut field_path = path.to_vec();
+    field_path.push(name.to_string());
+    value.validate_at(&field_path, scope, violations);
+}
+
+/// Adds a violation at the given path for each message from a struct's validation function; used
+/// by the `#[model]` attribute.
+pub fn add_violations(path: &[String], messages: Vec<String>, violations: &mut Vec<Violation>) {
+    violations.extend(messages.into_iter().map(|message| Violation {
+        path: path.to_vec(),
+        message,
+    }));
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn validate_at(&self, path: &[String], scope: Scope, violations: &mut Vec<Violation>) {
+        if let Some(value) = self {
+            value.validate_at(path, scope, violations);
+        }
+    }
+}
+
+/// Elements of lists are at their index, like they're stored in the data store.
+impl<T: Validate> Validate for Vec<T> {
+    fn validate_at(&self, path: &[String], scope: Scope, violations: &mut Vec<Violation>) {
+        for (i, value) in self.iter().enumerate() {
+            validate_field(value, path, &i.to_string(), scope, violations);
+        }
+    }
+}
+
+impl<K: AsRef<str>, V: Validate> Validate for HashMap<K, V> {
+    fn validate_at(&self, path: &[String], scope: Scope, violations: &mut Vec<Violation>) {
+        for (key, value) in self {
+            validate_field(value, path, key.as_ref(), scope, violations);
+        }
+    }
+}
+
+/// Types that don't contain model structs have no rules to check.  (Modeled types check their
+/// values when they're created, and get this from `string_impls_for`.)
+macro_rules! no_rules_for {
+    ($($for:ty),*) => {
+        $(
+            impl Validate for $for {
+                fn validate_at(&self, _: &[String], _: Scope, _: &mut Vec<Violation>) {}
+            }
+        )*
+    };
+}
+
+no_rules_for!(bool, i8, i16, i32, i64, u8, u16, u32, u64, String, Ipv4Addr);
+no_rules_for!(toml::Value, BottlerocketRelease);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BootstrapContainer, HostContainer, KubernetesSettings};
+    use serde_json::json;
+
+    fn host_containers(value: serde_json::Value) -> HashMap<String, HostContainer> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn enabled_host_container_needs_source() {
+        let containers = host_containers(json!({
+            "good": { "enabled": true, "source": "example.com/good" },
+            "bad": { "enabled": true, "superpowered": true },
+            "off": { "enabled": false },
+        }));
+
+        // A change may give the source elsewhere.
+        assert!(validate(&containers, "host-containers", Scope::Change).is_empty());
+
+        let violations = validate(&containers, "host-containers", Scope::Merged);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, vec!["host-containers", "bad"]);
+        assert_eq!(
+            violations[0].to_string(),
+            "host-containers.bad: source is required when enabled"
+        );
+    }
+
+    #[test]
+    fn all_violations() {
+        let containers: HashMap<String, BootstrapContainer> = serde_json::from_value(json!({
+            "once": { "mode": "once" },
+            "always": { "mode": "always", "essential": true },
+            "off": { "mode": "off" },
+        }))
+        .unwrap();
+        let mut paths: Vec<String> = validate(&containers, "bootstrap-containers", Scope::Merged)
+            .iter()
+            .map(|violation| violation.path.join("."))
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec!["bootstrap-containers.always", "bootstrap-containers.once"]
+        );
+    }
+
+    #[test]
+    fn tls_needs_bootstrap_token() {
+        let check = |value: serde_json::Value, scope| {
+            let kubernetes: KubernetesSettings = serde_json::from_value(value).unwrap();
+            validate(&kubernetes, "kubernetes", scope).len()
+        };
+        let tls = json!({ "authentication-mode": "tls" });
+        assert_eq!(check(tls.clone(), Scope::Change), 0);
+        assert_eq!(check(tls, Scope::Merged), 1);
+        let token = json!({
+            "authentication-mode": "tls",
+            "bootstrap-token": "abcdef.0123456789abcdef",
+        });
+        assert_eq!(check(token, Scope::Merged), 0);
+        let standalone = json!({ "authentication-mode": "tls", "standalone-mode": true });
+        assert_eq!(check(standalone, Scope::Merged), 0);
+        assert_eq!(
+            check(json!({ "authentication-mode": "aws" }), Scope::Merged),
+            0
+        );
+    }
+
+    #[test]
+    fn nested_static_pods() {
+        let kubernetes: KubernetesSettings = serde_json::from_value(json!({
+            "static-pods": {
+                "empty": { "enabled": true, "manifest": "" },
+                "missing": { "enabled": true },
+                "disabled": { "enabled": false, "manifest": "" },
+            },
+        }))
+        .unwrap();
+
+        // An empty manifest is a problem even in a change, but a missing one may be given later.
+        let violations: Vec<String> = validate(&kubernetes, "kubernetes", Scope::Change)
+            .iter()
+            .map(|violation| violation.to_string())
+            .collect();
+        assert_eq!(
+            violations,
+            vec!["kubernetes.static-pods.empty: manifest must not be empty when enabled"]
+        );
+        assert_eq!(validate(&kubernetes, "kubernetes", Scope::Merged).len(), 2);
+    }
+
+    #[test]
+    fn quoted_paths() {
+        let violation = Violation {
+            path: vec!["settings".into(), "a.b".into()],
+            message: "problem".into(),
+        };
+        assert_eq!(violation.to_string(), "settings.\"a.b\": problem");
+    }
+}
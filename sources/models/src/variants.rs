@@ -0,0 +1,234 @@
+//! Each variant's model is compiled as its own module here, so tools can work with the models of
+//! more than one variant in a single build, through the registry of variants by name.
+//!
+//! The variant being built, picked by the `VARIANT` environment variable in build.rs, is also
+//! available as the `current` module, which the crate re-exports for on-host binaries.
+
+use crate::schema::{self, JsonSchema};
+use crate::validation::{self, Scope, Validate, Violation};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+// This is the top-level model exposed by the API system. It contains the common sections for all
+// variants.  This allows a single API call to retrieve everything the API system knows, which is
+// useful as a check and also, for example, as a data source for templated configuration files.
+macro_rules! variant_model {
+    () => {
+        use crate::{ConfigurationFiles, Services};
+        use bottlerocket_release::BottlerocketRelease;
+        use model_derive::model;
+        use serde::{Deserialize, Serialize};
+
+        #[model]
+        struct Model {
+            settings: Settings,
+            services: Services,
+            configuration_files: ConfigurationFiles,
+            os: BottlerocketRelease,
+        }
+    };
+}
+
+// Variants that share a model link their mod.rs to another variant's, so each model is compiled
+// once, named for the directory that holds it.  The variant's Settings are compiled from that file,
+// and its module adds the Model around them.
+
+#[path = "aws-dev/mod.rs"]
+mod aws_dev_settings;
+
+pub mod aws_dev {
+    pub use super::aws_dev_settings::*;
+    variant_model!();
+}
+
+#[path = "aws-ecs-1/mod.rs"]
+mod aws_ecs_1_settings;
+
+pub mod aws_ecs_1 {
+    pub use super::aws_ecs_1_settings::*;
+    variant_model!();
+}
+
+#[path = "aws-k8s-1.19/mod.rs"]
+mod aws_k8s_1_19_settings;
+
+pub mod aws_k8s_1_19 {
+    pub use super::aws_k8s_1_19_settings::*;
+    variant_model!();
+}
+
+#[path = "vmware-dev/mod.rs"]
+mod vmware_dev_settings;
+
+pub mod vmware_dev {
+    pub use super::vmware_dev_settings::*;
+    variant_model!();
+}
+
+#[path = "vmware-k8s-1.21/mod.rs"]
+mod vmware_k8s_1_21_settings;
+
+pub mod vmware_k8s_1_21 {
+    pub use super::vmware_k8s_1_21_settings::*;
+    variant_model!();
+}
+
+/// A variant's model, for tools that work with more than one variant.
+pub struct Variant {
+    name: &'static str,
+    schema: fn() -> Value,
+    validate: fn(Value, Scope) -> serde_json::Result<Vec<Violation>>,
+}
+
+impl Variant {
+    /// Returns the name of the variant, like "aws-k8s-1.21".
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns a JSON Schema document for the variant's Model.
+    pub fn schema(&self) -> Value {
+        (self.schema)()
+    }
+
+    /// Checks the given settings, like `{"motd": "hi"}`, against the variant's Settings.  Returns
+    /// an error if they don't deserialize, or else the rules they break, if any.
+    pub fn validate_settings(
+        &self,
+        settings: Value,
+        scope: Scope,
+    ) -> serde_json::Result<Vec<Violation>> {
+        (self.validate)(settings, scope)
+    }
+}
+
+fn schema_of<T: JsonSchema>() -> Value {
+    schema::schema_for::<T>()
+}
+
+fn validate_as<S: DeserializeOwned + Validate>(
+    settings: Value,
+    scope: Scope,
+) -> serde_json::Result<Vec<Violation>> {
+    let settings: S = serde_json::from_value(settings)?;
+    Ok(validation::validate(&settings, "settings", scope))
+}
+
+// Lists each variant with the module of its model; this builds the registry, and picks the
+// `current` module for the variant being built.
+macro_rules! variants {
+    ($($name:literal => $module:ident,)*) => {
+        static VARIANTS: &[Variant] = &[
+            $(
+                Variant {
+                    name: $name,
+                    schema: schema_of::<$module::Model>,
+                    validate: validate_as::<$module::Settings>,
+                },
+            )*
+        ];
+
+        $(
+            #[cfg(variant = $name)]
+            pub use $module as current;
+        )*
+    };
+}
+
+variants! {
+    "aws-dev" => aws_dev,
+    "aws-ecs-1" => aws_ecs_1,
+    "aws-k8s-1.16" => aws_k8s_1_19,
+    "aws-k8s-1.17" => aws_k8s_1_19,
+    "aws-k8s-1.18" => aws_k8s_1_19,
+    "aws-k8s-1.19" => aws_k8s_1_19,
+    "aws-k8s-1.20" => aws_k8s_1_19,
+    "aws-k8s-1.21" => aws_k8s_1_19,
+    "vmware-dev" => vmware_dev,
+    "vmware-k8s-1.20" => vmware_k8s_1_21,
+    "vmware-k8s-1.21" => vmware_k8s_1_21,
+}
+
+/// Returns every variant's model.
+pub fn all() -> &'static [Variant] {
+    VARIANTS
+}
+
+/// Returns the model of the variant with the given name, if there is one.
+pub fn get(name: &str) -> Option<&'static Variant> {
+    VARIANTS.iter().find(|variant| variant.name == name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use regex::Regex;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn every_variant_directory_registered() {
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut dirs: Vec<String> = fs::read_dir(&src)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.join("defaults.d").is_dir())
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        dirs.sort();
+
+        let names: Vec<&str> = all().iter().map(Variant::name).collect();
+        assert_eq!(names, dirs);
+
+        // Each variant's mod.rs, through any symlinks, must be the file its registered module was
+        // compiled from.  Both mappings are read from this file: `"name" => module,` entries in
+        // the variants! list, and the `#[path = "dir/mod.rs"]` of each module's settings.
+        let source = fs::read_to_string(src.join("variants.rs")).unwrap();
+        let entry = Regex::new(r#"(?m)^\s*"([^"]+)" => (\w+),$"#).unwrap();
+        let modules: HashMap<&str, &str> = entry
+            .captures_iter(&source)
+            .map(|c| (c.get(1).unwrap().as_str(), c.get(2).unwrap().as_str()))
+            .collect();
+        let module_path = Regex::new(r#"#\[path = "([^"]+)"\]\s*mod (\w+)_settings;"#).unwrap();
+        let paths: HashMap<&str, &str> = module_path
+            .captures_iter(&source)
+            .map(|c| (c.get(2).unwrap().as_str(), c.get(1).unwrap().as_str()))
+            .collect();
+        for name in &dirs {
+            let module = modules[name.as_str()];
+            let compiled = src.join(paths[module]).canonicalize().unwrap();
+            let linked = src.join(name).join("mod.rs").canonicalize().unwrap();
+            assert_eq!(
+                linked,
+                compiled,
+                "{} is registered with {}, but its mod.rs is {}",
+                name,
+                module,
+                linked.display()
+            );
+        }
+    }
+
+    #[test]
+    fn settings_by_variant() {
+        let ecs = get("aws-ecs-1").unwrap();
+        let k8s = get("aws-k8s-1.21").unwrap();
+        assert!(get("aws-nope").is_none());
+
+        let settings = json!({"ecs": {"cluster": "default"}});
+        assert!(ecs
+            .validate_settings(settings.clone(), Scope::Change)
+            .is_ok());
+        assert!(k8s.validate_settings(settings, Scope::Change).is_err());
+
+        let settings = json!({"kubernetes": {"cluster-name": "c"}});
+        assert!(k8s.validate_settings(settings, Scope::Change).is_ok());
+
+        let schema = k8s.schema();
+        assert_eq!(schema["$ref"], "#/$defs/Model");
+        assert!(schema["$defs"]["Settings"]["properties"]["kubernetes"].is_object());
+        assert!(ecs.schema()["$defs"]["Settings"]["properties"]["kubernetes"].is_null());
+    }
+}